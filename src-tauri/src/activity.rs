@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::settings::SettingsStore;
+
+/// How long activity is kept on disk.
+const RETENTION: Duration = Duration::from_secs(60 * 60 * 24 * 90);
+/// How often the periodic report is considered.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How many peers are listed in a report.
+const TOP_PEERS: usize = 5;
+/// Smaller transfers are mostly connection setup, their throughput says
+/// little about the link.
+const MIN_SAMPLE_BYTES: u64 = 1024 * 1024;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Something that happened, as recorded in the activity log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Activity {
+    /// The user shared a path.
+    Shared { name: String, ok: bool },
+    /// A request for one of our shares was served.
+    Served {
+        peer: Option<String>,
+        bytes: u64,
+        /// Whether the whole share was requested, as opposed to a probe or resume.
+        complete: bool,
+        ok: bool,
+        /// Unset in records of older versions.
+        #[serde(default)]
+        elapsed_ms: Option<u64>,
+    },
+    /// The user downloaded a share.
+    Received {
+        name: String,
+        bytes: u64,
+        ok: bool,
+        /// Hash of the collection, unset in records of older versions.
+        #[serde(default)]
+        hash: Option<String>,
+        /// Paths of the top level files and directories that were written.
+        #[serde(default)]
+        saved: Vec<String>,
+        /// How long fetching took, unset for resumed or copied downloads and
+        /// in records of older versions.
+        #[serde(default)]
+        elapsed_ms: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    time: u64,
+    #[serde(flatten)]
+    activity: Activity,
+}
+
+/// Append only log of transfer activity, one json record per line.
+#[derive(Debug)]
+pub struct ActivityLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl ActivityLog {
+    /// Open the log at `path`, dropping records older than the retention period.
+    pub fn open(path: PathBuf) -> Self {
+        let log = Self {
+            path,
+            lock: Mutex::new(()),
+        };
+        if let Err(err) = log.prune() {
+            log!("failed to prune activity log: {:#}", err);
+        }
+        log
+    }
+
+    fn read(&self) -> Vec<Record> {
+        let data = std::fs::read_to_string(&self.path).unwrap_or_default();
+        data.lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+
+    fn prune(&self) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let cutoff = now().saturating_sub(RETENTION.as_secs());
+        let records = self.read();
+        if records.iter().all(|r| r.time >= cutoff) {
+            return Ok(());
+        }
+        let kept = records.into_iter().filter(|r| r.time >= cutoff);
+        self.write(kept)
+    }
+
+    /// Replace the log with `records`.
+    fn write(&self, records: impl IntoIterator<Item = Record>) -> anyhow::Result<()> {
+        let mut data = String::new();
+        for record in records {
+            data.push_str(&serde_json::to_string(&record)?);
+            data.push('\n');
+        }
+        let tmp = self.path.with_extension("jsonl.tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Forget all successful downloads, returning how many were removed.
+    pub fn clear_received(&self) -> anyhow::Result<usize> {
+        let _guard = self.lock.lock().unwrap();
+        let (removed, kept): (Vec<_>, Vec<_>) = self
+            .read()
+            .into_iter()
+            .partition(|r| matches!(r.activity, Activity::Received { ok: true, .. }));
+        if !removed.is_empty() {
+            self.write(kept)?;
+        }
+        Ok(removed.len())
+    }
+
+    pub fn record(&self, activity: Activity) {
+        let record = Record {
+            time: now(),
+            activity,
+        };
+        let _guard = self.lock.lock().unwrap();
+        let res = (|| -> anyhow::Result<()> {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            writeln!(file, "{}", serde_json::to_string(&record)?)?;
+            Ok(())
+        })();
+        if let Err(err) = res {
+            log!("failed to record activity: {:#}", err);
+        }
+    }
+
+    /// The latest successful download of the collection `hash`, with its time
+    /// and the paths that were written.
+    pub fn received(&self, hash: &str) -> Option<(u64, Vec<String>)> {
+        let _guard = self.lock.lock().unwrap();
+        self.read()
+            .into_iter()
+            .rev()
+            .find_map(|record| match record.activity {
+                Activity::Received {
+                    ok: true,
+                    hash: Some(h),
+                    saved,
+                    ..
+                } if h == hash => Some((record.time, saved)),
+                _ => None,
+            })
+    }
+
+    /// Throughput in bytes per second of up to `limit` recent successful
+    /// transfers, newest first. `sent` selects served shares over downloads.
+    pub fn throughputs(&self, sent: bool, limit: usize) -> Vec<u64> {
+        let _guard = self.lock.lock().unwrap();
+        self.read()
+            .into_iter()
+            .rev()
+            .filter_map(|record| {
+                let (bytes, ms) = match record.activity {
+                    Activity::Served {
+                        bytes,
+                        complete: true,
+                        ok: true,
+                        elapsed_ms: Some(ms),
+                        ..
+                    } if sent => (bytes, ms),
+                    Activity::Received {
+                        bytes,
+                        ok: true,
+                        elapsed_ms: Some(ms),
+                        ..
+                    } if !sent => (bytes, ms),
+                    _ => return None,
+                };
+                (bytes >= MIN_SAMPLE_BYTES && ms > 0).then(|| bytes * 1000 / ms)
+            })
+            .take(limit)
+            .collect()
+    }
+
+    /// Summarize the activity of the last `period`.
+    pub fn report(&self, period: Period) -> ActivityReport {
+        let until = now();
+        let since = until.saturating_sub(period.duration().as_secs());
+        let _guard = self.lock.lock().unwrap();
+        let mut report = ActivityReport {
+            since,
+            until,
+            ..Default::default()
+        };
+        let mut peers: HashMap<String, PeerActivity> = HashMap::new();
+        for record in self.read().into_iter().filter(|r| r.time >= since) {
+            match record.activity {
+                Activity::Shared { ok: true, .. } => report.shared += 1,
+                Activity::Shared { ok: false, .. } => report.failures += 1,
+                Activity::Served {
+                    peer,
+                    bytes,
+                    complete,
+                    ok,
+                    ..
+                } => {
+                    report.bytes_sent += bytes;
+                    if !ok {
+                        report.failures += 1;
+                    } else if complete {
+                        report.served += 1;
+                    }
+                    if let Some(peer) = peer {
+                        let entry = peers.entry(peer.clone()).or_insert(PeerActivity {
+                            peer,
+                            bytes: 0,
+                            downloads: 0,
+                        });
+                        entry.bytes += bytes;
+                        entry.downloads += (ok && complete) as u64;
+                    }
+                }
+                Activity::Received { bytes, ok, .. } => {
+                    if ok {
+                        report.received += 1;
+                        report.bytes_received += bytes;
+                    } else {
+                        report.failures += 1;
+                    }
+                }
+            }
+        }
+        let mut peers: Vec<_> = peers.into_values().collect();
+        peers.sort_by_key(|p| std::cmp::Reverse(p.bytes));
+        peers.truncate(TOP_PEERS);
+        report.top_peers = peers;
+        report
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    fn duration(self) -> Duration {
+        let days = match self {
+            Period::Day => 1,
+            Period::Week => 7,
+            Period::Month => 30,
+        };
+        Duration::from_secs(60 * 60 * 24 * days)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerActivity {
+    pub peer: String,
+    pub bytes: u64,
+    pub downloads: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ActivityReport {
+    /// Start of the reported period, unix time.
+    pub since: u64,
+    /// End of the reported period, unix time.
+    pub until: u64,
+    pub shared: u64,
+    /// Complete downloads of our shares by peers.
+    pub served: u64,
+    pub bytes_sent: u64,
+    pub received: u64,
+    pub bytes_received: u64,
+    pub failures: u64,
+    /// The peers we sent the most to.
+    pub top_peers: Vec<PeerActivity>,
+}
+
+/// Send a weekly report through the configured notifications, if enabled.
+pub fn spawn_weekly_report(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let settings = app.state::<SettingsStore>().get();
+            if settings.notifications.weekly_report {
+                let week = Period::Week.duration().as_secs();
+                match settings.activity_report_sent {
+                    // start counting from when the report was enabled
+                    None => {
+                        app.state::<SettingsStore>()
+                            .update(|s| s.activity_report_sent = Some(now()))
+                            .ok();
+                    }
+                    Some(sent) if now() >= sent + week => {
+                        let report = app.state::<Arc<ActivityLog>>().report(Period::Week);
+                        crate::notify::send_report(&app, &report).await;
+                        app.state::<SettingsStore>()
+                            .update(|s| s.activity_report_sent = Some(now()))
+                            .ok();
+                    }
+                    Some(_) => {}
+                }
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub fn activity_report(period: Period, activity: State<'_, Arc<ActivityLog>>) -> ActivityReport {
+    activity.report(period)
+}
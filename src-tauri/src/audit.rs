@@ -0,0 +1,108 @@
+use std::{
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use tokio::sync::broadcast;
+
+/// How many entries the frontend gets at most.
+const MAX_ENTRIES: usize = 1000;
+
+/// How many events a slow subscriber may fall behind before it misses some.
+const CHANNEL_SIZE: usize = 64;
+
+/// A security relevant event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// The tickets of a collection were revoked.
+    Revoked { hash: String },
+    /// A revocation was lifted.
+    Unrevoked { hash: String },
+    /// A request of a peer was refused.
+    Refused {
+        peer: Option<String>,
+        hash: String,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Unix time of the event.
+    pub time: u64,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+/// Append only log of security relevant events, one json record per line.
+///
+/// Unlike the activity log it is never pruned.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+    events: broadcast::Sender<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn open(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+            events: broadcast::channel(CHANNEL_SIZE).0,
+        }
+    }
+
+    /// Events recorded from now on, see [`crate::security`].
+    pub fn subscribe(&self) -> broadcast::Receiver<AuditEntry> {
+        self.events.subscribe()
+    }
+
+    pub fn record(&self, event: AuditEvent) {
+        let entry = AuditEntry {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            event,
+        };
+        let _guard = self.lock.lock().unwrap();
+        let res = (|| -> anyhow::Result<()> {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+            Ok(())
+        })();
+        if let Err(err) = res {
+            log!("failed to record audit event: {:#}", err);
+        }
+        // nobody listening is fine
+        self.events.send(entry).ok();
+    }
+
+    /// The latest entries, newest first.
+    pub fn entries(&self, limit: usize) -> Vec<AuditEntry> {
+        let _guard = self.lock.lock().unwrap();
+        let data = std::fs::read_to_string(&self.path).unwrap_or_default();
+        data.lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .take(limit)
+            .collect()
+    }
+}
+
+#[tauri::command]
+pub fn audit_log(limit: Option<usize>, audit: State<'_, Arc<AuditLog>>) -> Vec<AuditEntry> {
+    audit.entries(limit.unwrap_or(MAX_ENTRIES).min(MAX_ENTRIES))
+}
@@ -0,0 +1,64 @@
+use rand::Rng;
+use tauri::{State, Window};
+
+/// Labels of the windows we create ourselves and trust with the session token.
+const TRUSTED_WINDOWS: &[&str] = &["share", "receive"];
+
+/// A per-session token that sensitive commands require.
+///
+/// The token is minted once at startup and only handed out to our own windows,
+/// so injected content in a webview can not trigger destructive operations
+/// (identity export, settings changes, history wipes) without it.
+#[derive(Debug)]
+pub struct SessionToken(String);
+
+impl SessionToken {
+    pub fn generate() -> Self {
+        let bytes = rand::thread_rng().gen::<[u8; 32]>();
+        Self(hex::encode(bytes))
+    }
+
+    /// Check a token passed in from the frontend.
+    pub fn verify(&self, token: &str) -> Result<(), String> {
+        if tokens_equal(&self.0, token) {
+            Ok(())
+        } else {
+            Err("invalid session token".to_string())
+        }
+    }
+}
+
+/// Compare two secret tokens in constant time, the length is not secret.
+pub fn tokens_equal(expected: &str, given: &str) -> bool {
+    let expected = expected.as_bytes();
+    let given = given.as_bytes();
+    expected.len() == given.len()
+        && expected
+            .iter()
+            .zip(given)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn is_trusted(window: &Window) -> bool {
+    if !TRUSTED_WINDOWS.contains(&window.label()) {
+        return false;
+    }
+    // only our bundled assets, or the dev server in debug builds
+    let url = window.url();
+    match url.scheme() {
+        "tauri" => true,
+        "https" => url.host_str() == Some("tauri.localhost"),
+        "http" => cfg!(debug_assertions) && url.host_str() == Some("localhost"),
+        _ => false,
+    }
+}
+
+#[tauri::command]
+pub fn session_token(window: Window, token: State<'_, SessionToken>) -> Result<String, String> {
+    if !is_trusted(&window) {
+        log!("refusing session token for window {}", window.label());
+        return Err("untrusted window".to_string());
+    }
+    Ok(token.0.clone())
+}
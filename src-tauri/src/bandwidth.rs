@@ -0,0 +1,251 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use iroh_bytes::{
+    get::db::DownloadProgress,
+    util::progress::{IdGenerator, ProgressSendError, ProgressSender},
+};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::{auth::SessionToken, sched::Scheduler, settings::SettingsStore};
+
+/// App wide rate caps in bytes per second, no limit if unset. Shares and
+/// downloads can set their own cap instead.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthLimits {
+    /// Total upload rate of all shares.
+    pub upload: Option<u64>,
+    /// Total download rate of all downloads.
+    pub download: Option<u64>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    rate: Option<u64>,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket for a rate cap in bytes per second, shared by all streams it
+/// applies to. Up to a second worth of bytes can be sent at once.
+#[derive(Debug)]
+pub struct Bucket(Mutex<BucketState>);
+
+impl Default for Bucket {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Bucket {
+    pub fn new(rate: Option<u64>) -> Self {
+        let rate = rate.filter(|r| *r > 0);
+        Self(Mutex::new(BucketState {
+            rate,
+            tokens: rate.unwrap_or_default() as f64,
+            last_refill: Instant::now(),
+        }))
+    }
+
+    /// Change the cap, `None` for no limit.
+    pub fn set_rate(&self, rate: Option<u64>) {
+        let mut state = self.0.lock().unwrap();
+        state.rate = rate.filter(|r| *r > 0);
+        state.tokens = state.tokens.min(state.rate.unwrap_or_default() as f64);
+    }
+
+    /// Take `len` bytes from the bucket, returning how long to wait before
+    /// they may be sent.
+    pub fn take(&self, len: usize) -> Option<Duration> {
+        let mut state = self.0.lock().unwrap();
+        let rate = state.rate? as f64;
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.last_refill = now;
+        state.tokens = (state.tokens + elapsed * rate).min(rate);
+        state.tokens -= len as f64;
+        (state.tokens < 0.0).then(|| Duration::from_secs_f64(-state.tokens / rate))
+    }
+}
+
+/// The app wide download cap, shared by all downloads without their own.
+#[derive(Debug, Default)]
+pub struct DownloadCap(pub Arc<Bucket>);
+
+/// Throttles a download through its progress reports.
+///
+/// The getter reports progress synchronously after every write to the store.
+/// The reports take the written bytes from the bucket, and the download
+/// future, run with [`Throttle::hold`], is not polled again until the bucket
+/// has them. QUIC flow control then slows down the provider.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    bucket: Arc<Bucket>,
+    next_id: Arc<AtomicU64>,
+    /// Id and offset of the latest progress report.
+    last: Arc<Mutex<(u64, u64)>>,
+    /// How long to hold up the download, from the latest reports.
+    wait: Arc<Mutex<Option<Duration>>>,
+}
+
+impl Throttle {
+    pub fn new(bucket: Arc<Bucket>) -> Self {
+        Self {
+            bucket,
+            next_id: Default::default(),
+            last: Default::default(),
+            wait: Default::default(),
+        }
+    }
+
+    /// Run `fut`, which reports its progress to this throttle, at the rate of
+    /// the bucket.
+    pub fn hold<F: Future>(&self, fut: F) -> Throttled<F> {
+        Throttled {
+            inner: Box::pin(fut),
+            throttle: self.clone(),
+            sleep: None,
+        }
+    }
+
+    /// Bytes written since the previous report. The first report of a blob
+    /// counts nothing, a resumed blob does not start at zero.
+    fn advance(&self, id: u64, offset: u64) -> u64 {
+        let mut last = self.last.lock().unwrap();
+        let len = if last.0 == id {
+            offset.saturating_sub(last.1)
+        } else {
+            0
+        };
+        *last = (id, offset);
+        len
+    }
+}
+
+impl ProgressSender for Throttle {
+    type Msg = DownloadProgress;
+
+    type SendFuture<'a> = futures::future::Ready<Result<(), ProgressSendError>>;
+
+    fn send(&self, _msg: DownloadProgress) -> Self::SendFuture<'_> {
+        futures::future::ready(Ok(()))
+    }
+
+    fn try_send(&self, msg: DownloadProgress) -> Result<(), ProgressSendError> {
+        if let DownloadProgress::Progress { id, offset } = msg {
+            let len = self.advance(id, offset);
+            if let Some(delay) = self.bucket.take(len as usize) {
+                let mut wait = self.wait.lock().unwrap();
+                *wait = Some(wait.map_or(delay, |wait| wait.max(delay)));
+            }
+        }
+        Ok(())
+    }
+
+    fn blocking_send(&self, msg: DownloadProgress) -> Result<(), ProgressSendError> {
+        self.try_send(msg)
+    }
+}
+
+impl IdGenerator for Throttle {
+    fn new_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// A future run with [`Throttle::hold`].
+pub struct Throttled<F> {
+    inner: Pin<Box<F>>,
+    throttle: Throttle,
+    sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl<F: Future> Future for Throttled<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        loop {
+            if let Some(sleep) = &mut self.sleep {
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+            if let Poll::Ready(res) = self.inner.as_mut().poll(cx) {
+                return Poll::Ready(res);
+            }
+            let wait = self.throttle.wait.lock().unwrap().take();
+            match wait {
+                Some(wait) => self.sleep = Some(Box::pin(tokio::time::sleep(wait))),
+                None => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Set the app wide upload and download caps.
+#[tauri::command]
+pub fn set_bandwidth_limits(
+    limits: BandwidthLimits,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+    scheduler: State<'_, Arc<Scheduler>>,
+    downloads: State<'_, DownloadCap>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let current = settings
+        .update(|s| s.bandwidth = limits)
+        .map_err(|e| e.to_string())?;
+    scheduler.set_upload_cap(current.bandwidth.upload);
+    downloads.0.set_rate(current.bandwidth.download);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_tokens() {
+        let bucket = Bucket::new(Some(1000));
+        // a second worth of bytes goes through at once
+        assert_eq!(bucket.take(1000), None);
+        let delay = bucket.take(500).unwrap();
+        assert!(delay > Duration::from_millis(400) && delay <= Duration::from_millis(500));
+        bucket.set_rate(None);
+        assert_eq!(bucket.take(1_000_000), None);
+        let throttle = Throttle::new(Arc::new(bucket));
+        assert_eq!(throttle.advance(1, 4096), 0);
+        assert_eq!(throttle.advance(1, 8192), 4096);
+        assert_eq!(throttle.advance(2, 100), 0);
+    }
+
+    /// Held up downloads sleep on the runtime, which is a current thread one
+    /// for the getter.
+    #[tokio::test]
+    async fn holds_up_downloads() {
+        let throttle = Throttle::new(Arc::new(Bucket::new(Some(1_000_000))));
+        let sender = throttle.clone();
+        let start = Instant::now();
+        let written = throttle
+            .hold(async move {
+                let progress = |offset| DownloadProgress::Progress { id: 1, offset };
+                sender.try_send(progress(0)).unwrap();
+                // 100ms over the cap
+                sender.try_send(progress(1_100_000)).unwrap();
+                tokio::task::yield_now().await;
+                Instant::now()
+            })
+            .await;
+        assert!(written - start >= Duration::from_millis(90));
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use futures::{SinkExt, StreamExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request, Response},
+        http::StatusCode,
+        protocol::WebSocketConfig,
+        Message as WsMessage,
+    },
+    WebSocketStream,
+};
+
+use crate::{
+    auth::{self, SessionToken},
+    i18n::I18n,
+    ratelimit::RateLimiter,
+    settings::SettingsStore,
+    upload::ShareOptions,
+    webdav,
+};
+
+/// Largest message accepted from the extension.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+/// A localhost WebSocket server a companion browser extension talks to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BridgeSettings {
+    /// Port of the server, off if unset.
+    pub port: Option<u16>,
+    /// Origins of the extensions allowed to connect, e.g.
+    /// `chrome-extension://<id>`. Web pages can not fake their origin.
+    pub origins: Vec<String>,
+    /// Secret the extension has to send first, set when the bridge is
+    /// enabled and shown to the user to pair the extension.
+    pub token: Option<String>,
+}
+
+/// A message from the extension, answered with a [`Reply`] of the same id.
+#[derive(Debug, Deserialize)]
+struct Message {
+    id: u64,
+    #[serde(flatten)]
+    call: Call,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum Call {
+    /// Has to come first.
+    Hello { token: String },
+    /// Share a file the browser downloaded, replying with its ticket.
+    Share { path: PathBuf },
+    /// A ticket or `sendme://` link found on a web page. The user confirms
+    /// the download in the app.
+    Ticket { ticket: String },
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Reply {
+    id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ticket: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Sent to the frontend as `bridge-shared` for every file shared from the
+/// browser.
+#[derive(Debug, Clone, Serialize)]
+struct BridgeShare {
+    name: String,
+    ticket: String,
+}
+
+/// Complete the WebSocket handshake of a connection, if it comes from an
+/// allowed extension to localhost and the bridge has a pairing token.
+async fn accept(
+    stream: TcpStream,
+    port: u16,
+    settings: &BridgeSettings,
+) -> anyhow::Result<WebSocketStream<TcpStream>> {
+    // the response type is tungstenite's
+    #[allow(clippy::result_large_err)]
+    let check = |request: &Request, response: Response| {
+        let header = |name| request.headers().get(name).and_then(|v| v.to_str().ok());
+        let local = header("host").is_some_and(|host| webdav::is_local(host, port));
+        let origin = header("origin");
+        let allowed = origin.is_some_and(|origin| settings.origins.iter().any(|o| o == origin));
+        if local && allowed && settings.token.is_some() {
+            return Ok(response);
+        }
+        let mut forbidden = ErrorResponse::new(None);
+        *forbidden.status_mut() = StatusCode::FORBIDDEN;
+        Err(forbidden)
+    };
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE),
+        max_frame_size: Some(MAX_MESSAGE),
+        ..Default::default()
+    };
+    let ws = tokio_tungstenite::accept_hdr_async_with_config(stream, check, Some(config)).await?;
+    Ok(ws)
+}
+
+async fn send_reply(ws: &mut WebSocketStream<TcpStream>, reply: &Reply) -> anyhow::Result<()> {
+    ws.send(WsMessage::Text(serde_json::to_string(reply)?))
+        .await?;
+    Ok(())
+}
+
+/// `path` if it is a file in the download folder. The extension may not
+/// share anything else.
+fn check_download(app: &AppHandle, path: &Path) -> anyhow::Result<PathBuf> {
+    let settings = app.state::<SettingsStore>().get();
+    let dir = crate::download::default_download_dir(&settings)?.canonicalize()?;
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("cannot read {}", path.display()))?;
+    anyhow::ensure!(
+        path.starts_with(&dir) && path.is_file(),
+        "only files in the download folder can be shared"
+    );
+    Ok(path)
+}
+
+async fn share(app: &AppHandle, path: &Path) -> anyhow::Result<String> {
+    let path = check_download(app, path)?;
+    let i18n = app.state::<I18n>();
+    app.state::<RateLimiter>()
+        .check("upload", &i18n)
+        .map_err(anyhow::Error::msg)?;
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ticket = crate::share(app, vec![path], ShareOptions::default())
+        .await?
+        .to_string();
+    crate::notify::desktop(app, "bridge.shared", &[("name", &name)]);
+    let share = BridgeShare {
+        name,
+        ticket: ticket.clone(),
+    };
+    app.emit_all("bridge-shared", share).ok();
+    Ok(ticket)
+}
+
+/// Hand a ticket to the download confirmation view as `bridge-ticket`.
+fn receive(app: &AppHandle, ticket: &str) -> anyhow::Result<()> {
+    let (ticket, hint) = match ticket.trim() {
+        link if link.starts_with(crate::deeplink::SCHEME) => crate::deeplink::parse(link)?,
+        ticket => crate::password::parse_ticket(ticket)?,
+    };
+    let link = crate::deeplink::describe(&ticket, hint.as_deref());
+    crate::notify::desktop(app, "bridge.ticket", &[("sender", &link.sender)]);
+    app.emit_all("bridge-ticket", link).ok();
+    Ok(())
+}
+
+async fn handle(stream: TcpStream, app: &AppHandle, port: u16) -> anyhow::Result<()> {
+    let settings = app.state::<SettingsStore>().get().bridge;
+    let mut ws = accept(stream, port, &settings).await?;
+    let expected = settings.token.unwrap_or_default();
+    let mut paired = false;
+    // pings are answered by tungstenite
+    while let Some(message) = ws.next().await {
+        let text = match message? {
+            WsMessage::Text(text) => text,
+            WsMessage::Close(_) => break,
+            WsMessage::Binary(_) => anyhow::bail!("unsupported binary message"),
+            _ => continue,
+        };
+        let message: Message = serde_json::from_str(&text).context("invalid message")?;
+        let mut reply = Reply {
+            id: message.id,
+            ..Default::default()
+        };
+        let res = match message.call {
+            Call::Hello { token } => {
+                paired = auth::tokens_equal(&expected, &token);
+                if paired {
+                    Ok(())
+                } else {
+                    Err(anyhow::anyhow!("invalid token"))
+                }
+            }
+            _ if !paired => Err(anyhow::anyhow!("not paired")),
+            Call::Share { path } => share(app, &path)
+                .await
+                .map(|ticket| reply.ticket = Some(ticket)),
+            Call::Ticket { ticket } => receive(app, &ticket),
+        };
+        if let Err(err) = res {
+            reply.error = Some(format!("{:#}", err));
+        }
+        send_reply(&mut ws, &reply).await?;
+        if !paired {
+            break;
+        }
+    }
+    ws.close(None).await.ok();
+    Ok(())
+}
+
+/// Serve the browser extension bridge on localhost, if enabled.
+pub fn spawn_server(app: AppHandle) {
+    let Some(port) = app.state::<SettingsStore>().get().bridge.port else {
+        return;
+    };
+    if let Err(err) = crate::users::check_port(crate::users::Protocol::Tcp, port) {
+        log!("not starting the browser bridge: {:#}", err);
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        let listener = match tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                log!(
+                    "failed to start the browser bridge on port {}: {}",
+                    port,
+                    err
+                );
+                return;
+            }
+        };
+        log!("browser bridge listening at ws://127.0.0.1:{}/", port);
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let app = app.clone();
+            tokio::spawn(async move {
+                if let Err(err) = handle(stream, &app, port).await {
+                    log!("browser bridge connection failed: {:#}", err);
+                }
+            });
+        }
+    });
+}
+
+/// The bridge settings, without the pairing token.
+#[tauri::command]
+pub fn get_bridge_settings(settings: State<'_, SettingsStore>) -> BridgeSettings {
+    BridgeSettings {
+        token: None,
+        ..settings.get().bridge
+    }
+}
+
+/// The pairing token to show the user, if the bridge is on.
+#[tauri::command]
+pub fn bridge_pairing_token(
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<Option<String>, String> {
+    session.verify(&token)?;
+    Ok(settings.get().bridge.token)
+}
+
+/// Enable the bridge on `port` for the extensions with `origins`, or disable
+/// it with `None`. A new pairing token is made when it is enabled. The server
+/// starts with the app.
+#[tauri::command]
+pub fn set_bridge_settings(
+    port: Option<u16>,
+    origins: Vec<String>,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<BridgeSettings, String> {
+    session.verify(&token)?;
+    let pairing = port.map(|_| hex::encode(rand::thread_rng().gen::<[u8; 16]>()));
+    let current = settings
+        .update(|s| {
+            s.bridge = BridgeSettings {
+                port,
+                origins,
+                token: pairing,
+            }
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(current.bridge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn checks_the_handshake() {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let settings = BridgeSettings {
+            port: Some(port),
+            origins: vec!["chrome-extension://abc".to_string()],
+            token: Some("secret".to_string()),
+        };
+        let connect = |origin: &'static str| async move {
+            let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+                .await
+                .unwrap();
+            let mut request =
+                tokio_tungstenite::tungstenite::client::IntoClientRequest::into_client_request(
+                    format!("ws://127.0.0.1:{}/", port),
+                )
+                .unwrap();
+            request
+                .headers_mut()
+                .insert("origin", origin.parse().unwrap());
+            tokio_tungstenite::client_async(request, stream).await
+        };
+        for (origin, allowed) in [
+            ("chrome-extension://abc", true),
+            ("https://evil.example", false),
+        ] {
+            let (server, client) = tokio::join!(
+                async { accept(listener.accept().await.unwrap().0, port, &settings).await },
+                connect(origin),
+            );
+            assert_eq!(server.is_ok(), allowed, "{}", origin);
+            let Ok((mut client, _)) = client else {
+                assert!(!allowed);
+                continue;
+            };
+            let mut server = server.unwrap();
+            client.send(WsMessage::Text("hi".into())).await.unwrap();
+            assert_eq!(
+                server.next().await.unwrap().unwrap(),
+                WsMessage::Text("hi".into())
+            );
+            // larger messages are refused
+            let large = "a".repeat(MAX_MESSAGE + 1);
+            client.send(WsMessage::Text(large)).await.unwrap();
+            assert!(server.next().await.unwrap().is_err());
+        }
+        let message: Message =
+            serde_json::from_str(r#"{"id": 3, "method": "share", "path": "/a"}"#).unwrap();
+        assert_eq!(message.id, 3);
+        assert!(matches!(message.call, Call::Share { path } if path == Path::new("/a")));
+    }
+}
@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::{
+    errors::{ErrorCode, UserError},
+    i18n::I18n,
+    password,
+    ratelimit::RateLimiter,
+    upload::ShareOptions,
+};
+
+const VERSION: u32 = 1;
+const EXTENSION: &str = "sendme-bundle";
+
+/// A `.sendme-bundle` file: several labeled tickets distributed together.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    pub tickets: Vec<BundleEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleEntry {
+    pub label: String,
+    pub ticket: String,
+}
+
+impl Bundle {
+    pub fn new(tickets: Vec<BundleEntry>) -> Self {
+        Self {
+            version: VERSION,
+            tickets,
+        }
+    }
+
+    /// Write the bundle to `path`, adding the bundle extension if it has none.
+    pub fn save(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        let mut path = path.to_path_buf();
+        if path.extension().is_none() {
+            path.set_extension(EXTENSION);
+        }
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(&path, data)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// The tickets as plain text, one labeled ticket per paragraph.
+    pub fn summary(&self) -> String {
+        self.tickets
+            .iter()
+            .map(|e| format!("{}:\n{}\n", e.label, e.ticket))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let bundle: Bundle = serde_json::from_slice(&data).context("invalid bundle")?;
+        anyhow::ensure!(
+            bundle.version <= VERSION,
+            "bundle version {} is not supported",
+            bundle.version
+        );
+        Ok(bundle)
+    }
+}
+
+/// A bundle entry as shown to the user, with the ticket already checked.
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleItem {
+    pub label: String,
+    pub ticket: String,
+    pub hash: Option<String>,
+    pub error: Option<String>,
+}
+
+impl From<BundleEntry> for BundleItem {
+    fn from(entry: BundleEntry) -> Self {
+        let (hash, error) = match password::parse_ticket(&entry.ticket) {
+            Ok((ticket, _)) => (Some(ticket.hash().to_hex().to_string()), None),
+            Err(err) => (None, Some(format!("{:#}", err))),
+        };
+        Self {
+            label: entry.label,
+            ticket: entry.ticket,
+            hash,
+            error,
+        }
+    }
+}
+
+/// List the tickets in a bundle, so the user can pick which ones to download.
+#[tauri::command]
+pub fn open_bundle(path: String) -> Result<Vec<BundleItem>, String> {
+    let bundle = Bundle::load(Path::new(&path)).map_err(|e| e.to_string())?;
+    Ok(bundle.tickets.into_iter().map(BundleItem::from).collect())
+}
+
+/// The result of [`share_batch`].
+#[derive(Debug, Serialize)]
+pub struct BatchShare {
+    pub bundle: Bundle,
+    /// Human readable list of the tickets, for pasting into a chat.
+    pub summary: String,
+    /// Where the bundle was saved, if requested.
+    pub saved_to: Option<String>,
+}
+
+/// The paths to create tickets for, the top level entries of directories
+/// if `individually` is set.
+fn expand(paths: Vec<PathBuf>, individually: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let mut res = Vec::new();
+    for path in paths {
+        if individually && path.is_dir() {
+            let mut entries = std::fs::read_dir(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            entries.sort();
+            res.extend(entries);
+        } else {
+            res.push(path);
+        }
+    }
+    Ok(res)
+}
+
+fn label(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Share several paths at once, one ticket each, and collect them in a bundle.
+#[tauri::command]
+pub async fn share_batch(
+    paths: Vec<PathBuf>,
+    individually: bool,
+    options: Option<ShareOptions>,
+    save_to: Option<PathBuf>,
+    limiter: State<'_, RateLimiter>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<BatchShare, UserError> {
+    limiter
+        .check("share_batch", &i18n)
+        .map_err(|msg| UserError::new(ErrorCode::RateLimited, msg, &i18n))?;
+    let paths = expand(paths, individually).map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    let opts = options.unwrap_or_default();
+    let mut tickets = Vec::with_capacity(paths.len());
+    for path in paths {
+        let label = label(&path);
+        let ticket = crate::share(&app, vec![path], opts.clone())
+            .await
+            .map_err(|e| UserError::from_anyhow(&e.context(label.clone()), &i18n))?;
+        tickets.push(BundleEntry {
+            label,
+            ticket: ticket.to_string(),
+        });
+    }
+    let bundle = Bundle::new(tickets);
+    let saved_to = match save_to {
+        Some(path) => Some(
+            bundle
+                .save(&path)
+                .map_err(|e| UserError::from_anyhow(&e, &i18n))?
+                .display()
+                .to_string(),
+        ),
+        None => None,
+    };
+    Ok(BatchShare {
+        summary: bundle.summary(),
+        bundle,
+        saved_to,
+    })
+}
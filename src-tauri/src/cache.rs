@@ -0,0 +1,254 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use iroh_bytes::{
+    protocol::RangeSpec,
+    provider::{send_blob, SentStatus},
+    store::{Map, MapEntry},
+    Hash,
+};
+use iroh_io::AsyncStreamWriter;
+use serde::Serialize;
+use tauri::State as TauriState;
+
+use crate::{auth::SessionToken, settings::SettingsStore};
+
+/// Memory used for cached blobs unless configured otherwise.
+pub const DEFAULT_BUDGET: u64 = 64 * 1024 * 1024;
+
+/// Largest blob that is cached, larger blobs are always read from the store.
+const MAX_ENTRY: u64 = 8 * 1024 * 1024;
+
+/// Size of the writes of cached data, so the bandwidth scheduler sees the
+/// same slices as for data read from the store.
+const WRITE_SIZE: usize = 16 * 1024;
+
+/// A blob and the requested ranges, the same request always encodes to the
+/// same bytes.
+type Key = (Hash, RangeSpec);
+
+/// How well the cache works, for the `chunk_cache_stats` command.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CacheReport {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: u64,
+    pub budget: u64,
+}
+
+#[derive(Debug)]
+struct Cached {
+    data: Bytes,
+    /// When the entry was last used, its key in [`Lru::order`].
+    used: u64,
+}
+
+#[derive(Debug)]
+struct Lru {
+    budget: u64,
+    bytes: u64,
+    tick: u64,
+    entries: HashMap<Key, Cached>,
+    order: BTreeMap<u64, Key>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Lru {
+    fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            bytes: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Bytes> {
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.tick += 1;
+        self.order.remove(&entry.used);
+        entry.used = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(entry.data.clone())
+    }
+
+    fn insert(&mut self, key: Key, data: Bytes) {
+        if data.len() as u64 > self.budget {
+            return;
+        }
+        self.tick += 1;
+        self.bytes += data.len() as u64;
+        self.order.insert(self.tick, key.clone());
+        let entry = Cached {
+            data,
+            used: self.tick,
+        };
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.order.remove(&old.used);
+            self.bytes -= old.data.len() as u64;
+        }
+        self.evict();
+    }
+
+    /// Drop the least recently used entries until the cache fits its budget.
+    fn evict(&mut self) {
+        while self.bytes > self.budget {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.data.len() as u64;
+            }
+        }
+    }
+}
+
+/// Recently sent blobs, bao encoded and verified, shared by all shares.
+///
+/// When many peers download the same share, e.g. a release, only the first
+/// download reads from disk. Blobs are content addressed, so shares of the
+/// same data share the cached entries.
+#[derive(Debug)]
+pub struct ChunkCache(Mutex<Lru>);
+
+impl Default for ChunkCache {
+    fn default() -> Self {
+        Self(Mutex::new(Lru::new(DEFAULT_BUDGET)))
+    }
+}
+
+impl ChunkCache {
+    /// Set the memory the cache may use, [`DEFAULT_BUDGET`] if unset and off if 0.
+    pub fn set_budget(&self, budget: Option<u64>) {
+        let mut lru = self.0.lock().unwrap();
+        lru.budget = budget.unwrap_or(DEFAULT_BUDGET);
+        lru.evict();
+    }
+
+    pub fn report(&self) -> CacheReport {
+        let lru = self.0.lock().unwrap();
+        CacheReport {
+            hits: lru.hits,
+            misses: lru.misses,
+            entries: lru.entries.len(),
+            bytes: lru.bytes,
+            budget: lru.budget,
+        }
+    }
+
+    /// Whether blobs of `size` bytes are worth caching.
+    fn caches(&self, size: u64) -> bool {
+        let budget = self.0.lock().unwrap().budget;
+        size <= MAX_ENTRY.min(budget / 8)
+    }
+
+    /// Send the requested ranges of blob `hash` from the cache, or from `db`
+    /// and then cache them. Returns the size of the blob.
+    ///
+    /// Only blobs in `db` are sent, whatever the cache holds.
+    pub async fn send_blob<D: Map, W: AsyncStreamWriter>(
+        &self,
+        db: &D,
+        hash: Hash,
+        ranges: &RangeSpec,
+        mut writer: W,
+    ) -> anyhow::Result<(SentStatus, u64)> {
+        let Some(entry) = db.get(&hash) else {
+            return Ok((SentStatus::NotFound, 0));
+        };
+        if !self.caches(entry.size()) {
+            let (status, size, _) = send_blob(db, hash, ranges, writer).await?;
+            return Ok((status, size));
+        }
+        let key = (hash, ranges.clone());
+        let cached = self.0.lock().unwrap().get(&key);
+        let data = match cached {
+            Some(data) => data,
+            None => {
+                let mut encoded = Vec::new();
+                let (status, _, _) = send_blob(db, hash, ranges, &mut encoded).await?;
+                if status == SentStatus::NotFound {
+                    return Ok((status, 0));
+                }
+                let data = Bytes::from(encoded);
+                self.0.lock().unwrap().insert(key, data.clone());
+                data
+            }
+        };
+        for start in (0..data.len()).step_by(WRITE_SIZE) {
+            let end = (start + WRITE_SIZE).min(data.len());
+            writer.write_bytes(data.slice(start..end)).await?;
+        }
+        Ok((SentStatus::Sent, entry.size()))
+    }
+}
+
+/// Set the memory used to cache sent blobs in bytes, the default if unset and
+/// off if 0.
+#[tauri::command]
+pub fn set_chunk_cache_size(
+    size: Option<u64>,
+    token: String,
+    session: TauriState<'_, SessionToken>,
+    settings: TauriState<'_, SettingsStore>,
+    cache: TauriState<'_, Arc<ChunkCache>>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| s.chunk_cache_size = size)
+        .map_err(|e| e.to_string())?;
+    cache.set_budget(size);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn chunk_cache_stats(cache: TauriState<'_, Arc<ChunkCache>>) -> CacheReport {
+    cache.report()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(i: u8) -> Key {
+        (Hash::new([i]), RangeSpec::all())
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut lru = Lru::new(30);
+        lru.insert(key(1), Bytes::from(vec![1; 10]));
+        lru.insert(key(2), Bytes::from(vec![2; 10]));
+        lru.insert(key(3), Bytes::from(vec![3; 10]));
+        // 1 is now more recent than 2
+        assert!(lru.get(&key(1)).is_some());
+        lru.insert(key(4), Bytes::from(vec![4; 10]));
+        assert!(lru.get(&key(2)).is_none());
+        assert!(lru.get(&key(1)).is_some());
+        assert!(lru.get(&key(3)).is_some());
+        assert_eq!(lru.bytes, 30);
+        // too large to be cached at all
+        lru.insert(key(5), Bytes::from(vec![5; 31]));
+        assert!(lru.get(&key(5)).is_none());
+        assert_eq!(lru.entries.len(), 3);
+        // replacing an entry does not count it twice
+        lru.insert(key(1), Bytes::from(vec![1; 5]));
+        assert_eq!(lru.bytes, 25);
+        lru.budget = 10;
+        lru.evict();
+        assert_eq!(lru.entries.len(), 1);
+        assert_eq!((lru.hits, lru.misses), (3, 2));
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Context;
+use tauri::{AppHandle, State};
+
+use crate::{
+    errors::{ErrorCode, UserError},
+    i18n::I18n,
+    ratelimit::RateLimiter,
+    upload::ShareOptions,
+};
+
+/// Longest recording that can be requested, in seconds.
+const MAX_DURATION: u64 = 120;
+
+/// File extension of the recordings.
+#[cfg(target_os = "macos")]
+const EXTENSION: &str = "mov";
+#[cfg(not(target_os = "macos"))]
+const EXTENSION: &str = "mp4";
+
+/// The command recording the screen for `duration` seconds to `out`.
+#[cfg(target_os = "macos")]
+fn recorder(out: &Path, duration: u64) -> Command {
+    let mut cmd = Command::new("screencapture");
+    cmd.arg("-x")
+        .arg("-v")
+        .arg("-V")
+        .arg(duration.to_string())
+        .arg(out);
+    cmd
+}
+
+#[cfg(target_os = "windows")]
+fn recorder(out: &Path, duration: u64) -> Command {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-y", "-f", "gdigrab", "-framerate", "30", "-t"])
+        .arg(duration.to_string())
+        .args(["-i", "desktop", "-pix_fmt", "yuv420p"])
+        .arg(out);
+    cmd
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn recorder(out: &Path, duration: u64) -> Command {
+    let display = std::env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string());
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-y", "-f", "x11grab", "-framerate", "30", "-t"])
+        .arg(duration.to_string())
+        .arg("-i")
+        .arg(display)
+        .args(["-pix_fmt", "yuv420p"])
+        .arg(out);
+    cmd
+}
+
+/// Record the screen into a new directory below `dir`, returning the file.
+fn record(dir: &Path, duration: u64) -> anyhow::Result<PathBuf> {
+    let name = chrono::Local::now().format("recording-%Y-%m-%d-%H%M%S");
+    // one directory per recording, sharing puts its data dir next to the file
+    let dir = dir.join(name.to_string());
+    std::fs::create_dir_all(&dir)?;
+    let out = dir.join(format!("{}.{}", name, EXTENSION));
+    let output = recorder(&out, duration)
+        .output()
+        .context("could not start the screen recorder")?;
+    anyhow::ensure!(
+        output.status.success() && out.exists(),
+        "screen recording failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(out)
+}
+
+/// Record the screen for `duration` seconds and share the clip.
+#[tauri::command]
+pub async fn share_screen_recording(
+    duration: u64,
+    limiter: State<'_, RateLimiter>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<String, UserError> {
+    limiter
+        .check("share_screen_recording", &i18n)
+        .map_err(|msg| UserError::new(ErrorCode::RateLimited, msg, &i18n))?;
+    let duration = duration.clamp(1, MAX_DURATION);
+    let res = async {
+        let dir = app
+            .path_resolver()
+            .app_cache_dir()
+            .context("no app cache dir")?
+            .join("recordings");
+        let path = tokio::task::spawn_blocking(move || record(&dir, duration)).await??;
+        crate::share(&app, vec![path], ShareOptions::default()).await
+    }
+    .await;
+    let ticket = res.map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    Ok(ticket.to_string())
+}
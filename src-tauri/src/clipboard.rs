@@ -0,0 +1,161 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use base64::Engine;
+use iroh_bytes::Hash;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, ClipboardManager, State};
+
+use crate::{
+    auth::SessionToken,
+    errors::{ErrorCode, UserError},
+    i18n::I18n,
+    message::format_size,
+    password,
+    ratelimit::RateLimiter,
+    settings::SettingsStore,
+    transfers::TransferManager,
+    upload::ShareOptions,
+};
+
+/// Sharing of clipboard items, off unless the user opts in.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardSettings {
+    pub enabled: bool,
+    /// Largest item that may be shared, in bytes.
+    pub max_size: u64,
+}
+
+impl Default for ClipboardSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// The content of the clipboard, as read by the frontend.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ClipboardItem {
+    Text {
+        text: String,
+    },
+    /// A png image, base64 encoded.
+    Image {
+        png: String,
+    },
+}
+
+impl ClipboardItem {
+    /// The file name and content the item is shared as.
+    fn into_file(self) -> anyhow::Result<(&'static str, Vec<u8>)> {
+        match self {
+            ClipboardItem::Text { text } => Ok(("clipboard.txt", text.into_bytes())),
+            ClipboardItem::Image { png } => {
+                let data = base64::engine::general_purpose::STANDARD
+                    .decode(png)
+                    .context("invalid image data")?;
+                Ok(("clipboard.png", data))
+            }
+        }
+    }
+}
+
+/// Write an item below `dir`, in a directory named by its hash, so the same
+/// content is only stored once.
+fn store_item(dir: &Path, name: &str, data: &[u8]) -> anyhow::Result<PathBuf> {
+    let item_dir = dir.join(Hash::new(data).to_hex());
+    std::fs::create_dir_all(&item_dir)?;
+    let path = item_dir.join(name);
+    if !path.exists() {
+        std::fs::write(&path, data)?;
+    }
+    Ok(path)
+}
+
+/// Share a clipboard item, returning its ticket.
+#[tauri::command]
+pub async fn share_clipboard(
+    item: ClipboardItem,
+    limiter: State<'_, RateLimiter>,
+    i18n: State<'_, I18n>,
+    settings: State<'_, SettingsStore>,
+    app: AppHandle,
+) -> Result<String, UserError> {
+    limiter
+        .check("share_clipboard", &i18n)
+        .map_err(|msg| UserError::new(ErrorCode::RateLimited, msg, &i18n))?;
+    let clipboard = settings.get().clipboard;
+    if !clipboard.enabled {
+        let msg = i18n.translate("clipboard.disabled", &[]);
+        return Err(UserError::new(ErrorCode::Unknown, msg, &i18n));
+    }
+    let (name, data) = item
+        .into_file()
+        .map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    if data.len() as u64 > clipboard.max_size {
+        let size = format_size(data.len() as u64);
+        let max = format_size(clipboard.max_size);
+        let msg = i18n.translate("clipboard.too_large", &[("size", &size), ("max", &max)]);
+        return Err(UserError::new(ErrorCode::Unknown, msg, &i18n));
+    }
+    let path = app
+        .path_resolver()
+        .app_cache_dir()
+        .context("no app cache dir")
+        .and_then(|dir| store_item(&dir.join("clipboard"), name, &data))
+        .map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    let ticket = crate::share(&app, vec![path], ShareOptions::default())
+        .await
+        .map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    Ok(ticket.to_string())
+}
+
+/// Put the ticket of share `id` on the clipboard.
+#[tauri::command]
+pub fn copy_ticket_to_clipboard(
+    id: u64,
+    transfers: State<'_, TransferManager>,
+    app: AppHandle,
+) -> Result<(), String> {
+    let transfer = transfers
+        .status(id)
+        .ok_or_else(|| format!("no transfer {}", id))?;
+    app.clipboard_manager()
+        .write_text(transfer.ticket)
+        .map_err(|e| e.to_string())
+}
+
+/// The ticket on the clipboard, if it holds one.
+#[tauri::command]
+pub fn read_ticket_from_clipboard(app: AppHandle) -> Result<Option<String>, String> {
+    let text = app
+        .clipboard_manager()
+        .read_text()
+        .map_err(|e| e.to_string())?
+        .unwrap_or_default();
+    let ticket = password::parse_ticket(&text).ok();
+    Ok(ticket.map(|(ticket, hint)| password::ticket_text(&ticket, hint.as_deref())))
+}
+
+#[tauri::command]
+pub fn get_clipboard_settings(settings: State<'_, SettingsStore>) -> ClipboardSettings {
+    settings.get().clipboard
+}
+
+#[tauri::command]
+pub fn set_clipboard_settings(
+    clipboard: ClipboardSettings,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| s.clipboard = clipboard)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tauri::{
+    api::http::{Body, Client, ClientBuilder, HttpRequestBuilder},
+    AppHandle, Manager, State,
+};
+use walkdir::WalkDir;
+
+use crate::{auth::SessionToken, errors::UserError, i18n::I18n, settings::SettingsStore};
+
+/// How often an upload is attempted before giving up.
+const ATTEMPTS: u32 = 3;
+
+/// A WebDAV folder, e.g. a Nextcloud folder at
+/// `https://cloud.example.com/remote.php/dav/files/<user>/SendMe`.
+/// The password is stored in plain text in the settings file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebDavSettings {
+    pub url: String,
+    pub username: String,
+    pub password: String,
+}
+
+impl WebDavSettings {
+    /// The url of `path`, relative to the configured folder.
+    fn url(&self, path: &Path) -> String {
+        let mut url = self.url.trim_end_matches('/').to_string();
+        for part in path.components() {
+            url.push('/');
+            url.push_str(&encode_segment(&part.as_os_str().to_string_lossy()));
+        }
+        url
+    }
+
+    fn authorization(&self) -> String {
+        let credentials = format!("{}:{}", self.username, self.password);
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        )
+    }
+}
+
+/// Percent-encode a single path segment of a url.
+pub fn encode_segment(segment: &str) -> String {
+    let mut res = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{:02X}", b));
+        }
+    }
+    res
+}
+
+/// Sent to the frontend as `cloud-progress` after every uploaded file.
+#[derive(Debug, Clone, Serialize)]
+struct CloudProgress {
+    file: String,
+    done: usize,
+    total: usize,
+}
+
+async fn request(
+    client: &Client,
+    dav: &WebDavSettings,
+    method: &str,
+    path: &Path,
+    body: Option<Body>,
+) -> anyhow::Result<u16> {
+    let mut request = HttpRequestBuilder::new(method, dav.url(path))?
+        .header("Authorization", dav.authorization())?
+        .timeout(Duration::from_secs(300));
+    if let Some(body) = body {
+        request = request.body(body);
+    }
+    let response = client.send(request).await?;
+    Ok(response.status().as_u16())
+}
+
+/// Create a collection, which may already exist.
+async fn mkcol(client: &Client, dav: &WebDavSettings, path: &Path) -> anyhow::Result<()> {
+    let status = request(client, dav, "MKCOL", path, None).await?;
+    // 405 means the collection exists already
+    anyhow::ensure!(
+        (200..300).contains(&status) || status == 405,
+        "creating {} failed with {}",
+        path.display(),
+        status
+    );
+    Ok(())
+}
+
+/// Upload a file, retrying with a growing delay.
+async fn put(
+    client: &Client,
+    dav: &WebDavSettings,
+    source: &Path,
+    target: &Path,
+) -> anyhow::Result<()> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let res = async {
+            let data = tokio::fs::read(source).await?;
+            let status = request(client, dav, "PUT", target, Some(Body::Bytes(data))).await?;
+            anyhow::ensure!(
+                (200..300).contains(&status),
+                "upload failed with {}",
+                status
+            );
+            Ok(())
+        }
+        .await;
+        match res {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt < ATTEMPTS => {
+                log!("uploading {} failed, retrying: {:#}", source.display(), err);
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+            }
+            Err(err) => return Err(err.context(format!("could not upload {}", source.display()))),
+        }
+    }
+}
+
+/// Upload files and directories to the configured WebDAV folder, keeping
+/// their names. Returns the number of uploaded files.
+pub async fn upload(app: &AppHandle, paths: &[PathBuf]) -> anyhow::Result<usize> {
+    let dav = app
+        .state::<SettingsStore>()
+        .get()
+        .cloud
+        .context("no cloud destination configured")?;
+    let client = ClientBuilder::new()
+        .connect_timeout(Duration::from_secs(10))
+        .build()?;
+    let mut files = Vec::new();
+    for path in paths {
+        let root = path.parent().context("no parent")?;
+        for entry in WalkDir::new(path) {
+            let entry = entry?;
+            let relative = entry.path().strip_prefix(root)?.to_path_buf();
+            files.push((entry.file_type().is_dir(), entry.into_path(), relative));
+        }
+    }
+    let total = files.iter().filter(|(dir, _, _)| !dir).count();
+    let mut done = 0;
+    // walkdir lists directories before their contents
+    for (dir, source, target) in files {
+        if dir {
+            mkcol(&client, &dav, &target).await?;
+            continue;
+        }
+        put(&client, &dav, &source, &target).await?;
+        done += 1;
+        let progress = CloudProgress {
+            file: target.display().to_string(),
+            done,
+            total,
+        };
+        app.emit_all("cloud-progress", progress).ok();
+    }
+    Ok(done)
+}
+
+/// Upload received files to the configured cloud destination.
+#[tauri::command]
+pub async fn save_to_cloud(
+    paths: Vec<PathBuf>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<usize, UserError> {
+    upload(&app, &paths)
+        .await
+        .map_err(|e| UserError::from_anyhow(&e, &i18n))
+}
+
+#[tauri::command]
+pub fn get_cloud_settings(settings: State<'_, SettingsStore>) -> Option<WebDavSettings> {
+    settings.get().cloud
+}
+
+#[tauri::command]
+pub fn set_cloud_settings(
+    cloud: Option<WebDavSettings>,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| s.cloud = cloud)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use iroh_net::ticket::BlobTicket;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::{
+    auth::SessionToken,
+    download::{default_download_dir, free_path, sanitize},
+    settings::{Settings, SettingsStore},
+};
+
+/// How downloads from a trusted contact are saved.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReceiveRule {
+    /// Where downloads are saved unless another folder is chosen.
+    pub folder: Option<PathBuf>,
+    /// New names for the top level files and folders, e.g.
+    /// `{date}-{sender}-{name}`. `{name}` is the original name without the
+    /// extension, files keep theirs.
+    pub name_template: Option<String>,
+}
+
+/// The receive rule for downloads of `ticket`, if it was made by a trusted
+/// contact with one. The ticket names the node the sender shares from, which
+/// is the one they download with, too.
+pub fn receive_rule(settings: &Settings, ticket: &BlobTicket) -> Option<ReceiveRule> {
+    let sender = ticket.node_addr().node_id.to_string();
+    if !settings.trusted_peers.contains(&sender) {
+        return None;
+    }
+    settings.receive_rules.get(&sender).cloned()
+}
+
+/// Where `ticket` is downloaded to unless another folder is chosen: the
+/// folder of its sender's receive rule, or the default download folder.
+pub fn download_dir(settings: &Settings, ticket: &BlobTicket) -> anyhow::Result<PathBuf> {
+    match receive_rule(settings, ticket).and_then(|rule| rule.folder) {
+        Some(folder) => Ok(folder),
+        None => default_download_dir(settings),
+    }
+}
+
+fn template_name(template: &str, name: &str, is_file: bool, sender: &str, date: &str) -> String {
+    let path = Path::new(name);
+    let (stem, extension) = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) if is_file => (
+            stem.to_string_lossy().into_owned(),
+            Some(extension.to_string_lossy()),
+        ),
+        _ => (name.to_string(), None),
+    };
+    let renamed = template
+        .replace("{date}", date)
+        .replace("{sender}", sender)
+        .replace("{name}", &stem);
+    let renamed = sanitize(&renamed);
+    match extension {
+        Some(extension) => format!("{}.{}", renamed, extension),
+        None => renamed,
+    }
+}
+
+/// Rename the top level files and folders `names` below `root` with the
+/// name template of a receive rule, returning their new names.
+pub fn rename(
+    template: &str,
+    root: &Path,
+    names: &[String],
+    sender: &str,
+) -> anyhow::Result<Vec<String>> {
+    let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let mut renamed = Vec::new();
+    for name in names {
+        let source = root.join(name);
+        let mut target = root.join(template_name(
+            template,
+            name,
+            source.is_file(),
+            sender,
+            &date,
+        ));
+        if target != source && target.exists() {
+            target = free_path(&target);
+        }
+        std::fs::rename(&source, &target)?;
+        let file_name = target.file_name().unwrap_or_default();
+        renamed.push(file_name.to_string_lossy().into_owned());
+    }
+    Ok(renamed)
+}
+
+#[tauri::command]
+pub fn get_receive_rules(settings: State<'_, SettingsStore>) -> BTreeMap<String, ReceiveRule> {
+    settings.get().receive_rules
+}
+
+/// Set how downloads from the trusted contact `peer` are saved, or remove
+/// the rule with `None`.
+#[tauri::command]
+pub fn set_receive_rule(
+    peer: String,
+    rule: Option<ReceiveRule>,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    if !settings.get().trusted_peers.contains(&peer) {
+        return Err(format!("{} is not a trusted contact", peer));
+    }
+    let rule = rule.map(|rule| ReceiveRule {
+        name_template: rule.name_template.filter(|t| !t.trim().is_empty()),
+        ..rule
+    });
+    settings
+        .update(|s| match rule {
+            Some(rule) => {
+                s.receive_rules.insert(peer, rule);
+            }
+            None => {
+                s.receive_rules.remove(&peer);
+            }
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_with_templates() {
+        let template = "{date}-{sender}-{name}";
+        let name = |name, is_file| template_name(template, name, is_file, "ab12", "2024-05-01");
+        assert_eq!(name("photo.jpg", true), "2024-05-01-ab12-photo.jpg");
+        assert_eq!(name("photos.2023", false), "2024-05-01-ab12-photos.2023");
+        assert_eq!(name("notes", true), "2024-05-01-ab12-notes");
+        // a template can not move files elsewhere
+        let escaped = template_name("../{name}", "a.txt", true, "ab12", "2024-05-01");
+        assert_eq!(escaped, ".._a.txt");
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), b"a").unwrap();
+        std::fs::write(tmp.path().join("x-a.txt"), b"taken").unwrap();
+        std::fs::create_dir(tmp.path().join("b")).unwrap();
+        let names = ["a.txt".to_string(), "b".to_string()];
+        let renamed = rename("x-{name}", tmp.path(), &names, "ab12").unwrap();
+        assert_eq!(renamed, ["x-a (1).txt", "x-b"]);
+        assert_eq!(std::fs::read(tmp.path().join("x-a (1).txt")).unwrap(), b"a");
+        assert!(tmp.path().join("x-b").is_dir());
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{
+    api::http::{Body, ClientBuilder, HttpRequestBuilder},
+    AppHandle, Manager, State, Window,
+};
+
+use crate::{auth::SessionToken, i18n::I18n, settings::SettingsStore};
+
+/// Number of log lines kept for crash reports.
+const LOG_TAIL_LINES: usize = 200;
+
+static LOG_TAIL: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Print a line and keep it in the log tail attached to crash reports.
+macro_rules! log {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        println!("{}", line);
+        $crate::crash::record_log(line);
+    }};
+}
+
+pub fn record_log(line: String) {
+    let Ok(mut tail) = LOG_TAIL.lock() else {
+        return;
+    };
+    if tail.len() == LOG_TAIL_LINES {
+        tail.pop_front();
+    }
+    tail.push_back(line);
+}
+
+/// A crash report, written locally and only sent if the user agrees.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashReport {
+    pub id: String,
+    pub app_version: String,
+    pub os: String,
+    pub message: String,
+    pub location: Option<String>,
+    pub backtrace: String,
+    pub log_tail: Vec<String>,
+}
+
+/// Strip anything identifying the user, i.e. their home directory.
+fn redact(text: &str) -> String {
+    match std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")) {
+        Ok(home) if !home.is_empty() => text.replace(&home, "~"),
+        _ => text.to_string(),
+    }
+}
+
+fn report_path(dir: &Path, id: &str) -> anyhow::Result<PathBuf> {
+    anyhow::ensure!(
+        !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()),
+        "invalid crash report id"
+    );
+    Ok(dir.join(format!("{}.json", id)))
+}
+
+/// Install a panic hook that writes a redacted report to `dir`.
+///
+/// The previous hook still runs, so panics are printed as usual. Installed
+/// first thing in `main`, so panics while the app starts are caught, too.
+pub fn install(dir: PathBuf) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(s) => s.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(s) => s.clone(),
+                None => "unknown panic".to_string(),
+            },
+        };
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+            .to_string();
+        let log_tail = match LOG_TAIL.try_lock() {
+            Ok(tail) => tail.iter().map(|line| redact(line)).collect(),
+            Err(_) => Vec::new(),
+        };
+        let report = CrashReport {
+            id: id.clone(),
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            os: std::env::consts::OS.to_string(),
+            message: redact(&message),
+            location: info.location().map(|l| redact(&l.to_string())),
+            backtrace: redact(&Backtrace::force_capture().to_string()),
+            log_tail,
+        };
+        if let Ok(data) = serde_json::to_vec_pretty(&report) {
+            std::fs::create_dir_all(&dir).ok();
+            std::fs::write(dir.join(format!("{}.json", id)), data).ok();
+        }
+        previous(info);
+    }));
+}
+
+/// Directory the crash reports are written to, kept in the tauri state.
+#[derive(Debug)]
+pub struct CrashDir(pub PathBuf);
+
+fn pending(dir: &Path) -> Vec<CrashReport> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let data = std::fs::read(entry.ok()?.path()).ok()?;
+            serde_json::from_slice(&data).ok()
+        })
+        .collect()
+}
+
+/// Reports from previous runs that the user has not reviewed yet.
+#[tauri::command]
+pub fn pending_crash_reports(dir: State<'_, CrashDir>) -> Vec<CrashReport> {
+    pending(&dir.0)
+}
+
+async fn submit(dir: &Path, id: &str, endpoint: String) -> Result<(), String> {
+    let path = report_path(dir, id).map_err(|e| e.to_string())?;
+    let data = std::fs::read(&path).map_err(|e| e.to_string())?;
+    let report: serde_json::Value = serde_json::from_slice(&data).map_err(|e| e.to_string())?;
+    let client = ClientBuilder::new()
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let request = HttpRequestBuilder::new("POST", endpoint)
+        .map_err(|e| e.to_string())?
+        .body(Body::Json(report));
+    let response = client.send(request).await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "crash report endpoint returned {}",
+            response.status()
+        ));
+    }
+    std::fs::remove_file(path).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Ask on start whether to send the reports previous runs left behind, if
+/// there is an endpoint to send them to. Declined reports are deleted.
+pub fn prompt_pending(app: &AppHandle) {
+    let dir = app.state::<CrashDir>().0.clone();
+    let reports = pending(&dir);
+    let endpoint = app.state::<SettingsStore>().get().crash_report_endpoint;
+    let Some(endpoint) = endpoint.filter(|_| !reports.is_empty()) else {
+        return;
+    };
+    let count = reports.len().to_string();
+    let message = app.state::<I18n>().translate(
+        "crash.prompt",
+        &[("count", count.as_str()), ("endpoint", endpoint.as_str())],
+    );
+    tauri::api::dialog::ask(None::<&Window>, "SendMe", message, move |send| {
+        for report in reports {
+            if !send {
+                if let Ok(path) = report_path(&dir, &report.id) {
+                    std::fs::remove_file(path).ok();
+                }
+                continue;
+            }
+            let (dir, endpoint) = (dir.clone(), endpoint.clone());
+            tauri::async_runtime::spawn(async move {
+                if let Err(err) = submit(&dir, &report.id, endpoint).await {
+                    log!("failed to send crash report {}: {}", report.id, err);
+                }
+            });
+        }
+    });
+}
+
+/// Send a report to the configured endpoint and remove it locally.
+#[tauri::command]
+pub async fn submit_crash_report(
+    id: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    dir: State<'_, CrashDir>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let endpoint = settings
+        .get()
+        .crash_report_endpoint
+        .ok_or_else(|| "no crash report endpoint configured".to_string())?;
+    submit(&dir.0, &id, endpoint).await
+}
+
+/// Delete a report without sending it.
+#[tauri::command]
+pub fn dismiss_crash_report(
+    id: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    dir: State<'_, CrashDir>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let path = report_path(&dir.0, &id).map_err(|e| e.to_string())?;
+    std::fs::remove_file(path).map_err(|e| e.to_string())
+}
@@ -0,0 +1,164 @@
+use std::{path::Path, sync::Mutex};
+
+use anyhow::Context;
+use iroh_net::ticket::BlobTicket;
+use serde::Serialize;
+use tauri::State;
+
+use crate::password;
+
+/// Links like `sendme://<ticket>` open the app.
+pub const SCHEME: &str = "sendme";
+
+/// Name of the desktop entry handling the scheme on Linux.
+#[cfg(target_os = "linux")]
+const DESKTOP_FILE: &str = "sendme-url-handler.desktop";
+
+/// What the download confirmation view needs to know about a link.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeepLink {
+    pub ticket: String,
+    pub hash: String,
+    /// Short node id of the sharing peer.
+    pub sender: String,
+    /// Whether the share needs a password.
+    pub protected: bool,
+}
+
+/// The ticket of a `sendme://` link and its password hint, see
+/// [`password::parse_ticket`].
+pub fn parse(url: &str) -> anyhow::Result<(BlobTicket, Option<String>)> {
+    let rest = url
+        .trim()
+        .strip_prefix(SCHEME)
+        .and_then(|rest| rest.strip_prefix(':'))
+        .context("not a sendme link")?;
+    let ticket = rest.trim_start_matches('/').trim_end_matches('/');
+    password::parse_ticket(ticket).context("the link does not contain a valid ticket")
+}
+
+/// What the download confirmation view shows for `ticket`.
+pub fn describe(ticket: &BlobTicket, hint: Option<&str>) -> DeepLink {
+    DeepLink {
+        hash: ticket.hash().to_hex().to_string(),
+        sender: ticket.node_addr().node_id.fmt_short(),
+        ticket: password::ticket_text(ticket, hint),
+        protected: hint.is_some(),
+    }
+}
+
+fn deep_link(url: &str) -> anyhow::Result<DeepLink> {
+    let (ticket, hint) = parse(url)?;
+    Ok(describe(&ticket, hint.as_deref()))
+}
+
+/// The link the app was started with, until the frontend takes it.
+#[derive(Debug, Default)]
+pub struct PendingDeepLink(Mutex<Option<String>>);
+
+impl PendingDeepLink {
+    /// Linux and Windows start the handler with the link as an argument.
+    pub fn from_args() -> Self {
+        let prefix = format!("{}:", SCHEME);
+        let link = std::env::args()
+            .skip(1)
+            .find(|arg| arg.starts_with(&prefix));
+        Self(Mutex::new(link))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn register_scheme(exe: &Path) -> anyhow::Result<()> {
+    let dir = tauri::api::path::data_dir()
+        .context("no data dir")?
+        .join("applications");
+    std::fs::create_dir_all(&dir)?;
+    let entry = format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=sendme\n\
+         Exec=\"{}\" %u\n\
+         NoDisplay=true\n\
+         MimeType=x-scheme-handler/{};\n",
+        exe.display(),
+        SCHEME
+    );
+    std::fs::write(dir.join(DESKTOP_FILE), entry)?;
+    let mime = format!("x-scheme-handler/{}", SCHEME);
+    let status = std::process::Command::new("xdg-mime")
+        .args(["default", DESKTOP_FILE, &mime])
+        .status()?;
+    anyhow::ensure!(status.success(), "xdg-mime failed with {}", status);
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn register_scheme(exe: &Path) -> anyhow::Result<()> {
+    let key = format!(r"HKCU\Software\Classes\{}", SCHEME);
+    let command_key = format!(r"{}\shell\open\command", key);
+    let command = format!("\"{}\" \"%1\"", exe.display());
+    let description = format!("URL:{}", SCHEME);
+    let commands: [&[&str]; 3] = [
+        &["add", &key, "/ve", "/d", &description, "/f"],
+        &["add", &key, "/v", "URL Protocol", "/d", "", "/f"],
+        &["add", &command_key, "/ve", "/d", &command, "/f"],
+    ];
+    for args in commands {
+        let status = std::process::Command::new("reg").args(args).status()?;
+        anyhow::ensure!(status.success(), "reg failed with {}", status);
+    }
+    Ok(())
+}
+
+/// macOS hands links to apps only through Apple events, which tauri 1 does not
+/// expose, so the scheme is not registered there.
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn register_scheme(_exe: &Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Make this executable the handler of `sendme://` links for the user.
+pub fn register() -> anyhow::Result<()> {
+    let exe = std::env::current_exe()?;
+    register_scheme(&exe)
+}
+
+/// Parse a `sendme://` link, for the download confirmation view.
+#[tauri::command]
+pub fn handle_deep_link(url: String) -> Result<DeepLink, String> {
+    deep_link(&url).map_err(|e| format!("{:#}", e))
+}
+
+/// The link the app was started with, if any. Only returned once.
+#[tauri::command]
+pub fn take_deep_link(pending: State<'_, PendingDeepLink>) -> Result<Option<DeepLink>, String> {
+    let link = pending.0.lock().unwrap().take();
+    link.map(handle_deep_link).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::{BlobFormat, Hash};
+    use iroh_net::{key::SecretKey, NodeAddr};
+
+    use super::*;
+
+    #[test]
+    fn parses_links() {
+        let addr = NodeAddr::new(SecretKey::generate().public())
+            .with_derp_url("https://derp.example.com".parse().unwrap());
+        let ticket = BlobTicket::new(addr, Hash::new(b"hello"), BlobFormat::HashSeq).unwrap();
+        for url in [
+            format!("sendme://{}", ticket),
+            format!("sendme:{}/", ticket),
+            format!(" sendme://{}\n", ticket),
+        ] {
+            assert_eq!(parse(&url).unwrap().0.hash(), ticket.hash());
+        }
+        let (_, hint) = parse(&format!("sendme://{}?pw=abcd", ticket)).unwrap();
+        assert_eq!(hint.as_deref(), Some("abcd"));
+        assert!(parse(&ticket.to_string()).is_err());
+        assert!(parse("sendme://nonsense").is_err());
+        assert!(parse("https://example.com").is_err());
+    }
+}
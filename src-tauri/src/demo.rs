@@ -0,0 +1,121 @@
+use std::{
+    path::PathBuf,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use iroh_bytes::{BlobFormat, Hash};
+use iroh_net::{key::SecretKey, ticket::BlobTicket, NodeAddr};
+
+use crate::{
+    download::DownloadStats,
+    progress::{Progress, TransferProgress},
+    upload::{display_name, ShareHandle},
+};
+
+/// Node id of the peer that downloads demo shares.
+const DEMO_PEER: &str = "demo-peer";
+
+/// Time between two fake progress events.
+const STEP: Duration = Duration::from_millis(200);
+
+/// Progress events per imported file.
+const IMPORT_STEPS: u64 = 10;
+
+/// Whether the app was started with `--demo`.
+///
+/// In demo mode nothing is shared or downloaded for real: no endpoint is
+/// bound, settings, history and the identity live in a temporary directory
+/// that is emptied on every start, and progress is made up, the same on
+/// every run.
+pub fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| std::env::args().any(|arg| arg == "--demo"))
+}
+
+/// Where settings and history are kept in demo mode, emptied on start.
+///
+/// The temp dir can be shared by all users, so each gets their own.
+pub fn dir() -> PathBuf {
+    let name = format!("sendme-demo-{}", crate::users::current_user());
+    let dir = std::env::temp_dir().join(name);
+    std::fs::remove_dir_all(&dir).ok();
+    dir
+}
+
+/// Size of the `i`th file of a demo share.
+fn fake_size(i: usize) -> u64 {
+    (i as u64 + 1) * 3 * 1024 * 1024
+}
+
+/// A ticket that looks real, derived from the share's name only. Tickets
+/// need an address, this one is from the documentation range.
+fn ticket(name: &str) -> anyhow::Result<BlobTicket> {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let addr = NodeAddr::new(node_id).with_direct_addresses([([192, 0, 2, 7], 11204).into()]);
+    BlobTicket::new(addr, Hash::new(name), BlobFormat::HashSeq)
+}
+
+/// Pretend to share `paths`, playing back an import and a download by
+/// [`DEMO_PEER`].
+pub fn share(paths: &[PathBuf], progress: Progress) -> anyhow::Result<(BlobTicket, ShareHandle)> {
+    let name = display_name(paths);
+    let files = paths
+        .iter()
+        .map(|path| display_name(std::slice::from_ref(path)))
+        .collect();
+    let ticket = ticket(&name)?;
+    tokio::spawn(play(progress, name, files));
+    Ok((ticket, ShareHandle::idle()))
+}
+
+async fn play(progress: Progress, share: String, files: Vec<String>) {
+    for (i, file) in files.iter().enumerate() {
+        for step in 1..=IMPORT_STEPS {
+            tokio::time::sleep(STEP).await;
+            progress.emit(TransferProgress::Import {
+                share: share.clone(),
+                file: file.clone(),
+                bytes: fake_size(i) * step / IMPORT_STEPS,
+            });
+        }
+    }
+    let total = (0..files.len()).map(fake_size).sum();
+    let mut sent = 0;
+    for (i, file) in files.iter().enumerate() {
+        tokio::time::sleep(STEP * 5).await;
+        sent += fake_size(i);
+        progress.emit(TransferProgress::Send {
+            share: share.clone(),
+            peer: Some(DEMO_PEER.to_string()),
+            file: Some(file.clone()),
+            bytes: sent,
+            total,
+        });
+    }
+    progress.emit(TransferProgress::Finished {
+        share,
+        peer: Some(DEMO_PEER.to_string()),
+        bytes: sent,
+        ok: true,
+    });
+}
+
+/// Pretend to download `ticket`, without writing anything.
+pub async fn download(ticket: &BlobTicket) -> anyhow::Result<DownloadStats> {
+    let start = Instant::now();
+    tokio::time::sleep(STEP * 10).await;
+    let files = 3;
+    let size = (0..files).map(fake_size).sum();
+    Ok(DownloadStats {
+        hash: ticket.hash().to_hex().to_string(),
+        files,
+        size,
+        bytes_read: size,
+        resumed: 0,
+        elapsed_ms: start.elapsed().as_millis() as u64,
+        saved: Vec::new(),
+        organized: Vec::new(),
+        trace: 0,
+    })
+}
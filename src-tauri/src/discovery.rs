@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use futures::{future::BoxFuture, FutureExt};
+use iroh_net::{key::PublicKey, magicsock::Discovery, AddrInfo};
+use serde::Serialize;
+use tauri::State;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::settings::SettingsStore;
+
+/// The current addresses of the active shares, to be published in DNS.
+#[derive(Debug, Default)]
+pub struct DnsRecords(Mutex<HashMap<PublicKey, AddrInfo>>);
+
+impl DnsRecords {
+    pub fn remove(&self, node_id: &PublicKey) {
+        self.0.lock().unwrap().remove(node_id);
+    }
+}
+
+/// A TXT record to publish, so receivers can find a share by node id.
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsRecord {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+fn record_name(node_id: &PublicKey, origin: &str) -> String {
+    format!("_sendme.{}.{}", node_id, origin)
+}
+
+fn txt_values(info: &AddrInfo) -> Vec<String> {
+    let mut values = Vec::new();
+    if let Some(url) = &info.derp_url {
+        values.push(format!("derp={}", url));
+    }
+    values.extend(
+        info.direct_addresses
+            .iter()
+            .map(|addr| format!("addr={}", addr)),
+    );
+    values
+}
+
+fn parse_txt(values: impl Iterator<Item = String>) -> anyhow::Result<AddrInfo> {
+    let mut info = AddrInfo::default();
+    for value in values {
+        match value.split_once('=') {
+            Some(("derp", url)) => info.derp_url = Some(url.parse().context("invalid derp url")?),
+            Some(("addr", addr)) => {
+                info.direct_addresses
+                    .insert(addr.parse().context("invalid address")?);
+            }
+            // ignore unknown keys, so newer versions can add more
+            _ => {}
+        }
+    }
+    anyhow::ensure!(!info.is_empty(), "no addresses found");
+    Ok(info)
+}
+
+/// Node discovery through DNS TXT records below a domain the user controls.
+///
+/// Every share records its current addresses, the user (or a script polling
+/// [`dns_records`]) publishes them as `_sendme.<node id>.<origin>`. Receivers
+/// look up that name to find a provider whose addresses changed since the
+/// ticket was made.
+#[derive(Debug)]
+pub struct DnsDiscovery {
+    origin: String,
+    node_id: PublicKey,
+    records: Arc<DnsRecords>,
+}
+
+impl DnsDiscovery {
+    pub fn new(origin: String, node_id: PublicKey, records: Arc<DnsRecords>) -> Self {
+        Self {
+            origin,
+            node_id,
+            records,
+        }
+    }
+}
+
+impl Discovery for DnsDiscovery {
+    fn publish(&self, info: &AddrInfo) {
+        self.records
+            .0
+            .lock()
+            .unwrap()
+            .insert(self.node_id, info.clone());
+    }
+
+    fn resolve<'a>(&'a self, node_id: &'a PublicKey) -> BoxFuture<'a, anyhow::Result<AddrInfo>> {
+        async move {
+            let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+            let lookup = resolver
+                .txt_lookup(record_name(node_id, &self.origin))
+                .await?;
+            parse_txt(lookup.iter().map(|txt| txt.to_string()))
+        }
+        .boxed()
+    }
+}
+
+/// The records to publish for the active shares, empty if DNS discovery is off.
+#[tauri::command]
+pub fn dns_records(
+    settings: State<'_, SettingsStore>,
+    records: State<'_, Arc<DnsRecords>>,
+) -> Vec<DnsRecord> {
+    let Some(origin) = settings.get().dns_discovery else {
+        return Vec::new();
+    };
+    records
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(node_id, info)| DnsRecord {
+            name: record_name(node_id, &origin),
+            values: txt_values(info),
+        })
+        .collect()
+}
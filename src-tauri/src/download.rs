@@ -0,0 +1,1321 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::OsStr,
+    future::Future,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use iroh_bytes::{
+    format::collection::Collection,
+    get::{
+        db::{get_to_db, DownloadProgress},
+        fsm,
+        request::get_hash_seq_and_sizes,
+        Stats,
+    },
+    hashseq::HashSeq,
+    protocol::{GetRequest, RangeSpec, RangeSpecSeq, ALPN},
+    store::{flat, ExportMode, ImportMode, Map, MapEntry, PartialMap, PossiblyPartialEntry, Store},
+    util::{
+        progress::{IdGenerator, IgnoreProgressSender, ProgressSender},
+        total_bytes,
+    },
+    BlobFormat, Hash, HashAndFormat, TempTag,
+};
+use iroh_io::AsyncSliceReader;
+use iroh_net::{key::SecretKey, ticket::BlobTicket, MagicEndpoint, NodeId};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tokio::{sync::watch, task::AbortHandle};
+use tokio_util::task::LocalPoolHandle;
+use tracing::Instrument;
+
+use crate::{
+    activity::ActivityLog,
+    bandwidth::{Bucket, DownloadCap, Throttle},
+    contacts::{receive_rule, rename},
+    errors::ErrorCode,
+    identity::Identity,
+    organize::{organize, Placement},
+    pack, password,
+    pause::{PauseState, Paused},
+    settings::{Settings, SettingsStore},
+    upload::entry_path,
+};
+
+/// How long the connection of a preview is kept open for the download.
+const WARM_FOR: Duration = Duration::from_secs(30);
+
+/// Largest hash seq accepted from a provider, in bytes.
+pub const MAX_HASH_SEQ_SIZE: u64 = 1024 * 1024 * 32;
+
+/// Size of the chunks blobs are read in when verifying them.
+const VERIFY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Per download options chosen by the user.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct DownloadOptions {
+    /// Keep downloading during quiet hours.
+    pub urgent: bool,
+    /// Upload the files to the configured cloud folder once they are saved.
+    pub save_to_cloud: bool,
+    /// Copy the files of an earlier download of the same collection instead of
+    /// fetching them again, if they are still there.
+    pub reuse_previous: bool,
+    /// What happens to files that exist in the destination already.
+    pub conflicts: Conflicts,
+    /// Maximum download rate in bytes per second, instead of the app wide cap.
+    pub download_limit: Option<u64>,
+    /// Password of a protected share.
+    pub password: Option<String>,
+    /// Files and folders to take unchanged files from instead of fetching
+    /// them again, e.g. an earlier version of the same folder.
+    pub seed_from: Vec<PathBuf>,
+    /// More folders the files are saved to, e.g. an external drive, copied
+    /// from the local store after fetching them once.
+    pub mirrors: Vec<PathBuf>,
+    /// Only fetch the files into the app's store, to save them later with
+    /// [`export_from_store`] or share them again.
+    pub store_only: bool,
+}
+
+/// The directory in the app data dir that downloads with
+/// [`DownloadOptions::store_only`] are kept in, kept in the tauri state.
+#[derive(Debug)]
+pub struct ReceivedDir(pub PathBuf);
+
+impl ReceivedDir {
+    pub fn store(&self, hash: &Hash) -> PathBuf {
+        self.0.join(hash.to_hex().as_str())
+    }
+}
+
+/// What to do with a received file whose path exists already.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    /// Keep the existing file and drop the received one.
+    Skip,
+    Overwrite,
+    /// Save the received file next to it, as `name (1).ext`.
+    #[default]
+    Rename,
+}
+
+/// How conflicts with existing files are resolved, chosen by the user after
+/// [`download_conflicts`] listed them.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Conflicts {
+    /// For files without a choice of their own.
+    pub default: Resolution,
+    /// By the name of the file in the collection, e.g. `dir/a.txt`.
+    pub files: HashMap<String, Resolution>,
+}
+
+impl Conflicts {
+    fn resolution(&self, name: &str) -> Resolution {
+        self.files.get(name).copied().unwrap_or(self.default)
+    }
+}
+
+/// A received file that would replace an existing one.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    /// Name of the file in the collection.
+    pub name: String,
+    /// The existing file.
+    pub path: String,
+    /// Size of the existing file.
+    pub size: u64,
+}
+
+/// An earlier download of a collection whose files are still where they were
+/// saved.
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviousDownload {
+    /// Unix time of the download.
+    pub time: u64,
+    pub saved: Vec<String>,
+}
+
+/// What a finished download reports to the frontend.
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadStats {
+    pub hash: String,
+    pub files: usize,
+    /// Size of all files.
+    pub size: u64,
+    /// Bytes received over the network, less than `size` when resuming.
+    pub bytes_read: u64,
+    /// Bytes that were already there from an earlier, interrupted attempt.
+    pub resumed: u64,
+    pub elapsed_ms: u64,
+    /// Paths of the top level files and directories that were written.
+    pub saved: Vec<String>,
+    /// Where the top level files were sorted into, empty if organizing is off.
+    pub organized: Vec<Placement>,
+    /// Id of the download's spans, see [`crate::trace::trace_transfer`].
+    pub trace: u64,
+}
+
+/// The file name of the entry name component `component`, checked to be a
+/// plain name.
+fn validate_path_component(component: &str) -> anyhow::Result<Cow<'_, OsStr>> {
+    anyhow::ensure!(
+        !component.contains('/'),
+        "path components must not contain the only correct path separator, /"
+    );
+    anyhow::ensure!(
+        !component.is_empty() && component != "." && component != "..",
+        "invalid path component {:?}",
+        component
+    );
+    let name = entry_path(component);
+    // e.g. `\` and drive prefixes on windows, or encoded separators
+    let mut components = Path::new(&*name).components();
+    anyhow::ensure!(
+        matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(c)), None) if c == &*name
+        ),
+        "path component {:?} is not a plain name on this platform",
+        component
+    );
+    Ok(name)
+}
+
+pub fn get_export_path(root: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    let parts = name.split('/');
+    let mut path = root.to_path_buf();
+    for part in parts {
+        path.push(validate_path_component(part)?);
+    }
+    Ok(path)
+}
+
+/// `path` with ` (n)` appended to the file stem, for the lowest `n` that is
+/// not taken.
+pub fn free_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path.extension().map(|e| e.to_string_lossy().into_owned());
+    (1..)
+        .map(|n| {
+            let name = match &extension {
+                Some(extension) => format!("{} ({}).{}", stem, n, extension),
+                None => format!("{} ({})", stem, n),
+            };
+            path.with_file_name(name)
+        })
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// Where the file `name` is written to below `root`, `None` to skip it.
+///
+/// Renamed files are added to `renamed`.
+fn export_target(
+    root: &Path,
+    name: &str,
+    conflicts: &Conflicts,
+    renamed: &mut HashMap<String, PathBuf>,
+) -> anyhow::Result<Option<PathBuf>> {
+    let target = get_export_path(root, name)?;
+    if !target.exists() {
+        return Ok(Some(target));
+    }
+    match conflicts.resolution(name) {
+        Resolution::Skip => {
+            log!("skipping {}, it exists already", name);
+            Ok(None)
+        }
+        Resolution::Overwrite => {
+            anyhow::ensure!(
+                !target.is_dir(),
+                "{} can not be overwritten, it is a folder",
+                target.display()
+            );
+            std::fs::remove_file(&target)?;
+            Ok(Some(target))
+        }
+        Resolution::Rename => {
+            let free = free_path(&target);
+            renamed.insert(name.to_string(), free.clone());
+            Ok(Some(free))
+        }
+    }
+}
+
+/// Whether the data of blob `hash` in `db` still matches its hash.
+///
+/// The data is verified as it arrives, this catches what was corrupted on
+/// disk since, e.g. in a store kept from an earlier attempt.
+async fn verify_blob<D: Map>(db: &D, hash: &Hash) -> anyhow::Result<bool> {
+    let entry = db.get(hash).context("blob missing from the store")?;
+    let mut reader = entry.data_reader().await?;
+    let mut hasher = blake3::Hasher::new();
+    let mut offset = 0;
+    while offset < entry.size() {
+        let chunk = reader.read_at(offset, VERIFY_CHUNK_SIZE).await?;
+        if chunk.is_empty() {
+            // truncated
+            return Ok(false);
+        }
+        hasher.update(&chunk);
+        offset += chunk.len() as u64;
+    }
+    Ok(hasher.finalize() == entry.hash())
+}
+
+/// Sent to the frontend as `verification-failed` for each file whose data
+/// does not match its hash.
+#[derive(Debug, Clone, Serialize)]
+struct VerificationFailed {
+    /// The collection the file belongs to.
+    hash: String,
+    name: String,
+}
+
+/// Verify the blobs of `collection` before anything is exported, so corrupt
+/// data is never written to the destination.
+///
+/// Corrupt blobs are removed from `db`, so trying again fetches them again.
+async fn verify(
+    app: &AppHandle,
+    db: &impl Store,
+    collection: &Collection,
+    hash: Hash,
+) -> anyhow::Result<()> {
+    let mut corrupt = Vec::new();
+    for (name, blob) in collection.iter() {
+        if verify_blob(db, blob).await? {
+            continue;
+        }
+        log!("{} does not match its hash {}", name, blob.to_hex());
+        db.delete(blob).await.ok();
+        let event = VerificationFailed {
+            hash: hash.to_hex().to_string(),
+            name: name.clone(),
+        };
+        app.emit_all("verification-failed", event).ok();
+        corrupt.push(name.as_str());
+    }
+    if !corrupt.is_empty() {
+        return Err(anyhow::Error::from(ErrorCode::HashMismatch)
+            .context(format!("corrupt data for {}", corrupt.join(", "))));
+    }
+    Ok(())
+}
+
+/// Whether the file `name` is one of `entries` or in one of the folders
+/// among them. All files are if `entries` is empty.
+pub fn selected(entries: &[String], name: &str) -> bool {
+    entries.is_empty()
+        || entries.iter().any(|entry| {
+            name.strip_prefix(entry.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+}
+
+/// Export the files of `collection` in `entries` below `root`, unpacking
+/// packed files.
+///
+/// Returns where files were saved under another name because of conflicts.
+pub async fn export(
+    db: impl Store,
+    collection: &Collection,
+    root: &Path,
+    entries: &[String],
+    conflicts: &Conflicts,
+) -> anyhow::Result<HashMap<String, PathBuf>> {
+    let mut renamed = HashMap::new();
+    for (name, hash) in collection.iter() {
+        if pack::is_internal(name) || !selected(entries, name) {
+            continue;
+        }
+        let Some(target) = export_target(root, name, conflicts, &mut renamed)? else {
+            continue;
+        };
+        db.export(*hash, target, ExportMode::TryReference, |_position| Ok(()))
+            .await?;
+    }
+    pack::unpack(&db, collection, |name, data| {
+        if !selected(entries, name) {
+            return Ok(());
+        }
+        let Some(target) = export_target(root, name, conflicts, &mut renamed)? else {
+            return Ok(());
+        };
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(target, data)?;
+        Ok(())
+    })
+    .await?;
+    Ok(renamed)
+}
+
+/// Sent to the frontend as `export-finished` once the files of a download
+/// are saved to its destination, and once for each of its mirrors.
+#[derive(Debug, Clone, Serialize)]
+struct ExportFinished {
+    hash: String,
+    /// The destination or mirror as requested.
+    dest: String,
+    /// Where the files were saved.
+    root: Option<String>,
+    error: Option<String>,
+}
+
+impl ExportFinished {
+    fn new(hash: &Hash, dest: &Path, res: &anyhow::Result<PathBuf>) -> Self {
+        Self {
+            hash: hash.to_hex().to_string(),
+            dest: dest.display().to_string(),
+            root: res.as_ref().ok().map(|root| root.display().to_string()),
+            error: res.as_ref().err().map(|err| format!("{:#}", err)),
+        }
+    }
+}
+
+/// Replace characters that would turn a template value into several path
+/// components.
+pub fn sanitize(value: &str) -> String {
+    let value = value.replace(['/', '\\', ':'], "_");
+    match value.as_str() {
+        "" | "." | ".." => "_".to_string(),
+        _ => value,
+    }
+}
+
+/// Where a collection is exported to for an export template like
+/// `~/Downloads/sendme/{date}/{name}`.
+///
+/// Supported variables are `{date}`, `{sender}` and `{name}`. Relative
+/// templates are resolved against `dest`.
+fn expand_template(
+    template: &str,
+    dest: &Path,
+    ticket: &BlobTicket,
+    files: &[String],
+) -> anyhow::Result<PathBuf> {
+    let name = match top_level(files).as_slice() {
+        [single] => Path::new(single)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| single.clone()),
+        _ => ticket.hash().to_hex()[..8].to_string(),
+    };
+    let expanded = template
+        .replace(
+            "{date}",
+            &chrono::Local::now().format("%Y-%m-%d").to_string(),
+        )
+        .replace(
+            "{sender}",
+            &sanitize(&ticket.node_addr().node_id.fmt_short()),
+        )
+        .replace("{name}", &sanitize(&name));
+    let path = match expanded.strip_prefix("~/") {
+        Some(rest) => tauri::api::path::home_dir()
+            .context("no home directory")?
+            .join(rest),
+        None => dest.join(expanded),
+    };
+    Ok(path)
+}
+
+/// Where the files of a download into `dest` are saved, following the
+/// export template.
+pub fn export_root(
+    settings: &Settings,
+    dest: &Path,
+    ticket: &BlobTicket,
+    files: &[String],
+) -> anyhow::Result<PathBuf> {
+    match &settings.export_template {
+        Some(template) => expand_template(template, dest, ticket, files),
+        None => Ok(dest.to_path_buf()),
+    }
+}
+
+/// Sent to the frontend as `download-resumed` when a download continues an
+/// earlier attempt.
+#[derive(Debug, Clone, Serialize)]
+struct Resumed {
+    hash: String,
+    /// Bytes that do not have to be fetched again.
+    bytes: u64,
+    total: u64,
+    percent: u8,
+}
+
+/// How many bytes of the files in `hash_seq` are in `db` already.
+async fn local_bytes<D: PartialMap>(db: &D, hash_seq: &HashSeq) -> u64 {
+    let mut bytes = 0;
+    // the first blob is the collection's metadata
+    for hash in hash_seq.iter().skip(1) {
+        bytes += match db.get_possibly_partial(&hash) {
+            PossiblyPartialEntry::Complete(entry) => entry.size(),
+            PossiblyPartialEntry::Partial(entry) => entry
+                .available_ranges()
+                .await
+                .map(|ranges| total_bytes(ranges, entry.size()))
+                .unwrap_or_default(),
+            PossiblyPartialEntry::NotFound => 0,
+        };
+    }
+    bytes
+}
+
+/// Import the files below `paths` into `db`, so the blobs they have in
+/// common with a collection are not fetched again. The tags keep them from
+/// being collected.
+async fn seed<D: Store>(db: &D, paths: &[PathBuf]) -> anyhow::Result<Vec<TempTag>> {
+    let mut tags = Vec::new();
+    for entry in paths.iter().flat_map(walkdir::WalkDir::new) {
+        // files removed since are simply fetched
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        // copied, the export may overwrite the file
+        let (tag, _) = db
+            .import_file(
+                entry.path().to_path_buf(),
+                ImportMode::Copy,
+                BlobFormat::Raw,
+                IgnoreProgressSender::default(),
+            )
+            .await?;
+        tags.push(tag);
+    }
+    Ok(tags)
+}
+
+/// The top level names of the files of a collection, in order.
+pub fn top_level(files: &[String]) -> Vec<String> {
+    let mut names = Vec::new();
+    for name in files {
+        let first = name.split('/').next().unwrap_or(name);
+        if !names.iter().any(|n| n == first) {
+            names.push(first.to_string());
+        }
+    }
+    names
+}
+
+/// Bind an endpoint for outgoing connections with the network settings.
+pub async fn bind(settings: &Settings, secret_key: SecretKey) -> anyhow::Result<MagicEndpoint> {
+    let builder = MagicEndpoint::builder()
+        .alpns(vec![])
+        .secret_key(secret_key)
+        .transport_config(settings.keep_alive.transport_config());
+    settings.network.bind(builder).await
+}
+
+/// Bind an endpoint with the network settings and connect to the provider of
+/// `ticket`, giving it the password first for protected shares. The
+/// connection closes when the endpoint is dropped.
+pub async fn connect(
+    ticket: &BlobTicket,
+    settings: &Settings,
+    secret_key: SecretKey,
+    password: Option<&str>,
+) -> anyhow::Result<(MagicEndpoint, quinn::Connection)> {
+    let endpoint = bind(settings, secret_key).await?;
+    log!("connecting to {}", ticket.node_addr().node_id);
+    if let Some(password) = password {
+        password::unlock(&endpoint, ticket, password)
+            .instrument(tracing::info_span!("unlock"))
+            .await?;
+    }
+    let connection = endpoint
+        .connect(ticket.node_addr().clone(), ALPN)
+        .instrument(tracing::info_span!("connect"))
+        .await?;
+    Ok((endpoint, connection))
+}
+
+/// A connection left open after a preview.
+struct Warm {
+    endpoint: MagicEndpoint,
+    connection: quinn::Connection,
+    /// Whether the provider was given the password.
+    unlocked: bool,
+    since: Instant,
+}
+
+/// Connections of tickets that were just previewed, kept open for
+/// [`WARM_FOR`] so the download that usually follows starts right away
+/// instead of connecting and punching holes again.
+#[derive(Default)]
+pub struct WarmConnections(Mutex<HashMap<(NodeId, Hash), Warm>>);
+
+impl WarmConnections {
+    /// Keep the connection of a preview of `ticket` open for a while.
+    pub fn keep(
+        &self,
+        app: &AppHandle,
+        ticket: &BlobTicket,
+        endpoint: MagicEndpoint,
+        connection: quinn::Connection,
+        unlocked: bool,
+    ) {
+        let warm = Warm {
+            endpoint,
+            connection,
+            unlocked,
+            since: Instant::now(),
+        };
+        let key = (ticket.node_addr().node_id, ticket.hash());
+        self.0.lock().unwrap().insert(key, warm);
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(WARM_FOR).await;
+            // closed when dropped
+            app.state::<WarmConnections>()
+                .0
+                .lock()
+                .unwrap()
+                .retain(|_, warm| warm.since.elapsed() < WARM_FOR);
+        });
+    }
+
+    /// The open connection of a recent preview of `ticket`, if it can be
+    /// used for a download with or without a password.
+    fn take(
+        &self,
+        ticket: &BlobTicket,
+        password: bool,
+    ) -> Option<(MagicEndpoint, quinn::Connection)> {
+        let key = (ticket.node_addr().node_id, ticket.hash());
+        let warm = self.0.lock().unwrap().remove(&key)?;
+        let usable = warm.since.elapsed() < WARM_FOR
+            && warm.connection.close_reason().is_none()
+            && (warm.unlocked || !password);
+        usable.then_some((warm.endpoint, warm.connection))
+    }
+}
+
+/// The content of blob `hash`, fetched over `connection`.
+async fn fetch_blob(connection: &quinn::Connection, hash: Hash) -> anyhow::Result<Vec<u8>> {
+    let connected = fsm::start(connection.clone(), GetRequest::single(hash))
+        .next()
+        .await?;
+    let fsm::ConnectedNext::StartRoot(start) = connected.next().await? else {
+        anyhow::bail!("the provider does not have {}", hash.to_hex());
+    };
+    let (end, data) = start.next().concatenate_into_vec().await?;
+    if let fsm::EndBlobNext::Closing(closing) = end.next() {
+        closing.next().await?;
+    }
+    Ok(data)
+}
+
+/// Aborts a task when dropped.
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Fetch the missing parts of the collection `hash` into `db`.
+///
+/// Checking partial blobs is not Send, so the fetch runs on a thread of its
+/// own. It stops when the future is dropped, keeping what was written, and
+/// is held up by `throttle` when it reports progress to it.
+async fn fetch<P>(
+    db: &flat::Store,
+    connection: quinn::Connection,
+    hash: Hash,
+    progress: P,
+    throttle: Throttle,
+) -> anyhow::Result<Stats>
+where
+    P: ProgressSender<Msg = DownloadProgress> + IdGenerator,
+{
+    let db = db.clone();
+    let content = HashAndFormat {
+        hash,
+        format: BlobFormat::HashSeq,
+    };
+    let span = tracing::Span::current();
+    let task = LocalPoolHandle::new(1).spawn_pinned(move || {
+        async move {
+            let fetch = get_to_db(&db, connection, &content, progress);
+            throttle.hold(fetch).await
+        }
+        .instrument(span)
+    });
+    let _abort = AbortOnDrop(task.abort_handle());
+    task.await?
+}
+
+/// The names of the files of the collection `hash`, fetching only the
+/// collection and its pack index.
+pub async fn fetch_file_names(
+    connection: &quinn::Connection,
+    hash: Hash,
+) -> anyhow::Result<Vec<String>> {
+    // the hash seq and the metadata blob with the names
+    let ranges = RangeSpecSeq::new([RangeSpec::all(), RangeSpec::all(), RangeSpec::EMPTY]);
+    let connected = fsm::start(connection.clone(), GetRequest::new(hash, ranges))
+        .next()
+        .await?;
+    let fsm::ConnectedNext::StartRoot(start) = connected.next().await? else {
+        anyhow::bail!("the provider does not have {}", hash.to_hex());
+    };
+    let (next, _, collection) = Collection::read_fsm(start).await?;
+    let closing = match next {
+        fsm::EndBlobNext::MoreChildren(more) => more.finish(),
+        fsm::EndBlobNext::Closing(closing) => closing,
+    };
+    closing.next().await?;
+    let index = match pack::index_hash(&collection) {
+        Some(index) => fetch_blob(connection, index).await?,
+        None => Vec::new(),
+    };
+    pack::names_with_index(&collection, &index)
+}
+
+/// Fetch the collection of `ticket` and export it into `dest`, or where the
+/// export template points to.
+///
+/// The data is fetched into a store in `dest`, which is kept if the download
+/// fails, so trying again continues where it stopped. The frontend is told
+/// how much was there already.
+pub async fn get(
+    app: &AppHandle,
+    ticket: &BlobTicket,
+    dest: &Path,
+    settings: &Settings,
+    secret_key: SecretKey,
+    opts: &DownloadOptions,
+) -> anyhow::Result<DownloadStats> {
+    anyhow::ensure!(
+        ticket.format() == BlobFormat::HashSeq,
+        "the ticket does not point to a collection"
+    );
+    let hash = ticket.hash();
+    let iroh_data_dir = match opts.store_only {
+        true => app.state::<ReceivedDir>().store(&hash),
+        false => dest.join(format!(".sendme-get-{}", hash.to_hex())),
+    };
+    std::fs::create_dir_all(&iroh_data_dir)?;
+    let db = flat::Store::load(&iroh_data_dir).await?;
+    let seeded = seed(&db, &opts.seed_from)
+        .instrument(tracing::info_span!("seed", paths = opts.seed_from.len()))
+        .await?;
+    let warm = app
+        .state::<WarmConnections>()
+        .take(ticket, opts.password.is_some());
+    let (_endpoint, connection) = match warm {
+        Some(warm) => {
+            log!("reusing the connection of the preview");
+            warm
+        }
+        None => connect(ticket, settings, secret_key, opts.password.as_deref()).await?,
+    };
+    let (hash_seq, sizes) = get_hash_seq_and_sizes(&connection, &hash, MAX_HASH_SEQ_SIZE)
+        .instrument(tracing::info_span!("sizes"))
+        .await?;
+    let size = sizes.iter().skip(1).sum::<u64>();
+    log!(
+        "getting collection {}, {} files, {} bytes",
+        hash.to_hex(),
+        sizes.len().saturating_sub(1),
+        size
+    );
+    let resumed = local_bytes(&db, &hash_seq).await.min(size);
+    if resumed > 0 {
+        let percent = (resumed * 100 / size.max(1)) as u8;
+        log!("resuming {} at {}%", hash.to_hex(), percent);
+        let event = Resumed {
+            hash: hash.to_hex().to_string(),
+            bytes: resumed,
+            total: size,
+            percent,
+        };
+        app.emit_all("download-resumed", event).ok();
+    }
+    let cap = match opts.download_limit {
+        Some(rate) => Arc::new(Bucket::new(Some(rate))),
+        None => app.state::<DownloadCap>().0.clone(),
+    };
+    let throttle = Throttle::new(cap);
+    let stats = fetch(&db, connection, hash, throttle.clone(), throttle)
+        .instrument(tracing::info_span!("fetch", size, resumed))
+        .await?;
+    tracing::info!(bytes_read = stats.bytes_read, "fetched");
+    drop(seeded);
+    let collection = Collection::load(&db, &hash).await?;
+    verify(app, &db, &collection, hash)
+        .instrument(tracing::info_span!("verify"))
+        .await?;
+    let files = pack::file_names(&db, &collection).await?;
+    if opts.store_only {
+        log!("keeping {} in the store", hash.to_hex());
+        return Ok(DownloadStats {
+            hash: hash.to_hex().to_string(),
+            files: files.len(),
+            size,
+            bytes_read: stats.bytes_read,
+            resumed,
+            elapsed_ms: stats.elapsed.as_millis() as u64,
+            saved: Vec::new(),
+            organized: Vec::new(),
+            trace: 0,
+        });
+    }
+    // the template may point anywhere
+    let root = export_root(settings, dest, ticket, &files)?;
+    app.state::<SettingsStore>()
+        .policy()
+        .check_destination(&root)?;
+    let renamed = export(db.clone(), &collection, &root, &[], &opts.conflicts)
+        .instrument(tracing::info_span!("export", files = files.len()))
+        .await?;
+    let finished = ExportFinished::new(&hash, dest, &Ok(root.clone()));
+    app.emit_all("export-finished", finished).ok();
+    // a failed mirror does not fail the download
+    for mirror in &opts.mirrors {
+        let res = async {
+            let root = export_root(settings, mirror, ticket, &files)?;
+            app.state::<SettingsStore>()
+                .policy()
+                .check_destination(&root)?;
+            export(db.clone(), &collection, &root, &[], &opts.conflicts).await?;
+            anyhow::Ok(root)
+        }
+        .instrument(tracing::info_span!("mirror"))
+        .await;
+        if let Err(err) = &res {
+            log!("failed to save to {}: {:#}", mirror.display(), err);
+        }
+        let finished = ExportFinished::new(&hash, mirror, &res);
+        app.emit_all("export-finished", finished).ok();
+    }
+    std::fs::remove_dir_all(&iroh_data_dir).ok();
+    // a renamed top level file is only found under its new name
+    let names = top_level(&files)
+        .into_iter()
+        .map(|name| {
+            let path = renamed
+                .get(&name)
+                .cloned()
+                .unwrap_or_else(|| root.join(entry_path(&name)));
+            path.file_name()
+                .map_or(name, |n| n.to_string_lossy().into_owned())
+        })
+        .collect::<Vec<_>>();
+    // mirrors keep the names the sender chose
+    let names = match receive_rule(settings, ticket).and_then(|rule| rule.name_template) {
+        Some(template) => {
+            let sender = ticket.node_addr().node_id.fmt_short();
+            rename(&template, &root, &names, &sender)?
+        }
+        None => names,
+    };
+    let organized = organize(&settings.organize, &root, &names)?;
+    let saved = names
+        .iter()
+        .map(|name| {
+            organized
+                .iter()
+                .find(|p| p.file == *name)
+                .map_or_else(|| root.join(name), |p| p.path.clone())
+                .display()
+                .to_string()
+        })
+        .collect();
+    Ok(DownloadStats {
+        hash: hash.to_hex().to_string(),
+        files: files.len(),
+        size,
+        bytes_read: stats.bytes_read,
+        resumed,
+        elapsed_ms: stats.elapsed.as_millis() as u64,
+        saved,
+        organized,
+        trace: 0,
+    })
+}
+
+/// The latest download of `hash` recorded in the activity log, if its files
+/// still exist.
+pub fn previous(activity: &ActivityLog, hash: &str) -> Option<PreviousDownload> {
+    let (time, saved) = activity.received(hash)?;
+    let complete = !saved.is_empty() && saved.iter().all(|p| Path::new(p).exists());
+    complete.then_some(PreviousDownload { time, saved })
+}
+
+/// Copy the files of an earlier download into `dest`.
+pub fn copy_previous(
+    hash: &str,
+    previous: &PreviousDownload,
+    dest: &Path,
+    conflicts: &Conflicts,
+) -> anyhow::Result<DownloadStats> {
+    let start = std::time::Instant::now();
+    let (mut files, mut size, mut saved) = (0, 0, Vec::new());
+    let mut renamed = HashMap::new();
+    for source in &previous.saved {
+        let source = Path::new(source);
+        let root = source.parent().context("no parent")?;
+        for entry in walkdir::WalkDir::new(source) {
+            let entry = entry?;
+            let relative = entry.path().strip_prefix(root)?;
+            if entry.file_type().is_dir() {
+                std::fs::create_dir_all(dest.join(relative))?;
+                continue;
+            }
+            if dest.join(relative) == entry.path() {
+                continue;
+            }
+            let name = crate::upload::canonicalized_path_to_string(relative, true)?;
+            let Some(target) = export_target(dest, &name, conflicts, &mut renamed)? else {
+                continue;
+            };
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            size += std::fs::copy(entry.path(), &target)?;
+            files += 1;
+        }
+        let name = source.file_name().context("no file name")?;
+        let target = renamed
+            .get(name.to_string_lossy().as_ref())
+            .cloned()
+            .unwrap_or_else(|| dest.join(name));
+        saved.push(target.display().to_string());
+    }
+    Ok(DownloadStats {
+        hash: hash.to_string(),
+        files,
+        size,
+        bytes_read: 0,
+        resumed: 0,
+        elapsed_ms: start.elapsed().as_millis() as u64,
+        saved,
+        organized: Vec::new(),
+        trace: 0,
+    })
+}
+
+/// Download `ticket` into `dest`, recording it in the telemetry and the
+/// activity log.
+///
+/// While transfers are paused the download waits. A pause during the
+/// transfer parks it: the connection is closed, the data fetched so far stays
+/// in the store and the download continues from there once the pause is over.
+pub async fn download_paused(
+    app: &AppHandle,
+    ticket: &BlobTicket,
+    dest: &Path,
+    opts: &DownloadOptions,
+) -> anyhow::Result<DownloadStats> {
+    let paused = app.state::<PauseState>().subscribe();
+    if crate::demo::enabled() {
+        return unpaused(paused, opts.urgent, || crate::demo::download(ticket)).await;
+    }
+    let settings = app.state::<SettingsStore>().get();
+    let secret_key = app.state::<Identity>().secret_key();
+    unpaused(paused, opts.urgent, || {
+        get(app, ticket, dest, &settings, secret_key.clone(), opts)
+    })
+    .await
+}
+
+/// Run `attempt` while `paused` does not apply.
+///
+/// A pause drops the running attempt and starts a new one when it is over,
+/// so each attempt has to continue from what the ones before left behind.
+async fn unpaused<T, F, Fut>(
+    mut paused: watch::Receiver<Paused>,
+    urgent: bool,
+    mut attempt: F,
+) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    loop {
+        paused
+            .wait_for(|p| !p.applies(urgent))
+            .await
+            .context("the app is shutting down")?;
+        tokio::select! {
+            res = attempt() => return res,
+            _ = paused.wait_for(|p| p.applies(urgent)) => log!("download paused"),
+        }
+    }
+}
+
+/// A download shared by all requests for the same collection and
+/// destination.
+type Job = Shared<BoxFuture<'static, Result<DownloadStats, Arc<anyhow::Error>>>>;
+
+/// The downloads in progress, so requesting the same ticket for the same
+/// destination again attaches to the running download instead of opening
+/// another connection and store. The options it was started with are kept
+/// to check the attached requests against.
+#[derive(Default)]
+pub struct ActiveDownloads(Mutex<HashMap<(Hash, PathBuf), (DownloadOptions, Job)>>);
+
+impl ActiveDownloads {
+    /// The download of `ticket` into `dest`, started unless it is running
+    /// already. The flag tells whether it was running.
+    ///
+    /// Fails if it is running with other options, e.g. not urgent or with
+    /// other conflict resolutions, rather than ignoring the new ones.
+    pub fn join(
+        &self,
+        app: &AppHandle,
+        ticket: &BlobTicket,
+        dest: &Path,
+        opts: &DownloadOptions,
+        span: tracing::Span,
+    ) -> anyhow::Result<(Job, bool)> {
+        let key = (ticket.hash(), dest.to_path_buf());
+        let mut jobs = self.0.lock().unwrap();
+        if let Some((running, job)) = jobs.get(&key) {
+            anyhow::ensure!(
+                running == opts,
+                "{} is being downloaded to {} with other options already",
+                ticket.hash().to_hex(),
+                dest.display()
+            );
+            return Ok((job.clone(), true));
+        }
+        let (app, ticket, started) = (app.clone(), ticket.clone(), opts.clone());
+        let running = key.clone();
+        let job = async move {
+            let res = download_paused(&app, &ticket, &running.1, &started)
+                .instrument(span)
+                .await;
+            app.state::<ActiveDownloads>()
+                .0
+                .lock()
+                .unwrap()
+                .remove(&running);
+            res.map_err(Arc::new)
+        }
+        .boxed()
+        .shared();
+        jobs.insert(key, (opts.clone(), job.clone()));
+        Ok((job, false))
+    }
+}
+
+/// The download folder from the settings, or the system's.
+pub fn default_download_dir(settings: &Settings) -> anyhow::Result<PathBuf> {
+    match &settings.download_dir {
+        Some(dir) => Ok(dir.clone()),
+        None => tauri::api::path::download_dir().context("no download folder"),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use iroh_bytes::{
+        provider::{handle_connection, Event, EventSender},
+        util::progress::ProgressSendError,
+    };
+    use iroh_net::{derp::DerpMode, NodeAddr};
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::upload::canonicalized_path_to_string;
+
+    /// A file name that is valid on all platforms.
+    fn component() -> impl Strategy<Value = String> {
+        "[a-zA-Z0-9 _.~äöüß日本語-]{1,16}".prop_filter("not . or ..", |c| c != "." && c != "..")
+    }
+
+    proptest! {
+        #[test]
+        fn importable_paths_round_trip(parts in prop::collection::vec(component(), 1..6)) {
+            let relative = parts.iter().collect::<PathBuf>();
+            let name = canonicalized_path_to_string(&relative, true).unwrap();
+            let root = Path::new("root");
+            prop_assert_eq!(get_export_path(root, &name).unwrap(), root.join(&relative));
+        }
+
+        #[test]
+        fn invalid_components_are_rejected(
+            parts in prop::collection::vec(component(), 0..4),
+            invalid in prop::sample::select(vec!["", ".", ".."]),
+            at in any::<prop::sample::Index>(),
+        ) {
+            let mut parts = parts;
+            parts.insert(at.index(parts.len() + 1), invalid.to_string());
+            prop_assert!(get_export_path(Path::new("root"), &parts.join("/")).is_err());
+        }
+
+        #[test]
+        fn absolute_paths_are_not_importable(parts in prop::collection::vec(component(), 1..4)) {
+            let absolute = std::env::temp_dir().join(parts.iter().collect::<PathBuf>());
+            prop_assert!(canonicalized_path_to_string(absolute, true).is_err());
+        }
+
+        #[test]
+        fn export_paths_stay_below_root(name in any::<String>()) {
+            let root = Path::new("root");
+            if let Ok(path) = get_export_path(root, &name) {
+                let relative = path.strip_prefix(root).unwrap();
+                prop_assert!(relative.components().all(|c| matches!(c, Component::Normal(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn resolves_conflicts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        for name in ["a.txt", "a (1).txt", "b", "c"] {
+            std::fs::write(root.join(name), b"old").unwrap();
+        }
+        let conflicts = Conflicts {
+            default: Resolution::Rename,
+            files: [
+                ("b".to_string(), Resolution::Skip),
+                ("c".to_string(), Resolution::Overwrite),
+            ]
+            .into(),
+        };
+        let mut renamed = HashMap::new();
+        let target = |name, renamed: &mut _| export_target(root, name, &conflicts, renamed);
+        assert_eq!(
+            target("a.txt", &mut renamed).unwrap(),
+            Some(root.join("a (2).txt"))
+        );
+        assert_eq!(target("b", &mut renamed).unwrap(), None);
+        assert_eq!(target("c", &mut renamed).unwrap(), Some(root.join("c")));
+        assert!(!root.join("c").exists());
+        assert_eq!(target("d", &mut renamed).unwrap(), Some(root.join("d")));
+        assert_eq!(renamed.len(), 1);
+        assert_eq!(renamed["a.txt"], root.join("a (2).txt"));
+    }
+
+    #[test]
+    fn selects_entries() {
+        assert!(selected(&[], "a/b.txt"));
+        let entries = ["a".to_string(), "c.txt".to_string()];
+        assert!(selected(&entries, "a/b.txt"));
+        assert!(selected(&entries, "c.txt"));
+        assert!(!selected(&entries, "ab/c.txt"));
+        assert!(!selected(&entries, "c.txt.bak"));
+    }
+
+    #[tokio::test]
+    async fn detects_corrupt_blobs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let db = flat::Store::load(dir).await.unwrap();
+        let data = vec![7u8; 100_000];
+        let tag = db
+            .import_bytes(data.clone().into(), BlobFormat::Raw)
+            .await
+            .unwrap();
+        let hash = *tag.hash();
+        assert!(verify_blob(&db, &hash).await.unwrap());
+        drop((tag, db));
+        let path = dir.join("complete").join(format!("{}.data", hash.to_hex()));
+        let mut corrupt = data;
+        corrupt[50_000] = 8;
+        std::fs::write(&path, &corrupt).unwrap();
+        let db = flat::Store::load(&dir).await.unwrap();
+        assert!(!verify_blob(&db, &hash).await.unwrap());
+        std::fs::write(&path, &corrupt[..10]).unwrap();
+        assert!(!verify_blob(&db, &hash).await.unwrap());
+    }
+
+    /// Pauses the transfers once a blob is written up to `at`, and holds up
+    /// the writer until the pause has landed.
+    #[derive(Debug, Clone)]
+    struct PauseAt {
+        pause: PauseState,
+        at: u64,
+    }
+
+    impl ProgressSender for PauseAt {
+        type Msg = DownloadProgress;
+
+        type SendFuture<'a> = futures::future::Ready<Result<(), ProgressSendError>>;
+
+        fn send(&self, _msg: DownloadProgress) -> Self::SendFuture<'_> {
+            futures::future::ready(Ok(()))
+        }
+
+        fn try_send(&self, msg: DownloadProgress) -> Result<(), ProgressSendError> {
+            if let DownloadProgress::Progress { offset, .. } = msg {
+                if offset >= self.at && self.pause.set(true) {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+            }
+            Ok(())
+        }
+
+        fn blocking_send(&self, msg: DownloadProgress) -> Result<(), ProgressSendError> {
+            self.try_send(msg)
+        }
+    }
+
+    impl IdGenerator for PauseAt {
+        fn new_id(&self) -> u64 {
+            0
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct NoEvents;
+
+    impl EventSender for NoEvents {
+        fn send(&self, _event: Event) -> BoxFuture<'_, ()> {
+            async {}.boxed()
+        }
+    }
+
+    /// Serve `db` on localhost, returning the address to fetch from.
+    pub(crate) async fn provider(db: flat::Store) -> NodeAddr {
+        let endpoint = MagicEndpoint::builder()
+            .alpns(vec![ALPN.to_vec()])
+            .derp_mode(DerpMode::Disabled)
+            .bind(0)
+            .await
+            .unwrap();
+        let port = endpoint.local_addr().unwrap().0.port();
+        let addr =
+            NodeAddr::new(endpoint.node_id())
+                .with_direct_addresses([([127, 0, 0, 1], port).into()]);
+        tokio::spawn(async move {
+            let rt = LocalPoolHandle::new(1);
+            while let Some(connecting) = endpoint.accept().await {
+                let db = db.clone();
+                tokio::spawn(handle_connection(connecting, db, NoEvents, rt.clone()));
+            }
+        });
+        addr
+    }
+
+    /// An endpoint to fetch from [`provider`] with.
+    pub(crate) async fn receiver() -> MagicEndpoint {
+        MagicEndpoint::builder()
+            .alpns(vec![])
+            .derp_mode(DerpMode::Disabled)
+            .bind(0)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn continues_paused_downloads() {
+        let tmp = tempfile::tempdir().unwrap();
+        let provider_db = flat::Store::load(tmp.path().join("provider"))
+            .await
+            .unwrap();
+        let data = (0..8_000_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let size = data.len() as u64;
+        let blob = provider_db
+            .import_bytes(data.into(), BlobFormat::Raw)
+            .await
+            .unwrap();
+        let file = *blob.hash();
+        let collection: Collection = [("file", file)].into_iter().collect();
+        let tag = collection.store(&provider_db).await.unwrap();
+        let hash = *tag.hash();
+        let addr = provider(provider_db).await;
+
+        let pause = PauseState::default();
+        let resume = pause.clone();
+        tokio::spawn(async move {
+            let mut paused = resume.subscribe();
+            paused.wait_for(|p| p.manual).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            resume.set(false);
+        });
+        let receiver = receiver().await;
+        let db = flat::Store::load(tmp.path().join("receiver"))
+            .await
+            .unwrap();
+        let (receiver, addr, db) = (&receiver, &addr, &db);
+        let mut attempts = 0;
+        let stats = unpaused(pause.subscribe(), false, || {
+            attempts += 1;
+            // only the first attempt is paused, halfway through
+            let progress = PauseAt {
+                pause: pause.clone(),
+                at: if attempts == 1 { size / 2 } else { u64::MAX },
+            };
+            async move {
+                let connection = receiver.connect(addr.clone(), ALPN).await?;
+                let throttle = Throttle::new(Default::default());
+                fetch(db, connection, hash, progress, throttle).await
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(attempts, 2);
+        assert!(stats.bytes_read < size);
+        assert!(verify_blob(db, &file).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn quiet_hours_park_downloads() {
+        let pause = PauseState::default();
+        pause.set_quiet_hours(true);
+        // urgent downloads run through quiet hours
+        let res = unpaused(pause.subscribe(), true, || async { Ok(()) });
+        tokio::time::timeout(Duration::from_secs(1), res)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let end = pause.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            end.set_quiet_hours(false);
+        });
+        let mut attempts = 0;
+        let res = unpaused(pause.subscribe(), false, || {
+            attempts += 1;
+            let (pause, first) = (pause.clone(), attempts == 1);
+            async move {
+                if first {
+                    // quiet hours start again while the download runs
+                    pause.set_quiet_hours(true);
+                    let end = pause.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        end.set_quiet_hours(false);
+                    });
+                    futures::future::pending::<()>().await;
+                }
+                Ok(())
+            }
+        });
+        tokio::time::timeout(Duration::from_secs(5), res)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(attempts, 2);
+    }
+}
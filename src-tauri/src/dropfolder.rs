@@ -0,0 +1,190 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, ClipboardManager, Manager, State};
+
+use crate::{auth::SessionToken, settings::SettingsStore, upload::ShareOptions};
+
+/// How often the drop folder is scanned.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Extension of the files the tickets are written to.
+const TICKET_EXTENSION: &str = "ticket";
+
+/// A folder whose new files and folders are shared automatically.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DropFolderSettings {
+    /// The watched folder, off if unset.
+    pub folder: Option<PathBuf>,
+    /// Copy the ticket of a new share to the clipboard.
+    pub copy_ticket: bool,
+    /// Write the ticket of a new share next to it, as `<name>.ticket`.
+    pub ticket_file: bool,
+}
+
+impl Default for DropFolderSettings {
+    fn default() -> Self {
+        Self {
+            folder: None,
+            copy_ticket: true,
+            ticket_file: true,
+        }
+    }
+}
+
+/// Sent to the frontend as `drop-folder-shared` for every file or folder
+/// shared from the drop folder.
+#[derive(Debug, Clone, Serialize)]
+struct DropShare {
+    name: String,
+    ticket: String,
+}
+
+/// The entries of `dir` with their current size, without hidden entries and
+/// ticket files.
+fn scan(dir: &Path) -> HashMap<PathBuf, u64> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            !hidden && path.extension() != Some(OsStr::new(TICKET_EXTENSION))
+        })
+        .filter_map(|path| {
+            let size = crate::upload::total_size(std::slice::from_ref(&path)).ok()?;
+            Some((path, size))
+        })
+        .collect()
+}
+
+/// Where the ticket of `path` is written to.
+fn ticket_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(TICKET_EXTENSION);
+    path.with_file_name(name)
+}
+
+/// Share every file and folder that appears in the drop folder.
+///
+/// Like the spool folder, entries already there when the folder is first
+/// scanned are left alone and new ones are shared once their size stayed the
+/// same between two scans.
+pub fn spawn_watcher(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut folder: Option<PathBuf> = None;
+        let mut seen = HashSet::new();
+        let mut pending = HashMap::new();
+        loop {
+            let settings = app.state::<SettingsStore>().get().drop_folder;
+            if settings.folder != folder {
+                folder = settings.folder.clone();
+                pending.clear();
+                seen = folder
+                    .as_deref()
+                    .map(|dir| scan(dir).into_keys().collect())
+                    .unwrap_or_default();
+            }
+            if let Some(dir) = &folder {
+                for (path, size) in scan(dir) {
+                    if seen.contains(&path) {
+                        continue;
+                    }
+                    if pending.insert(path.clone(), size) != Some(size) {
+                        continue;
+                    }
+                    pending.remove(&path);
+                    seen.insert(path.clone());
+                    share(&app, path, &settings).await;
+                }
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
+
+async fn share(app: &AppHandle, path: PathBuf, settings: &DropFolderSettings) {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ticket = match crate::share(app, vec![path.clone()], ShareOptions::default()).await {
+        Ok(ticket) => ticket.to_string(),
+        Err(err) => {
+            log!("failed to share {} from the drop folder: {:#}", name, err);
+            return;
+        }
+    };
+    if settings.ticket_file {
+        let target = ticket_path(&path);
+        if let Err(err) = std::fs::write(&target, format!("{}\n", ticket))
+            .with_context(|| format!("cannot write {}", target.display()))
+        {
+            log!("failed to save the ticket of {}: {:#}", name, err);
+        }
+    }
+    if settings.copy_ticket {
+        if let Err(err) = app.clipboard_manager().write_text(ticket.clone()) {
+            log!("failed to copy ticket: {}", err);
+        }
+    }
+    crate::notify::desktop(app, "drop_folder.shared", &[("name", &name)]);
+    app.emit_all("drop-folder-shared", DropShare { name, ticket })
+        .ok();
+}
+
+#[tauri::command]
+pub fn get_drop_folder(settings: State<'_, SettingsStore>) -> DropFolderSettings {
+    settings.get().drop_folder
+}
+
+/// Change the drop folder, or stop watching with an unset folder.
+#[tauri::command]
+pub fn set_drop_folder(
+    drop_folder: DropFolderSettings,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    if let Some(folder) = &drop_folder.folder {
+        std::fs::create_dir_all(folder).map_err(|e| e.to_string())?;
+    }
+    settings
+        .update(|s| s.drop_folder = drop_folder)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scans_new_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("a.txt"), b"hello").unwrap();
+        std::fs::write(ticket_path(&dir.join("a.txt")), b"ticket").unwrap();
+        std::fs::write(dir.join(".hidden"), b"").unwrap();
+        std::fs::create_dir(dir.join("b")).unwrap();
+        std::fs::write(dir.join("b").join("c"), b"abc").unwrap();
+        let entries = scan(dir);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[&dir.join("a.txt")], 5);
+        assert_eq!(entries[&dir.join("b")], 3);
+        assert_eq!(ticket_path(&dir.join("b")), dir.join("b.ticket"));
+    }
+}
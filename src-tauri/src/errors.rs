@@ -0,0 +1,250 @@
+use std::fmt;
+
+use iroh_bytes::get::fsm::DecodeError;
+use serde::Serialize;
+
+use crate::{i18n::I18n, password, serve, version};
+
+/// TLS alert `no_application_protocol` as a QUIC crypto error code.
+const NO_APPLICATION_PROTOCOL: u64 = 0x100 | 120;
+
+/// User facing classes of failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// No relay server could be reached.
+    RelayUnreachable,
+    /// No direct path to the peer could be established.
+    HolePunchFailed,
+    /// The peer did not answer.
+    PeerOffline,
+    /// The peer sent data that does not match the requested hash.
+    HashMismatch,
+    /// The peer speaks a different protocol version.
+    AlpnMismatch,
+    /// The peer refused the connection because it is paused.
+    Paused,
+    /// The peer does not have the requested data.
+    NotFound,
+    /// The peer refused the request because the share was stopped.
+    Expired,
+    /// The share is restricted to other recipients.
+    NotAllowed,
+    /// The share is protected by a password and none was given.
+    PasswordRequired,
+    WrongPassword,
+    RateLimited,
+    /// A file or folder to share or write to does not exist.
+    PathNotFound,
+    /// A file or folder can't be read or written with the user's permissions.
+    PermissionDenied,
+    Io,
+    Unknown,
+}
+
+impl ErrorCode {
+    fn key(self) -> &'static str {
+        match self {
+            ErrorCode::RelayUnreachable => "relay_unreachable",
+            ErrorCode::HolePunchFailed => "hole_punch_failed",
+            ErrorCode::PeerOffline => "peer_offline",
+            ErrorCode::HashMismatch => "hash_mismatch",
+            ErrorCode::AlpnMismatch => "alpn_mismatch",
+            ErrorCode::Paused => "paused",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Expired => "expired",
+            ErrorCode::NotAllowed => "not_allowed",
+            ErrorCode::PasswordRequired => "password_required",
+            ErrorCode::WrongPassword => "wrong_password",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::PathNotFound => "path_not_found",
+            ErrorCode::PermissionDenied => "permission_denied",
+            ErrorCode::Io => "io",
+            ErrorCode::Unknown => "unknown",
+        }
+    }
+}
+
+impl ErrorCode {
+    /// Whether trying the same again may work, so the frontend offers to
+    /// retry. The others need the user or the sender to change something
+    /// first.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::RelayUnreachable
+                | ErrorCode::HolePunchFailed
+                | ErrorCode::PeerOffline
+                | ErrorCode::Paused
+                | ErrorCode::RateLimited
+                | ErrorCode::Io
+                | ErrorCode::Unknown
+        )
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.key())
+    }
+}
+
+/// An [`ErrorCode`] can be returned directly where the cause is known.
+impl std::error::Error for ErrorCode {}
+
+fn classify_connection(err: &quinn::ConnectionError) -> Option<ErrorCode> {
+    use quinn::ConnectionError::*;
+    match err {
+        VersionMismatch => Some(ErrorCode::AlpnMismatch),
+        TransportError(err) if u64::from(err.code) == NO_APPLICATION_PROTOCOL => {
+            Some(ErrorCode::AlpnMismatch)
+        }
+        ConnectionClosed(close) if u64::from(close.error_code) == NO_APPLICATION_PROTOCOL => {
+            Some(ErrorCode::AlpnMismatch)
+        }
+        ApplicationClosed(close) if close.reason.as_ref() == b"paused" => Some(ErrorCode::Paused),
+        ApplicationClosed(close)
+            if close.error_code == quinn::VarInt::from(version::INCOMPATIBLE) =>
+        {
+            Some(ErrorCode::AlpnMismatch)
+        }
+        ApplicationClosed(close) if close.error_code == quinn::VarInt::from(serve::NOT_ALLOWED) => {
+            Some(ErrorCode::NotAllowed)
+        }
+        ApplicationClosed(close)
+            if close.error_code == quinn::VarInt::from(password::PASSWORD_REQUIRED) =>
+        {
+            Some(ErrorCode::PasswordRequired)
+        }
+        ApplicationClosed(close)
+            if close.error_code == quinn::VarInt::from(password::WRONG_PASSWORD) =>
+        {
+            Some(ErrorCode::WrongPassword)
+        }
+        TimedOut => Some(ErrorCode::PeerOffline),
+        _ => None,
+    }
+}
+
+/// Whether a provider reset a stream because it does not know the hash.
+fn is_unknown_hash(cause: &(dyn std::error::Error + 'static)) -> bool {
+    let read = cause.downcast_ref::<quinn::ReadError>().or_else(|| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .and_then(|e| e.get_ref())
+            .and_then(|e| e.downcast_ref::<quinn::ReadError>())
+    });
+    let unknown = quinn::VarInt::from(serve::UNKNOWN_HASH);
+    matches!(read, Some(quinn::ReadError::Reset(code)) if *code == unknown)
+}
+
+/// The version a peer advertised when it refused us as incompatible.
+fn peer_version(err: &anyhow::Error) -> Option<String> {
+    err.chain().find_map(
+        |cause| match cause.downcast_ref::<quinn::ConnectionError>()? {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                version::parse_refusal(&close.reason)
+            }
+            _ => None,
+        },
+    )
+}
+
+/// Find the most specific code for an error, looking through its causes.
+pub fn classify(err: &anyhow::Error) -> ErrorCode {
+    for cause in err.chain() {
+        if let Some(code) = cause.downcast_ref::<ErrorCode>() {
+            return *code;
+        }
+        if let Some(code) = cause
+            .downcast_ref::<quinn::ConnectionError>()
+            .and_then(classify_connection)
+        {
+            return code;
+        }
+        if is_unknown_hash(cause) {
+            return ErrorCode::Expired;
+        }
+        match cause.downcast_ref::<std::io::Error>().map(|e| e.kind()) {
+            Some(std::io::ErrorKind::NotFound) => return ErrorCode::PathNotFound,
+            Some(std::io::ErrorKind::PermissionDenied) => return ErrorCode::PermissionDenied,
+            _ => {}
+        }
+        match cause.downcast_ref::<DecodeError>() {
+            Some(DecodeError::ParentHashMismatch(_) | DecodeError::LeafHashMismatch(_)) => {
+                return ErrorCode::HashMismatch;
+            }
+            Some(
+                DecodeError::NotFound
+                | DecodeError::ParentNotFound(_)
+                | DecodeError::LeafNotFound(_),
+            ) => return ErrorCode::NotFound,
+            _ => {}
+        }
+    }
+    // iroh-net reports most connectivity problems as plain messages
+    let message = format!("{:#}", err).to_lowercase();
+    if message.contains("derp") || message.contains("relay") {
+        ErrorCode::RelayUnreachable
+    } else if message.contains("hole punch") || message.contains("holepunch") {
+        ErrorCode::HolePunchFailed
+    } else if err.chain().any(|c| c.is::<std::io::Error>()) {
+        ErrorCode::Io
+    } else {
+        ErrorCode::Unknown
+    }
+}
+
+/// An error as reported to the frontend by commands that share or download
+/// files.
+///
+/// The code is stable, so the frontend can react to it, the hint tells the
+/// user what they can do about it.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserError {
+    pub code: ErrorCode,
+    /// The underlying error, for details and bug reports.
+    pub message: String,
+    /// What the user can do about it, translated.
+    pub hint: String,
+    /// Whether to offer trying again, see [`ErrorCode::is_retryable`].
+    pub retryable: bool,
+}
+
+impl UserError {
+    pub fn new(code: ErrorCode, message: String, i18n: &I18n) -> Self {
+        let hint = i18n.translate(&format!("hint.{}", code.key()), &[]);
+        Self {
+            code,
+            message,
+            hint,
+            retryable: code.is_retryable(),
+        }
+    }
+
+    pub fn from_anyhow(err: &anyhow::Error, i18n: &I18n) -> Self {
+        let mut res = Self::new(classify(err), format!("{:#}", err), i18n);
+        if let Some(version) = peer_version(err) {
+            res.hint = i18n.translate("hint.alpn_mismatch_version", &[("version", &version)]);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_io_errors() {
+        let missing = std::fs::read("/nonexistent/sendme").unwrap_err();
+        let err = anyhow::Error::from(missing).context("cannot share");
+        assert_eq!(classify(&err), ErrorCode::PathNotFound);
+        assert!(!classify(&err).is_retryable());
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert_eq!(classify(&denied.into()), ErrorCode::PermissionDenied);
+        let err = anyhow::anyhow!("no relay server reachable");
+        assert_eq!(classify(&err), ErrorCode::RelayUnreachable);
+        assert!(classify(&err).is_retryable());
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use crypto_secretbox::{
+    aead::{Aead, KeyInit},
+    Key, Nonce, XSalsa20Poly1305,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tauri::{api::dialog::blocking::FileDialogBuilder, AppHandle, State, Window};
+
+use crate::{
+    auth::SessionToken,
+    download::{DownloadOptions, DownloadStats},
+    errors::{ErrorCode, UserError},
+    i18n::I18n,
+    ratelimit::RateLimiter,
+};
+
+/// Extension of ticket files.
+const EXTENSION: &str = "sendme";
+
+const FORMAT_VERSION: u32 = 1;
+
+/// scrypt cost of new files, N = 2^15 with r = 8 takes 32 MiB.
+const LOG_N: u8 = 15;
+const R: u32 = 8;
+const P: u32 = 1;
+
+/// Largest costs accepted from a file, so a crafted one can't exhaust memory
+/// or keep the cpu busy. scrypt needs 128 * r * 2^log_n bytes, so opening a
+/// file takes at most 64 MiB, twice what new files need.
+const MAX_LOG_N: u8 = 16;
+const MAX_R: u32 = 8;
+const MAX_P: u32 = 1;
+
+const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// A ticket encrypted with a passphrase, safe to keep in a shared drive or
+/// send by email. Without the passphrase the ticket can't be read.
+#[derive(Debug, Serialize, Deserialize)]
+struct TicketFile {
+    version: u32,
+    /// scrypt parameters the key was derived with.
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+    nonce: String,
+    /// The ticket, encrypted with XSalsa20-Poly1305.
+    ciphertext: String,
+}
+
+impl TicketFile {
+    fn key(&self, passphrase: &str) -> anyhow::Result<Key> {
+        anyhow::ensure!(
+            self.log_n <= MAX_LOG_N && self.r <= MAX_R && self.p <= MAX_P,
+            "the key derivation of the ticket file is too costly"
+        );
+        anyhow::ensure!(self.r > 0 && self.p > 0, "invalid ticket file");
+        let params = scrypt::Params::new(self.log_n, self.r, self.p, Key::default().len())
+            .map_err(|_| anyhow::anyhow!("invalid ticket file"))?;
+        let salt = hex::decode(&self.salt).context("invalid salt")?;
+        let mut key = Key::default();
+        scrypt::scrypt(passphrase.as_bytes(), &salt, &params, &mut key)
+            .map_err(|_| anyhow::anyhow!("failed to derive the key"))?;
+        Ok(key)
+    }
+
+    fn seal(ticket: &str, passphrase: &str) -> anyhow::Result<Self> {
+        let mut file = TicketFile {
+            version: FORMAT_VERSION,
+            log_n: LOG_N,
+            r: R,
+            p: P,
+            salt: hex::encode(rand::thread_rng().gen::<[u8; 16]>()),
+            nonce: String::new(),
+            ciphertext: String::new(),
+        };
+        let nonce = rand::thread_rng().gen::<[u8; 24]>();
+        let cipher = XSalsa20Poly1305::new(&file.key(passphrase)?);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), ticket.as_bytes())
+            .map_err(|_| anyhow::anyhow!("failed to encrypt the ticket"))?;
+        file.nonce = hex::encode(nonce);
+        file.ciphertext = hex::encode(ciphertext);
+        Ok(file)
+    }
+
+    fn open(&self, passphrase: &str) -> anyhow::Result<String> {
+        anyhow::ensure!(
+            self.version == FORMAT_VERSION,
+            "unsupported ticket file version {}",
+            self.version
+        );
+        let nonce = hex::decode(&self.nonce).context("invalid nonce")?;
+        anyhow::ensure!(nonce.len() == 24, "invalid nonce");
+        let ciphertext = hex::decode(&self.ciphertext).context("invalid ciphertext")?;
+        let cipher = XSalsa20Poly1305::new(&self.key(passphrase)?);
+        // a wrong passphrase and a damaged file look the same
+        let ticket = cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| ErrorCode::WrongPassword)?;
+        String::from_utf8(ticket).context("invalid ticket")
+    }
+}
+
+/// `path` with the ticket file extension if it has none.
+fn with_extension(path: PathBuf) -> PathBuf {
+    match path.extension() {
+        Some(_) => path,
+        None => path.with_extension(EXTENSION),
+    }
+}
+
+async fn read_ticket_file(path: &Path, passphrase: String) -> anyhow::Result<String> {
+    let data = std::fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let file: TicketFile = serde_json::from_slice(&data).context("not a ticket file")?;
+    // scrypt takes a while on purpose
+    tokio::task::spawn_blocking(move || file.open(&passphrase)).await?
+}
+
+/// Let the user pick where to save the ticket file of `hash`.
+async fn pick_path(window: Window, hash: String) -> anyhow::Result<Option<PathBuf>> {
+    let path = tauri::async_runtime::spawn_blocking(move || {
+        FileDialogBuilder::new()
+            .set_parent(&window)
+            .add_filter("sendme", &[EXTENSION])
+            .set_file_name(&format!("{}.{}", hash, EXTENSION))
+            .save_file()
+    })
+    .await?;
+    Ok(path)
+}
+
+/// Encrypt `ticket` with `passphrase` into a ticket file, for handing it over
+/// through a shared drive or email. The user picks where in a save dialog.
+/// Returns where it was written, unset if the dialog was closed.
+#[tauri::command]
+pub async fn save_ticket_file(
+    ticket: String,
+    passphrase: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    window: Window,
+) -> Result<Option<String>, String> {
+    session.verify(&token)?;
+    let res = async {
+        let (parsed, _) = crate::password::parse_ticket(&ticket)?;
+        anyhow::ensure!(
+            passphrase.chars().count() >= MIN_PASSPHRASE_LEN,
+            "the passphrase needs at least {} characters",
+            MIN_PASSPHRASE_LEN
+        );
+        let name = parsed.hash().to_hex()[..8].to_string();
+        let Some(path) = pick_path(window, name).await? else {
+            return anyhow::Ok(None);
+        };
+        let ticket = ticket.trim().to_string();
+        let file =
+            tokio::task::spawn_blocking(move || TicketFile::seal(&ticket, &passphrase)).await??;
+        let path = with_extension(path);
+        // written next to the target, so a failed save leaves neither a
+        // partial file nor a stray temp file behind
+        let parent = path.parent().context("no parent")?;
+        let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+        tmp.write_all(&serde_json::to_vec_pretty(&file)?)?;
+        tmp.persist(&path)?;
+        log!("saved a ticket file to {}", path.display());
+        Ok(Some(path.display().to_string()))
+    }
+    .await;
+    res.map_err(|e| format!("{:#}", e))
+}
+
+/// Decrypt the ticket file at `path` with `passphrase` and download its
+/// ticket, like [`crate::receive::download`].
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn open_ticket_file(
+    path: PathBuf,
+    passphrase: String,
+    dest: Option<String>,
+    options: Option<DownloadOptions>,
+    token: String,
+    session: State<'_, SessionToken>,
+    limiter: State<'_, RateLimiter>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<DownloadStats, UserError> {
+    session
+        .verify(&token)
+        .map_err(|msg| UserError::new(ErrorCode::Unknown, msg, &i18n))?;
+    let ticket = read_ticket_file(&path, passphrase)
+        .await
+        .map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    crate::receive::download(ticket, dest, options, limiter, i18n, app).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seals_tickets() {
+        let mut file = TicketFile::seal("ticket", "correct horse").unwrap();
+        assert_eq!(file.open("correct horse").unwrap(), "ticket");
+        let err = file.open("battery staple").unwrap_err();
+        assert_eq!(crate::errors::classify(&err), ErrorCode::WrongPassword);
+        // a crafted file can't ask for more than the caps
+        file.log_n = MAX_LOG_N + 1;
+        assert!(file.open("correct horse").is_err());
+        file.log_n = LOG_N;
+        file.p = 0;
+        assert!(file.open("correct horse").is_err());
+    }
+}
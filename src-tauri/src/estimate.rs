@@ -0,0 +1,140 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use iroh_bytes::get::request::get_hash_seq_and_sizes;
+use iroh_net::ticket::BlobTicket;
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+
+use crate::{
+    activity::ActivityLog,
+    download::{self, WarmConnections, MAX_HASH_SEQ_SIZE},
+    errors::UserError,
+    i18n::I18n,
+    identity::Identity,
+    password,
+    settings::SettingsStore,
+    transfers::TransferManager,
+    upload,
+};
+
+/// How many recent transfers the throughput is taken from.
+const SAMPLES: usize = 20;
+
+/// Connecting and asking for the sizes take about this many round trips.
+const SETUP_ROUND_TRIPS: u32 = 3;
+
+/// How long a transfer is expected to take, for the preview dialog.
+#[derive(Debug, Clone, Serialize)]
+pub struct Estimate {
+    pub size: u64,
+    /// Round trip time to the sharing peer, only known for tickets.
+    pub rtt_ms: Option<u64>,
+    /// How the sharing peer is reached, `direct`, `relay` or `mixed`, only
+    /// known for tickets.
+    pub path: Option<String>,
+    /// How many recent transfers the range is based on.
+    pub samples: usize,
+    /// Expected duration in seconds, unset without recent transfers to go by.
+    pub min_secs: Option<u64>,
+    pub max_secs: Option<u64>,
+}
+
+/// Seconds for `size` bytes at the faster and the slower quartile of
+/// `throughputs`, in bytes per second.
+fn eta(size: u64, rtt: Duration, mut throughputs: Vec<u64>) -> Option<(u64, u64)> {
+    if throughputs.is_empty() {
+        return None;
+    }
+    throughputs.sort_unstable();
+    let slow = throughputs[throughputs.len() / 4];
+    let fast = throughputs[throughputs.len() * 3 / 4];
+    let setup = rtt * SETUP_ROUND_TRIPS;
+    let secs = |rate: u64| {
+        let transfer = Duration::from_secs_f64(size as f64 / rate.max(1) as f64);
+        (setup + transfer).as_secs_f64().ceil() as u64
+    };
+    Some((secs(fast), secs(slow)))
+}
+
+/// Size of the collection of `ticket`, the round trip time and how the
+/// provider is reached.
+async fn probe(
+    app: &AppHandle,
+    ticket: &BlobTicket,
+    password: Option<&str>,
+) -> anyhow::Result<(u64, Duration, String)> {
+    let settings = app.state::<SettingsStore>().get();
+    let secret_key = app.state::<Identity>().secret_key();
+    let (endpoint, connection) = download::connect(ticket, &settings, secret_key, password).await?;
+    let (_, sizes) = get_hash_seq_and_sizes(&connection, &ticket.hash(), MAX_HASH_SEQ_SIZE).await?;
+    let size = sizes.iter().skip(1).sum();
+    let rtt = connection.rtt();
+    let path = endpoint
+        .connection_info(ticket.node_addr().node_id)
+        .await?
+        .map(|info| info.conn_type.to_string())
+        .unwrap_or_default();
+    app.state::<WarmConnections>()
+        .keep(app, ticket, endpoint, connection, password.is_some());
+    Ok((size, rtt, path))
+}
+
+/// Estimate how long a transfer takes before starting it.
+///
+/// `target` is either a ticket, whose provider is asked for the size, or the
+/// id of a running share. The range comes from the throughput of recent
+/// transfers in the same direction. Protected tickets need their `password`.
+#[tauri::command]
+pub async fn estimate_transfer(
+    target: String,
+    password: Option<String>,
+    i18n: State<'_, I18n>,
+    activity: State<'_, Arc<ActivityLog>>,
+    transfers: State<'_, TransferManager>,
+    app: AppHandle,
+) -> Result<Estimate, UserError> {
+    let res = async {
+        let (size, rtt, path, sent) = match target.trim().parse::<u64>() {
+            Ok(id) => {
+                let info = transfers.status(id).context("no such share")?;
+                (upload::total_size(&info.paths)?, None, None, true)
+            }
+            Err(_) => {
+                let (ticket, hint) = password::parse_ticket(&target)?;
+                password::check_hint(&ticket, hint.as_deref(), password.as_deref()).await?;
+                let (size, rtt, path) = probe(&app, &ticket, password.as_deref()).await?;
+                (size, Some(rtt), Some(path), false)
+            }
+        };
+        let throughputs = activity.throughputs(sent, SAMPLES);
+        let samples = throughputs.len();
+        let range = eta(size, rtt.unwrap_or_default(), throughputs);
+        anyhow::Ok(Estimate {
+            size,
+            rtt_ms: rtt.map(|rtt| rtt.as_millis() as u64),
+            path,
+            samples,
+            min_secs: range.map(|(min, _)| min),
+            max_secs: range.map(|(_, max)| max),
+        })
+    }
+    .await;
+    res.map_err(|e| UserError::from_anyhow(&e, &i18n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_spans_the_middle_throughputs() {
+        assert_eq!(eta(100, Duration::ZERO, Vec::new()), None);
+        let mbit = 125_000;
+        let throughputs = vec![mbit, 4 * mbit, 2 * mbit, 8 * mbit];
+        // 2 and 8 Mbit/s are the quartiles
+        assert_eq!(eta(10 * mbit, Duration::ZERO, throughputs), Some((2, 5)));
+        let rtt = Duration::from_millis(500);
+        assert_eq!(eta(0, rtt, vec![mbit]), Some((2, 2)));
+    }
+}
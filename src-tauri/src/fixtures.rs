@@ -0,0 +1,274 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::auth::SessionToken;
+
+/// Upper bound for the number of files and directories in a fixture.
+const MAX_ENTRIES: u64 = 100_000;
+
+/// Names that are valid on all platforms but tend to break path handling.
+const UNICODE_NAMES: &[&str] = &[
+    "grüße",
+    "日本語",
+    "emoji-🎉",
+    // the same name, composed and decomposed
+    "caf\u{e9}",
+    "cafe\u{301}",
+    "right-to-left-\u{5e9}\u{5dc}\u{5d5}\u{5dd}",
+];
+
+/// Length of long names, just below the 255 bytes most file systems allow.
+const LONG_NAME_LEN: usize = 240;
+
+/// Which awkward names a fixture contains.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pathologies {
+    pub unicode: bool,
+    pub long_names: bool,
+    /// Names differing only in case, which collide on case-insensitive file systems.
+    pub case_collisions: bool,
+}
+
+/// What tree to generate. The same spec always gives the same tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FixtureSpec {
+    pub seed: u64,
+    /// Levels of directories below the root.
+    pub depth: u32,
+    /// Subdirectories per directory.
+    pub dirs: u32,
+    /// Files per directory.
+    pub files: u32,
+    /// Smallest file size in bytes.
+    pub min_size: u64,
+    /// Largest file size in bytes.
+    pub max_size: u64,
+    pub pathologies: Pathologies,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            depth: 2,
+            dirs: 2,
+            files: 3,
+            min_size: 0,
+            max_size: 64 * 1024,
+            pathologies: Pathologies::default(),
+        }
+    }
+}
+
+impl FixtureSpec {
+    /// Number of files and directories the spec asks for.
+    fn entries(&self) -> u64 {
+        let copies = if self.pathologies.case_collisions {
+            2
+        } else {
+            1
+        };
+        let per_dir = self.files as u64 * copies;
+        let mut dirs = 1u64;
+        let mut level = 1u64;
+        for _ in 0..self.depth {
+            level = level.saturating_mul(self.dirs as u64);
+            dirs = dirs.saturating_add(level);
+        }
+        dirs.saturating_mul(per_dir + 1)
+    }
+}
+
+/// A generated tree.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Fixture {
+    pub root: PathBuf,
+    pub files: u64,
+    pub bytes: u64,
+    /// Files the file system refused or merged with another, e.g. case
+    /// collisions on case-insensitive file systems.
+    pub skipped: Vec<String>,
+}
+
+/// splitmix64, so trees are the same on every platform and rand version.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, `n` must not be 0.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn fill(&mut self, data: &mut [u8]) {
+        for chunk in data.chunks_mut(8) {
+            let bytes = self.next().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+fn name(rng: &mut Rng, prefix: &str, i: u32, pathologies: &Pathologies) -> String {
+    let plain = format!("{}-{}", prefix, i);
+    let mut kinds = vec![0];
+    if pathologies.unicode {
+        kinds.push(1);
+    }
+    if pathologies.long_names {
+        kinds.push(2);
+    }
+    match kinds[rng.below(kinds.len() as u64) as usize] {
+        1 => {
+            let unicode = UNICODE_NAMES[rng.below(UNICODE_NAMES.len() as u64) as usize];
+            format!("{}-{}", unicode, i)
+        }
+        2 => {
+            let mut name = format!("{}-", plain);
+            while name.len() < LONG_NAME_LEN {
+                name.push(char::from(b'a' + rng.below(26) as u8));
+            }
+            name
+        }
+        _ => plain,
+    }
+}
+
+fn write_file(
+    path: &Path,
+    rng: &mut Rng,
+    spec: &FixtureSpec,
+    fixture: &mut Fixture,
+) -> anyhow::Result<()> {
+    let size = spec.min_size + rng.below(spec.max_size - spec.min_size + 1);
+    let mut data = vec![0u8; size as usize];
+    rng.fill(&mut data);
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path);
+    match file {
+        Ok(mut file) => {
+            file.write_all(&data)?;
+            fixture.files += 1;
+            fixture.bytes += size;
+        }
+        Err(_) => fixture.skipped.push(path.display().to_string()),
+    }
+    Ok(())
+}
+
+fn write_dir(
+    dir: &Path,
+    level: u32,
+    rng: &mut Rng,
+    spec: &FixtureSpec,
+    fixture: &mut Fixture,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for i in 0..spec.files {
+        let name = format!("{}.bin", name(rng, "file", i, &spec.pathologies));
+        write_file(&dir.join(&name), rng, spec, fixture)?;
+        if spec.pathologies.case_collisions {
+            write_file(&dir.join(name.to_uppercase()), rng, spec, fixture)?;
+        }
+    }
+    if level < spec.depth {
+        for i in 0..spec.dirs {
+            let sub = dir.join(name(rng, "dir", i, &spec.pathologies));
+            write_dir(&sub, level + 1, rng, spec, fixture)?;
+        }
+    }
+    Ok(())
+}
+
+/// Generate the tree described by `spec` in a new directory `root`.
+pub fn generate(root: &Path, spec: &FixtureSpec) -> anyhow::Result<Fixture> {
+    anyhow::ensure!(spec.min_size <= spec.max_size, "min_size exceeds max_size");
+    anyhow::ensure!(
+        spec.entries() <= MAX_ENTRIES,
+        "more than {} entries",
+        MAX_ENTRIES
+    );
+    anyhow::ensure!(!root.exists(), "{} already exists", root.display());
+    let mut fixture = Fixture {
+        root: root.to_path_buf(),
+        ..Default::default()
+    };
+    let mut rng = Rng(spec.seed);
+    write_dir(root, 0, &mut rng, spec, &mut fixture)?;
+    Ok(fixture)
+}
+
+/// Developer command: generate a test tree in `dest`.
+#[tauri::command]
+pub async fn generate_fixtures(
+    dest: String,
+    spec: Option<FixtureSpec>,
+    token: String,
+    session: State<'_, SessionToken>,
+) -> Result<Fixture, String> {
+    session.verify(&token)?;
+    let spec = spec.unwrap_or_default();
+    tokio::task::spawn_blocking(move || generate(Path::new(&dest), &spec))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use walkdir::WalkDir;
+
+    use super::*;
+
+    fn read_tree(root: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files = WalkDir::new(root)
+            .into_iter()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| {
+                let name = entry.path().strip_prefix(root).unwrap().to_path_buf();
+                (name, std::fs::read(entry.path()).unwrap())
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn same_seed_same_tree() {
+        let tmp = tempfile::tempdir().unwrap();
+        let scratch = tmp.path();
+        let spec = FixtureSpec {
+            seed: 42,
+            pathologies: Pathologies {
+                unicode: true,
+                long_names: true,
+                case_collisions: false,
+            },
+            ..Default::default()
+        };
+        let a = generate(&scratch.join("a"), &spec).unwrap();
+        let b = generate(&scratch.join("b"), &spec).unwrap();
+        assert_eq!(a.files, 21);
+        assert_eq!(a.bytes, b.bytes);
+        assert_eq!(read_tree(&a.root), read_tree(&b.root));
+        let other = FixtureSpec { seed: 43, ..spec };
+        let c = generate(&scratch.join("c"), &other).unwrap();
+        assert_ne!(read_tree(&a.root), read_tree(&c.root));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{path::PathBuf, sync::Mutex};
+
+use anyhow::Context;
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+
+use crate::{
+    i18n::I18n,
+    store::{ShareStore, StoreKind},
+};
+
+/// What is verified on startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    /// The settings file parses.
+    Settings,
+    /// The persisted node identity could be loaded.
+    Identity,
+    /// An on disk store can be opened.
+    Store,
+    /// The app data dir is writable.
+    DataDir,
+    /// The administrator's policy parses, if there is one.
+    Policy,
+}
+
+impl Check {
+    fn key(self) -> &'static str {
+        match self {
+            Check::Settings => "health.settings",
+            Check::Identity => "health.identity",
+            Check::Store => "health.store",
+            Check::DataDir => "health.data_dir",
+            Check::Policy => "health.policy",
+        }
+    }
+}
+
+/// The outcome of a single check, `error` is unset if it passed.
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub check: Check,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    /// Whether any check failed. The app still runs, but e.g. with defaults.
+    pub degraded: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+/// Results of the startup checks, kept in the tauri state.
+#[derive(Debug, Default)]
+pub struct Health(Mutex<Vec<CheckResult>>);
+
+impl Health {
+    pub fn record(&self, check: Check, res: anyhow::Result<()>) {
+        let error = res.err().map(|err| format!("{:#}", err));
+        if let Some(error) = &error {
+            log!("startup check {:?} failed: {}", check, error);
+        }
+        let mut checks = self.0.lock().unwrap();
+        checks.retain(|c| c.check != check);
+        checks.push(CheckResult { check, error });
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let checks = self.0.lock().unwrap().clone();
+        HealthReport {
+            degraded: checks.iter().any(|c| c.error.is_some()),
+            checks,
+        }
+    }
+}
+
+fn check_data_dir(dir: &std::path::Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let probe = dir.join(".write-test");
+    std::fs::write(&probe, b"ok").with_context(|| format!("{} is not writable", dir.display()))?;
+    std::fs::remove_file(&probe)?;
+    Ok(())
+}
+
+async fn check_store(dir: PathBuf) -> anyhow::Result<()> {
+    let res = ShareStore::open(StoreKind::Flat, 0, dir.clone())
+        .await
+        .map(drop);
+    std::fs::remove_dir_all(&dir).ok();
+    res
+}
+
+/// Show failed checks in the tray tooltip.
+pub fn update_tooltip(app: &AppHandle) {
+    let report = app.state::<Health>().report();
+    let i18n = app.state::<I18n>();
+    let mut tooltip = "SendMe".to_string();
+    for check in report.checks.iter().filter(|c| c.error.is_some()) {
+        let error = check.error.as_deref().unwrap_or_default();
+        tooltip.push('\n');
+        tooltip.push_str(&i18n.translate(check.check.key(), &[("error", error)]));
+    }
+    if let Err(err) = app.tray_handle().set_tooltip(&tooltip) {
+        log!("failed to update tray tooltip: {}", err);
+    }
+}
+
+/// Run the checks that touch the disk in the background, then update the tray.
+pub fn spawn_checks(app: AppHandle, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        let health = app.state::<Health>();
+        health.record(Check::DataDir, check_data_dir(&data_dir));
+        let res = match app.path_resolver().app_cache_dir() {
+            Some(dir) => check_store(dir.join("health-check")).await,
+            None => Err(anyhow::anyhow!("no app cache dir")),
+        };
+        health.record(Check::Store, res);
+        update_tooltip(&app);
+    });
+}
+
+#[tauri::command]
+pub fn health(health: State<'_, Health>) -> HealthReport {
+    health.report()
+}
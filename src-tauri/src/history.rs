@@ -0,0 +1,204 @@
+use std::{
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::watch;
+
+use crate::{
+    auth::SessionToken,
+    errors::{ErrorCode, UserError},
+    i18n::I18n,
+    ratelimit::RateLimiter,
+    serve::Downloads,
+    upload::ShareOptions,
+};
+
+/// How many entries are kept, older ones are dropped.
+const MAX_ENTRIES: usize = 1000;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Shared,
+    Received,
+}
+
+/// A past share or download.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub direction: Direction,
+    pub name: String,
+    /// The shared paths, or where received files were saved.
+    pub paths: Vec<PathBuf>,
+    pub hash: String,
+    pub ticket: String,
+    pub size: u64,
+    /// Unix time the share was started or the download finished.
+    pub time: u64,
+    /// Unix time of the last complete download of a share.
+    pub last_transfer: Option<u64>,
+    /// Complete downloads of a share, 1 for received entries.
+    pub transfers: u64,
+    /// Whether downscaled previews were shared instead of the originals.
+    #[serde(default)]
+    pub preview: bool,
+}
+
+/// Shares and downloads, newest last, persisted as json in the app data dir.
+///
+/// Unlike the activity log it keeps tickets and paths, so a share can be
+/// started again later.
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    entries: Mutex<Vec<HistoryEntry>>,
+}
+
+impl History {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn save(&self, entries: &[HistoryEntry]) {
+        let res = (|| -> anyhow::Result<()> {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let tmp = self.path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(entries)?)?;
+            std::fs::rename(&tmp, &self.path)?;
+            Ok(())
+        })();
+        if let Err(err) = res {
+            log!("failed to save history: {:#}", err);
+        }
+    }
+
+    /// Add an entry, filling in its id and time. Returns the id.
+    pub fn add(&self, mut entry: HistoryEntry) -> u64 {
+        let mut entries = self.entries.lock().unwrap();
+        entry.id = entries.last().map_or(1, |e| e.id + 1);
+        entry.time = now();
+        let id = entry.id;
+        entries.push(entry);
+        let excess = entries.len().saturating_sub(MAX_ENTRIES);
+        entries.drain(..excess);
+        self.save(&entries);
+        id
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut HistoryEntry)) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+            f(entry);
+            self.save(&entries);
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<HistoryEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().find(|e| e.id == id).cloned()
+    }
+
+    /// All entries, newest first.
+    pub fn list(&self) -> Vec<HistoryEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().rev().cloned().collect()
+    }
+
+    pub fn delete(&self, id: u64) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let len = entries.len();
+        entries.retain(|e| e.id != id);
+        let deleted = entries.len() != len;
+        if deleted {
+            self.save(&entries);
+        }
+        deleted
+    }
+}
+
+/// Count the downloads of share `id` in its history entry.
+pub fn watch_share(app: AppHandle, id: u64, mut downloads: watch::Receiver<Downloads>) {
+    tauri::async_runtime::spawn(async move {
+        while downloads.changed().await.is_ok() {
+            let count = downloads.borrow_and_update().count;
+            app.state::<History>().update(id, |entry| {
+                entry.transfers = count;
+                entry.last_transfer = Some(now());
+            });
+        }
+    });
+}
+
+#[tauri::command]
+pub fn history_list(history: State<'_, History>) -> Vec<HistoryEntry> {
+    history.list()
+}
+
+#[tauri::command]
+pub fn history_delete(
+    id: u64,
+    token: String,
+    session: State<'_, SessionToken>,
+    history: State<'_, History>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    if history.delete(id) {
+        Ok(())
+    } else {
+        Err(format!("no history entry {}", id))
+    }
+}
+
+/// Share the paths of a history entry again, returning the new ticket.
+///
+/// The files are imported again, so the ticket is only the same as before if
+/// they did not change.
+#[tauri::command]
+pub async fn reshare(
+    history_id: u64,
+    limiter: State<'_, RateLimiter>,
+    i18n: State<'_, I18n>,
+    history: State<'_, History>,
+    app: AppHandle,
+) -> Result<String, UserError> {
+    limiter
+        .check("upload", &i18n)
+        .map_err(|msg| UserError::new(ErrorCode::RateLimited, msg, &i18n))?;
+    let res = async {
+        let entry = history
+            .get(history_id)
+            .ok_or_else(|| anyhow::anyhow!("no history entry {}", history_id))?;
+        if let Some(missing) = entry.paths.iter().find(|p| !p.exists()) {
+            anyhow::bail!("{} no longer exists", missing.display());
+        }
+        let opts = ShareOptions {
+            preview: entry.preview,
+            ..Default::default()
+        };
+        crate::share(&app, entry.paths, opts).await
+    }
+    .await;
+    let ticket = res.map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    Ok(ticket.to_string())
+}
@@ -0,0 +1,135 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+    sync::RwLock,
+};
+
+use tauri::State;
+
+use crate::{auth::SessionToken, settings::SettingsStore};
+
+/// Catalogs shipped with the app, as (locale, json) pairs.
+const BUNDLED: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.json")),
+    ("de", include_str!("../locales/de.json")),
+];
+
+const FALLBACK_LOCALE: &str = "en";
+
+type Catalog = HashMap<String, String>;
+
+/// Translation catalogs for strings produced on the rust side.
+///
+/// User provided catalogs in `<config dir>/locales/<locale>.json` override
+/// bundled entries key by key.
+#[derive(Debug)]
+pub struct I18n {
+    locale: RwLock<String>,
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl I18n {
+    pub fn load(user_dir: &Path, locale: Option<String>) -> Self {
+        let mut catalogs = HashMap::<String, Catalog>::new();
+        for (locale, data) in BUNDLED {
+            let catalog = serde_json::from_str(data).expect("invalid bundled catalog");
+            catalogs.insert(locale.to_string(), catalog);
+        }
+        if let Ok(entries) = std::fs::read_dir(user_dir) {
+            for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+                if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                let Some(locale) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                let catalog: Catalog = match std::fs::read(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|data| Ok(serde_json::from_slice(&data)?))
+                {
+                    Ok(catalog) => catalog,
+                    Err(err) => {
+                        log!("ignoring catalog {}: {}", path.display(), err);
+                        continue;
+                    }
+                };
+                catalogs
+                    .entry(locale.to_string())
+                    .or_default()
+                    .extend(catalog);
+            }
+        }
+        let locale = locale
+            .or_else(tauri::api::os::locale)
+            .unwrap_or_else(|| FALLBACK_LOCALE.to_string());
+        Self {
+            locale: RwLock::new(locale),
+            catalogs,
+        }
+    }
+
+    pub fn set_locale(&self, locale: String) {
+        *self.locale.write().unwrap() = locale;
+    }
+
+    pub fn locales(&self) -> BTreeSet<String> {
+        self.catalogs.keys().cloned().collect()
+    }
+
+    fn lookup(&self, key: &str) -> Option<&str> {
+        let locale = self.locale.read().unwrap();
+        // try `de-AT`, then `de`, then the fallback
+        let language = locale.split(['-', '_']).next().unwrap_or_default();
+        let text = [locale.as_str(), language, FALLBACK_LOCALE]
+            .into_iter()
+            .find_map(|l| self.catalogs.get(l)?.get(key));
+        text.map(|s| s.as_str())
+    }
+
+    /// Translate `key`, replacing `{name}` placeholders with `args`.
+    ///
+    /// Unknown keys are returned as is, so a missing translation is visible
+    /// but never fatal.
+    pub fn translate(&self, key: &str, args: &[(&str, &str)]) -> String {
+        let mut text = self.lookup(key).unwrap_or(key).to_string();
+        for (name, value) in args {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        text
+    }
+}
+
+#[tauri::command]
+pub fn translate(
+    key: String,
+    args: Option<HashMap<String, String>>,
+    i18n: State<'_, I18n>,
+) -> String {
+    let args = args.unwrap_or_default();
+    let args = args
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect::<Vec<_>>();
+    i18n.translate(&key, &args)
+}
+
+#[tauri::command]
+pub fn available_locales(i18n: State<'_, I18n>) -> Vec<String> {
+    i18n.locales().into_iter().collect()
+}
+
+#[tauri::command]
+pub fn set_locale(
+    locale: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    i18n: State<'_, I18n>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| s.locale = Some(locale.clone()))
+        .map_err(|e| e.to_string())?;
+    i18n.set_locale(locale);
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use iroh_net::key::SecretKey;
+use tauri::State;
+
+use crate::auth::SessionToken;
+
+/// The node secret key, persisted in the app data dir so the node id stays
+/// the same across runs.
+#[derive(Debug)]
+pub struct Identity {
+    path: PathBuf,
+    key: Mutex<SecretKey>,
+}
+
+/// Write `key` to `path`, readable by the current user only.
+fn write_key(path: &Path, key: &SecretKey) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("bin.tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    file.write_all(&key.to_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn read_key(path: &Path) -> anyhow::Result<SecretKey> {
+    let data = std::fs::read(path)?;
+    SecretKey::try_from(data.as_slice()).context("invalid key file")
+}
+
+impl Identity {
+    /// Load the key from `path`, or generate and store a new one.
+    ///
+    /// `IROH_SECRET` takes precedence and is never written to disk.
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let key = match std::env::var("IROH_SECRET") {
+            Ok(secret) => SecretKey::from_str(&secret).context("invalid secret")?,
+            Err(_) if path.exists() => read_key(&path)?,
+            Err(_) => {
+                let key = SecretKey::generate();
+                write_key(&path, &key)?;
+                log!("generated node id {}", key.public());
+                key
+            }
+        };
+        Ok(Self {
+            path,
+            key: Mutex::new(key),
+        })
+    }
+
+    /// A fresh key that is not written to `path`, for when loading failed.
+    pub fn ephemeral(path: PathBuf) -> Self {
+        Self {
+            path,
+            key: Mutex::new(SecretKey::generate()),
+        }
+    }
+
+    pub fn secret_key(&self) -> SecretKey {
+        self.key.lock().unwrap().clone()
+    }
+
+    /// Replace the key with a fresh one. Running shares keep their old node id.
+    pub fn regenerate(&self) -> anyhow::Result<SecretKey> {
+        let key = SecretKey::generate();
+        write_key(&self.path, &key)?;
+        *self.key.lock().unwrap() = key.clone();
+        Ok(key)
+    }
+}
+
+#[tauri::command]
+pub fn node_id(identity: State<'_, Identity>) -> String {
+    identity.secret_key().public().to_string()
+}
+
+/// Switch to a new node id, returning it.
+#[tauri::command]
+pub fn regenerate_identity(
+    token: String,
+    session: State<'_, SessionToken>,
+    identity: State<'_, Identity>,
+) -> Result<String, String> {
+    session.verify(&token)?;
+    let key = identity.regenerate().map_err(|e| e.to_string())?;
+    log!("switched to node id {}", key.public());
+    Ok(key.public().to_string())
+}
@@ -0,0 +1,367 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use iroh_bytes::BlobFormat;
+use iroh_net::{key::SecretKey, ticket::BlobTicket};
+use rand::Rng;
+use serde::Serialize;
+use tauri::State;
+use walkdir::WalkDir;
+
+use crate::{
+    activity::ActivityLog,
+    audit::AuditLog,
+    auth::SessionToken,
+    discovery::DnsRecords,
+    fixtures::{FixtureSpec, Pathologies},
+    keepalive::KeepAlive,
+    password,
+    pause::PauseState,
+    progress::Progress,
+    publish::PublishStore,
+    reputation::Reputation,
+    revoke::Revocations,
+    sched::Scheduler,
+    store::StoreKind,
+    upload::{provide, ShareEnv, ShareOptions},
+    webdav::WebDavShares,
+};
+
+/// The sendme CLI release the app is tested against.
+pub const PINNED_CLI_VERSION: &str = "0.5.0";
+
+/// How long a single case may take.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InteropResult {
+    pub case: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl InteropResult {
+    fn new(case: &'static str, res: anyhow::Result<String>) -> Self {
+        match res {
+            Ok(detail) => Self {
+                case,
+                ok: true,
+                detail,
+            },
+            Err(err) => Self {
+                case,
+                ok: false,
+                detail: format!("{:#}", err),
+            },
+        }
+    }
+}
+
+/// The version from the output of `sendme --version`.
+fn parse_version(output: &str) -> Option<&str> {
+    output.split_whitespace().nth(1)
+}
+
+/// The first ticket printed by the CLI, if any.
+fn find_ticket(line: &str) -> Option<BlobTicket> {
+    line.split_whitespace()
+        .find_map(|word| password::parse_ticket(word).ok())
+        .map(|(ticket, _)| ticket)
+}
+
+/// A fresh directory in the temp dir.
+pub fn scratch_dir() -> anyhow::Result<PathBuf> {
+    let suffix = rand::thread_rng().gen::<[u8; 8]>();
+    let dir = std::env::temp_dir().join(format!("sendme-interop-{}", hex::encode(suffix)));
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Create a directory with a small, a nested and an empty file.
+fn write_fixture(dir: &Path) -> anyhow::Result<PathBuf> {
+    let root = dir.join("fixture");
+    std::fs::create_dir_all(root.join("nested"))?;
+    std::fs::write(root.join("hello.txt"), b"hello sendme\n")?;
+    std::fs::write(root.join("empty.txt"), b"")?;
+    let mut data = vec![0u8; 1024 * 1024 + 7];
+    rand::thread_rng().fill(&mut data[..]);
+    std::fs::write(root.join("nested").join("data.bin"), data)?;
+    Ok(root)
+}
+
+/// The files below `root`, by path relative to `root`.
+fn read_tree(root: &Path) -> anyhow::Result<BTreeMap<PathBuf, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    for entry in WalkDir::new(root) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let name = entry.path().strip_prefix(root)?.to_path_buf();
+            files.insert(name, std::fs::read(entry.path())?);
+        }
+    }
+    Ok(files)
+}
+
+fn compare_trees(expected: &Path, actual: &Path) -> anyhow::Result<()> {
+    let expected = read_tree(expected)?;
+    let actual = read_tree(actual)?;
+    for (name, data) in &expected {
+        match actual.get(name) {
+            Some(other) if other == data => {}
+            Some(_) => anyhow::bail!("{} differs", name.display()),
+            None => anyhow::bail!("{} is missing", name.display()),
+        }
+    }
+    if let Some(name) = actual.keys().find(|name| !expected.contains_key(*name)) {
+        anyhow::bail!("unexpected file {}", name.display());
+    }
+    Ok(())
+}
+
+fn drain(mut reader: impl Read + Send + 'static) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).ok();
+        buf
+    })
+}
+
+fn run_with_timeout(cmd: &mut Command) -> anyhow::Result<Output> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    // read the output while waiting, so the child never blocks on a full pipe
+    let stdout = drain(child.stdout.take().context("no stdout")?);
+    let stderr = drain(child.stderr.take().context("no stderr")?);
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            child.kill().ok();
+            anyhow::bail!("timed out after {:?}", TIMEOUT);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// A share environment separate from the app, so test transfers are not
+/// paused, throttled or recorded.
+pub fn env(scratch: &Path) -> ShareEnv {
+    let audit = Arc::new(AuditLog::open(scratch.join("audit.jsonl")));
+    ShareEnv {
+        secret_key: SecretKey::generate(),
+        pause: PauseState::default(),
+        scheduler: Arc::new(Scheduler::default()),
+        activity: Arc::new(ActivityLog::open(scratch.join("activity.jsonl"))),
+        keep_alive: KeepAlive::default(),
+        dns_discovery: None,
+        dns_records: Arc::new(DnsRecords::default()),
+        store: StoreKind::default(),
+        scratch_dir: scratch.join("shares"),
+        webdav: Arc::new(WebDavShares::default()),
+        progress: Progress::ignore(),
+        network: Default::default(),
+        hash_format: Default::default(),
+        cache: Default::default(),
+        revocations: Arc::new(Revocations::load(
+            scratch.join("revoked.json"),
+            audit.clone(),
+        )),
+        reputation: Arc::new(Reputation::load(scratch.join("reputation.json"))),
+        audit,
+        publish: Arc::new(PublishStore::load(scratch.join("publish.json"))),
+    }
+}
+
+fn check_version(cli: &Path) -> anyhow::Result<String> {
+    let output = run_with_timeout(Command::new(cli).arg("--version"))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = parse_version(&stdout).context("unexpected --version output")?;
+    anyhow::ensure!(
+        version == PINNED_CLI_VERSION,
+        "cli version {} is not the pinned {}",
+        version,
+        PINNED_CLI_VERSION
+    );
+    Ok(version.to_string())
+}
+
+/// Share `path` from the app and download it with the CLI.
+async fn app_to_cli(cli: &Path, scratch: &Path, path: &Path) -> anyhow::Result<String> {
+    let (downloads, _) = tokio::sync::watch::channel(Default::default());
+    let (ticket, handle) = provide(
+        vec![path.to_path_buf()],
+        ShareOptions::default(),
+        env(scratch),
+        Arc::new(downloads),
+    )
+    .await?;
+    let out = scratch.join(format!("out-{}", ticket.hash().to_hex()));
+    std::fs::create_dir_all(&out)?;
+    let output = {
+        let cli = cli.to_path_buf();
+        let out = out.clone();
+        let ticket = ticket.to_string();
+        tokio::task::spawn_blocking(move || {
+            run_with_timeout(Command::new(cli).arg("get").arg(ticket).current_dir(out))
+        })
+        .await?
+    };
+    handle.stop().await;
+    let output = output?;
+    anyhow::ensure!(
+        output.status.success(),
+        "cli failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let name = path.file_name().context("no file name")?;
+    if path.is_dir() {
+        compare_trees(path, &out.join(name))?;
+    } else {
+        anyhow::ensure!(
+            std::fs::read(path)? == std::fs::read(out.join(name))?,
+            "downloaded file differs"
+        );
+    }
+    Ok(format!("{} transferred", ticket.hash().to_hex()))
+}
+
+/// Share `path` with the CLI and check the app understands its ticket.
+fn cli_to_app(cli: &Path, path: &Path) -> anyhow::Result<String> {
+    let mut child = Command::new(cli)
+        .arg("provide")
+        .arg(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdout = child.stdout.take().context("no stdout")?;
+    // the cli keeps serving, so kill it once the ticket is known
+    let ticket = BufReader::new(stdout)
+        .lines()
+        .map_while(Result::ok)
+        .find_map(|line| find_ticket(&line));
+    child.kill().ok();
+    child.wait().ok();
+    let ticket = ticket.context("the cli printed no ticket the app can parse")?;
+    anyhow::ensure!(
+        ticket.format() == BlobFormat::HashSeq,
+        "expected a collection ticket, got {:?}",
+        ticket.format()
+    );
+    // TODO: download the ticket once the app can receive
+    Ok(format!("ticket for {} parsed", ticket.hash().to_hex()))
+}
+
+/// Run all interop cases against the CLI at `cli`.
+pub async fn run(cli: &Path) -> anyhow::Result<Vec<InteropResult>> {
+    let scratch = scratch_dir()?;
+    let fixture = write_fixture(&scratch)?;
+    // a single file shared on its own, not as part of the fixture
+    let single = scratch.join("single");
+    std::fs::create_dir_all(&single)?;
+    let file = single.join("hello.txt");
+    std::fs::copy(fixture.join("hello.txt"), &file)?;
+    let mut results = vec![InteropResult::new("cli version", check_version(cli))];
+    results.push(InteropResult::new(
+        "app to cli, single file",
+        app_to_cli(cli, &scratch, &file).await,
+    ));
+    results.push(InteropResult::new(
+        "app to cli, directory",
+        app_to_cli(cli, &scratch, &fixture).await,
+    ));
+    let spec = FixtureSpec {
+        pathologies: Pathologies {
+            unicode: true,
+            long_names: true,
+            // the cli may run on a case-insensitive file system
+            case_collisions: false,
+        },
+        ..Default::default()
+    };
+    let generated = crate::fixtures::generate(&scratch.join("generated"), &spec)?;
+    results.push(InteropResult::new(
+        "app to cli, unusual names",
+        app_to_cli(cli, &scratch, &generated.root).await,
+    ));
+    let res = {
+        let cli = cli.to_path_buf();
+        let fixture = fixture.clone();
+        tokio::task::spawn_blocking(move || cli_to_app(&cli, &fixture)).await?
+    };
+    results.push(InteropResult::new("cli to app, ticket", res));
+    std::fs::remove_dir_all(&scratch).ok();
+    Ok(results)
+}
+
+/// Developer command: run the interop matrix against a sendme CLI binary.
+#[tauri::command]
+pub async fn run_interop_tests(
+    cli: String,
+    token: String,
+    session: State<'_, SessionToken>,
+) -> Result<Vec<InteropResult>, String> {
+    session.verify(&token)?;
+    run(Path::new(&cli)).await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::Hash;
+    use iroh_net::{key::SecretKey, NodeAddr};
+
+    use super::*;
+
+    #[test]
+    fn parses_cli_version() {
+        assert_eq!(parse_version("sendme 0.5.0\n"), Some("0.5.0"));
+        assert_eq!(parse_version(""), None);
+    }
+
+    #[test]
+    fn finds_ticket_in_cli_output() {
+        let addr = NodeAddr::new(SecretKey::generate().public())
+            .with_derp_url("https://derp.example.com".parse().unwrap());
+        let ticket = BlobTicket::new(addr, Hash::new(b"hello"), BlobFormat::HashSeq).unwrap();
+        let line = format!("sendme get {}", ticket);
+        assert_eq!(find_ticket(&line).unwrap().hash(), ticket.hash());
+        assert!(find_ticket("to get this data, use").is_none());
+    }
+
+    #[test]
+    fn compares_trees() {
+        let scratch = scratch_dir().unwrap();
+        let a = write_fixture(&scratch.join("a")).unwrap();
+        let b = scratch.join("b");
+        std::fs::create_dir_all(b.join("nested")).unwrap();
+        for name in ["hello.txt", "empty.txt", "nested/data.bin"] {
+            std::fs::copy(a.join(name), b.join(name)).unwrap();
+        }
+        compare_trees(&a, &b).unwrap();
+        std::fs::write(b.join("hello.txt"), b"changed").unwrap();
+        assert!(compare_trees(&a, &b).is_err());
+        std::fs::remove_dir_all(scratch).ok();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs the pinned sendme cli, set SENDME_CLI to its path"]
+    async fn interop_matrix() {
+        let cli = std::env::var("SENDME_CLI").expect("SENDME_CLI not set");
+        let results = run(Path::new(&cli)).await.unwrap();
+        for result in &results {
+            assert!(result.ok, "{}: {}", result.case, result.detail);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use iroh_net::MagicEndpoint;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::{auth::SessionToken, settings::SettingsStore};
+
+/// How shares keep their connections and relay mapping alive while idle.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeepAlive {
+    /// Seconds between keep-alive packets on open connections, 0 to disable.
+    pub interval: u64,
+    /// Seconds between health checks of a share's relay connection, 0 to disable.
+    pub health_check: u64,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            interval: 15,
+            health_check: 60,
+        }
+    }
+}
+
+impl KeepAlive {
+    pub fn transport_config(&self) -> quinn::TransportConfig {
+        let mut config = quinn::TransportConfig::default();
+        if self.interval > 0 {
+            config.keep_alive_interval(Some(Duration::from_secs(self.interval)));
+        }
+        config
+    }
+
+    pub fn health_check_interval(&self) -> Option<Duration> {
+        (self.health_check > 0).then(|| Duration::from_secs(self.health_check))
+    }
+}
+
+/// Check that the endpoint is still reachable, and re-establish its relay
+/// connection and public addresses if it is not.
+pub async fn probe(endpoint: &MagicEndpoint) {
+    if endpoint.my_derp().is_none() {
+        log!("lost the relay connection, probing the network again");
+        endpoint.network_change().await;
+    }
+}
+
+#[tauri::command]
+pub fn get_keep_alive(settings: State<'_, SettingsStore>) -> KeepAlive {
+    settings.get().keep_alive
+}
+
+/// Change the keep-alive behaviour, effective for shares created afterwards.
+#[tauri::command]
+pub fn set_keep_alive(
+    keep_alive: KeepAlive,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| s.keep_alive = keep_alive)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
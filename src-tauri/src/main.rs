@@ -1,81 +1,559 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+#[macro_use]
+mod crash;
+
+mod activity;
+mod audit;
+mod auth;
+mod bandwidth;
+mod bridge;
+mod bundle;
+mod cache;
+mod capture;
+mod clipboard;
+mod cloud;
+mod contacts;
+mod deeplink;
+mod demo;
+mod discovery;
+mod download;
+mod dropfolder;
+mod errors;
+mod escrow;
+mod estimate;
+mod fixtures;
+mod health;
+mod history;
+mod i18n;
+mod identity;
+mod interop;
+mod keepalive;
+mod maintenance;
+mod media;
+mod message;
+mod mount;
+mod network;
+mod notify;
+mod organize;
+mod pack;
+mod password;
+mod pause;
+mod picker;
+mod policy;
+mod progress;
+mod publish;
+mod qr;
+mod quiet;
+mod ratelimit;
+mod receive;
+mod recurring;
+mod reputation;
+mod revoke;
+mod sched;
+mod security;
+mod serve;
+mod settings;
+mod sms;
+mod soak;
+mod spool;
+mod store;
+mod telemetry;
+mod trace;
+mod transfers;
+mod tray;
+mod update;
 mod upload;
+mod users;
+mod version;
+mod webdav;
+
+/// Share `paths` in one ticket, recording it in the telemetry and the recent shares.
+async fn share(
+    app: &tauri::AppHandle,
+    paths: Vec<PathBuf>,
+    opts: upload::ShareOptions,
+) -> anyhow::Result<BlobTicket> {
+    app.state::<settings::SettingsStore>()
+        .policy()
+        .check_sharing()?;
+    for path in &paths {
+        log!("uploading {}", path.display());
+    }
+
+    let name = upload::display_name(&paths);
+    let transfer = trace::next_id();
+    let span = tracing::info_span!("share", transfer, name);
+    let originals = paths.clone();
+    let preview = opts.preview;
+    let expiry = opts.expiry;
+    let paths = if opts.preview {
+        let mut previews = Vec::with_capacity(paths.len());
+        for path in paths {
+            previews.push(media::prepare(app, path).await?);
+        }
+        previews
+    } else {
+        paths
+    };
+    let (downloads, downloaded) = tokio::sync::watch::channel(Default::default());
+    let settings = app.state::<settings::SettingsStore>().get();
+    let env = upload::ShareEnv {
+        secret_key: app.state::<identity::Identity>().secret_key(),
+        pause: app.state::<pause::PauseState>().inner().clone(),
+        scheduler: app.state::<Arc<sched::Scheduler>>().inner().clone(),
+        activity: app.state::<Arc<activity::ActivityLog>>().inner().clone(),
+        keep_alive: settings.keep_alive,
+        network: settings.network.clone(),
+        hash_format: settings.hash_format,
+        dns_discovery: settings.dns_discovery,
+        dns_records: app.state::<Arc<discovery::DnsRecords>>().inner().clone(),
+        store: opts.store.unwrap_or(settings.store),
+        scratch_dir: maintenance::scratch_root(app)?,
+        webdav: app.state::<Arc<webdav::WebDavShares>>().inner().clone(),
+        progress: progress::Progress::emitter(app.clone()),
+        revocations: app.state::<Arc<revoke::Revocations>>().inner().clone(),
+        reputation: app.state::<Arc<reputation::Reputation>>().inner().clone(),
+        cache: app.state::<Arc<cache::ChunkCache>>().inner().clone(),
+        audit: app.state::<Arc<audit::AuditLog>>().inner().clone(),
+        publish: app.state::<Arc<publish::PublishStore>>().inner().clone(),
+    };
+    let res = if demo::enabled() {
+        demo::share(&paths, env.progress)
+    } else {
+        upload::provide(paths.clone(), opts, env, Arc::new(downloads))
+            .instrument(span)
+            .await
+    };
+    app.state::<telemetry::Telemetry>()
+        .record_share(res.is_ok());
+    app.state::<Arc<activity::ActivityLog>>()
+        .record(activity::Activity::Shared {
+            name: name.clone(),
+            ok: res.is_ok(),
+        });
+    let (ticket, handle) = res?;
+    let history_id = app.state::<history::History>().add(history::HistoryEntry {
+        id: 0,
+        direction: history::Direction::Shared,
+        name: name.clone(),
+        paths: originals,
+        hash: ticket.hash().to_hex().to_string(),
+        ticket: ticket.to_string(),
+        size: upload::total_size(&paths).unwrap_or_default(),
+        time: 0,
+        last_transfer: None,
+        transfers: 0,
+        preview,
+    });
+    history::watch_share(app.clone(), history_id, downloaded.clone());
+    let health = handle.health();
+    let id = app.state::<transfers::TransferManager>().add(
+        name.clone(),
+        paths.clone(),
+        ticket.to_string(),
+        downloaded.clone(),
+        handle,
+        transfer,
+        expiry,
+    );
+    transfers::watch_health(app.clone(), id, name.clone(), health);
+    notify::watch_share(app.clone(), name.clone(), paths, downloaded.clone());
+    tray::watch_share(app.clone(), downloaded);
+    app.state::<tray::RecentShares>().push(name);
+    tray::rebuild(app);
 
+    Ok(ticket)
+}
+
+/// Share the given files and directories in a single ticket.
+///
+/// Paths that are not valid unicode are sent as a [`upload::PathPayload`].
+/// Tickets of password protected shares come with the password hint.
 #[tauri::command]
-async fn upload(file: String) -> Result<String, String> {
-    let path = PathBuf::from(file);
-    println!("uploading {}", path.display());
+async fn upload(
+    files: Vec<upload::PathPayload>,
+    options: Option<upload::ShareOptions>,
+    limiter: tauri::State<'_, ratelimit::RateLimiter>,
+    i18n: tauri::State<'_, i18n::I18n>,
+    app: tauri::AppHandle,
+) -> Result<String, errors::UserError> {
+    limiter
+        .check("upload", &i18n)
+        .map_err(|msg| errors::UserError::new(errors::ErrorCode::RateLimited, msg, &i18n))?;
+    let opts = options.unwrap_or_default();
+    let protected = opts.password.clone();
+    let ticket = async {
+        let paths = files
+            .into_iter()
+            .map(upload::PathPayload::into_path)
+            .collect::<anyhow::Result<_>>()?;
+        share(&app, paths, opts).await
+    }
+    .await
+    .map_err(|e| errors::UserError::from_anyhow(&e, &i18n))?;
 
-    let (ticket, handle) = upload::provide(path).await.map_err(|e| e.to_string())?;
-    // TODO: deal with handle
+    match protected {
+        Some(password) => password::protected_ticket(&ticket, &password)
+            .await
+            .map_err(|e| errors::UserError::from_anyhow(&e, &i18n)),
+        None => Ok(ticket.to_string()),
+    }
+}
 
-    Ok(ticket.to_string())
+/// Sent to the frontend as `share-created` after files were dropped onto a window.
+#[derive(Debug, Clone, serde::Serialize)]
+struct ShareCreated {
+    name: String,
+    ticket: String,
 }
 
-use std::path::PathBuf;
+/// Share files dropped onto a window, reporting the ticket with `share-created`
+/// or the failure with `share-failed`.
+fn share_dropped(app: tauri::AppHandle, paths: Vec<PathBuf>) {
+    tauri::async_runtime::spawn(async move {
+        let i18n = app.state::<i18n::I18n>();
+        let res = match app.state::<ratelimit::RateLimiter>().check("upload", &i18n) {
+            Ok(()) => share(&app, paths.clone(), Default::default())
+                .await
+                .map_err(|e| errors::UserError::from_anyhow(&e, &i18n)),
+            Err(msg) => Err(errors::UserError::new(
+                errors::ErrorCode::RateLimited,
+                msg,
+                &i18n,
+            )),
+        };
+        match res {
+            Ok(ticket) => {
+                let created = ShareCreated {
+                    name: upload::display_name(&paths),
+                    ticket: ticket.to_string(),
+                };
+                app.emit_all("share-created", created).ok();
+            }
+            Err(err) => {
+                log!("failed to share dropped files: {}", err.message);
+                app.emit_all("share-failed", err).ok();
+            }
+        }
+    });
+}
 
-use tauri::{CustomMenuItem, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem};
+use std::{path::PathBuf, sync::Arc};
 
-fn main() {
-    let quit = CustomMenuItem::new("quit".to_string(), "Quit");
-    let share = CustomMenuItem::new("share".to_string(), "Share with SendMe");
-    let tray_menu = SystemTrayMenu::new()
-        .add_item(share)
-        .add_native_item(SystemTrayMenuItem::Separator)
-        .add_item(quit);
+use anyhow::Context;
+use iroh_net::ticket::BlobTicket;
+use tauri::{FileDropEvent, Manager, SystemTray, SystemTrayEvent, WindowEvent};
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
 
-    let system_tray = SystemTray::new().with_menu(tray_menu);
+/// The config and data dirs of the app, or of the demo.
+fn app_dirs(config: &tauri::Config) -> anyhow::Result<(PathBuf, PathBuf)> {
+    if demo::enabled() {
+        let dir = demo::dir();
+        log!(
+            "demo mode, keeping settings and history in {}",
+            dir.display()
+        );
+        return Ok((dir.join("config"), dir.join("data")));
+    }
+    let config_dir = tauri::api::path::app_config_dir(config).context("no app config dir")?;
+    let data_dir = tauri::api::path::app_data_dir(config).context("no app data dir")?;
+    Ok((config_dir, data_dir))
+}
 
+fn main() {
+    let context = tauri::generate_context!();
+    let dirs = app_dirs(context.config());
+    if let Ok((_, data_dir)) = &dirs {
+        crash::install(data_dir.join("crashes"));
+    }
+    let traces = Arc::new(trace::Traces::default());
+    let subscriber = tracing_subscriber::registry().with(trace::TraceLayer::new(traces.clone()));
+    if let Err(err) = tracing::subscriber::set_global_default(subscriber) {
+        log!("failed to record traces: {}", err);
+    }
     tauri::Builder::default()
-        .system_tray(system_tray)
+        .manage(auth::SessionToken::generate())
+        .manage(ratelimit::RateLimiter::default())
+        .manage(tray::RecentShares::default())
+        .manage(transfers::TransferManager::default())
+        .manage(health::Health::default())
+        .manage(soak::Soak::default())
+        .manage(deeplink::PendingDeepLink::from_args())
+        .manage(traces)
+        .manage(pause::PauseState::default())
+        .manage(Arc::new(sched::Scheduler::default()))
+        .manage(bandwidth::DownloadCap::default())
+        .manage(download::ActiveDownloads::default())
+        .manage(download::WarmConnections::default())
+        .manage(Arc::new(cache::ChunkCache::default()))
+        .manage(Arc::new(discovery::DnsRecords::default()))
+        .manage(Arc::new(webdav::WebDavShares::default()))
+        .manage(mount::Mounts::default())
+        .setup(|app| {
+            let (config_dir, data_dir) = dirs?;
+            app.manage(crash::CrashDir(data_dir.join("crashes")));
+            app.manage(download::ReceivedDir(data_dir.join("received")));
+            app.manage(Arc::new(activity::ActivityLog::open(
+                data_dir.join("activity.jsonl"),
+            )));
+            let health = app.state::<health::Health>();
+            let identity_path = data_dir.join("keypair.bin");
+            let identity = match identity::Identity::load(identity_path.clone()) {
+                Ok(identity) => {
+                    health.record(health::Check::Identity, Ok(()));
+                    identity
+                }
+                Err(err) => {
+                    health.record(health::Check::Identity, Err(err));
+                    identity::Identity::ephemeral(identity_path)
+                }
+            };
+            app.manage(identity);
+            let audit = Arc::new(audit::AuditLog::open(data_dir.join("audit.jsonl")));
+            app.manage(Arc::new(revoke::Revocations::load(
+                data_dir.join("revoked.json"),
+                audit.clone(),
+            )));
+            app.manage(audit);
+            app.manage(Arc::new(reputation::Reputation::load(
+                data_dir.join("reputation.json"),
+            )));
+            app.manage(Arc::new(recurring::Recurring::load(
+                data_dir.join("recurring.json"),
+            )));
+            app.manage(Arc::new(publish::PublishStore::load(
+                data_dir.join("publish.json"),
+            )));
+            app.manage(history::History::load(data_dir.join("history.json")));
+            app.manage(telemetry::Telemetry::load(data_dir.join("telemetry.json")));
+            // stores of shares that were running when the app last quit
+            maintenance::clear_scratch(&app.handle());
+            let policy = match policy::path() {
+                Some(path) => policy::Policy::load(&path),
+                None => Ok(Default::default()),
+            };
+            let policy = match policy {
+                Ok(policy) => {
+                    health.record(health::Check::Policy, Ok(()));
+                    policy
+                }
+                Err(err) => {
+                    health.record(health::Check::Policy, Err(err));
+                    Default::default()
+                }
+            };
+            let settings = settings::SettingsStore::load(config_dir.join("settings.json"), policy);
+            let res = match settings.load_error() {
+                Some(err) => Err(anyhow::anyhow!("{}", err)),
+                None => Ok(()),
+            };
+            health.record(health::Check::Settings, res);
+            let i18n = i18n::I18n::load(&config_dir.join("locales"), settings.get().locale);
+            app.state::<Arc<sched::Scheduler>>()
+                .set_peer_cap(settings.get().peer_rate_limit);
+            app.state::<Arc<sched::Scheduler>>()
+                .set_upload_cap(settings.get().bandwidth.upload);
+            app.state::<bandwidth::DownloadCap>()
+                .0
+                .set_rate(settings.get().bandwidth.download);
+            app.state::<Arc<cache::ChunkCache>>()
+                .set_budget(settings.get().chunk_cache_size);
+            app.manage(settings);
+            app.manage(i18n);
+            tray::rebuild(&app.handle());
+            crash::prompt_pending(&app.handle());
+            health::update_tooltip(&app.handle());
+            health::spawn_checks(app.handle(), data_dir);
+            quiet::spawn_scheduler(app.handle());
+            security::spawn_monitor(app.handle());
+            if !demo::enabled() {
+                telemetry::spawn_reporter(app.handle());
+                update::spawn_startup_check(app.handle());
+                activity::spawn_weekly_report(app.handle());
+                spool::spawn_watcher(app.handle());
+                dropfolder::spawn_watcher(app.handle());
+                recurring::spawn_scheduler(app.handle());
+                publish::spawn_republish(app.handle());
+                publish::spawn_poller(app.handle());
+                maintenance::spawn_auto_cleanup(app.handle());
+                tauri::async_runtime::spawn_blocking(|| {
+                    if let Err(err) = deeplink::register() {
+                        log!(
+                            "failed to register {}:// links: {:#}",
+                            deeplink::SCHEME,
+                            err
+                        );
+                    }
+                });
+            }
+            webdav::spawn_server(app.handle());
+            bridge::spawn_server(app.handle());
+            Ok(())
+        })
+        .on_window_event(|event| {
+            if let WindowEvent::FileDrop(FileDropEvent::Dropped(paths)) = event.event() {
+                share_dropped(event.window().app_handle(), paths.clone());
+            }
+        })
+        .system_tray(SystemTray::new())
         .on_system_tray_event(|app, event| match event {
             SystemTrayEvent::LeftClick {
                 position: _,
                 size: _,
                 ..
             } => {
-                println!("system tray received a left click");
+                log!("system tray received a left click");
             }
             SystemTrayEvent::RightClick {
                 position: _,
                 size: _,
                 ..
             } => {
-                println!("system tray received a right click");
+                log!("system tray received a right click");
             }
             SystemTrayEvent::DoubleClick {
                 position: _,
                 size: _,
                 ..
             } => {
-                println!("system tray received a double click");
+                log!("system tray received a double click");
+            }
+            SystemTrayEvent::MenuItemClick { id, .. } => {
+                tray::handle_menu_click(app, &id);
             }
-            SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
-                "quit" => {
-                    std::process::exit(0);
-                }
-                "share" => {
-                    let local_window = tauri::WindowBuilder::new(
-                        app,
-                        "share",
-                        tauri::WindowUrl::App("index.html".into()),
-                    )
-                    .build()
-                    .expect("unable to create window");
-                }
-                _ => {}
-            },
             _ => {}
         })
-        .invoke_handler(tauri::generate_handler![upload])
-        .build(tauri::generate_context!())
+        .invoke_handler(tauri::generate_handler![
+            upload,
+            auth::session_token,
+            telemetry::telemetry_preview,
+            telemetry::set_telemetry_enabled,
+            crash::pending_crash_reports,
+            crash::submit_crash_report,
+            crash::dismiss_crash_report,
+            update::check_for_updates,
+            update::remind_me_later,
+            i18n::translate,
+            i18n::available_locales,
+            i18n::set_locale,
+            tray::get_tray_layout,
+            tray::set_tray_layout,
+            pause::pause_all,
+            pause::resume_all,
+            quiet::get_quiet_hours,
+            quiet::set_quiet_hours,
+            sched::set_peer_rate_limit,
+            bandwidth::set_bandwidth_limits,
+            bridge::get_bridge_settings,
+            bridge::set_bridge_settings,
+            bridge::bridge_pairing_token,
+            webdav::webdav_login,
+            mount::mount_ticket,
+            mount::unmount_ticket,
+            recurring::list_recurring_shares,
+            recurring::save_recurring_share,
+            recurring::delete_recurring_share,
+            publish::list_publications,
+            publish::publish,
+            publish::unpublish,
+            publish::list_subscriptions,
+            publish::subscribe,
+            publish::unsubscribe,
+            publish::update_subscription,
+            escrow::save_ticket_file,
+            escrow::open_ticket_file,
+            cache::set_chunk_cache_size,
+            cache::chunk_cache_stats,
+            settings::get_settings,
+            network::get_network_settings,
+            network::set_network_settings,
+            network::test_relay,
+            settings::set_settings,
+            bundle::open_bundle,
+            bundle::share_batch,
+            message::share_message,
+            notify::get_notification_settings,
+            notify::set_notification_settings,
+            activity::activity_report,
+            keepalive::get_keep_alive,
+            keepalive::set_keep_alive,
+            discovery::dns_records,
+            interop::run_interop_tests,
+            clipboard::share_clipboard,
+            clipboard::get_clipboard_settings,
+            clipboard::set_clipboard_settings,
+            capture::share_screen_recording,
+            media::get_preview_settings,
+            media::set_preview_settings,
+            spool::get_spool_folder,
+            spool::set_spool_folder,
+            dropfolder::get_drop_folder,
+            dropfolder::set_drop_folder,
+            cloud::save_to_cloud,
+            cloud::get_cloud_settings,
+            cloud::set_cloud_settings,
+            sms::split_ticket,
+            sms::join_ticket,
+            qr::ticket_qr,
+            qr::scan_qr_frames,
+            receive::download,
+            receive::previous_download,
+            receive::download_conflicts,
+            receive::export_from_store,
+            receive::get_export_template,
+            receive::set_export_template,
+            contacts::get_receive_rules,
+            contacts::set_receive_rule,
+            organize::get_organize_settings,
+            organize::set_organize_settings,
+            transfers::list_transfers,
+            transfers::transfer_status,
+            transfers::transfer_stats,
+            transfers::cancel_transfer,
+            transfers::pause_transfer,
+            transfers::resume_transfer,
+            transfers::create_sub_share,
+            identity::node_id,
+            identity::regenerate_identity,
+            revoke::revoke_ticket,
+            revoke::unrevoke_ticket,
+            revoke::list_revoked,
+            picker::pick_files,
+            picker::pick_folder,
+            policy::get_policy,
+            reputation::peer_reputation,
+            reputation::trust_suggestions,
+            reputation::trust_peer,
+            reputation::untrust_peer,
+            reputation::dismiss_trust_suggestion,
+            audit::audit_log,
+            clipboard::copy_ticket_to_clipboard,
+            clipboard::read_ticket_from_clipboard,
+            maintenance::stop_all_shares,
+            maintenance::clear_completed_downloads,
+            maintenance::cleanup_store,
+            health::health,
+            fixtures::generate_fixtures,
+            history::history_list,
+            history::history_delete,
+            history::reshare,
+            soak::start_soak,
+            soak::stop_soak,
+            trace::trace_transfer,
+            deeplink::handle_deep_link,
+            deeplink::take_deep_link,
+            estimate::estimate_transfer
+        ])
+        .build(context)
         .expect("error while running tauri application")
-        .run(|_app_handle, event| match event {
+        .run(|app_handle, event| match event {
             tauri::RunEvent::ExitRequested { api, .. } => {
                 api.prevent_exit();
             }
+            tauri::RunEvent::Exit => maintenance::clear_scratch(app_handle),
             _ => {}
         })
 }
@@ -0,0 +1,209 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+use walkdir::WalkDir;
+
+use crate::{
+    activity::ActivityLog, auth::SessionToken, settings::SettingsStore, transfers::TransferManager,
+};
+
+/// Directories below the app cache dir with copies of shared data.
+const CACHE_DIRS: &[&str] = &["clipboard", "previews", "recordings"];
+
+/// How old cached data has to be for the tray's clean up to remove it.
+const TRAY_CLEANUP_AGE: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+/// How often the automatic clean up runs.
+const AUTO_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// How long shares get to close their connections when quitting.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a clean up removed.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CleanupReport {
+    /// Number of removed files and directories.
+    pub removed: u64,
+    pub bytes: u64,
+}
+
+fn disk_usage(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Remove the entries of the cache dirs below `cache_dir` that were not
+/// modified within `older_than`, except those running shares still serve.
+fn cleanup(
+    cache_dir: &Path,
+    older_than: Duration,
+    in_use: &[PathBuf],
+) -> anyhow::Result<CleanupReport> {
+    let cutoff = SystemTime::now()
+        .checked_sub(older_than)
+        .context("invalid age")?;
+    let mut report = CleanupReport::default();
+    for dir in CACHE_DIRS {
+        let Ok(entries) = std::fs::read_dir(cache_dir.join(dir)) else {
+            continue;
+        };
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            if metadata.modified()? > cutoff || in_use.iter().any(|p| p.starts_with(&path)) {
+                continue;
+            }
+            let bytes = disk_usage(&path);
+            let res = if metadata.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            };
+            match res {
+                Ok(()) => {
+                    report.removed += 1;
+                    report.bytes += bytes;
+                }
+                Err(err) => log!("failed to remove {}: {}", path.display(), err),
+            }
+        }
+    }
+    Ok(report)
+}
+
+async fn cleanup_cache(app: &AppHandle, older_than: Duration) -> anyhow::Result<CleanupReport> {
+    let cache_dir = app
+        .path_resolver()
+        .app_cache_dir()
+        .context("no app cache dir")?;
+    let in_use = app.state::<TransferManager>().paths();
+    let report =
+        tokio::task::spawn_blocking(move || cleanup(&cache_dir, older_than, &in_use)).await??;
+    log!(
+        "cleaned up {} cache entries, {} bytes",
+        report.removed,
+        report.bytes
+    );
+    Ok(report)
+}
+
+/// Stop all shares, for the tray.
+pub fn stop_all(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        app.state::<TransferManager>().stop_all().await;
+        crate::tray::rebuild(&app);
+    });
+}
+
+/// The directory in the app cache dir the on disk stores of shares live in.
+pub fn scratch_root(app: &AppHandle) -> anyhow::Result<PathBuf> {
+    let dir = app
+        .path_resolver()
+        .app_cache_dir()
+        .context("no app cache dir")?;
+    Ok(dir.join("shares"))
+}
+
+/// Remove the stores of all shares. Only safe when no share is running.
+pub fn clear_scratch(app: &AppHandle) {
+    if let Ok(dir) = scratch_root(app) {
+        if dir.exists() {
+            if let Err(err) = std::fs::remove_dir_all(&dir) {
+                log!("failed to remove {}: {}", dir.display(), err);
+            }
+        }
+    }
+}
+
+/// Stop all shares, remove their stores and exit.
+///
+/// Peers get a proper close instead of a timeout. Exiting skips all
+/// destructors, so the stores are removed here.
+pub fn quit(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let transfers = app.state::<TransferManager>();
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, transfers.stop_all())
+            .await
+            .is_err()
+        {
+            log!("shares did not stop within {:?}", SHUTDOWN_TIMEOUT);
+        }
+        clear_scratch(&app);
+        app.exit(0);
+    });
+}
+
+/// Remove old cached data, for the tray.
+pub fn cleanup_old(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(err) = cleanup_cache(&app, TRAY_CLEANUP_AGE).await {
+            log!("clean up failed: {:#}", err);
+        }
+    });
+}
+
+/// Remove old cached data once a day, if the settings ask for it.
+pub fn spawn_auto_cleanup(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            if let Some(days) = app.state::<SettingsStore>().get().auto_cleanup_days {
+                let older_than = Duration::from_secs(days * 24 * 60 * 60);
+                if let Err(err) = cleanup_cache(&app, older_than).await {
+                    log!("automatic clean up failed: {:#}", err);
+                }
+            }
+            tokio::time::sleep(AUTO_CLEANUP_INTERVAL).await;
+        }
+    });
+}
+
+/// Stop all shares, returning how many there were.
+#[tauri::command]
+pub async fn stop_all_shares(
+    token: String,
+    session: State<'_, SessionToken>,
+    transfers: State<'_, TransferManager>,
+) -> Result<usize, String> {
+    session.verify(&token)?;
+    Ok(transfers.stop_all().await)
+}
+
+/// Remove successful downloads from the history, returning how many there were.
+#[tauri::command]
+pub fn clear_completed_downloads(
+    token: String,
+    session: State<'_, SessionToken>,
+    activity: State<'_, Arc<ActivityLog>>,
+) -> Result<usize, String> {
+    session.verify(&token)?;
+    activity.clear_received().map_err(|e| e.to_string())
+}
+
+/// Remove cached copies of shared data older than `older_than` seconds.
+#[tauri::command]
+pub async fn cleanup_store(
+    older_than: u64,
+    token: String,
+    session: State<'_, SessionToken>,
+    app: AppHandle,
+) -> Result<CleanupReport, String> {
+    session.verify(&token)?;
+    cleanup_cache(&app, Duration::from_secs(older_than))
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,269 @@
+use std::{
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::Context;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+use walkdir::WalkDir;
+
+use crate::{auth::SessionToken, settings::SettingsStore};
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "heic", "tif", "tiff", "bmp"];
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "m4v", "mkv", "avi", "webm"];
+
+/// Bitrate reserved for the audio track of video previews, in kbit/s.
+const AUDIO_KBPS: u64 = 96;
+
+/// How photos and videos are shrunk when sharing a preview.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreviewSettings {
+    /// Longest side of downscaled images, in pixels.
+    pub max_image_size: u32,
+    /// Height of transcoded videos, in pixels.
+    pub video_height: u32,
+    /// Size a transcoded video should end up at, in MiB.
+    pub video_target_mb: u64,
+    /// The ffmpeg binary to use, looked up on the `PATH` if unset.
+    pub ffmpeg: Option<PathBuf>,
+}
+
+impl Default for PreviewSettings {
+    fn default() -> Self {
+        Self {
+            max_image_size: 1920,
+            video_height: 720,
+            video_target_mb: 25,
+            ffmpeg: None,
+        }
+    }
+}
+
+impl PreviewSettings {
+    fn ffmpeg(&self) -> Command {
+        Command::new(self.ffmpeg.as_deref().unwrap_or(Path::new("ffmpeg")))
+    }
+
+    /// ffprobe is expected next to ffmpeg.
+    fn ffprobe(&self) -> Command {
+        match &self.ffmpeg {
+            Some(ffmpeg) => Command::new(ffmpeg.with_file_name("ffprobe")),
+            None => Command::new("ffprobe"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Media {
+    Image,
+    Video,
+}
+
+fn media_kind(path: &Path) -> Option<Media> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    if IMAGE_EXTENSIONS.contains(&ext.as_str()) {
+        Some(Media::Image)
+    } else if VIDEO_EXTENSIONS.contains(&ext.as_str()) {
+        Some(Media::Video)
+    } else {
+        None
+    }
+}
+
+/// Where the preview of `path` is written to, relative to its directory.
+fn preview_name(path: &Path, kind: Media) -> PathBuf {
+    let ext = match kind {
+        Media::Image if media_ext(path) == "png" => "png",
+        Media::Image => "jpg",
+        Media::Video => "mp4",
+    };
+    path.with_extension(ext)
+}
+
+fn media_ext(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Progress of a single file, sent to the frontend as `preview-progress`.
+#[derive(Debug, Clone, Serialize)]
+struct PreviewProgress {
+    file: String,
+    /// From 0 to 1.
+    progress: f32,
+}
+
+/// Duration of a video in seconds.
+fn duration(settings: &PreviewSettings, path: &Path) -> anyhow::Result<f64> {
+    let output = settings
+        .ffprobe()
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "csv=p=0",
+        ])
+        .arg(path)
+        .output()
+        .context("could not start ffprobe")?;
+    anyhow::ensure!(output.status.success(), "ffprobe failed");
+    let duration = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<f64>()?;
+    anyhow::ensure!(duration > 0.0, "video has no duration");
+    Ok(duration)
+}
+
+/// Run ffmpeg, reporting progress through `-progress` if the duration is known.
+fn run(
+    mut cmd: Command,
+    duration: Option<f64>,
+    mut progress: impl FnMut(f32),
+) -> anyhow::Result<()> {
+    let mut child = cmd
+        .args(["-progress", "pipe:1", "-nostats", "-loglevel", "error"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("could not start ffmpeg")?;
+    let stdout = child.stdout.take().context("no stdout")?;
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        let (Some(duration), Some(us)) = (duration, line.strip_prefix("out_time_us=")) else {
+            continue;
+        };
+        if let Ok(us) = us.parse::<f64>() {
+            progress((us / 1_000_000.0 / duration).clamp(0.0, 1.0) as f32);
+        }
+    }
+    let status = child.wait()?;
+    anyhow::ensure!(status.success(), "ffmpeg failed with {}", status);
+    Ok(())
+}
+
+fn transcode(
+    settings: &PreviewSettings,
+    src: &Path,
+    dst: &Path,
+    kind: Media,
+    progress: impl FnMut(f32),
+) -> anyhow::Result<()> {
+    let mut cmd = settings.ffmpeg();
+    cmd.arg("-y").arg("-i").arg(src);
+    let duration = match kind {
+        Media::Image => {
+            let max = settings.max_image_size;
+            cmd.arg("-vf")
+                .arg(format!(
+                    "scale='min({max},iw)':'min({max},ih)':force_original_aspect_ratio=decrease"
+                ))
+                .args(["-frames:v", "1"]);
+            None
+        }
+        Media::Video => {
+            let duration = duration(settings, src)?;
+            let total_kbit = settings.video_target_mb * 8 * 1024;
+            let video_kbps = (total_kbit as f64 / duration) as u64;
+            let video_kbps = video_kbps.saturating_sub(AUDIO_KBPS).max(100);
+            cmd.arg("-vf")
+                .arg(format!("scale=-2:'min({},ih)'", settings.video_height))
+                .args(["-c:v", "libx264", "-preset", "veryfast"])
+                .arg("-b:v")
+                .arg(format!("{}k", video_kbps))
+                .args(["-c:a", "aac", "-b:a"])
+                .arg(format!("{}k", AUDIO_KBPS));
+            Some(duration)
+        }
+    };
+    cmd.arg(dst);
+    run(cmd, duration, progress)
+}
+
+/// Write a preview of `path` below `root`: photos and videos are shrunk, other
+/// files are linked, or copied where linking is not possible.
+fn write_preview(
+    app: &AppHandle,
+    settings: &PreviewSettings,
+    path: &Path,
+    root: &Path,
+) -> anyhow::Result<PathBuf> {
+    let parent = path.parent().context("no parent")?;
+    let mut shared = None;
+    for entry in WalkDir::new(path) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(parent)?;
+        let target = match media_kind(relative) {
+            Some(kind) => {
+                let target = root.join(preview_name(relative, kind));
+                std::fs::create_dir_all(target.parent().context("no parent")?)?;
+                let file = relative.display().to_string();
+                transcode(settings, entry.path(), &target, kind, |progress| {
+                    let progress = PreviewProgress {
+                        file: file.clone(),
+                        progress,
+                    };
+                    app.emit_all("preview-progress", progress).ok();
+                })
+                .with_context(|| format!("could not shrink {}", relative.display()))?;
+                target
+            }
+            None => {
+                let target = root.join(relative);
+                std::fs::create_dir_all(target.parent().context("no parent")?)?;
+                if std::fs::hard_link(entry.path(), &target).is_err() {
+                    std::fs::copy(entry.path(), &target)?;
+                }
+                target
+            }
+        };
+        shared = Some(target);
+    }
+    // a single file may have been renamed, a directory keeps its name
+    if path.is_dir() {
+        Ok(root.join(path.file_name().context("no file name")?))
+    } else {
+        shared.context("nothing to share")
+    }
+}
+
+/// Prepare a preview of `path` in the app cache dir and return the path to share.
+pub async fn prepare(app: &AppHandle, path: PathBuf) -> anyhow::Result<PathBuf> {
+    let settings = app.state::<SettingsStore>().get().preview;
+    let suffix = rand::thread_rng().gen::<[u8; 8]>();
+    let root = app
+        .path_resolver()
+        .app_cache_dir()
+        .context("no app cache dir")?
+        .join("previews")
+        .join(hex::encode(suffix));
+    let app = app.clone();
+    tokio::task::spawn_blocking(move || write_preview(&app, &settings, &path, &root)).await?
+}
+
+#[tauri::command]
+pub fn get_preview_settings(settings: State<'_, SettingsStore>) -> PreviewSettings {
+    settings.get().preview
+}
+
+#[tauri::command]
+pub fn set_preview_settings(
+    preview: PreviewSettings,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| s.preview = preview)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
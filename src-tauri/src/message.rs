@@ -0,0 +1,184 @@
+use std::path::Path;
+
+use anyhow::Context;
+use base64::Engine;
+use qrcode::{render::svg, QrCode};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use walkdir::WalkDir;
+
+use crate::{i18n::I18n, password, settings::SettingsStore};
+
+/// How many files are listed before the rest is summarized.
+pub const MAX_LISTED_FILES: usize = 20;
+
+const DEFAULT_MARKDOWN: &str = "\
+I'm sending you **{name}** ({size}) with [SendMe](https://github.com/dignifiedquire/sendme-tauri).
+
+Paste this ticket into SendMe to download it:
+
+```
+{ticket}
+```
+
+{qr}
+
+Files:
+{files}
+
+Available: {expiry}
+";
+
+const DEFAULT_HTML: &str = "\
+<p>I'm sending you <strong>{name}</strong> ({size}) with \
+<a href=\"https://github.com/dignifiedquire/sendme-tauri\">SendMe</a>.</p>
+<p>Paste this ticket into SendMe to download it:</p>
+<pre>{ticket}</pre>
+{qr}
+<p>Files:</p>
+{files}
+<p>Available: {expiry}</p>
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageFormat {
+    Markdown,
+    Html,
+}
+
+/// User templates for share messages, the bundled ones are used if unset.
+///
+/// Placeholders: `{name}`, `{ticket}`, `{qr}`, `{files}`, `{size}`, `{expiry}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageTemplates {
+    pub markdown: Option<String>,
+    pub html: Option<String>,
+}
+
+impl MessageTemplates {
+    fn get(&self, format: MessageFormat) -> &str {
+        match format {
+            MessageFormat::Markdown => self.markdown.as_deref().unwrap_or(DEFAULT_MARKDOWN),
+            MessageFormat::Html => self.html.as_deref().unwrap_or(DEFAULT_HTML),
+        }
+    }
+}
+
+/// Format a byte count for humans, e.g. `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The files below `path` with their sizes, relative to the shared root.
+pub fn list_files(path: &Path) -> anyhow::Result<Vec<(String, u64)>> {
+    let root = path.parent().unwrap_or(path);
+    let mut files = Vec::new();
+    for entry in WalkDir::new(path).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        files.push((name.display().to_string(), entry.metadata()?.len()));
+    }
+    Ok(files)
+}
+
+fn render_files(files: &[(String, u64)], format: MessageFormat) -> String {
+    let rest = files.len().saturating_sub(MAX_LISTED_FILES);
+    let listed = &files[..files.len() - rest];
+    match format {
+        MessageFormat::Markdown => {
+            let mut lines: Vec<_> = listed
+                .iter()
+                .map(|(name, size)| format!("- `{}` ({})", name, format_size(*size)))
+                .collect();
+            if rest > 0 {
+                lines.push(format!("- … and {} more", rest));
+            }
+            lines.join("\n")
+        }
+        MessageFormat::Html => {
+            let mut items: Vec<_> = listed
+                .iter()
+                .map(|(name, size)| {
+                    format!(
+                        "<li><code>{}</code> ({})</li>",
+                        escape_html(name),
+                        format_size(*size)
+                    )
+                })
+                .collect();
+            if rest > 0 {
+                items.push(format!("<li>… and {} more</li>", rest));
+            }
+            format!("<ul>\n{}\n</ul>", items.join("\n"))
+        }
+    }
+}
+
+fn render_qr(ticket: &str, format: MessageFormat) -> anyhow::Result<String> {
+    let code = QrCode::new(ticket.as_bytes()).context("ticket too long for a qr code")?;
+    let image = code.render::<svg::Color>().min_dimensions(240, 240).build();
+    Ok(match format {
+        MessageFormat::Markdown => format!(
+            "![SendMe ticket](data:image/svg+xml;base64,{})",
+            base64::engine::general_purpose::STANDARD.encode(image)
+        ),
+        MessageFormat::Html => image,
+    })
+}
+
+/// Render a ready to paste message for a share.
+#[tauri::command]
+pub fn share_message(
+    ticket: String,
+    path: String,
+    format: MessageFormat,
+    settings: State<'_, SettingsStore>,
+    i18n: State<'_, I18n>,
+) -> Result<String, String> {
+    password::parse_ticket(&ticket).map_err(|e| format!("{:#}", e))?;
+    let path = Path::new(&path);
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+    let files = list_files(path).map_err(|e| e.to_string())?;
+    let size = format_size(files.iter().map(|(_, size)| size).sum());
+    let qr = render_qr(&ticket, format).map_err(|e| e.to_string())?;
+    let expiry = i18n.translate("message.no_expiry", &[]);
+    let name = match format {
+        MessageFormat::Markdown => name,
+        MessageFormat::Html => escape_html(&name),
+    };
+
+    let template = settings.get().message_templates.get(format).to_string();
+    Ok(template
+        .replace("{name}", &name)
+        .replace("{size}", &size)
+        .replace("{files}", &render_files(&files, format))
+        .replace("{expiry}", &expiry)
+        .replace("{qr}", &qr)
+        .replace("{ticket}", &ticket))
+}
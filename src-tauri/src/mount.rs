@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+
+use bao_tree::{
+    io::{fsm::BaoContentItem, Leaf},
+    ByteNum, ChunkRanges,
+};
+use bytes::{Bytes, BytesMut};
+use futures::FutureExt;
+use iroh_bytes::{
+    format::collection::Collection,
+    get::{fsm, request::get_hash_seq_and_sizes},
+    protocol::{GetRequest, RangeSpec, RangeSpecSeq, ALPN},
+    BlobFormat, Hash,
+};
+use iroh_net::{ticket::BlobTicket, MagicEndpoint};
+use tauri::{AppHandle, Manager, State};
+
+use crate::{
+    auth::SessionToken,
+    download::{connect, MAX_HASH_SEQ_SIZE},
+    errors::{ErrorCode, UserError},
+    i18n::I18n,
+    identity::Identity,
+    pack, password,
+    settings::SettingsStore,
+    webdav::{ReadFn, ShareView, WebDavShares},
+};
+
+/// The provider of a mounted ticket, connected to again when the connection
+/// was lost.
+struct Remote {
+    endpoint: MagicEndpoint,
+    ticket: BlobTicket,
+    password: Option<String>,
+    connection: tokio::sync::Mutex<quinn::Connection>,
+}
+
+impl Remote {
+    async fn connection(&self) -> anyhow::Result<quinn::Connection> {
+        let mut connection = self.connection.lock().await;
+        if connection.close_reason().is_some() {
+            if let Some(password) = &self.password {
+                password::unlock(&self.endpoint, &self.ticket, password).await?;
+            }
+            let addr = self.ticket.node_addr().clone();
+            *connection = self.endpoint.connect(addr, ALPN).await?;
+        }
+        Ok(connection.clone())
+    }
+}
+
+/// `len` bytes at `offset` of blob `hash`. Only the chunks covering them are
+/// requested, and they are verified against the hash as they arrive.
+async fn read_range(
+    connection: quinn::Connection,
+    hash: Hash,
+    offset: u64,
+    len: usize,
+) -> anyhow::Result<Bytes> {
+    let end = offset + len as u64;
+    let chunks = ChunkRanges::from(ByteNum(offset).full_chunks()..ByteNum(end).chunks());
+    let request = GetRequest::new(hash, RangeSpecSeq::from_ranges([chunks]));
+    let connected = fsm::start(connection, request).next().await?;
+    let fsm::ConnectedNext::StartRoot(start) = connected.next().await? else {
+        anyhow::bail!("the provider does not have {}", hash.to_hex());
+    };
+    let (mut content, size) = start.next().next().await?;
+    let end = end.min(size);
+    let mut data = BytesMut::zeroed(end.saturating_sub(offset) as usize);
+    let done = loop {
+        match content.next().await {
+            fsm::BlobContentNext::More((next, item)) => {
+                if let BaoContentItem::Leaf(Leaf {
+                    offset: at,
+                    data: leaf,
+                }) = item?
+                {
+                    // the leaf may start before and end after the range
+                    let from = offset.max(at.0);
+                    let to = end.min(at.0 + leaf.len() as u64);
+                    if from < to {
+                        let src = &leaf[(from - at.0) as usize..(to - at.0) as usize];
+                        data[(from - offset) as usize..(to - offset) as usize].copy_from_slice(src);
+                    }
+                }
+                content = next;
+            }
+            fsm::BlobContentNext::Done(done) => break done,
+        }
+    };
+    if let fsm::EndBlobNext::Closing(closing) = done.next() {
+        closing.next().await?;
+    }
+    Ok(data.freeze())
+}
+
+fn reader(remote: Arc<Remote>) -> ReadFn {
+    Arc::new(move |hash, offset, len| {
+        let remote = remote.clone();
+        async move {
+            let connection = remote.connection().await?;
+            read_range(connection, hash, offset, len).await
+        }
+        .map(|res| res.map_err(io::Error::other))
+        .boxed()
+    })
+}
+
+/// The files of the collection `hash`, as (name, hash, size). Packed
+/// small files are left out, they can only be read by unpacking their pack.
+async fn files(
+    connection: &quinn::Connection,
+    hash: Hash,
+) -> anyhow::Result<Vec<(String, Hash, u64)>> {
+    let (_, sizes) = get_hash_seq_and_sizes(connection, &hash, MAX_HASH_SEQ_SIZE).await?;
+    // the hash seq and the metadata blob with the names
+    let ranges = RangeSpecSeq::new([RangeSpec::all(), RangeSpec::all(), RangeSpec::EMPTY]);
+    let connected = fsm::start(connection.clone(), GetRequest::new(hash, ranges))
+        .next()
+        .await?;
+    let fsm::ConnectedNext::StartRoot(start) = connected.next().await? else {
+        anyhow::bail!("the provider does not have {}", hash.to_hex());
+    };
+    let (next, _, collection) = Collection::read_fsm(start).await?;
+    let closing = match next {
+        fsm::EndBlobNext::MoreChildren(more) => more.finish(),
+        fsm::EndBlobNext::Closing(closing) => closing,
+    };
+    closing.next().await?;
+    // the first child is the metadata blob
+    let files = collection
+        .iter()
+        .zip(sizes.iter().skip(1))
+        .filter(|((name, _), _)| !pack::is_internal(name))
+        .map(|((name, hash), size)| (name.clone(), *hash, *size))
+        .collect();
+    Ok(files)
+}
+
+/// Mounted tickets, by the WebDAV folder they are shown in.
+#[derive(Default)]
+pub struct Mounts(Mutex<HashMap<String, Arc<Remote>>>);
+
+async fn mount(
+    app: &AppHandle,
+    ticket: BlobTicket,
+    password: Option<String>,
+) -> anyhow::Result<String> {
+    anyhow::ensure!(
+        ticket.format() == BlobFormat::HashSeq,
+        "the ticket does not point to a collection"
+    );
+    let settings = app.state::<SettingsStore>().get();
+    anyhow::ensure!(
+        settings.webdav_port.is_some(),
+        "turn on the WebDAV server to mount tickets"
+    );
+    let secret_key = app.state::<Identity>().secret_key();
+    let (endpoint, connection) =
+        connect(&ticket, &settings, secret_key, password.as_deref()).await?;
+    let files = files(&connection, ticket.hash()).await?;
+    let remote = Arc::new(Remote {
+        endpoint,
+        ticket,
+        password,
+        connection: tokio::sync::Mutex::new(connection),
+    });
+    let view = ShareView {
+        files,
+        read: reader(remote.clone()),
+    };
+    let name = app
+        .state::<Arc<WebDavShares>>()
+        .insert(&remote.ticket.hash(), view);
+    app.state::<Mounts>()
+        .0
+        .lock()
+        .unwrap()
+        .insert(name.clone(), remote);
+    Ok(name)
+}
+
+/// Show the collection of `ticket` as a read only folder of the WebDAV
+/// server, fetching file contents only when they are read. The operating
+/// system mounts the server like any other WebDAV share, e.g. Finder's
+/// "Connect to Server", a mapped network drive in Explorer, or gvfs and
+/// davfs2 on Linux, so no FUSE, WinFsp or Dokan driver is needed.
+///
+/// Returns the name of the folder, to unmount it with.
+#[tauri::command]
+pub async fn mount_ticket(
+    ticket: String,
+    password: Option<String>,
+    token: String,
+    session: State<'_, SessionToken>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<String, UserError> {
+    session
+        .verify(&token)
+        .map_err(|msg| UserError::new(ErrorCode::Unknown, msg, &i18n))?;
+    let res = async {
+        let (ticket, hint) = password::parse_ticket(&ticket)?;
+        password::check_hint(&ticket, hint.as_deref(), password.as_deref()).await?;
+        log!("mounting {}", ticket.hash().to_hex());
+        mount(&app, ticket, password).await
+    }
+    .await;
+    res.map_err(|e| UserError::from_anyhow(&e, &i18n))
+}
+
+/// Remove a mounted ticket from the WebDAV server and close its connection.
+#[tauri::command]
+pub async fn unmount_ticket(
+    name: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    mounts: State<'_, Mounts>,
+    shares: State<'_, Arc<WebDavShares>>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let remote = mounts
+        .0
+        .lock()
+        .unwrap()
+        .remove(&name)
+        .ok_or_else(|| format!("{} is not mounted", name))?;
+    shares.remove(&name);
+    remote.endpoint.close(0u32.into(), b"unmounted").await.ok();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::store::{flat, Store};
+
+    use super::*;
+    use crate::download::tests::{provider, receiver};
+
+    #[tokio::test]
+    async fn reads_ranges_on_demand() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = flat::Store::load(tmp.path()).await.unwrap();
+        let data = (0..100_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let blob = db
+            .import_bytes(data.clone().into(), BlobFormat::Raw)
+            .await
+            .unwrap();
+        let file = *blob.hash();
+        let collection: Collection = [("dir/file", file)].into_iter().collect();
+        let tag = collection.store(&db).await.unwrap();
+        let addr = provider(db).await;
+        let connection = receiver().await.connect(addr, ALPN).await.unwrap();
+
+        let listed = files(&connection, *tag.hash()).await.unwrap();
+        assert_eq!(listed, [("dir/file".to_string(), file, 100_000)]);
+        // not aligned to chunks, and past the end of the blob
+        for (offset, len) in [(0, 1024), (5_000, 20_000), (99_000, 4096)] {
+            let read = read_range(connection.clone(), file, offset, len)
+                .await
+                .unwrap();
+            let end = (offset as usize + len).min(data.len());
+            assert_eq!(read, data[offset as usize..end]);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use iroh_net::{
+    defaults::{default_derp_map, DEFAULT_DERP_STUN_PORT},
+    derp::{http::ClientBuilder, DerpMap, DerpMode, DerpNode},
+    key::SecretKey,
+    magic_endpoint::MagicEndpointBuilder,
+    MagicEndpoint, NodeAddr,
+};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use url::Url;
+
+use crate::{
+    auth::SessionToken,
+    settings::SettingsStore,
+    users::{self, Protocol},
+};
+
+/// How long [`test_relay`] waits for a relay to connect and answer a ping.
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Which direct addresses of a share are put into its tickets.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    /// Drop the direct addresses of other families from `addr`.
+    pub fn restrict(self, addr: &mut NodeAddr) {
+        addr.info.direct_addresses.retain(|a| match self {
+            AddressFamily::Any => true,
+            AddressFamily::Ipv4 => a.is_ipv4(),
+            AddressFamily::Ipv6 => a.is_ipv6(),
+        });
+    }
+}
+
+/// How endpoints for shares and downloads reach the network.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// UDP port to bind for IPv4, a random one if unset or if the port is
+    /// taken, e.g. by another share. IPv6 binds the next port, so a firewall
+    /// needs to let both through.
+    pub port: Option<u16>,
+    /// Which direct addresses tickets contain, so peers only try the family
+    /// a firewall lets through.
+    ///
+    /// Endpoints always listen on all interfaces for both families,
+    /// iroh-net does not support binding a specific address.
+    pub family: AddressFamily,
+    /// Url of a relay server to use instead of the default ones.
+    pub relay: Option<String>,
+    /// Further relay servers, used together with `relay` instead of the
+    /// default ones.
+    pub relays: Vec<RelayServer>,
+}
+
+/// A relay server of a custom relay map.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayServer {
+    pub url: String,
+    /// Only use the server to find out the public address with STUN, not to
+    /// relay traffic.
+    #[serde(default)]
+    pub stun_only: bool,
+    /// The default STUN port if unset.
+    #[serde(default)]
+    pub stun_port: Option<u16>,
+}
+
+fn parse_url(url: &str) -> anyhow::Result<Url> {
+    Url::parse(url).map_err(|e| anyhow::anyhow!("invalid relay url {:?}: {}", url, e))
+}
+
+impl NetworkSettings {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.derp_mode()?;
+        Ok(())
+    }
+
+    /// The configured relay map, `None` for the default one.
+    fn derp_map(&self) -> anyhow::Result<Option<DerpMap>> {
+        if self.relay.is_none() && self.relays.is_empty() {
+            return Ok(None);
+        }
+        let mut nodes = Vec::new();
+        if let Some(relay) = &self.relay {
+            nodes.push(DerpNode {
+                url: parse_url(relay)?,
+                stun_only: false,
+                stun_port: DEFAULT_DERP_STUN_PORT,
+            });
+        }
+        for server in &self.relays {
+            nodes.push(DerpNode {
+                url: parse_url(&server.url)?,
+                stun_only: server.stun_only,
+                stun_port: server.stun_port.unwrap_or(0),
+            });
+        }
+        Ok(Some(DerpMap::from_nodes(nodes)?))
+    }
+
+    fn derp_mode(&self) -> anyhow::Result<DerpMode> {
+        Ok(match self.derp_map()? {
+            Some(map) => DerpMode::Custom(map),
+            None => DerpMode::Default,
+        })
+    }
+
+    /// Bind `builder` with these settings.
+    ///
+    /// Fails if another user holds the configured port, instead of falling
+    /// back to a random port that the user's firewall rules do not cover.
+    pub async fn bind(&self, builder: MagicEndpointBuilder) -> anyhow::Result<MagicEndpoint> {
+        if let Some(port) = self.port {
+            users::check_port(Protocol::Udp, port)?;
+            if let Some(v6) = port.checked_add(1) {
+                users::check_port(Protocol::Udp, v6)?;
+            }
+        }
+        builder
+            .derp_mode(self.derp_mode()?)
+            .bind(self.port.unwrap_or(0))
+            .await
+    }
+}
+
+#[tauri::command]
+pub fn get_network_settings(settings: State<'_, SettingsStore>) -> NetworkSettings {
+    settings.get().network
+}
+
+/// Latency to a relay server, as reported by [`test_relay`].
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayLatency {
+    pub url: String,
+    /// Round trip time of a ping, unset if the relay could not be reached.
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Connect to the relay at `url` and measure a ping.
+async fn ping_relay(url: Url) -> anyhow::Result<Duration> {
+    let (client, _receiver) = ClientBuilder::new(url).build(SecretKey::generate());
+    let res = tokio::time::timeout(RELAY_TIMEOUT, async {
+        client.connect().await?;
+        client.ping().await
+    })
+    .await;
+    client.close().await.ok();
+    Ok(res.map_err(|_| anyhow::anyhow!("timed out"))??)
+}
+
+/// Ping every relay server of `network`, or of the saved settings if unset,
+/// so a relay can be checked before it is saved.
+///
+/// STUN only servers are skipped.
+#[tauri::command]
+pub async fn test_relay(
+    network: Option<NetworkSettings>,
+    settings: State<'_, SettingsStore>,
+) -> Result<Vec<RelayLatency>, String> {
+    let network = network.unwrap_or_else(|| settings.get().network);
+    let map = network
+        .derp_map()
+        .map_err(|e| e.to_string())?
+        .unwrap_or_else(default_derp_map);
+    let pings = map
+        .nodes()
+        .filter(|(_, node)| !node.stun_only)
+        .map(|(url, _)| {
+            let url = url.clone();
+            async move {
+                let res = ping_relay(url.clone()).await;
+                RelayLatency {
+                    url: url.to_string(),
+                    latency_ms: res.as_ref().ok().map(|d| d.as_millis() as u64),
+                    error: res.err().map(|e| format!("{:#}", e)),
+                }
+            }
+        });
+    Ok(futures::future::join_all(pings).await)
+}
+
+/// Change the port, address family and relay. Running shares keep theirs.
+#[tauri::command]
+pub fn set_network_settings(
+    network: NetworkSettings,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    network.validate().map_err(|e| e.to_string())?;
+    settings
+        .update(|s| s.network = network)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,355 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport, Message,
+    Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use tauri::{
+    api::{
+        http::{Body, ClientBuilder, HttpRequestBuilder},
+        notification::Notification,
+    },
+    AppHandle, Manager, State,
+};
+use tokio::sync::watch;
+
+use crate::{
+    activity::ActivityReport,
+    auth::SessionToken,
+    i18n::I18n,
+    message::{format_size, list_files, MAX_LISTED_FILES},
+    progress::TransferProgress,
+    serve::Downloads,
+    settings::SettingsStore,
+};
+
+/// Where to notify the user about their shares, nothing is sent if both are unset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    /// Notify when a share is downloaded completely for the first time.
+    pub on_download: bool,
+    /// Notify when a share ends without ever being downloaded.
+    pub on_expiry: bool,
+    /// Send a summary of the transfer activity once a week.
+    pub weekly_report: bool,
+    /// Show native notifications when peers connect to a share, when a peer
+    /// received a share and when a download finished.
+    pub desktop: bool,
+    pub verbosity: Verbosity,
+    /// Url that receives a json POST for every notification.
+    pub webhook: Option<String>,
+    /// Url that receives a json POST for every security event, e.g. a
+    /// request for a revoked ticket.
+    pub security_webhook: Option<String>,
+    /// Mail server used to email the notifications.
+    pub smtp: Option<SmtpSettings>,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            on_download: true,
+            on_expiry: true,
+            weekly_report: false,
+            desktop: true,
+            verbosity: Verbosity::default(),
+            webhook: None,
+            security_webhook: None,
+            smtp: None,
+        }
+    }
+}
+
+/// How much notifications tell.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// Send no notifications at all.
+    Silent,
+    /// Only what happened to which share.
+    #[default]
+    Terse,
+    /// Also the files, their sizes and who downloaded them. The body starts
+    /// with what happened, so screen readers announce that first, and lists
+    /// the files one per line.
+    Descriptive,
+}
+
+/// Mail server settings. The password is stored in plain text in the settings file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    /// Submission port, 587 if unset.
+    pub port: Option<u16>,
+    pub username: String,
+    pub password: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareEvent {
+    /// The share was downloaded completely for the first time.
+    Downloaded,
+    /// The share ended without being downloaded.
+    Expired,
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    event: ShareEvent,
+    share: &'a str,
+    /// The notification text, as verbose as configured.
+    message: &'a str,
+    time: u64,
+}
+
+pub async fn send_webhook(url: &str, payload: impl Serialize) -> anyhow::Result<()> {
+    let client = ClientBuilder::new()
+        .connect_timeout(Duration::from_secs(10))
+        .build()?;
+    let request =
+        HttpRequestBuilder::new("POST", url)?.body(Body::Json(serde_json::to_value(payload)?));
+    let response = client.send(request).await?;
+    anyhow::ensure!(
+        response.status().is_success(),
+        "webhook returned {}",
+        response.status()
+    );
+    Ok(())
+}
+
+async fn send_mail(
+    smtp: &SmtpSettings,
+    to: &str,
+    subject: String,
+    body: String,
+) -> anyhow::Result<()> {
+    let message = Message::builder()
+        .from(smtp.from.parse()?)
+        .to(to.parse()?)
+        .subject(subject)
+        .body(body)?;
+    let transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)?
+        .port(smtp.port.unwrap_or(587))
+        .credentials(Credentials::new(
+            smtp.username.clone(),
+            smtp.password.clone(),
+        ))
+        .build();
+    transport.send(message).await?;
+    Ok(())
+}
+
+/// Email `to` through the configured mail server, e.g. a ticket for a
+/// recipient.
+pub async fn mail(app: &AppHandle, to: &str, subject: String, body: String) -> anyhow::Result<()> {
+    let smtp = app.state::<SettingsStore>().get().notifications.smtp;
+    let smtp = smtp.context("no mail server configured")?;
+    send_mail(&smtp, to, subject, body).await
+}
+
+/// Send a notification through every configured channel.
+async fn deliver(app: &AppHandle, payload: impl Serialize, subject: String, body: String) {
+    let settings = app.state::<SettingsStore>().get().notifications;
+    if settings.verbosity == Verbosity::Silent {
+        return;
+    }
+    if let Some(url) = &settings.webhook {
+        if let Err(err) = send_webhook(url, &payload).await {
+            log!("failed to send webhook: {:#}", err);
+        }
+    }
+    if let Some(smtp) = &settings.smtp {
+        if let Err(err) = send_mail(smtp, &smtp.to, subject, body).await {
+            log!("failed to send notification mail: {:#}", err);
+        }
+    }
+}
+
+/// Show a native notification with the translation of `key`, unless desktop
+/// notifications are turned off.
+pub fn desktop(app: &AppHandle, key: &str, args: &[(&str, &str)]) {
+    let settings = app.state::<SettingsStore>().get().notifications;
+    if !settings.desktop || settings.verbosity == Verbosity::Silent {
+        return;
+    }
+    let body = app.state::<I18n>().translate(key, args);
+    let res = Notification::new(&app.config().tauri.bundle.identifier)
+        .title("SendMe")
+        .body(body)
+        .show();
+    if let Err(err) = res {
+        log!("failed to show notification: {}", err);
+    }
+}
+
+/// Show a desktop notification when a peer connects to a share.
+pub fn desktop_progress(app: &AppHandle, progress: &TransferProgress) {
+    if let TransferProgress::Connected { share, peer } = progress {
+        let unknown = app.state::<I18n>().translate("notify.unknown_peer", &[]);
+        desktop(
+            app,
+            "notify.peer_connected",
+            &[
+                ("name", share),
+                ("peer", peer.as_deref().unwrap_or(&unknown)),
+            ],
+        );
+    }
+}
+
+/// The descriptive notification text: what happened, the share's name, size
+/// and who downloaded it, then the files.
+fn describe(
+    i18n: &I18n,
+    event: ShareEvent,
+    share: &str,
+    paths: &[PathBuf],
+    peer: Option<&str>,
+) -> String {
+    let files = paths
+        .iter()
+        .flat_map(|path| list_files(path).unwrap_or_default())
+        .collect::<Vec<_>>();
+    let size = format_size(files.iter().map(|(_, size)| size).sum());
+    let unknown = i18n.translate("notify.unknown_peer", &[]);
+    let key = match event {
+        ShareEvent::Downloaded => "notify.downloaded_descriptive",
+        ShareEvent::Expired => "notify.expired_descriptive",
+    };
+    let mut body = i18n.translate(
+        key,
+        &[
+            ("name", share),
+            ("count", &files.len().to_string()),
+            ("size", &size),
+            ("peer", peer.unwrap_or(&unknown)),
+        ],
+    );
+    for (name, size) in files.iter().take(MAX_LISTED_FILES) {
+        body.push_str(&format!("\n{}, {}", name, format_size(*size)));
+    }
+    let rest = files.len().saturating_sub(MAX_LISTED_FILES);
+    if rest > 0 {
+        body.push('\n');
+        body.push_str(&i18n.translate("notify.more_files", &[("count", &rest.to_string())]));
+    }
+    body
+}
+
+async fn send(
+    app: &AppHandle,
+    event: ShareEvent,
+    share: &str,
+    paths: &[PathBuf],
+    peer: Option<&str>,
+) {
+    let settings = app.state::<SettingsStore>().get().notifications;
+    let (wanted, key) = match event {
+        ShareEvent::Downloaded => (settings.on_download, "notify.downloaded"),
+        ShareEvent::Expired => (settings.on_expiry, "notify.expired"),
+    };
+    if !wanted {
+        return;
+    }
+    let i18n = app.state::<I18n>();
+    let subject = i18n.translate(key, &[("name", share)]);
+    let body = match settings.verbosity {
+        Verbosity::Descriptive => describe(&i18n, event, share, paths, peer),
+        _ => subject.clone(),
+    };
+    let payload = WebhookPayload {
+        event,
+        share,
+        message: &body,
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    };
+    deliver(app, payload, subject, body.clone()).await;
+}
+
+/// Send a periodic activity report.
+pub async fn send_report(app: &AppHandle, report: &ActivityReport) {
+    let i18n = app.state::<I18n>();
+    let subject = i18n.translate("notify.report", &[]);
+    let body = i18n.translate(
+        "notify.report_body",
+        &[
+            ("shared", &report.shared.to_string()),
+            ("served", &report.served.to_string()),
+            ("sent", &format_size(report.bytes_sent)),
+            ("received", &report.received.to_string()),
+            ("received_size", &format_size(report.bytes_received)),
+            ("failures", &report.failures.to_string()),
+        ],
+    );
+    let payload = serde_json::json!({ "event": "report", "report": report });
+    deliver(app, payload, subject, body).await;
+}
+
+/// Notify about the first complete download of a share, or about the share
+/// ending before anyone downloaded it, and show a desktop notification for
+/// every complete download.
+pub fn watch_share(
+    app: AppHandle,
+    share: String,
+    paths: Vec<PathBuf>,
+    mut downloads: watch::Receiver<Downloads>,
+) {
+    {
+        let app = app.clone();
+        let share = share.clone();
+        let mut downloads = downloads.clone();
+        tauri::async_runtime::spawn(async move {
+            while downloads.changed().await.is_ok() {
+                let peer = downloads.borrow_and_update().last_peer.clone();
+                let unknown = app.state::<I18n>().translate("notify.unknown_peer", &[]);
+                desktop(
+                    &app,
+                    "notify.sent",
+                    &[
+                        ("name", &share),
+                        ("peer", peer.as_deref().unwrap_or(&unknown)),
+                    ],
+                );
+            }
+        });
+    }
+    tauri::async_runtime::spawn(async move {
+        let (event, peer) = match downloads.wait_for(|d| d.count > 0).await {
+            Ok(d) => (ShareEvent::Downloaded, d.last_peer.clone()),
+            // the share is gone
+            Err(_) => (ShareEvent::Expired, None),
+        };
+        send(&app, event, &share, &paths, peer.as_deref()).await;
+    });
+}
+
+#[tauri::command]
+pub fn get_notification_settings(settings: State<'_, SettingsStore>) -> NotificationSettings {
+    settings.get().notifications
+}
+
+#[tauri::command]
+pub fn set_notification_settings(
+    notifications: NotificationSettings,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| s.notifications = notifications)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::{auth::SessionToken, settings::SettingsStore};
+
+const IMAGES: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "bmp", "tiff", "svg", "raw",
+];
+const VIDEOS: &[&str] = &["mp4", "mov", "mkv", "avi", "webm", "m4v"];
+const AUDIO: &[&str] = &["mp3", "wav", "flac", "ogg", "m4a", "aac", "opus"];
+const DOCUMENTS: &[&str] = &[
+    "pdf", "doc", "docx", "odt", "rtf", "txt", "md", "xls", "xlsx", "ods", "csv", "ppt", "pptx",
+    "odp", "epub",
+];
+const ARCHIVES: &[&str] = &["zip", "tar", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar"];
+
+/// A user defined rule, moving files whose name matches `pattern` into
+/// `folder`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrganizeRule {
+    pub pattern: String,
+    pub folder: String,
+}
+
+/// How received files are sorted into subfolders after they were saved.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OrganizeSettings {
+    /// Sort files without a matching rule by their type.
+    pub by_type: bool,
+    /// Checked in order before sorting by type, the first match wins.
+    pub rules: Vec<OrganizeRule>,
+}
+
+impl OrganizeSettings {
+    fn enabled(&self) -> bool {
+        self.by_type || !self.rules.is_empty()
+    }
+}
+
+/// Where a received file ended up, reported in the download summary.
+#[derive(Debug, Clone, Serialize)]
+pub struct Placement {
+    pub file: String,
+    /// The subfolder the file was moved into, unset if it was left in place.
+    pub folder: Option<String>,
+    /// Where the file is now.
+    pub path: PathBuf,
+}
+
+fn category(name: &str) -> Option<&'static str> {
+    let ext = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+    let categories: [(&str, &[&str]); 5] = [
+        ("Images", IMAGES),
+        ("Videos", VIDEOS),
+        ("Audio", AUDIO),
+        ("Documents", DOCUMENTS),
+        ("Archives", ARCHIVES),
+    ];
+    categories
+        .iter()
+        .find(|(_, exts)| exts.contains(&ext.as_str()))
+        .map(|(folder, _)| *folder)
+}
+
+/// A path next to `path` that does not exist yet, e.g. `photo (1).jpg`.
+fn unused(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|i| path.with_file_name(format!("{} ({}){}", stem, i, ext)))
+        .find(|p| !p.exists())
+        .expect("unbounded")
+}
+
+/// Move the top level files in `root` named in `files` into subfolders.
+///
+/// Directories are left alone, their structure was chosen by the sender.
+pub fn organize(
+    settings: &OrganizeSettings,
+    root: &Path,
+    files: &[String],
+) -> anyhow::Result<Vec<Placement>> {
+    if !settings.enabled() {
+        return Ok(Vec::new());
+    }
+    let rules = settings
+        .rules
+        .iter()
+        .map(|rule| Ok((Regex::new(&rule.pattern)?, rule.folder.as_str())))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut placements = Vec::new();
+    for file in files {
+        let source = root.join(file);
+        if !source.is_file() {
+            continue;
+        }
+        let folder = rules
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(file))
+            .map(|(_, folder)| *folder)
+            .or_else(|| {
+                if settings.by_type {
+                    category(file)
+                } else {
+                    None
+                }
+            });
+        let path = match folder {
+            Some(folder) => {
+                let dir = root.join(folder);
+                std::fs::create_dir_all(&dir)?;
+                let target = unused(dir.join(file));
+                std::fs::rename(&source, &target)?;
+                target
+            }
+            None => source,
+        };
+        placements.push(Placement {
+            file: file.clone(),
+            folder: folder.map(str::to_string),
+            path,
+        });
+    }
+    Ok(placements)
+}
+
+#[tauri::command]
+pub fn get_organize_settings(settings: State<'_, SettingsStore>) -> OrganizeSettings {
+    settings.get().organize
+}
+
+#[tauri::command]
+pub fn set_organize_settings(
+    organize: OrganizeSettings,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    for rule in &organize.rules {
+        Regex::new(&rule.pattern).map_err(|e| e.to_string())?;
+        let folder = Path::new(&rule.folder);
+        if folder.is_absolute() || rule.folder.contains("..") {
+            return Err(format!("invalid folder {}", rule.folder));
+        }
+    }
+    settings
+        .update(|s| s.organize = organize)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use bytes::Bytes;
+use iroh_bytes::{
+    format::collection::Collection,
+    store::{Map, MapEntry, Store},
+    BlobFormat, Hash, TempTag,
+};
+use iroh_io::AsyncSliceReaderExt;
+use serde::{Deserialize, Serialize};
+
+/// Files up to this size are packed when packing is on.
+pub const MAX_PACKED_FILE: u64 = 16 * 1024;
+
+/// Size a pack is filled up to before the next one is started.
+const PACK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Collection entries below this directory are packs and their index, not
+/// shared files.
+pub const PACK_DIR: &str = ".sendme-packs";
+
+/// Where a packed file is, the index is a json list of these.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PackedFile {
+    name: String,
+    /// Number of the pack, its entry is `.sendme-packs/<pack>`.
+    pack: usize,
+    offset: u64,
+    len: u64,
+}
+
+fn pack_name(pack: usize) -> String {
+    format!("{}/{}", PACK_DIR, pack)
+}
+
+fn index_name() -> String {
+    format!("{}/index", PACK_DIR)
+}
+
+/// Whether a collection entry is a pack or the index rather than a file.
+pub fn is_internal(name: &str) -> bool {
+    name.strip_prefix(PACK_DIR)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Pack `files`, as (name, path, size), into blobs of about [`PACK_SIZE`].
+///
+/// Returns the collection entries of the packs and the index, with tags that
+/// keep them alive until the collection is stored.
+pub async fn pack(
+    db: &impl Store,
+    files: Vec<(String, PathBuf, u64)>,
+) -> anyhow::Result<Vec<(String, TempTag)>> {
+    let mut groups = vec![Vec::new()];
+    let mut filled = 0;
+    for (name, path, size) in files {
+        if filled > 0 && filled + size > PACK_SIZE {
+            groups.push(Vec::new());
+            filled = 0;
+        }
+        filled += size;
+        groups.last_mut().unwrap().push((name, path));
+    }
+    let mut index = Vec::new();
+    let mut entries = Vec::new();
+    for (pack, group) in groups.into_iter().enumerate() {
+        // the files are small, but there can be very many of them
+        let (data, files) = tokio::task::spawn_blocking(move || {
+            let mut data = Vec::new();
+            let mut files = Vec::new();
+            for (name, path) in group {
+                let file = std::fs::read(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                files.push(PackedFile {
+                    name,
+                    pack,
+                    offset: data.len() as u64,
+                    len: file.len() as u64,
+                });
+                data.extend_from_slice(&file);
+            }
+            anyhow::Ok((data, files))
+        })
+        .await??;
+        let tag = db.import_bytes(data.into(), BlobFormat::Raw).await?;
+        tracing::info!(pack, files = files.len(), "packed");
+        entries.push((pack_name(pack), tag));
+        index.extend(files);
+    }
+    let tag = db
+        .import_bytes(serde_json::to_vec(&index)?.into(), BlobFormat::Raw)
+        .await?;
+    entries.push((index_name(), tag));
+    Ok(entries)
+}
+
+fn entry(collection: &Collection, name: &str) -> Option<Hash> {
+    collection
+        .iter()
+        .find(|(entry, _)| entry == name)
+        .map(|(_, hash)| *hash)
+}
+
+async fn read<D: Map>(db: &D, hash: Hash) -> anyhow::Result<Bytes> {
+    let entry = db.get(&hash).context("pack not found")?;
+    let mut reader = entry.data_reader().await?;
+    Ok(reader.read_to_end().await?)
+}
+
+/// The blob of the index of `collection`, if anything is packed.
+pub fn index_hash(collection: &Collection) -> Option<Hash> {
+    entry(collection, &index_name())
+}
+
+fn parse_index(data: &[u8]) -> anyhow::Result<Vec<PackedFile>> {
+    serde_json::from_slice(data).context("invalid pack index")
+}
+
+/// The index of `collection`, empty if nothing is packed.
+async fn index<D: Map>(db: &D, collection: &Collection) -> anyhow::Result<Vec<PackedFile>> {
+    let Some(hash) = index_hash(collection) else {
+        return Ok(Vec::new());
+    };
+    parse_index(&read(db, hash).await?)
+}
+
+/// The names of all files of `collection`, with `index` the content of its
+/// [`index_hash`] blob if it has one.
+pub fn names_with_index(collection: &Collection, index: &[u8]) -> anyhow::Result<Vec<String>> {
+    let mut names = collection
+        .iter()
+        .map(|(name, _)| name)
+        .filter(|name| !is_internal(name))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !index.is_empty() {
+        names.extend(parse_index(index)?.into_iter().map(|f| f.name));
+    }
+    Ok(names)
+}
+
+/// The names of all files of `collection`, packed or not.
+pub async fn file_names<D: Map>(db: &D, collection: &Collection) -> anyhow::Result<Vec<String>> {
+    let index = match index_hash(collection) {
+        Some(hash) => read(db, hash).await?,
+        None => Bytes::new(),
+    };
+    names_with_index(collection, &index)
+}
+
+/// Call `f` with the name and content of every packed file of `collection`.
+pub async fn unpack<D: Map>(
+    db: &D,
+    collection: &Collection,
+    mut f: impl FnMut(&str, &[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut current: Option<(usize, Bytes)> = None;
+    for file in index(db, collection).await? {
+        // files are indexed in pack order, so each pack is read once
+        if current.as_ref().map(|(pack, _)| *pack) != Some(file.pack) {
+            let hash = entry(collection, &pack_name(file.pack))
+                .with_context(|| format!("pack {} is missing", file.pack))?;
+            current = Some((file.pack, read(db, hash).await?));
+        }
+        let data = &current.as_ref().unwrap().1;
+        let start = file.offset as usize;
+        let content = start
+            .checked_add(file.len as usize)
+            .and_then(|end| data.get(start..end))
+            .with_context(|| format!("{} is outside of its pack", file.name))?;
+        f(&file.name, content)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::store::mem;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn unpacks_what_was_packed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let scratch = tmp.path();
+        let mut files = Vec::new();
+        for (name, content) in [
+            ("a.txt", &b"hello"[..]),
+            ("dir/b", b""),
+            ("dir/c", b"world"),
+        ] {
+            let path = scratch.join(name.replace('/', "-"));
+            std::fs::write(&path, content).unwrap();
+            files.push((name.to_string(), path, content.len() as u64));
+        }
+        let db = mem::Store::new();
+        let entries = pack(&db, files).await.unwrap();
+        let (collection, _tags) = entries
+            .into_iter()
+            .map(|(name, tag)| ((name, *tag.hash()), tag))
+            .unzip::<_, _, Collection, Vec<_>>();
+        assert!(collection.iter().all(|(name, _)| is_internal(name)));
+        let names = file_names(&db, &collection).await.unwrap();
+        assert_eq!(names, ["a.txt", "dir/b", "dir/c"]);
+        let mut unpacked = Vec::new();
+        unpack(&db, &collection, |name, data| {
+            unpacked.push((name.to_string(), data.to_vec()));
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(unpacked[0], ("a.txt".to_string(), b"hello".to_vec()));
+        assert_eq!(unpacked[1], ("dir/b".to_string(), Vec::new()));
+        assert_eq!(unpacked[2], ("dir/c".to_string(), b"world".to_vec()));
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use iroh_bytes::Hash;
+use iroh_net::{magic_endpoint::get_remote_node_id, ticket::BlobTicket, MagicEndpoint, NodeId};
+use rand::Rng;
+
+use crate::{
+    audit::{AuditEvent, AuditLog},
+    auth::tokens_equal,
+    errors::ErrorCode,
+};
+
+/// Protocol of the password handshake, which unlocks the blob protocol of a
+/// password protected share for the peer.
+pub const ALPN: &[u8] = b"/sendme/password/1";
+
+/// Close code for blob connections of peers that did not give the password.
+pub const PASSWORD_REQUIRED: u32 = 5;
+
+/// Close code for password handshakes with a wrong answer.
+pub const WRONG_PASSWORD: u32 = 6;
+
+/// First byte of a handshake, quinn only tells the provider about a stream
+/// once something was sent on it.
+const VERSION: u8 = 1;
+
+const KDF_CONTEXT: &str = "sendme 2024-02 share password v1";
+
+/// scrypt cost of the key derivation, to slow down guessing the password
+/// from an answer. N = 2^15 with r = 8 takes 32 MiB.
+const KDF_LOG_N: u8 = 15;
+const KDF_R: u32 = 8;
+const KDF_P: u32 = 1;
+
+/// Separates the password hint from the ticket, `<ticket>?pw=<hint>`.
+const HINT_SEPARATOR: &str = "?pw=";
+
+/// The secret both sides derive from the password, salted with the hash of
+/// the collection so equal passwords give different tokens for different
+/// shares.
+///
+/// scrypt takes a while on purpose, so it runs on a blocking thread.
+async fn derive_token(hash: Hash, password: &str) -> anyhow::Result<[u8; 32]> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        let params = scrypt::Params::new(KDF_LOG_N, KDF_R, KDF_P, 32)
+            .map_err(|_| anyhow::anyhow!("invalid key derivation parameters"))?;
+        let salt = [KDF_CONTEXT.as_bytes(), hash.as_bytes()].concat();
+        let mut token = [0u8; 32];
+        scrypt::scrypt(password.as_bytes(), &salt, &params, &mut token)
+            .map_err(|_| anyhow::anyhow!("failed to derive the password token"))?;
+        Ok(token)
+    })
+    .await?
+}
+
+/// A few bits of the token, enough to catch typos before connecting, but
+/// too few to tell the password when guessing offline.
+fn hint(token: &[u8; 32]) -> String {
+    hex::encode(&blake3::hash(token).as_bytes()[..2])
+}
+
+/// The answer to `nonce`, bound to the node id of the peer unlocking, so it
+/// can't be replayed by another node.
+fn answer(token: &[u8; 32], nonce: &[u8; 32], peer: &NodeId) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new_keyed(token);
+    hasher.update(nonce);
+    hasher.update(peer.as_bytes());
+    *hasher.finalize().as_bytes()
+}
+
+/// The ticket of a password protected share with the password hint beside
+/// it, as it is given to the receiver.
+pub async fn protected_ticket(ticket: &BlobTicket, password: &str) -> anyhow::Result<String> {
+    let token = derive_token(ticket.hash(), password).await?;
+    Ok(ticket_text(ticket, Some(&hint(&token))))
+}
+
+/// The text of `ticket` with the password hint of a protected share, as
+/// [`parse_ticket`] reads it.
+pub fn ticket_text(ticket: &BlobTicket, hint: Option<&str>) -> String {
+    match hint {
+        Some(hint) => format!("{}{}{}", ticket, HINT_SEPARATOR, hint),
+        None => ticket.to_string(),
+    }
+}
+
+/// The ticket and password hint of a ticket as written by
+/// [`protected_ticket`], or of a plain ticket.
+pub fn parse_ticket(text: &str) -> anyhow::Result<(BlobTicket, Option<String>)> {
+    let text = text.trim();
+    let (ticket, hint) = match text.split_once(HINT_SEPARATOR) {
+        Some((ticket, hint)) => (ticket, Some(hint.to_string())),
+        None => (text, None),
+    };
+    let ticket = BlobTicket::from_str(ticket).context("invalid ticket")?;
+    Ok((ticket, hint))
+}
+
+/// Check the password for a share with a hint before connecting.
+pub async fn check_hint(
+    ticket: &BlobTicket,
+    hint: Option<&str>,
+    password: Option<&str>,
+) -> anyhow::Result<()> {
+    let Some(expected) = hint else {
+        return Ok(());
+    };
+    let Some(password) = password else {
+        return Err(ErrorCode::PasswordRequired.into());
+    };
+    if self::hint(&derive_token(ticket.hash(), password).await?) != expected {
+        return Err(ErrorCode::WrongPassword.into());
+    }
+    Ok(())
+}
+
+/// The password of a share and the peers that gave it.
+#[derive(Debug)]
+pub struct PasswordGate {
+    hash: Hash,
+    token: [u8; 32],
+    unlocked: Mutex<HashSet<NodeId>>,
+    audit: Arc<AuditLog>,
+}
+
+impl PasswordGate {
+    pub async fn new(hash: Hash, password: &str, audit: Arc<AuditLog>) -> anyhow::Result<Self> {
+        Ok(Self {
+            hash,
+            token: derive_token(hash, password).await?,
+            unlocked: Default::default(),
+            audit,
+        })
+    }
+
+    /// Whether `peer` gave the password, recording refusals in the audit log.
+    pub fn allow(&self, peer: Option<NodeId>) -> bool {
+        let unlocked = self.unlocked.lock().unwrap();
+        if peer.is_some_and(|peer| unlocked.contains(&peer)) {
+            return true;
+        }
+        self.refuse(peer, "password required");
+        false
+    }
+
+    fn refuse(&self, peer: Option<NodeId>, reason: &str) {
+        self.audit.record(AuditEvent::Refused {
+            peer: peer.map(|peer| peer.to_string()),
+            hash: self.hash.to_hex().to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Answer the password handshake on `connection`: send a random nonce
+    /// and unlock the peer if it answers with the token.
+    pub async fn handshake(&self, connection: &quinn::Connection) -> anyhow::Result<()> {
+        let peer = get_remote_node_id(connection)?;
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let mut version = [0u8; 1];
+        recv.read_exact(&mut version).await?;
+        anyhow::ensure!(version[0] == VERSION, "unknown handshake {}", version[0]);
+        let nonce = rand::thread_rng().gen::<[u8; 32]>();
+        send.write_all(&nonce).await?;
+        let mut given = [0u8; 32];
+        recv.read_exact(&mut given).await?;
+        let expected = answer(&self.token, &nonce, &peer);
+        if !tokens_equal(&hex::encode(expected), &hex::encode(given)) {
+            self.refuse(Some(peer), "wrong password");
+            connection.close(WRONG_PASSWORD.into(), b"wrong password");
+            anyhow::bail!("wrong password from {}", peer);
+        }
+        self.unlocked.lock().unwrap().insert(peer);
+        send.write_all(&[1]).await?;
+        send.finish().await?;
+        Ok(())
+    }
+}
+
+/// Give the provider of `ticket` the password, so it serves the blob
+/// requests of `endpoint` from now on.
+pub async fn unlock(
+    endpoint: &MagicEndpoint,
+    ticket: &BlobTicket,
+    password: &str,
+) -> anyhow::Result<()> {
+    let token = derive_token(ticket.hash(), password).await?;
+    let connection = endpoint.connect(ticket.node_addr().clone(), ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.write_all(&[VERSION]).await?;
+    let mut nonce = [0u8; 32];
+    recv.read_exact(&mut nonce).await?;
+    send.write_all(&answer(&token, &nonce, &endpoint.node_id()))
+        .await?;
+    send.finish().await?;
+    // the provider closes the connection instead for a wrong password
+    let mut unlocked = [0u8; 1];
+    recv.read_exact(&mut unlocked).await?;
+    connection.close(0u32.into(), b"unlocked");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::BlobFormat;
+    use iroh_net::{key::SecretKey, NodeAddr};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn checks_hints() {
+        let addr = NodeAddr::new(SecretKey::generate().public())
+            .with_derp_url("https://derp.example.com".parse().unwrap());
+        let ticket = BlobTicket::new(addr, Hash::new(b"hello"), BlobFormat::HashSeq).unwrap();
+        let text = protected_ticket(&ticket, "correct horse").await.unwrap();
+        let (parsed, hint) = parse_ticket(&format!(" {}\n", text)).unwrap();
+        assert_eq!(parsed.hash(), ticket.hash());
+        assert_eq!(ticket_text(&parsed, hint.as_deref()), text);
+        let hint = hint.as_deref();
+        assert!(check_hint(&parsed, hint, Some("correct horse"))
+            .await
+            .is_ok());
+        let wrong = check_hint(&parsed, hint, Some("battery staple"))
+            .await
+            .unwrap_err();
+        assert_eq!(crate::errors::classify(&wrong), ErrorCode::WrongPassword);
+        let missing = check_hint(&parsed, hint, None).await.unwrap_err();
+        assert_eq!(
+            crate::errors::classify(&missing),
+            ErrorCode::PasswordRequired
+        );
+        // plain tickets need no password
+        let (_, hint) = parse_ticket(&ticket.to_string()).unwrap();
+        assert!(check_hint(&ticket, hint.as_deref(), None).await.is_ok());
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::watch;
+
+use crate::{auth::SessionToken, tray};
+
+/// Why transfers are currently paused.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Paused {
+    /// Paused by the user, applies to everything.
+    pub manual: bool,
+    /// Paused by a quiet hours window, urgent transfers keep running.
+    pub quiet_hours: bool,
+}
+
+impl Paused {
+    /// Whether a transfer should be paused.
+    pub fn applies(&self, urgent: bool) -> bool {
+        self.manual || (self.quiet_hours && !urgent)
+    }
+}
+
+/// How a single share is paused by the user, on top of the global pause.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SharePause {
+    #[default]
+    Running,
+    /// New connections are refused, running downloads finish.
+    Paused,
+    /// New connections are refused and running downloads are dropped, like
+    /// with the global pause.
+    Suspended,
+}
+
+impl SharePause {
+    /// Whether new connections are refused.
+    pub fn refuses(self) -> bool {
+        self != SharePause::Running
+    }
+}
+
+/// Global pause switch for all shares and downloads.
+///
+/// While paused, providers refuse new connections and drop the ones in
+/// flight. Stores, tags and endpoints are kept, so resuming is instant and
+/// receivers can continue where they left off.
+#[derive(Debug, Clone)]
+pub struct PauseState(Arc<watch::Sender<Paused>>);
+
+impl Default for PauseState {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(Paused::default()).0))
+    }
+}
+
+impl PauseState {
+    pub fn get(&self) -> Paused {
+        *self.0.borrow()
+    }
+
+    /// Whether the user paused everything.
+    pub fn is_paused(&self) -> bool {
+        self.get().manual
+    }
+
+    fn modify(&self, f: impl FnOnce(&mut Paused)) -> bool {
+        self.0.send_if_modified(|current| {
+            let before = *current;
+            f(current);
+            before != *current
+        })
+    }
+
+    pub fn set(&self, paused: bool) -> bool {
+        self.modify(|p| p.manual = paused)
+    }
+
+    pub fn set_quiet_hours(&self, active: bool) -> bool {
+        self.modify(|p| p.quiet_hours = active)
+    }
+
+    /// Get notified whenever the pause state changes.
+    pub fn subscribe(&self) -> watch::Receiver<Paused> {
+        self.0.subscribe()
+    }
+}
+
+/// Tell the frontend and the tray about a changed pause state.
+pub fn notify(app: &AppHandle, paused: Paused) {
+    app.emit_all("pause-changed", paused).ok();
+    tray::rebuild(app);
+}
+
+fn set_paused(app: &AppHandle, pause: &PauseState, paused: bool) {
+    log!(
+        "{} all transfers",
+        if paused { "pausing" } else { "resuming" }
+    );
+    if pause.set(paused) {
+        notify(app, pause.get());
+    }
+}
+
+#[tauri::command]
+pub fn pause_all(
+    token: String,
+    session: State<'_, SessionToken>,
+    app: AppHandle,
+    pause: State<'_, PauseState>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    set_paused(&app, &pause, true);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn resume_all(
+    token: String,
+    session: State<'_, SessionToken>,
+    app: AppHandle,
+    pause: State<'_, PauseState>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    set_paused(&app, &pause, false);
+    Ok(())
+}
+
+/// Flip the pause state, used by the tray item.
+pub fn toggle(app: &AppHandle) {
+    let pause = app.state::<PauseState>();
+    set_paused(app, &pause, !pause.is_paused());
+}
@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use serde::Serialize;
+use tauri::{api::dialog::blocking::FileDialogBuilder, Window};
+
+use crate::upload::{self, PathPayload};
+
+/// A picked file or folder, checked to be readable.
+#[derive(Debug, Clone, Serialize)]
+pub struct PickedPath {
+    /// The path to pass to `upload`.
+    pub path: PathPayload,
+    pub name: String,
+    pub is_dir: bool,
+    /// Size of the file, or of all files below the folder.
+    pub size: u64,
+}
+
+fn check(path: PathBuf) -> anyhow::Result<PickedPath> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+    let is_dir = path.is_dir();
+    let readable = if is_dir {
+        std::fs::read_dir(&path).map(|_| ())
+    } else {
+        std::fs::File::open(&path).map(|_| ())
+    };
+    readable.with_context(|| format!("cannot read {}", path.display()))?;
+    let size = upload::total_size(std::slice::from_ref(&path))?;
+    Ok(PickedPath {
+        path: PathPayload::from_path(&path),
+        name,
+        is_dir,
+        size,
+    })
+}
+
+/// Show a dialog from `window` on a blocking thread, then check the picked
+/// paths. Empty if the dialog was closed.
+async fn pick(
+    window: Window,
+    dialog: impl FnOnce(FileDialogBuilder) -> Option<Vec<PathBuf>> + Send + 'static,
+) -> Result<Vec<PickedPath>, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let builder = FileDialogBuilder::new().set_parent(&window);
+        let paths = dialog(builder).unwrap_or_default();
+        paths
+            .into_iter()
+            .map(check)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|e| format!("{:#}", e))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Let the user pick files to share.
+#[tauri::command]
+pub async fn pick_files(window: Window) -> Result<Vec<PickedPath>, String> {
+    pick(window, |dialog| dialog.pick_files()).await
+}
+
+/// Let the user pick a folder to share, at most one entry.
+#[tauri::command]
+pub async fn pick_folder(window: Window) -> Result<Vec<PickedPath>, String> {
+    pick(window, |dialog| dialog.pick_folder().map(|p| vec![p])).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_picked_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("a.txt"), b"hello").unwrap();
+        let picked = check(dir.to_path_buf()).unwrap();
+        assert!(picked.is_dir);
+        assert_eq!(picked.size, 5);
+        let picked = check(dir.join("a.txt")).unwrap();
+        assert_eq!((picked.name.as_str(), picked.is_dir), ("a.txt", false));
+        assert!(check(dir.join("missing")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn picks_non_unicode_paths() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join(OsStr::from_bytes(b"caf\xe9"));
+        std::fs::write(&path, b"").unwrap();
+        let picked = check(path.clone()).unwrap();
+        assert_eq!(picked.name, "caf\u{fffd}");
+        assert_eq!(picked.path.into_path().unwrap(), path);
+    }
+}
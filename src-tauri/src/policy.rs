@@ -0,0 +1,254 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::State;
+
+use crate::settings::{Settings, SettingsStore};
+
+/// Settings an administrator forces for every user of the machine.
+///
+/// Read from `policy.toml` in a directory only administrators can write to,
+/// see [`path`]. For example:
+///
+/// ```toml
+/// disable_sharing = true
+/// relay = "https://relay.example.com"
+/// allowed_destinations = ["/srv/inbox"]
+///
+/// [settings]
+/// telemetry = false
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Refuse to create shares, downloads still work.
+    pub disable_sharing: bool,
+    /// Relay server used by all endpoints.
+    pub relay: Option<String>,
+    /// Folders downloads may be saved to, below any of them. Any folder if
+    /// empty.
+    pub allowed_destinations: Vec<PathBuf>,
+    /// Settings forced to the given values, with the names of the settings
+    /// file. Nested settings are merged, so only the given fields are locked.
+    pub settings: Map<String, Value>,
+}
+
+/// Where the policy is read from.
+pub fn path() -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        std::env::var_os("ProgramData")
+            .map(|dir| PathBuf::from(dir).join("sendme-tauri").join("policy.toml"))
+    } else if cfg!(target_os = "macos") {
+        Some(PathBuf::from(
+            "/Library/Application Support/sendme-tauri/policy.toml",
+        ))
+    } else {
+        Some(PathBuf::from("/etc/sendme-tauri/policy.toml"))
+    }
+}
+
+/// `path` with symlinks, `.` and `..` resolved, also if it does not exist
+/// yet: its nearest existing ancestor is canonicalized and the rest appended.
+/// `None` if the rest climbs up with `..`.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(mut resolved) = existing.canonicalize() {
+            for component in rest.into_iter().rev() {
+                match component {
+                    Component::Normal(name) => resolved.push(name),
+                    Component::CurDir => {}
+                    _ => return None,
+                }
+            }
+            return Some(resolved);
+        }
+        rest.push(existing.components().next_back()?);
+        existing = match existing.parent()? {
+            parent if parent.as_os_str().is_empty() => Path::new("."),
+            parent => parent,
+        };
+    }
+}
+
+/// Put the fields of `forced` into `value`, recursing into objects.
+fn merge(value: &mut Value, forced: &Map<String, Value>) {
+    let Value::Object(fields) = value else {
+        *value = Value::Object(forced.clone());
+        return;
+    };
+    for (key, forced) in forced {
+        match (fields.get_mut(key), forced) {
+            (Some(field @ Value::Object(_)), Value::Object(forced)) => merge(field, forced),
+            _ => {
+                fields.insert(key.clone(), forced.clone());
+            }
+        }
+    }
+}
+
+/// Dotted names of the fields of `forced`, e.g. `network.port`.
+fn leaves(prefix: &str, forced: &Map<String, Value>, names: &mut Vec<String>) {
+    for (key, value) in forced {
+        let name = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            Value::Object(fields) if !fields.is_empty() => leaves(&name, fields, names),
+            _ => names.push(name),
+        }
+    }
+}
+
+impl Policy {
+    /// Read the policy at `path`, the empty policy if there is none.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err).with_context(|| format!("cannot read {}", path.display())),
+        };
+        let policy: Self =
+            toml::from_str(&data).with_context(|| format!("invalid policy {}", path.display()))?;
+        // forced values have to make valid settings
+        policy.try_apply(Settings::default())?;
+        Ok(policy)
+    }
+
+    fn try_apply(&self, settings: Settings) -> anyhow::Result<Settings> {
+        if self.settings.is_empty() && self.relay.is_none() {
+            return Ok(settings);
+        }
+        let mut value = serde_json::to_value(settings)?;
+        merge(&mut value, &self.settings);
+        let mut settings: Settings =
+            serde_json::from_value(value).context("the policy forces invalid settings")?;
+        if let Some(relay) = &self.relay {
+            settings.network.relay = Some(relay.clone());
+        }
+        Ok(settings)
+    }
+
+    /// `settings` with the forced values.
+    pub fn apply(&self, settings: Settings) -> Settings {
+        // checked when loading
+        self.try_apply(settings.clone()).unwrap_or(settings)
+    }
+
+    /// Dotted names of the locked settings, for the settings view.
+    pub fn locked(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        leaves("", &self.settings, &mut names);
+        if self.relay.is_some() && !names.iter().any(|n| n == "network.relay") {
+            names.push("network.relay".to_string());
+        }
+        names
+    }
+
+    /// Whether downloads may be saved to `dest`.
+    pub fn allows_destination(&self, dest: &Path) -> bool {
+        if self.allowed_destinations.is_empty() {
+            return true;
+        }
+        // starts_with compares components, `..` must be gone
+        let Some(dest) = resolve(dest) else {
+            return false;
+        };
+        self.allowed_destinations.iter().any(|allowed| {
+            let allowed = resolve(allowed).unwrap_or_else(|| allowed.to_path_buf());
+            dest.starts_with(allowed)
+        })
+    }
+
+    pub fn check_sharing(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.disable_sharing,
+            "sharing is disabled by your administrator"
+        );
+        Ok(())
+    }
+
+    pub fn check_destination(&self, dest: &Path) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.allows_destination(dest),
+            "your administrator does not allow saving to {}",
+            dest.display()
+        );
+        Ok(())
+    }
+}
+
+/// What the settings view needs to know about the policy.
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyReport {
+    /// Where the policy is read from.
+    pub path: Option<String>,
+    /// Dotted names of the settings the user can not change.
+    pub locked: Vec<String>,
+    pub disable_sharing: bool,
+    pub allowed_destinations: Vec<PathBuf>,
+}
+
+#[tauri::command]
+pub fn get_policy(settings: State<'_, SettingsStore>) -> PolicyReport {
+    let policy = settings.policy();
+    PolicyReport {
+        path: path().map(|p| p.display().to_string()),
+        locked: policy.locked(),
+        disable_sharing: policy.disable_sharing,
+        allowed_destinations: policy.allowed_destinations.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{AddressFamily, NetworkSettings};
+
+    #[test]
+    fn forces_settings() {
+        let policy: Policy = toml::from_str(
+            r#"
+            relay = "https://relay.example.com"
+            allowed_destinations = ["/srv/inbox"]
+
+            [settings]
+            telemetry = false
+
+            [settings.network]
+            port = 4000
+            "#,
+        )
+        .unwrap();
+        let settings = policy.apply(Settings {
+            telemetry: true,
+            network: NetworkSettings {
+                family: AddressFamily::Ipv4,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert!(!settings.telemetry);
+        assert_eq!(settings.network.port, Some(4000));
+        // fields that are not forced keep the user's values
+        assert_eq!(settings.network.family, AddressFamily::Ipv4);
+        assert_eq!(
+            settings.network.relay.as_deref(),
+            Some("https://relay.example.com")
+        );
+        assert_eq!(
+            policy.locked(),
+            ["network.port", "telemetry", "network.relay"]
+        );
+        assert!(policy.allows_destination(Path::new("/srv/inbox/a")));
+        assert!(!policy.allows_destination(Path::new("/srv/other")));
+        assert!(!policy.allows_destination(Path::new("/srv/inbox/../../etc/x")));
+        let invalid: Policy = toml::from_str("[settings]\ntelemetry = 3").unwrap();
+        assert!(invalid.try_apply(Settings::default()).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use iroh_bytes::{
+    provider::{Event, EventSender},
+    store::ImportProgress,
+};
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+/// Minimum time between two progress events for the same file.
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// Sent to the frontend as `transfer-progress`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum TransferProgress {
+    /// A file of the share is being hashed.
+    Import {
+        share: String,
+        file: String,
+        bytes: u64,
+    },
+    /// A peer connected to the share.
+    Connected { share: String, peer: Option<String> },
+    /// A file of the share was sent to a peer.
+    Send {
+        share: String,
+        peer: Option<String>,
+        file: Option<String>,
+        /// Bytes sent to this peer so far.
+        bytes: u64,
+        total: u64,
+    },
+    /// A request of a peer ended.
+    Finished {
+        share: String,
+        peer: Option<String>,
+        bytes: u64,
+        ok: bool,
+    },
+}
+
+/// Where progress of shares is reported to.
+#[derive(Clone)]
+pub struct Progress(Arc<dyn Fn(TransferProgress) + Send + Sync>);
+
+impl Progress {
+    /// Emit progress to all windows of the app, with desktop notifications
+    /// for connecting peers.
+    pub fn emitter(app: AppHandle) -> Self {
+        Self(Arc::new(move |progress| {
+            crate::notify::desktop_progress(&app, &progress);
+            app.emit_all("transfer-progress", progress).ok();
+        }))
+    }
+
+    /// Drop all progress, for shares without a frontend.
+    pub fn ignore() -> Self {
+        Self(Arc::new(|_| {}))
+    }
+
+    pub fn emit(&self, progress: TransferProgress) {
+        (self.0)(progress)
+    }
+
+    /// Report the import progress from `recv` until the import is done.
+    ///
+    /// `sources` maps the names in the collection to the imported paths, the
+    /// names are what is reported.
+    pub async fn import(
+        self,
+        share: String,
+        sources: Vec<(String, PathBuf)>,
+        recv: flume::Receiver<ImportProgress>,
+    ) {
+        let mut files = HashMap::new();
+        let mut sizes = HashMap::new();
+        let mut last = HashMap::new();
+        while let Ok(msg) = recv.recv_async().await {
+            let (id, bytes) = match msg {
+                ImportProgress::Found { id, name } => {
+                    let name = sources
+                        .iter()
+                        .find(|(_, path)| path.as_path() == Path::new(&name))
+                        .map_or(name, |(name, _)| name.clone());
+                    files.insert(id, name);
+                    continue;
+                }
+                ImportProgress::Size { id, size } => {
+                    sizes.insert(id, size);
+                    continue;
+                }
+                ImportProgress::CopyProgress { id, offset }
+                | ImportProgress::OutboardProgress { id, offset } => (id, offset),
+                ImportProgress::OutboardDone { id, .. } => {
+                    last.remove(&id);
+                    (id, sizes.get(&id).copied().unwrap_or_default())
+                }
+            };
+            let now = Instant::now();
+            if let Some(t) = last.get(&id) {
+                if now.duration_since(*t) < INTERVAL {
+                    continue;
+                }
+            }
+            last.insert(id, now);
+            let Some(file) = files.get(&id) else {
+                continue;
+            };
+            self.emit(TransferProgress::Import {
+                share: share.clone(),
+                file: file.clone(),
+                bytes,
+            });
+        }
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Progress").finish()
+    }
+}
+
+/// What the progress of sending a share is reported with.
+#[derive(Debug, Clone)]
+pub struct ShareProgress {
+    pub share: String,
+    /// Names of the files of the share, in collection order.
+    pub files: Arc<[String]>,
+    pub total: u64,
+    pub progress: Progress,
+}
+
+impl ShareProgress {
+    /// Wrap the events of a single request of `peer`.
+    pub fn events<E>(&self, inner: E, peer: Option<String>) -> SendProgress<E> {
+        SendProgress {
+            inner,
+            share: self.clone(),
+            peer,
+            sent: Default::default(),
+        }
+    }
+
+    /// Report that `peer` connected.
+    pub fn connected(&self, peer: Option<String>) {
+        self.emit(TransferProgress::Connected {
+            share: self.share.clone(),
+            peer,
+        });
+    }
+
+    fn emit(&self, progress: TransferProgress) {
+        self.progress.emit(progress)
+    }
+}
+
+/// Reports what a single request of a peer sent, then passes the events on.
+#[derive(Debug, Clone)]
+pub struct SendProgress<E> {
+    inner: E,
+    share: ShareProgress,
+    peer: Option<String>,
+    sent: Arc<AtomicU64>,
+}
+
+impl<E: EventSender> EventSender for SendProgress<E> {
+    fn send(&self, event: Event) -> BoxFuture<'_, ()> {
+        match &event {
+            Event::TransferBlobCompleted { index, size, .. } => {
+                let bytes = self.sent.fetch_add(*size, Ordering::Relaxed) + size;
+                self.share.emit(TransferProgress::Send {
+                    share: self.share.share.clone(),
+                    peer: self.peer.clone(),
+                    file: self.share.files.get(*index as usize).cloned(),
+                    bytes,
+                    total: self.share.total,
+                });
+            }
+            Event::TransferCompleted { .. } | Event::TransferAborted { .. } => {
+                self.share.emit(TransferProgress::Finished {
+                    share: self.share.share.clone(),
+                    peer: self.peer.clone(),
+                    bytes: self.sent.load(Ordering::Relaxed),
+                    ok: matches!(event, Event::TransferCompleted { .. }),
+                });
+            }
+            _ => {}
+        }
+        let inner = self.inner.clone();
+        async move { inner.send(event).await }.boxed()
+    }
+}
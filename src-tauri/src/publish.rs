@@ -0,0 +1,560 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use iroh_net::{ticket::BlobTicket, MagicEndpoint};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::{
+    auth::SessionToken,
+    download::{Conflicts, DownloadOptions, DownloadStats, Resolution},
+    errors::{ErrorCode, UserError},
+    i18n::I18n,
+    identity::Identity,
+    settings::SettingsStore,
+    transfers::TransferManager,
+    upload::ShareOptions,
+};
+
+/// Protocol subscribers ask a publisher for the latest version of a
+/// publication with. The shares of publications speak it besides the blob
+/// protocol.
+pub const ALPN: &[u8] = b"/sendme/publication/1";
+
+/// How often subscriptions are checked for new versions.
+const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+const MAX_NAME_LEN: usize = 256;
+
+const MAX_POINTER_LEN: usize = 64 * 1024;
+
+/// The latest version of a publication, as sent to subscribers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pointer {
+    pub version: u64,
+    pub ticket: String,
+}
+
+/// A folder published under a stable name. Every publish of changed content
+/// is a new version, shared with a new ticket.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Publication {
+    pub name: String,
+    pub folder: PathBuf,
+    pub version: u64,
+    /// Hash of the collection of the latest version.
+    pub hash: String,
+    pub ticket: String,
+}
+
+/// A publication of someone else this app follows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub id: u64,
+    pub name: String,
+    /// Latest known version and its ticket.
+    pub version: u64,
+    pub ticket: String,
+    /// The version in `dest`, none before the first download.
+    pub downloaded: Option<u64>,
+    pub dest: PathBuf,
+    /// The files and folders of the downloaded version.
+    pub saved: Vec<PathBuf>,
+}
+
+impl Subscription {
+    pub fn has_update(&self) -> bool {
+        !matches!(self.downloaded, Some(version) if version >= self.version)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct PublishState {
+    publications: BTreeMap<String, Publication>,
+    next_id: u64,
+    subscriptions: BTreeMap<u64, Subscription>,
+}
+
+/// Our publications and subscriptions, persisted as json in the app data dir.
+#[derive(Debug)]
+pub struct PublishStore {
+    path: PathBuf,
+    state: Mutex<PublishState>,
+}
+
+impl PublishStore {
+    pub fn load(path: PathBuf) -> Self {
+        let state = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            state: Mutex::new(state),
+        }
+    }
+
+    fn save(&self, state: &PublishState) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn publications(&self) -> Vec<Publication> {
+        let state = self.state.lock().unwrap();
+        state.publications.values().cloned().collect()
+    }
+
+    /// The latest version of publication `name`.
+    fn pointer(&self, name: &str) -> Option<Pointer> {
+        let state = self.state.lock().unwrap();
+        state.publications.get(name).map(|publication| Pointer {
+            version: publication.version,
+            ticket: publication.ticket.clone(),
+        })
+    }
+
+    /// Record `ticket` as the latest share of publication `name`, a new
+    /// version if its content changed. Returns the publication and the
+    /// ticket of the share it replaces.
+    fn published(
+        &self,
+        name: &str,
+        folder: PathBuf,
+        ticket: &BlobTicket,
+    ) -> anyhow::Result<(Publication, Option<String>)> {
+        let mut state = self.state.lock().unwrap();
+        let hash = ticket.hash().to_hex().to_string();
+        let previous = state.publications.get(name);
+        let version = match previous {
+            Some(p) if p.hash == hash => p.version,
+            Some(p) => p.version + 1,
+            None => 1,
+        };
+        let replaced = previous.map(|p| p.ticket.clone());
+        let publication = Publication {
+            name: name.to_string(),
+            folder,
+            version,
+            hash,
+            ticket: ticket.to_string(),
+        };
+        state
+            .publications
+            .insert(name.to_string(), publication.clone());
+        self.save(&state)?;
+        Ok((publication, replaced))
+    }
+
+    fn unpublish(&self, name: &str) -> anyhow::Result<Publication> {
+        let mut state = self.state.lock().unwrap();
+        let publication = state
+            .publications
+            .remove(name)
+            .with_context(|| format!("no publication {}", name))?;
+        self.save(&state)?;
+        Ok(publication)
+    }
+
+    pub fn subscriptions(&self) -> Vec<Subscription> {
+        let state = self.state.lock().unwrap();
+        state.subscriptions.values().cloned().collect()
+    }
+
+    fn subscription(&self, id: u64) -> anyhow::Result<Subscription> {
+        let state = self.state.lock().unwrap();
+        state
+            .subscriptions
+            .get(&id)
+            .cloned()
+            .with_context(|| format!("no subscription {}", id))
+    }
+
+    fn subscribe(
+        &self,
+        name: String,
+        ticket: String,
+        dest: PathBuf,
+    ) -> anyhow::Result<Subscription> {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let subscription = Subscription {
+            id: state.next_id,
+            name,
+            version: 0,
+            ticket,
+            downloaded: None,
+            dest,
+            saved: Vec::new(),
+        };
+        state
+            .subscriptions
+            .insert(subscription.id, subscription.clone());
+        self.save(&state)?;
+        Ok(subscription)
+    }
+
+    fn unsubscribe(&self, id: u64) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        anyhow::ensure!(
+            state.subscriptions.remove(&id).is_some(),
+            "no subscription {}",
+            id
+        );
+        self.save(&state)
+    }
+
+    /// Record the latest version of subscription `id`, returning the
+    /// subscription if the version is new.
+    fn update(&self, id: u64, pointer: Pointer) -> anyhow::Result<Option<Subscription>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(subscription) = state.subscriptions.get_mut(&id) else {
+            return Ok(None);
+        };
+        if pointer.version <= subscription.version {
+            return Ok(None);
+        }
+        subscription.version = pointer.version;
+        subscription.ticket = pointer.ticket;
+        let subscription = subscription.clone();
+        self.save(&state)?;
+        Ok(Some(subscription))
+    }
+
+    fn downloaded(&self, id: u64, version: u64, saved: &[String]) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(subscription) = state.subscriptions.get_mut(&id) {
+            subscription.downloaded = Some(version);
+            subscription.saved = saved.iter().map(PathBuf::from).collect();
+        }
+        self.save(&state)
+    }
+}
+
+/// Split a link of [`Publication::link`] into the name and ticket.
+fn parse_link(link: &str) -> anyhow::Result<(String, BlobTicket)> {
+    let (name, ticket) = link
+        .trim()
+        .rsplit_once('@')
+        .context("not a publication link")?;
+    anyhow::ensure!(!name.is_empty(), "the link does not name a publication");
+    let ticket =
+        BlobTicket::from_str(ticket).context("the link does not contain a valid ticket")?;
+    Ok((name.to_string(), ticket))
+}
+
+/// Answer a subscriber asking for the latest version of a publication on
+/// `connection`, with json `null` for unknown names.
+pub async fn answer(connection: &quinn::Connection, store: &PublishStore) -> anyhow::Result<()> {
+    let (mut send, mut recv) = connection.accept_bi().await?;
+    let name = recv.read_to_end(MAX_NAME_LEN).await?;
+    let pointer = store.pointer(&String::from_utf8_lossy(&name));
+    send.write_all(&serde_json::to_vec(&pointer)?).await?;
+    send.finish().await?;
+    Ok(())
+}
+
+/// Ask the publisher of `ticket` for the latest version of publication
+/// `name`.
+async fn fetch_pointer(
+    endpoint: &MagicEndpoint,
+    ticket: &BlobTicket,
+    name: &str,
+) -> anyhow::Result<Pointer> {
+    let connection = endpoint.connect(ticket.node_addr().clone(), ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.write_all(name.as_bytes()).await?;
+    send.finish().await?;
+    let data = recv.read_to_end(MAX_POINTER_LEN).await?;
+    connection.close(0u32.into(), b"done");
+    let pointer = serde_json::from_slice::<Option<Pointer>>(&data)?
+        .with_context(|| format!("{} is no longer published", name))?;
+    let latest = BlobTicket::from_str(&pointer.ticket).context("invalid ticket")?;
+    // the latest version may not come from someone else
+    anyhow::ensure!(
+        latest.node_addr().node_id == ticket.node_addr().node_id,
+        "the publisher of {} changed",
+        name
+    );
+    Ok(pointer)
+}
+
+/// Share `folder` as the latest version of publication `name`, stopping the
+/// share of the version before.
+async fn publish_folder(
+    app: &AppHandle,
+    name: &str,
+    folder: PathBuf,
+) -> anyhow::Result<Publication> {
+    anyhow::ensure!(folder.is_dir(), "{} is not a folder", folder.display());
+    let opts = ShareOptions {
+        publication: Some(name.to_string()),
+        ..Default::default()
+    };
+    let ticket = crate::share(app, vec![folder.clone()], opts).await?;
+    let store = app.state::<Arc<PublishStore>>();
+    let (publication, replaced) = store.published(name, folder, &ticket)?;
+    log!("published {} version {}", name, publication.version);
+    if let Some(replaced) = replaced.filter(|t| *t != publication.ticket) {
+        stop_share(app, &replaced).await;
+    }
+    Ok(publication)
+}
+
+/// Stop the share with `ticket`, if it is still running.
+async fn stop_share(app: &AppHandle, ticket: &str) {
+    let transfers = app.state::<TransferManager>();
+    let ids = transfers
+        .list()
+        .into_iter()
+        .filter(|info| info.ticket == ticket)
+        .map(|info| info.id)
+        .collect::<Vec<_>>();
+    for id in ids {
+        transfers.cancel(id).await;
+    }
+    crate::tray::rebuild(app);
+}
+
+/// Share the latest content of every publication again after a start, so
+/// subscribers find it.
+pub fn spawn_republish(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let publications = app.state::<Arc<PublishStore>>().publications();
+        for publication in publications {
+            if let Err(err) = publish_folder(&app, &publication.name, publication.folder).await {
+                log!("failed to publish {}: {:#}", publication.name, err);
+            }
+        }
+    });
+}
+
+/// Ask the publishers of all subscriptions for new versions, telling the user
+/// about them with a notification and `publication-updated`.
+pub fn spawn_poller(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            if let Err(err) = poll(&app).await {
+                log!("failed to check subscriptions: {:#}", err);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
+
+async fn poll(app: &AppHandle) -> anyhow::Result<()> {
+    let store = app.state::<Arc<PublishStore>>();
+    let subscriptions = store.subscriptions();
+    if subscriptions.is_empty() {
+        return Ok(());
+    }
+    let settings = app.state::<SettingsStore>().get();
+    let secret_key = app.state::<Identity>().secret_key();
+    let endpoint = crate::download::bind(&settings, secret_key).await?;
+    for subscription in subscriptions {
+        let ticket = BlobTicket::from_str(&subscription.ticket)?;
+        let pointer = match fetch_pointer(&endpoint, &ticket, &subscription.name).await {
+            Ok(pointer) => pointer,
+            Err(err) => {
+                log!("failed to check {}: {:#}", subscription.name, err);
+                continue;
+            }
+        };
+        if let Some(updated) = store.update(subscription.id, pointer)? {
+            let version = updated.version.to_string();
+            crate::notify::desktop(
+                app,
+                "publication.updated",
+                &[("name", &updated.name), ("version", &version)],
+            );
+            app.emit_all("publication-updated", &updated).ok();
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_publications(store: State<'_, Arc<PublishStore>>) -> Vec<Publication> {
+    store.publications()
+}
+
+/// Publish `folder` under `name`, or a new version of it if it is published
+/// already. Teammates subscribe with the link of the returned publication.
+#[tauri::command]
+pub async fn publish(
+    name: String,
+    folder: PathBuf,
+    token: String,
+    session: State<'_, SessionToken>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<Publication, UserError> {
+    session
+        .verify(&token)
+        .map_err(|msg| UserError::new(ErrorCode::Unknown, msg, &i18n))?;
+    publish_folder(&app, name.trim(), folder)
+        .await
+        .map_err(|e| UserError::from_anyhow(&e, &i18n))
+}
+
+/// Stop publishing `name`, subscribers see no new versions any more.
+#[tauri::command]
+pub async fn unpublish(
+    name: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    store: State<'_, Arc<PublishStore>>,
+    app: AppHandle,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let publication = store.unpublish(&name).map_err(|e| e.to_string())?;
+    stop_share(&app, &publication.ticket).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_subscriptions(store: State<'_, Arc<PublishStore>>) -> Vec<Subscription> {
+    store.subscriptions()
+}
+
+/// Follow the publication of `link`, downloading its versions into `dest`,
+/// or the default download folder if unset. The latest version is looked up
+/// right away.
+#[tauri::command]
+pub async fn subscribe(
+    link: String,
+    dest: Option<PathBuf>,
+    token: String,
+    session: State<'_, SessionToken>,
+    store: State<'_, Arc<PublishStore>>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<Subscription, UserError> {
+    session
+        .verify(&token)
+        .map_err(|msg| UserError::new(ErrorCode::Unknown, msg, &i18n))?;
+    let res = async {
+        let (name, ticket) = parse_link(&link)?;
+        let settings = app.state::<SettingsStore>();
+        let dest = match dest {
+            Some(dest) => dest,
+            None => crate::download::default_download_dir(&settings.get())?,
+        };
+        settings.policy().check_destination(&dest)?;
+        let subscription = store.subscribe(name, ticket.to_string(), dest)?;
+        poll(&app).await?;
+        store.subscription(subscription.id)
+    }
+    .await;
+    res.map_err(|e| UserError::from_anyhow(&e, &i18n))
+}
+
+#[tauri::command]
+pub fn unsubscribe(
+    id: u64,
+    token: String,
+    session: State<'_, SessionToken>,
+    store: State<'_, Arc<PublishStore>>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    store.unsubscribe(id).map_err(|e| e.to_string())
+}
+
+/// Download the latest version of subscription `id` over the files of the
+/// version before. Files that did not change are taken from those instead of
+/// being fetched again.
+#[tauri::command]
+pub async fn update_subscription(
+    id: u64,
+    token: String,
+    session: State<'_, SessionToken>,
+    store: State<'_, Arc<PublishStore>>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<DownloadStats, UserError> {
+    session
+        .verify(&token)
+        .map_err(|msg| UserError::new(ErrorCode::Unknown, msg, &i18n))?;
+    let res = async {
+        let subscription = store.subscription(id)?;
+        anyhow::ensure!(
+            subscription.has_update(),
+            "{} is up to date",
+            subscription.name
+        );
+        let ticket = BlobTicket::from_str(&subscription.ticket)?;
+        let opts = DownloadOptions {
+            seed_from: subscription.saved.clone(),
+            conflicts: Conflicts {
+                default: Resolution::Overwrite,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        log!(
+            "updating {} to version {}",
+            subscription.name,
+            subscription.version
+        );
+        let stats =
+            crate::download::download_paused(&app, &ticket, &subscription.dest, &opts).await?;
+        store.downloaded(id, subscription.version, &stats.saved)?;
+        anyhow::Ok(stats)
+    }
+    .await;
+    res.map_err(|e| UserError::from_anyhow(&e, &i18n))
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_bytes::{BlobFormat, Hash};
+    use iroh_net::{key::SecretKey, NodeAddr};
+
+    use super::*;
+
+    #[test]
+    fn versions_publications() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let store = PublishStore::load(dir.join("publish.json"));
+        let addr = NodeAddr::new(SecretKey::generate().public())
+            .with_derp_url("https://derp.example.com".parse().unwrap());
+        let v1 = BlobTicket::new(addr.clone(), Hash::new(b"v1"), BlobFormat::HashSeq).unwrap();
+        let v2 = BlobTicket::new(addr, Hash::new(b"v2"), BlobFormat::HashSeq).unwrap();
+        let (publication, replaced) = store.published("docs", dir.to_path_buf(), &v1).unwrap();
+        assert_eq!((publication.version, replaced), (1, None));
+        // publishing the same content again is the same version
+        let (publication, _) = store.published("docs", dir.to_path_buf(), &v1).unwrap();
+        assert_eq!(publication.version, 1);
+        let (publication, replaced) = store.published("docs", dir.to_path_buf(), &v2).unwrap();
+        assert_eq!(publication.version, 2);
+        assert_eq!(replaced, Some(v1.to_string()));
+        let link = format!("{}@{}", publication.name, publication.ticket);
+        let (name, ticket) = parse_link(&link).unwrap();
+        assert_eq!((name.as_str(), ticket.hash()), ("docs", v2.hash()));
+
+        let subscription = store
+            .subscribe(name, v1.to_string(), dir.to_path_buf())
+            .unwrap();
+        assert!(subscription.has_update());
+        let pointer = store.pointer("docs").unwrap();
+        let updated = store.update(subscription.id, pointer.clone()).unwrap();
+        assert_eq!(updated.unwrap().version, 2);
+        // only new versions are reported
+        assert!(store.update(subscription.id, pointer).unwrap().is_none());
+        store.downloaded(subscription.id, 2, &[]).unwrap();
+        let reloaded = PublishStore::load(dir.join("publish.json"));
+        assert!(!reloaded.subscriptions()[0].has_update());
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Context;
+use qrcode::{render::svg, QrCode};
+use serde::Serialize;
+
+use crate::{
+    password,
+    sms::{self, Collected},
+};
+
+/// Longest ticket shown as a single code. Denser codes are hard to read with
+/// a webcam, so longer tickets are shown as an animated sequence.
+const SINGLE_MAX: usize = 400;
+
+/// Characters per frame of an animated code.
+const FRAME_LEN: usize = 200;
+
+/// How long the frontend shows each frame.
+const FRAME_MS: u64 = 300;
+
+/// A ticket as one or more qr codes, each an svg image.
+#[derive(Debug, Clone, Serialize)]
+pub struct TicketQr {
+    pub frames: Vec<String>,
+    pub frame_ms: u64,
+}
+
+fn render(data: &str) -> anyhow::Result<String> {
+    let code = QrCode::new(data.as_bytes()).context("too long for a qr code")?;
+    Ok(code.render::<svg::Color>().min_dimensions(240, 240).build())
+}
+
+/// Render a ticket as a qr code, or as a looping sequence of codes if it is
+/// too long for one. The frames use the same format as split tickets.
+#[tauri::command]
+pub fn ticket_qr(ticket: String) -> Result<TicketQr, String> {
+    let (ticket, hint) = password::parse_ticket(&ticket).map_err(|e| format!("{:#}", e))?;
+    let ticket = password::ticket_text(&ticket, hint.as_deref());
+    let data = if ticket.len() <= SINGLE_MAX {
+        vec![ticket]
+    } else {
+        sms::split(&ticket, FRAME_LEN).map_err(|e| e.to_string())?
+    };
+    let frames = data
+        .iter()
+        .map(|data| render(data))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| e.to_string())?;
+    Ok(TicketQr {
+        frames,
+        frame_ms: FRAME_MS,
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanProgress {
+    pub received: usize,
+    pub total: usize,
+    /// Set once all frames were read.
+    pub ticket: Option<String>,
+}
+
+/// Check the frames decoded from the webcam so far. The webview reads the
+/// codes, this puts animated sequences back together.
+#[tauri::command]
+pub fn scan_qr_frames(frames: Vec<String>) -> Result<ScanProgress, String> {
+    // a single code holds the plain ticket
+    if let Some(ticket) = frames.iter().find_map(|f| password::parse_ticket(f).ok()) {
+        return Ok(ScanProgress {
+            received: 1,
+            total: 1,
+            ticket: Some(password::ticket_text(&ticket.0, ticket.1.as_deref())),
+        });
+    }
+    let collected = sms::collect(frames.iter().map(String::as_str)).map_err(|e| e.to_string())?;
+    Ok(match collected {
+        Collected::Complete { ticket, count } => ScanProgress {
+            received: count,
+            total: count,
+            ticket: Some(ticket),
+        },
+        Collected::Partial { missing, count } => ScanProgress {
+            received: count - missing.len(),
+            total: count,
+            ticket: None,
+        },
+    })
+}
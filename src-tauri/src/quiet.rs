@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::{auth::SessionToken, pause::PauseState, settings::SettingsStore};
+
+/// How often the quiet hours are re-evaluated.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A daily window during which seeding and downloads are paused.
+///
+/// Downloads running when it starts are parked and continue from their
+/// partial data once it ends, see [`crate::download::download_paused`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuietHours {
+    /// Start of the window, `HH:MM` local time.
+    pub start: String,
+    /// End of the window, `HH:MM` local time. May be before `start` to span midnight.
+    pub end: String,
+    /// Days on which the window starts, every day if empty.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+}
+
+pub fn parse_time(s: &str) -> anyhow::Result<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .map_err(|_| anyhow::anyhow!("invalid time {:?}, expected HH:MM", s))
+}
+
+impl QuietHours {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        parse_time(&self.start)?;
+        parse_time(&self.end)?;
+        Ok(())
+    }
+
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    /// Whether `now` falls into this window.
+    pub fn contains(&self, now: NaiveDateTime) -> bool {
+        let (Ok(start), Ok(end)) = (parse_time(&self.start), parse_time(&self.end)) else {
+            return false;
+        };
+        let time = now.time();
+        let today = now.weekday();
+        if start <= end {
+            start <= time && time < end && self.starts_on(today)
+        } else if time >= start {
+            // before midnight, the window started today
+            self.starts_on(today)
+        } else {
+            // after midnight, the window started yesterday
+            time < end && self.starts_on(today.pred())
+        }
+    }
+}
+
+/// Periodically pause and resume transfers according to the configured windows.
+pub fn spawn_scheduler(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let windows = app.state::<SettingsStore>().get().quiet_hours;
+            let now = Local::now().naive_local();
+            let active = windows.iter().any(|w| w.contains(now));
+            let pause = app.state::<PauseState>();
+            if pause.set_quiet_hours(active) {
+                log!("quiet hours {}", if active { "started" } else { "ended" });
+                crate::pause::notify(&app, pause.get());
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub fn get_quiet_hours(settings: State<'_, SettingsStore>) -> Vec<QuietHours> {
+    settings.get().quiet_hours
+}
+
+#[tauri::command]
+pub fn set_quiet_hours(
+    windows: Vec<QuietHours>,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    for window in &windows {
+        window.validate().map_err(|e| e.to_string())?;
+    }
+    settings
+        .update(|s| s.quiet_hours = windows)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::i18n::I18n;
+
+/// A token bucket for a single command.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// Limits for one command: allow `burst` calls at once, refilling at `per_second`.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// Limits for the commands that spawn endpoints or do disk work.
+const LIMITS: &[(&str, Limit)] = &[
+    (
+        "upload",
+        Limit {
+            burst: 5,
+            per_second: 0.5,
+        },
+    ),
+    (
+        "share_batch",
+        Limit {
+            burst: 2,
+            per_second: 0.1,
+        },
+    ),
+    (
+        "share_clipboard",
+        Limit {
+            burst: 5,
+            per_second: 0.5,
+        },
+    ),
+    (
+        "share_screen_recording",
+        Limit {
+            burst: 1,
+            per_second: 0.1,
+        },
+    ),
+    (
+        "download",
+        Limit {
+            burst: 5,
+            per_second: 0.5,
+        },
+    ),
+];
+
+/// Rate limiter for IPC commands, kept in the tauri state.
+///
+/// A misbehaving frontend calling e.g. `upload` in a loop would otherwise
+/// spawn an endpoint and an import per call.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<&'static str, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Take one token for `command`, failing if the command is called too often.
+    ///
+    /// Commands without a configured limit are always allowed.
+    pub fn check(&self, command: &'static str, i18n: &I18n) -> Result<(), String> {
+        let Some((_, limit)) = LIMITS.iter().find(|(name, _)| *name == command) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(command).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            last: now,
+        });
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        bucket.last = now;
+        if bucket.tokens < 1.0 {
+            let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / limit.per_second);
+            log!("rate limited {}", command);
+            let seconds = (wait.as_secs() + 1).to_string();
+            return Err(i18n.translate(
+                "error.rate_limited",
+                &[("command", command), ("seconds", &seconds)],
+            ));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use iroh_bytes::{format::collection::Collection, store::flat, Hash};
+use tauri::{AppHandle, Manager, State};
+use tracing::Instrument;
+
+use crate::{
+    activity::{Activity, ActivityLog},
+    auth::SessionToken,
+    contacts::download_dir,
+    download::{
+        connect, copy_previous, export, export_root, fetch_file_names, get_export_path, previous,
+        selected, top_level, ActiveDownloads, Conflict, Conflicts, DownloadOptions, DownloadStats,
+        PreviousDownload, ReceivedDir, WarmConnections,
+    },
+    errors::{ErrorCode, UserError},
+    history::{Direction, History, HistoryEntry},
+    i18n::I18n,
+    identity::Identity,
+    pack, password,
+    ratelimit::RateLimiter,
+    settings::SettingsStore,
+    telemetry::Telemetry,
+    trace,
+    upload::entry_path,
+};
+
+/// The file names of `paths`, for the activity log.
+fn file_names(paths: &[String]) -> String {
+    paths
+        .iter()
+        .map(|p| {
+            Path::new(p)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| p.clone())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// What a download is called in the activity log and notifications.
+fn display_name(stats: &DownloadStats) -> String {
+    match stats.saved.is_empty() {
+        // kept in the store only
+        true => stats.hash.clone(),
+        false => file_names(&stats.saved),
+    }
+}
+
+/// Download `ticket` into `dest`, or the default download folder if unset.
+#[tauri::command]
+pub async fn download(
+    ticket: String,
+    dest: Option<String>,
+    options: Option<DownloadOptions>,
+    limiter: State<'_, RateLimiter>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<DownloadStats, UserError> {
+    limiter
+        .check("download", &i18n)
+        .map_err(|msg| UserError::new(ErrorCode::RateLimited, msg, &i18n))?;
+    let opts = options.unwrap_or_default();
+    let (ticket, hint) =
+        password::parse_ticket(&ticket).map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    password::check_hint(&ticket, hint.as_deref(), opts.password.as_deref())
+        .await
+        .map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    let dest = match dest {
+        Some(dest) => PathBuf::from(dest),
+        None => download_dir(&app.state::<SettingsStore>().get(), &ticket)
+            .map_err(|e| UserError::from_anyhow(&e, &i18n))?,
+    };
+    app.state::<SettingsStore>()
+        .policy()
+        .check_destination(&dest)
+        .map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    log!(
+        "downloading {} to {}",
+        ticket.hash().to_hex(),
+        dest.display()
+    );
+    let hash = ticket.hash().to_hex().to_string();
+    let activity = app.state::<Arc<ActivityLog>>();
+    let transfer = trace::next_id();
+    let span = tracing::info_span!("download", transfer, hash);
+    let res = match previous(&activity, &hash).filter(|_| opts.reuse_previous) {
+        Some(previous) => {
+            log!("copying {} from an earlier download", hash);
+            copy_previous(&hash, &previous, &dest, &opts.conflicts).map_err(Arc::new)
+        }
+        None => {
+            let (job, attached) = app
+                .state::<ActiveDownloads>()
+                .join(&app, &ticket, &dest, &opts, span)
+                .map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+            if attached {
+                // recorded by the request that started it
+                log!("{} is being downloaded already, attaching", hash);
+                return job.await.map_err(|e| UserError::from_anyhow(&e, &i18n));
+            }
+            let res = job.await;
+            app.state::<Telemetry>().record_download(res.is_ok());
+            res
+        }
+    };
+    let (name, bytes, saved) = match &res {
+        Ok(stats) => (display_name(stats), stats.size, stats.saved.clone()),
+        Err(_) => (hash.clone(), 0, Vec::new()),
+    };
+    // only fresh downloads tell how fast the link is
+    let elapsed_ms = res
+        .as_ref()
+        .ok()
+        .filter(|stats| stats.resumed == 0 && stats.bytes_read > 0)
+        .map(|stats| stats.elapsed_ms);
+    activity.record(Activity::Received {
+        name,
+        bytes,
+        ok: res.is_ok(),
+        hash: Some(hash),
+        saved,
+        elapsed_ms,
+    });
+    let mut stats = res.map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    stats.trace = transfer;
+    app.state::<History>().add(HistoryEntry {
+        id: 0,
+        direction: Direction::Received,
+        name: display_name(&stats),
+        paths: stats.saved.iter().map(PathBuf::from).collect(),
+        hash: stats.hash.clone(),
+        ticket: ticket.to_string(),
+        size: stats.size,
+        time: 0,
+        last_transfer: None,
+        transfers: 1,
+        preview: false,
+    });
+    crate::notify::desktop(&app, "notify.received", &[("name", &display_name(&stats))]);
+    if opts.save_to_cloud {
+        let paths = stats.saved.iter().map(PathBuf::from).collect::<Vec<_>>();
+        crate::cloud::upload(&app, &paths)
+            .await
+            .map_err(|e| UserError::from_anyhow(&e, &i18n))?;
+    }
+    Ok(stats)
+}
+
+/// The files of `ticket` that exist in `dest` already, or in the default
+/// download folder if unset, so the user can choose what happens to each
+/// with [`DownloadOptions::conflicts`] before downloading.
+///
+/// Only the names of the files are fetched.
+#[tauri::command]
+pub async fn download_conflicts(
+    ticket: String,
+    dest: Option<String>,
+    password: Option<String>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<Vec<Conflict>, UserError> {
+    let res = async {
+        let (ticket, hint) = password::parse_ticket(&ticket)?;
+        password::check_hint(&ticket, hint.as_deref(), password.as_deref()).await?;
+        let settings = app.state::<SettingsStore>().get();
+        let dest = match dest {
+            Some(dest) => PathBuf::from(dest),
+            None => download_dir(&settings, &ticket)?,
+        };
+        let secret_key = app.state::<Identity>().secret_key();
+        let (endpoint, connection) =
+            connect(&ticket, &settings, secret_key, password.as_deref()).await?;
+        let files = fetch_file_names(&connection, ticket.hash()).await?;
+        app.state::<WarmConnections>().keep(
+            &app,
+            &ticket,
+            endpoint,
+            connection,
+            password.is_some(),
+        );
+        let root = export_root(&settings, &dest, &ticket, &files)?;
+        app.state::<SettingsStore>()
+            .policy()
+            .check_destination(&root)?;
+        let mut conflicts = Vec::new();
+        for name in files {
+            let path = get_export_path(&root, &name)?;
+            if let Ok(metadata) = std::fs::metadata(&path) {
+                conflicts.push(Conflict {
+                    name,
+                    path: path.display().to_string(),
+                    size: metadata.len(),
+                });
+            }
+        }
+        anyhow::Ok(conflicts)
+    }
+    .await;
+    res.map_err(|e| UserError::from_anyhow(&e, &i18n))
+}
+
+/// Save files of a collection downloaded with
+/// [`DownloadOptions::store_only`] into `dest`, the given files and folders
+/// of it, or all if `entries` is empty. Returns the paths of the top level
+/// files and folders written.
+#[tauri::command]
+pub async fn export_from_store(
+    hash: String,
+    dest: String,
+    entries: Vec<String>,
+    token: String,
+    session: State<'_, SessionToken>,
+    i18n: State<'_, I18n>,
+    app: AppHandle,
+) -> Result<Vec<String>, UserError> {
+    session
+        .verify(&token)
+        .map_err(|msg| UserError::new(ErrorCode::Unknown, msg, &i18n))?;
+    let res = async {
+        let hash = Hash::from_str(&hash).context("invalid hash")?;
+        let dest = PathBuf::from(dest);
+        app.state::<SettingsStore>()
+            .policy()
+            .check_destination(&dest)?;
+        let dir = app.state::<ReceivedDir>().store(&hash);
+        anyhow::ensure!(dir.exists(), "{} is not in the store", hash.to_hex());
+        let db = flat::Store::load(&dir).await?;
+        let collection = Collection::load(&db, &hash).await?;
+        let files = pack::file_names(&db, &collection).await?;
+        let files = files
+            .into_iter()
+            .filter(|name| selected(&entries, name))
+            .collect::<Vec<_>>();
+        anyhow::ensure!(!files.is_empty(), "no such files in {}", hash.to_hex());
+        let conflicts = Conflicts::default();
+        let renamed = export(db, &collection, &dest, &entries, &conflicts)
+            .instrument(tracing::info_span!("export", files = files.len()))
+            .await?;
+        let saved = top_level(&files)
+            .into_iter()
+            .map(|name| {
+                renamed
+                    .get(&name)
+                    .cloned()
+                    .unwrap_or_else(|| dest.join(entry_path(&name)))
+            })
+            .map(|path| path.display().to_string())
+            .collect();
+        anyhow::Ok(saved)
+    }
+    .await;
+    res.map_err(|e| UserError::from_anyhow(&e, &i18n))
+}
+
+/// Check whether a ticket was downloaded before and its files are still there,
+/// so the user can skip the download or copy the files instead.
+#[tauri::command]
+pub fn previous_download(
+    ticket: String,
+    activity: State<'_, Arc<ActivityLog>>,
+) -> Result<Option<PreviousDownload>, String> {
+    let (ticket, _) = password::parse_ticket(&ticket).map_err(|e| format!("{:#}", e))?;
+    Ok(previous(&activity, &ticket.hash().to_hex()))
+}
+
+#[tauri::command]
+pub fn get_export_template(settings: State<'_, SettingsStore>) -> Option<String> {
+    settings.get().export_template
+}
+
+#[tauri::command]
+pub fn set_export_template(
+    template: Option<String>,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let template = template.filter(|t| !t.trim().is_empty());
+    settings
+        .update(|s| s.export_template = template)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
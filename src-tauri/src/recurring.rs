@@ -0,0 +1,372 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{Datelike, Local, NaiveDateTime, Weekday};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::{
+    auth::SessionToken, i18n::I18n, quiet::parse_time, transfers::ExpiryPolicy,
+    upload::ShareOptions,
+};
+
+/// How often the jobs are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Runs started later than this after their time, e.g. because the app was
+/// not running, are recorded as missed instead.
+const GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// Runs kept in the history of each job.
+const MAX_RUNS: usize = 50;
+
+/// A share started on a weekly schedule, e.g. a report every Friday at 17:00
+/// for 48 hours.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecurringShare {
+    /// Assigned when the job is saved the first time, 0 for new jobs.
+    #[serde(default)]
+    pub id: u64,
+    pub name: String,
+    /// Shared as they are at the time of each run.
+    pub paths: Vec<PathBuf>,
+    /// Days on which the share starts, every day if empty.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// When the share starts, `HH:MM` local time.
+    pub time: String,
+    /// How long each share runs, until it is stopped if unset.
+    pub duration_secs: Option<u64>,
+    /// Email address the ticket is sent to through the mail server of the
+    /// notification settings.
+    pub recipient: Option<String>,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl RecurringShare {
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.paths.is_empty(), "nothing to share");
+        parse_time(&self.time)?;
+        Ok(())
+    }
+
+    /// The latest time at or before `now` the share was due to start.
+    fn last_occurrence(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let time = parse_time(&self.time).ok()?;
+        (0..8)
+            .filter_map(|days_ago| now.date().checked_sub_days(chrono::Days::new(days_ago)))
+            .filter(|date| self.days.is_empty() || self.days.contains(&date.weekday()))
+            .map(|date| date.and_time(time))
+            .find(|at| *at <= now)
+    }
+}
+
+/// The outcome of one run of a job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Run {
+    pub job: u64,
+    /// Unix time the run started.
+    pub time: u64,
+    pub ok: bool,
+    pub ticket: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Job {
+    share: RecurringShare,
+    /// Only occurrences after this are run, the time the job was saved or
+    /// last checked.
+    since: NaiveDateTime,
+    /// Newest last.
+    runs: Vec<Run>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Jobs {
+    next_id: u64,
+    jobs: BTreeMap<u64, Job>,
+}
+
+/// What to do about a job now.
+#[derive(Debug, PartialEq, Eq)]
+enum Due {
+    Run,
+    /// The time passed too long ago.
+    Missed,
+}
+
+/// A job with the history of its runs, for the frontend.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecurringShareInfo {
+    #[serde(flatten)]
+    pub share: RecurringShare,
+    pub runs: Vec<Run>,
+}
+
+/// The recurring share jobs, persisted as json in the app data dir.
+#[derive(Debug)]
+pub struct Recurring {
+    path: PathBuf,
+    jobs: Mutex<Jobs>,
+}
+
+impl Recurring {
+    pub fn load(path: PathBuf) -> Self {
+        let jobs = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            jobs: Mutex::new(jobs),
+        }
+    }
+
+    fn save(&self, jobs: &Jobs) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(jobs)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<RecurringShareInfo> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.jobs
+            .values()
+            .map(|job| RecurringShareInfo {
+                share: job.share.clone(),
+                runs: job.runs.clone(),
+            })
+            .collect()
+    }
+
+    /// Add a job, or replace the one with the same id. Returns its id.
+    fn upsert(&self, mut share: RecurringShare, now: NaiveDateTime) -> anyhow::Result<u64> {
+        share.validate()?;
+        let mut jobs = self.jobs.lock().unwrap();
+        let runs = match jobs.jobs.remove(&share.id) {
+            Some(job) => job.runs,
+            None if share.id == 0 => {
+                jobs.next_id += 1;
+                share.id = jobs.next_id;
+                Vec::new()
+            }
+            None => anyhow::bail!("no recurring share {}", share.id),
+        };
+        let id = share.id;
+        let job = Job {
+            share,
+            since: now,
+            runs,
+        };
+        jobs.jobs.insert(id, job);
+        self.save(&jobs)?;
+        Ok(id)
+    }
+
+    fn remove(&self, id: u64) -> anyhow::Result<()> {
+        let mut jobs = self.jobs.lock().unwrap();
+        anyhow::ensure!(jobs.jobs.remove(&id).is_some(), "no recurring share {}", id);
+        self.save(&jobs)
+    }
+
+    /// The jobs whose time came since they were last checked, each returned
+    /// only once.
+    fn due(&self, now: NaiveDateTime) -> Vec<(RecurringShare, Due)> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut due = Vec::new();
+        for job in jobs.jobs.values_mut() {
+            let Some(at) = job.share.last_occurrence(now) else {
+                continue;
+            };
+            if !job.share.enabled || at <= job.since {
+                continue;
+            }
+            job.since = now;
+            let late = (now - at).to_std().unwrap_or_default();
+            let state = if late > GRACE { Due::Missed } else { Due::Run };
+            due.push((job.share.clone(), state));
+        }
+        if !due.is_empty() {
+            if let Err(err) = self.save(&jobs) {
+                log!("failed to save recurring shares: {:#}", err);
+            }
+        }
+        due
+    }
+
+    fn record(&self, run: Run) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.jobs.get_mut(&run.job) else {
+            return;
+        };
+        job.runs.push(run);
+        let excess = job.runs.len().saturating_sub(MAX_RUNS);
+        job.runs.drain(..excess);
+        if let Err(err) = self.save(&jobs) {
+            log!("failed to save recurring shares: {:#}", err);
+        }
+    }
+}
+
+/// Share the files of `job` and send the ticket to its recipient.
+async fn run(app: &AppHandle, job: &RecurringShare) -> anyhow::Result<String> {
+    let opts = ShareOptions {
+        expiry: ExpiryPolicy {
+            expires_after_secs: job.duration_secs,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let ticket = crate::share(app, job.paths.clone(), opts)
+        .await?
+        .to_string();
+    if let Some(to) = &job.recipient {
+        let i18n = app.state::<I18n>();
+        let subject = i18n.translate("recurring.subject", &[("name", &job.name)]);
+        let body = i18n.translate(
+            "recurring.body",
+            &[("name", &job.name), ("ticket", &ticket)],
+        );
+        crate::notify::mail(app, to, subject, body)
+            .await
+            .map_err(|err| err.context(format!("shared as {}, but", ticket)))?;
+    }
+    Ok(ticket)
+}
+
+/// Start the recurring shares when they are due, recording every run and
+/// reporting it to the frontend as `recurring-run`.
+pub fn spawn_scheduler(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let now = Local::now().naive_local();
+            let due = app.state::<Arc<Recurring>>().due(now);
+            for (job, state) in due {
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let res = match state {
+                    Due::Run => run(&app, &job).await,
+                    Due::Missed => Err(anyhow::anyhow!("missed, the app was not running")),
+                };
+                log!("recurring share {}: {:?}", job.name, res);
+                let (ticket, error) = match res {
+                    Ok(ticket) => (Some(ticket), None),
+                    Err(err) => (None, Some(format!("{:#}", err))),
+                };
+                let run = Run {
+                    job: job.id,
+                    time,
+                    ok: error.is_none(),
+                    ticket,
+                    error,
+                };
+                app.emit_all("recurring-run", &run).ok();
+                app.state::<Arc<Recurring>>().record(run);
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub fn list_recurring_shares(recurring: State<'_, Arc<Recurring>>) -> Vec<RecurringShareInfo> {
+    recurring.list()
+}
+
+/// Add a recurring share, or change the one with the same id. The first run
+/// is the next time after now.
+#[tauri::command]
+pub fn save_recurring_share(
+    share: RecurringShare,
+    token: String,
+    session: State<'_, SessionToken>,
+    recurring: State<'_, Arc<Recurring>>,
+) -> Result<u64, String> {
+    session.verify(&token)?;
+    recurring
+        .upsert(share, Local::now().naive_local())
+        .map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+pub fn delete_recurring_share(
+    id: u64,
+    token: String,
+    session: State<'_, SessionToken>,
+    recurring: State<'_, Arc<Recurring>>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    recurring.remove(id).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(day: u32, time: &str) -> NaiveDateTime {
+        // 2024-03-01 is a Friday
+        NaiveDate::from_ymd_opt(2024, 3, day)
+            .unwrap()
+            .and_time(parse_time(time).unwrap())
+    }
+
+    #[test]
+    fn runs_when_due() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recurring.json");
+        let recurring = Recurring::load(path.clone());
+        let share = RecurringShare {
+            id: 0,
+            name: "report".to_string(),
+            paths: vec![PathBuf::from("latest.pdf")],
+            days: vec![Weekday::Fri],
+            time: "17:00".to_string(),
+            duration_secs: Some(48 * 60 * 60),
+            recipient: None,
+            enabled: true,
+        };
+        let previous = NaiveDate::from_ymd_opt(2024, 2, 23).unwrap();
+        assert_eq!(
+            share.last_occurrence(at(1, "16:59")),
+            Some(previous.and_time(parse_time("17:00").unwrap()))
+        );
+        let id = recurring.upsert(share, at(1, "12:00")).unwrap();
+        assert!(recurring.due(at(1, "16:59")).is_empty());
+        let due = recurring.due(at(1, "17:00"));
+        assert_eq!(due.len(), 1);
+        assert_eq!((due[0].0.id, &due[0].1), (id, &Due::Run));
+        // only once
+        assert!(recurring.due(at(1, "17:01")).is_empty());
+        // the app was not running the next Friday
+        let due = recurring.due(at(9, "09:00"));
+        assert_eq!(due[0].1, Due::Missed);
+        recurring.record(Run {
+            job: id,
+            time: 0,
+            ok: false,
+            ticket: None,
+            error: Some("missed".to_string()),
+        });
+        let reloaded = Recurring::load(path.clone());
+        assert_eq!(reloaded.list()[0].runs.len(), 1);
+    }
+}
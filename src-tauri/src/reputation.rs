@@ -0,0 +1,262 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::{auth::SessionToken, i18n::I18n, settings::SettingsStore};
+
+/// Complete downloads after which a peer is suggested as a trusted contact.
+const SUGGEST_AFTER: u64 = 5;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// How a peer behaved when downloading our shares.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeerRecord {
+    /// Complete downloads of a share.
+    pub completed: u64,
+    /// Requests that failed or were cancelled by the peer.
+    pub aborted: u64,
+    /// Requests that were refused by policy, e.g. for a revoked ticket.
+    pub violations: u64,
+    /// Unix time of the first and the latest request.
+    pub first_seen: u64,
+    pub last_seen: u64,
+    /// Whether the user declined to trust the peer, so it is not suggested again.
+    pub dismissed: bool,
+}
+
+impl PeerRecord {
+    /// Whether the peer is worth suggesting as a trusted contact.
+    fn suggest(&self) -> bool {
+        !self.dismissed
+            && self.violations == 0
+            && self.completed >= SUGGEST_AFTER
+            && self.aborted < self.completed
+    }
+}
+
+/// What happened to a request of a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Completed,
+    Aborted,
+    Violation,
+}
+
+/// Success history of every peer that requested one of our shares, by node
+/// id. Unlike the activity log it is kept for good.
+#[derive(Debug)]
+pub struct Reputation {
+    path: PathBuf,
+    peers: RwLock<BTreeMap<String, PeerRecord>>,
+}
+
+impl Reputation {
+    /// Load the history persisted at `path`.
+    pub fn load(path: PathBuf) -> Self {
+        let peers = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            peers: RwLock::new(peers),
+        }
+    }
+
+    fn save(&self, peers: &BTreeMap<String, PeerRecord>) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(peers)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Count a request of `peer`, requests of unknown peers are not counted.
+    pub fn record(&self, peer: Option<&str>, outcome: Outcome) {
+        let Some(peer) = peer else {
+            return;
+        };
+        let mut peers = self.peers.write().unwrap();
+        let now = now();
+        let record = peers.entry(peer.to_string()).or_insert_with(|| PeerRecord {
+            first_seen: now,
+            ..Default::default()
+        });
+        record.last_seen = now;
+        match outcome {
+            Outcome::Completed => record.completed += 1,
+            Outcome::Aborted => record.aborted += 1,
+            Outcome::Violation => record.violations += 1,
+        }
+        if let Err(err) = self.save(&peers) {
+            log!("failed to save peer reputation: {:#}", err);
+        }
+    }
+
+    /// All known peers, the most recently seen first.
+    pub fn list(&self) -> Vec<PeerReputation> {
+        let peers = self.peers.read().unwrap();
+        let mut list: Vec<_> = peers
+            .iter()
+            .map(|(peer, record)| PeerReputation {
+                peer: peer.clone(),
+                record: record.clone(),
+            })
+            .collect();
+        list.sort_by_key(|p| std::cmp::Reverse(p.record.last_seen));
+        list
+    }
+
+    /// Stop suggesting `peer` as a trusted contact.
+    pub fn dismiss(&self, peer: &str) -> anyhow::Result<()> {
+        let mut peers = self.peers.write().unwrap();
+        let record = peers
+            .get_mut(peer)
+            .ok_or_else(|| anyhow::anyhow!("unknown peer {}", peer))?;
+        if !record.dismissed {
+            record.dismissed = true;
+            self.save(&peers)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerReputation {
+    pub peer: String,
+    #[serde(flatten)]
+    pub record: PeerRecord,
+}
+
+/// A peer the user may want to add to the trusted contacts.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrustSuggestion {
+    pub peer: String,
+    pub completed: u64,
+    pub message: String,
+}
+
+#[tauri::command]
+pub fn peer_reputation(reputation: State<'_, Arc<Reputation>>) -> Vec<PeerReputation> {
+    reputation.list()
+}
+
+/// Peers with a clean history that are not trusted yet.
+#[tauri::command]
+pub fn trust_suggestions(
+    reputation: State<'_, Arc<Reputation>>,
+    settings: State<'_, SettingsStore>,
+    i18n: State<'_, I18n>,
+) -> Vec<TrustSuggestion> {
+    let trusted = settings.get().trusted_peers;
+    reputation
+        .list()
+        .into_iter()
+        .filter(|p| p.record.suggest() && !trusted.contains(&p.peer))
+        .map(|p| {
+            let short = p.peer.chars().take(10).collect::<String>();
+            let count = p.record.completed.to_string();
+            TrustSuggestion {
+                message: i18n.translate(
+                    "reputation.suggest_trust",
+                    &[("peer", &short), ("count", &count)],
+                ),
+                completed: p.record.completed,
+                peer: p.peer,
+            }
+        })
+        .collect()
+}
+
+/// Add `peer` to the trusted contacts.
+#[tauri::command]
+pub fn trust_peer(
+    peer: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| {
+            if !s.trusted_peers.contains(&peer) {
+                s.trusted_peers.push(peer);
+            }
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Remove `peer` from the trusted contacts.
+#[tauri::command]
+pub fn untrust_peer(
+    peer: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| {
+            s.trusted_peers.retain(|p| p != &peer);
+            s.receive_rules.remove(&peer);
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn dismiss_trust_suggestion(
+    peer: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    reputation: State<'_, Arc<Reputation>>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    reputation.dismiss(&peer).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suggested(reputation: &Reputation, peer: &str) -> bool {
+        reputation.peers.read().unwrap()[peer].suggest()
+    }
+
+    #[test]
+    fn suggests_peers_with_a_clean_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reputation.json");
+        let reputation = Reputation::load(path.clone());
+        for _ in 0..SUGGEST_AFTER {
+            reputation.record(Some("a"), Outcome::Completed);
+            reputation.record(Some("b"), Outcome::Completed);
+        }
+        reputation.record(Some("b"), Outcome::Violation);
+        reputation.record(None, Outcome::Completed);
+        assert!(suggested(&reputation, "a"));
+        assert!(!suggested(&reputation, "b"));
+        // persisted, including dismissals
+        reputation.dismiss("a").unwrap();
+        let reloaded = Reputation::load(path.clone());
+        assert_eq!(reloaded.list().len(), 2);
+        assert!(!suggested(&reloaded, "a"));
+        assert!(reloaded.dismiss("c").is_err());
+    }
+}
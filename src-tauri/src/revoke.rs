@@ -0,0 +1,137 @@
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Context;
+use iroh_bytes::Hash;
+use tauri::State;
+
+use crate::{
+    audit::{AuditEvent, AuditLog},
+    auth::SessionToken,
+    password,
+};
+
+/// Collections whose tickets were revoked. Requests for them are refused by
+/// every share, even while the data is still in a store.
+#[derive(Debug)]
+pub struct Revocations {
+    path: PathBuf,
+    revoked: RwLock<BTreeSet<Hash>>,
+    audit: Arc<AuditLog>,
+}
+
+impl Revocations {
+    /// Load the list persisted at `path`.
+    pub fn load(path: PathBuf, audit: Arc<AuditLog>) -> Self {
+        let revoked = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            revoked: RwLock::new(revoked),
+            audit,
+        }
+    }
+
+    fn save(&self, revoked: &BTreeSet<Hash>) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(revoked)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn is_revoked(&self, hash: &Hash) -> bool {
+        self.revoked.read().unwrap().contains(hash)
+    }
+
+    /// Whether a request of `peer` for `hash` may be served, recording
+    /// refusals in the audit log.
+    pub fn allow(&self, hash: &Hash, peer: Option<&str>) -> bool {
+        if !self.is_revoked(hash) {
+            return true;
+        }
+        self.audit.record(AuditEvent::Refused {
+            peer: peer.map(str::to_string),
+            hash: hash.to_hex().to_string(),
+            reason: "revoked".to_string(),
+        });
+        false
+    }
+
+    pub fn revoke(&self, hash: Hash) -> anyhow::Result<()> {
+        let mut revoked = self.revoked.write().unwrap();
+        if revoked.insert(hash) {
+            self.save(&revoked)?;
+            self.audit.record(AuditEvent::Revoked {
+                hash: hash.to_hex().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn unrevoke(&self, hash: &Hash) -> anyhow::Result<()> {
+        let mut revoked = self.revoked.write().unwrap();
+        if revoked.remove(hash) {
+            self.save(&revoked)?;
+            self.audit.record(AuditEvent::Unrevoked {
+                hash: hash.to_hex().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<String> {
+        let revoked = self.revoked.read().unwrap();
+        revoked
+            .iter()
+            .map(|hash| hash.to_hex().to_string())
+            .collect()
+    }
+}
+
+/// The root hash of a ticket, or a hash given directly.
+fn parse_hash(ticket_or_hash: &str) -> anyhow::Result<Hash> {
+    let s = ticket_or_hash.trim();
+    match password::parse_ticket(s) {
+        Ok((ticket, _)) => Ok(ticket.hash()),
+        Err(_) => Hash::from_str(s).context("neither a ticket nor a hash"),
+    }
+}
+
+/// Revoke all tickets of a collection, given by a ticket or its root hash.
+#[tauri::command]
+pub fn revoke_ticket(
+    ticket: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    revocations: State<'_, Arc<Revocations>>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let hash = parse_hash(&ticket).map_err(|e| e.to_string())?;
+    revocations.revoke(hash).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn unrevoke_ticket(
+    ticket: String,
+    token: String,
+    session: State<'_, SessionToken>,
+    revocations: State<'_, Arc<Revocations>>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let hash = parse_hash(&ticket).map_err(|e| e.to_string())?;
+    revocations.unrevoke(&hash).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn list_revoked(revocations: State<'_, Arc<Revocations>>) -> Vec<String> {
+    revocations.list()
+}
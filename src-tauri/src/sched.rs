@@ -0,0 +1,364 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use futures::{future::LocalBoxFuture, FutureExt};
+use iroh_io::AsyncStreamWriter;
+use serde::{Deserialize, Serialize};
+use tauri::State as TauriState;
+use tokio::sync::Notify;
+
+use crate::{auth::SessionToken, bandwidth::Bucket, settings::SettingsStore};
+
+/// Upload priority of a share.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    fn weight(self) -> f64 {
+        match self {
+            Priority::High => 4.0,
+            Priority::Normal => 2.0,
+            Priority::Low => 1.0,
+        }
+    }
+}
+
+/// How far, in weighted bytes, a flow may run ahead of the slowest waiting flow.
+const SLACK: f64 = 256.0 * 1024.0;
+
+#[derive(Debug)]
+struct FlowState {
+    /// The share this flow belongs to.
+    group: u64,
+    /// Weight of the share's priority class.
+    class_weight: f64,
+    /// Effective weight, the class weight split across the share's flows.
+    weight: f64,
+    /// Weighted bytes written so far.
+    vtime: f64,
+    /// Writes waiting for their turn.
+    waiting: usize,
+    /// Writes in progress.
+    writing: usize,
+    /// Token bucket for the per peer cap, in bytes.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    flows: HashMap<u64, FlowState>,
+    next_id: u64,
+    /// Maximum upload rate per peer in bytes per second.
+    peer_cap: Option<u64>,
+}
+
+impl State {
+    /// Split the class weight of a share evenly across its connections, so a
+    /// share does not get more bandwidth just because more peers download it.
+    fn rebalance(&mut self, group: u64) {
+        let n = self.flows.values().filter(|f| f.group == group).count();
+        for f in self.flows.values_mut().filter(|f| f.group == group) {
+            f.weight = f.class_weight / n as f64;
+        }
+    }
+
+    /// Take `len` bytes from the flow's bucket, returning how long to wait.
+    fn throttle(&mut self, id: u64, len: usize) -> Option<Duration> {
+        let cap = self.peer_cap? as f64;
+        let f = self.flows.get_mut(&id)?;
+        let now = Instant::now();
+        let elapsed = now.duration_since(f.last_refill).as_secs_f64();
+        f.last_refill = now;
+        f.tokens = (f.tokens + elapsed * cap).min(cap);
+        f.tokens -= len as f64;
+        (f.tokens < 0.0).then(|| Duration::from_secs_f64(-f.tokens / cap))
+    }
+
+    /// Lowest virtual time of the flows other than `except` that want to write.
+    fn min_waiting(&self, except: u64) -> Option<f64> {
+        self.flows
+            .iter()
+            .filter(|(id, f)| **id != except && f.waiting > 0)
+            .map(|(_, f)| f.vtime)
+            .min_by(f64::total_cmp)
+    }
+
+    /// Lowest virtual time of the flows that are currently active.
+    fn min_active(&self) -> Option<f64> {
+        self.flows
+            .values()
+            .filter(|f| f.waiting > 0 || f.writing > 0)
+            .map(|f| f.vtime)
+            .min_by(f64::total_cmp)
+    }
+}
+
+/// Weighted fair scheduler for provider writes.
+///
+/// Every connection being served is a flow. Before a write, a flow has to wait
+/// until it is not too far ahead of the other flows that want to write, where
+/// bytes are weighted by the priority of the share. This is a weighted round
+/// robin across peers, so one fast peer can not hog the uplink. Flows blocked
+/// inside a write, e.g. on a slow peer, do not hold up the others.
+///
+/// Additionally every flow can be capped to a maximum rate, and all flows
+/// together to the app wide upload cap.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    state: Mutex<State>,
+    notify: Notify,
+    /// The app wide upload cap, for shares without their own.
+    upload: Arc<Bucket>,
+}
+
+impl Scheduler {
+    /// Allocate an id to group the flows of one share.
+    pub fn group(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        state.next_id
+    }
+
+    /// Set the maximum upload rate per peer, `None` for no limit.
+    pub fn set_peer_cap(&self, cap: Option<u64>) {
+        self.state.lock().unwrap().peer_cap = cap.filter(|c| *c > 0);
+        self.notify.notify_waiters();
+    }
+
+    /// Set the maximum upload rate of all shares together, `None` for no limit.
+    pub fn set_upload_cap(&self, cap: Option<u64>) {
+        self.upload.set_rate(cap);
+    }
+
+    /// Register a new flow for a connection of the share `group`, limited by
+    /// the share's own `cap` or the app wide one.
+    pub fn flow(
+        self: &Arc<Self>,
+        group: u64,
+        priority: Priority,
+        cap: Option<Arc<Bucket>>,
+    ) -> Flow {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        // start at the current level, so new flows don't get a burst
+        let vtime = state.min_active().unwrap_or_default();
+        let tokens = state.peer_cap.unwrap_or_default() as f64;
+        state.flows.insert(
+            id,
+            FlowState {
+                group,
+                class_weight: priority.weight(),
+                weight: priority.weight(),
+                vtime,
+                waiting: 0,
+                writing: 0,
+                tokens,
+                last_refill: Instant::now(),
+            },
+        );
+        state.rebalance(group);
+        Flow(Arc::new(FlowHandle {
+            id,
+            scheduler: self.clone(),
+            cap: cap.unwrap_or_else(|| self.upload.clone()),
+        }))
+    }
+
+    fn modify(&self, id: u64, f: impl FnOnce(&mut FlowState)) {
+        if let Some(flow) = self.state.lock().unwrap().flows.get_mut(&id) {
+            f(flow);
+        }
+        self.notify.notify_waiters();
+    }
+}
+
+#[derive(Debug)]
+struct FlowHandle {
+    id: u64,
+    scheduler: Arc<Scheduler>,
+    /// Shared with the other flows of the share or of all shares.
+    cap: Arc<Bucket>,
+}
+
+impl Drop for FlowHandle {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        if let Some(flow) = state.flows.remove(&self.id) {
+            state.rebalance(flow.group);
+        }
+        drop(state);
+        self.scheduler.notify.notify_waiters();
+    }
+}
+
+/// A registered flow, removed from the scheduler when the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct Flow(Arc<FlowHandle>);
+
+/// Permission to write, the write counts as in progress until this is dropped.
+struct Permit<'a>(&'a Flow);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let handle = &self.0 .0;
+        handle.scheduler.modify(handle.id, |f| f.writing -= 1);
+    }
+}
+
+/// Marks a flow as waiting, undone if the wait is cancelled.
+struct Waiting<'a>(Option<&'a Flow>);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if let Some(flow) = self.0.take() {
+            let handle = &flow.0;
+            handle.scheduler.modify(handle.id, |f| f.waiting -= 1);
+        }
+    }
+}
+
+impl Flow {
+    async fn acquire(&self, len: usize) -> Permit<'_> {
+        let FlowHandle { id, scheduler, cap } = &*self.0;
+        {
+            let mut state = scheduler.state.lock().unwrap();
+            let floor = state.min_active();
+            if let Some(f) = state.flows.get_mut(id) {
+                // don't bank credit while idle
+                if f.waiting == 0 && f.writing == 0 {
+                    f.vtime = f.vtime.max(floor.unwrap_or_default());
+                }
+                f.waiting += 1;
+            }
+        }
+        let mut waiting = Waiting(Some(self));
+        let mut delay = None;
+        loop {
+            let notified = scheduler.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            {
+                let mut state = scheduler.state.lock().unwrap();
+                let others = state.min_waiting(*id);
+                let Some(f) = state.flows.get_mut(id) else {
+                    break;
+                };
+                if others.is_none_or(|min| f.vtime <= min + SLACK) {
+                    waiting.0 = None;
+                    f.waiting -= 1;
+                    f.writing += 1;
+                    f.vtime += len as f64 / f.weight;
+                    delay = state.throttle(*id, len);
+                    break;
+                }
+            }
+            notified.await;
+        }
+        let delay = delay.max(cap.take(len));
+        let permit = Permit(self);
+        // wait for the rate cap while counted as writing, so others can go ahead
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        permit
+    }
+}
+
+/// A writer that takes turns with other flows before every write.
+#[derive(Debug)]
+pub struct ScheduledWriter<W> {
+    inner: W,
+    flow: Flow,
+    written: u64,
+}
+
+impl<W> ScheduledWriter<W> {
+    pub fn new(inner: W, flow: Flow) -> Self {
+        Self {
+            inner,
+            flow,
+            written: 0,
+        }
+    }
+
+    /// Bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncStreamWriter> AsyncStreamWriter for ScheduledWriter<W> {
+    type WriteFuture<'a>
+        = LocalBoxFuture<'a, io::Result<()>>
+    where
+        Self: 'a;
+
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> Self::WriteFuture<'a> {
+        async move {
+            let _permit = self.flow.acquire(data.len()).await;
+            self.inner.write(data).await?;
+            self.written += data.len() as u64;
+            Ok(())
+        }
+        .boxed_local()
+    }
+
+    type WriteBytesFuture<'a>
+        = LocalBoxFuture<'a, io::Result<()>>
+    where
+        Self: 'a;
+
+    fn write_bytes(&mut self, data: Bytes) -> Self::WriteBytesFuture<'_> {
+        async move {
+            let _permit = self.flow.acquire(data.len()).await;
+            let len = data.len() as u64;
+            self.inner.write_bytes(data).await?;
+            self.written += len;
+            Ok(())
+        }
+        .boxed_local()
+    }
+
+    type SyncFuture<'a>
+        = W::SyncFuture<'a>
+    where
+        Self: 'a;
+
+    fn sync(&mut self) -> Self::SyncFuture<'_> {
+        self.inner.sync()
+    }
+}
+
+/// Set the maximum upload rate per peer in bytes per second, `None` to disable.
+#[tauri::command]
+pub fn set_peer_rate_limit(
+    limit: Option<u64>,
+    token: String,
+    session: TauriState<'_, SessionToken>,
+    settings: TauriState<'_, SettingsStore>,
+    scheduler: TauriState<'_, Arc<Scheduler>>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| s.peer_rate_limit = limit)
+        .map_err(|e| e.to_string())?;
+    scheduler.set_peer_cap(limit);
+    Ok(())
+}
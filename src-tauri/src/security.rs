@@ -0,0 +1,131 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    audit::{AuditEntry, AuditEvent, AuditLog},
+    notify,
+    settings::SettingsStore,
+};
+
+/// Refusals of a peer are counted over this many seconds.
+const WINDOW_SECS: u64 = 60 * 60;
+
+/// Refusals within the window after which the user is notified, a peer
+/// retrying a revoked ticket this often is likely probing.
+const REPEATED: usize = 3;
+
+/// A security incident, emitted to the frontend as `security-event` and
+/// posted to the security webhook.
+#[derive(Debug, Clone, Serialize)]
+pub struct SecurityEvent {
+    /// Why the request was refused, e.g. `revoked`.
+    pub reason: String,
+    pub peer: Option<String>,
+    pub hash: String,
+    /// Refusals of the same peer within the last hour, including this one.
+    pub attempts: usize,
+    pub time: u64,
+}
+
+/// Refusal times by peer, to tell single mistakes from repeated attempts.
+#[derive(Debug, Default)]
+struct Attempts(HashMap<String, Vec<u64>>);
+
+impl Attempts {
+    fn count(&mut self, peer: Option<&str>, time: u64) -> usize {
+        let Some(peer) = peer else {
+            return 1;
+        };
+        self.0
+            .retain(|_, times| times.last().is_some_and(|t| t + WINDOW_SECS > time));
+        let times = self.0.entry(peer.to_string()).or_default();
+        times.retain(|t| t + WINDOW_SECS > time);
+        times.push(time);
+        times.len()
+    }
+}
+
+fn incident(entry: AuditEntry, attempts: &mut Attempts) -> Option<SecurityEvent> {
+    let AuditEvent::Refused { peer, hash, reason } = entry.event else {
+        return None;
+    };
+    Some(SecurityEvent {
+        attempts: attempts.count(peer.as_deref(), entry.time),
+        reason,
+        peer,
+        hash,
+        time: entry.time,
+    })
+}
+
+async fn report(app: &AppHandle, event: &SecurityEvent) {
+    app.emit_all("security-event", event).ok();
+    if event.attempts == REPEATED {
+        let peer = event.peer.as_deref().unwrap_or_default();
+        let short = peer.chars().take(10).collect::<String>();
+        let count = event.attempts.to_string();
+        notify::desktop(
+            app,
+            "security.repeated",
+            &[("peer", &short), ("count", &count)],
+        );
+    }
+    let url = app
+        .state::<SettingsStore>()
+        .get()
+        .notifications
+        .security_webhook;
+    if let Some(url) = url {
+        if let Err(err) = notify::send_webhook(&url, event).await {
+            log!("failed to send security webhook: {:#}", err);
+        }
+    }
+}
+
+/// Report the security incidents recorded in the audit log as they happen.
+pub fn spawn_monitor(app: AppHandle) {
+    let mut events = app.state::<Arc<AuditLog>>().subscribe();
+    tauri::async_runtime::spawn(async move {
+        let mut attempts = Attempts::default();
+        loop {
+            let entry = match events.recv().await {
+                Ok(entry) => entry,
+                Err(RecvError::Lagged(missed)) => {
+                    log!("security monitor missed {} audit events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            if let Some(event) = incident(entry, &mut attempts) {
+                report(&app, &event).await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_attempts_per_peer() {
+        let mut attempts = Attempts::default();
+        assert_eq!(attempts.count(Some("a"), 0), 1);
+        assert_eq!(attempts.count(Some("a"), 10), 2);
+        assert_eq!(attempts.count(Some("b"), 20), 1);
+        assert_eq!(attempts.count(None, 30), 1);
+        assert_eq!(attempts.count(Some("a"), 40), 3);
+        // the first two fell out of the window
+        assert_eq!(attempts.count(Some("a"), WINDOW_SECS + 15), 2);
+        let entry = AuditEntry {
+            time: 0,
+            event: AuditEvent::Revoked {
+                hash: String::new(),
+            },
+        };
+        assert!(incident(entry, &mut attempts).is_none());
+    }
+}
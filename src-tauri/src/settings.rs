@@ -0,0 +1,219 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::{
+    auth::SessionToken,
+    bandwidth::{BandwidthLimits, DownloadCap},
+    bridge::BridgeSettings,
+    cache::ChunkCache,
+    clipboard::ClipboardSettings,
+    cloud::WebDavSettings,
+    contacts::ReceiveRule,
+    dropfolder::DropFolderSettings,
+    i18n::I18n,
+    keepalive::KeepAlive,
+    media::PreviewSettings,
+    message::MessageTemplates,
+    network::NetworkSettings,
+    notify::NotificationSettings,
+    organize::OrganizeSettings,
+    policy::Policy,
+    quiet::QuietHours,
+    sched::Scheduler,
+    store::StoreKind,
+    tray::TrayLayout,
+    update::UpdateChannel,
+    upload::Format,
+};
+
+/// User settings, persisted as json in the app config dir.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Whether anonymous usage telemetry may be sent.
+    pub telemetry: bool,
+    /// Where telemetry reports are sent to. Nothing is sent if unset.
+    pub telemetry_endpoint: Option<String>,
+    /// Where crash reports are sent to, after the user reviewed them.
+    pub crash_report_endpoint: Option<String>,
+    /// Release channel used for update checks.
+    pub update_channel: UpdateChannel,
+    /// Unix time until which startup update checks are skipped.
+    pub update_remind_after: Option<u64>,
+    /// Locale for strings produced by the backend, the system locale if unset.
+    pub locale: Option<String>,
+    /// Actions shown in the tray menu.
+    pub tray: TrayLayout,
+    /// Windows during which transfers are paused automatically.
+    pub quiet_hours: Vec<QuietHours>,
+    /// Maximum upload rate per peer in bytes per second.
+    pub peer_rate_limit: Option<u64>,
+    /// Maximum upload and download rates of the whole app.
+    pub bandwidth: BandwidthLimits,
+    /// Memory used to cache sent blobs in bytes, the default if unset.
+    pub chunk_cache_size: Option<u64>,
+    /// Templates for the messages generated for shares.
+    pub message_templates: MessageTemplates,
+    /// How the user is told about downloads of their shares.
+    pub notifications: NotificationSettings,
+    /// Unix time the last weekly activity report was sent.
+    pub activity_report_sent: Option<u64>,
+    /// How idle shares stay reachable.
+    pub keep_alive: KeepAlive,
+    /// Domain under which shares publish their addresses for DNS discovery.
+    pub dns_discovery: Option<String>,
+    /// Where shared data is kept while it is served.
+    pub store: StoreKind,
+    /// Whether and which clipboard items may be shared.
+    pub clipboard: ClipboardSettings,
+    /// How photos and videos are shrunk for preview shares.
+    pub preview: PreviewSettings,
+    /// Folder whose new PDFs are shared automatically, e.g. a print to file target.
+    pub spool_folder: Option<PathBuf>,
+    /// Folder whose new files and folders are shared automatically.
+    pub drop_folder: DropFolderSettings,
+    /// WebDAV folder received files can be uploaded to.
+    pub cloud: Option<WebDavSettings>,
+    /// Port of the localhost WebDAV server showing the active shares, off if unset.
+    pub webdav_port: Option<u16>,
+    /// Localhost server for the companion browser extension.
+    pub bridge: BridgeSettings,
+    /// Where received files are saved, e.g. `~/Downloads/sendme/{date}/{name}`,
+    /// relative to the chosen folder unless it starts with `~/`.
+    pub export_template: Option<String>,
+    /// How received files are sorted into subfolders.
+    pub organize: OrganizeSettings,
+    /// Where downloads are saved unless another folder is chosen, the
+    /// system's download folder if unset.
+    pub download_dir: Option<PathBuf>,
+    /// Port and relay used by shares and downloads.
+    pub network: NetworkSettings,
+    /// How hashes are printed in the log.
+    pub hash_format: Format,
+    /// Remove cached copies of shared data older than this many days, once a
+    /// day. Off if unset.
+    pub auto_cleanup_days: Option<u64>,
+    /// Node ids of peers the user trusts, see [`crate::reputation`].
+    pub trusted_peers: Vec<String>,
+    /// How downloads from trusted peers are saved, by node id.
+    pub receive_rules: BTreeMap<String, ReceiveRule>,
+}
+
+/// The current settings together with the file they are persisted to.
+#[derive(Debug)]
+pub struct SettingsStore {
+    path: PathBuf,
+    current: Mutex<Settings>,
+    /// Why the settings file could not be used, if it exists but is invalid.
+    load_error: Option<String>,
+    /// Values forced by the administrator, applied on top of the user's.
+    policy: Policy,
+}
+
+impl SettingsStore {
+    /// Load the settings from `path`, falling back to the defaults.
+    pub fn load(path: PathBuf, policy: Policy) -> Self {
+        let mut load_error = None;
+        let current = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+                log!("ignoring invalid settings {}: {}", path.display(), err);
+                load_error = Some(err.to_string());
+                Settings::default()
+            }),
+            Err(_) => Settings::default(),
+        };
+        Self {
+            path,
+            current: Mutex::new(current),
+            load_error,
+            policy,
+        }
+    }
+
+    pub fn load_error(&self) -> Option<&str> {
+        self.load_error.as_deref()
+    }
+
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// The settings in effect, the user's with the values the policy forces.
+    pub fn get(&self) -> Settings {
+        self.policy.apply(self.current.lock().unwrap().clone())
+    }
+
+    /// Modify the settings and write them to disk.
+    ///
+    /// The in memory settings are only changed if writing succeeded. Values
+    /// locked by the policy can be changed, but do not take effect.
+    pub fn update(&self, f: impl FnOnce(&mut Settings)) -> anyhow::Result<Settings> {
+        let mut current = self.current.lock().unwrap();
+        let mut next = current.clone();
+        f(&mut next);
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&next)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)?;
+        *current = next.clone();
+        Ok(self.policy.apply(next))
+    }
+}
+
+/// The current settings, without the bridge pairing token, see
+/// [`crate::bridge::bridge_pairing_token`].
+#[tauri::command]
+pub fn get_settings(settings: State<'_, SettingsStore>) -> Settings {
+    let mut current = settings.get();
+    current.bridge.token = None;
+    current
+}
+
+/// Replace all settings at once, applying those that affect running shares.
+#[tauri::command]
+pub fn set_settings(
+    values: Settings,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+    app: AppHandle,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    for window in &values.quiet_hours {
+        window.validate().map_err(|e| e.to_string())?;
+    }
+    values.network.validate().map_err(|e| e.to_string())?;
+    let current = settings
+        .update(|s| {
+            // the frontend never sees the pairing token, keep it while the
+            // bridge stays on
+            let pairing = s.bridge.token.take();
+            *s = values;
+            s.bridge.token = s.bridge.port.and(pairing);
+        })
+        .map_err(|e| e.to_string())?;
+    app.state::<Arc<Scheduler>>()
+        .set_peer_cap(current.peer_rate_limit);
+    app.state::<Arc<Scheduler>>()
+        .set_upload_cap(current.bandwidth.upload);
+    app.state::<DownloadCap>()
+        .0
+        .set_rate(current.bandwidth.download);
+    app.state::<Arc<ChunkCache>>()
+        .set_budget(current.chunk_cache_size);
+    if let Some(locale) = current.locale {
+        app.state::<I18n>().set_locale(locale);
+    }
+    crate::tray::rebuild(&app);
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use anyhow::Context;
+use iroh_bytes::Hash;
+
+use crate::password;
+
+const PREFIX: &str = "sendme";
+
+/// Length of a single SMS with the GSM 7-bit alphabet.
+const SMS_LEN: usize = 160;
+
+/// Short hex digest, used as the message id and as the checksum of a part.
+fn digest(data: &str) -> String {
+    Hash::new(data.as_bytes()).to_hex()[..4].to_string()
+}
+
+/// Split a ticket into parts of at most `max_len` characters, each of the
+/// form `sendme:<id>:<index>/<count>:<checksum>:<data>`.
+pub fn split(ticket: &str, max_len: usize) -> anyhow::Result<Vec<String>> {
+    let id = digest(ticket);
+    // the header grows with the number of parts, so guess it and retry
+    let mut count = 1;
+    loop {
+        let header = format!("{}:{}:{}/{}:0000:", PREFIX, id, count, count).len();
+        anyhow::ensure!(
+            max_len > header,
+            "parts of {} characters are too short",
+            max_len
+        );
+        let chunk = max_len - header;
+        let needed = ticket.len().div_ceil(chunk);
+        if needed <= count {
+            // tickets are ascii, so splitting by bytes is fine
+            let parts = ticket
+                .as_bytes()
+                .chunks(chunk)
+                .map(|c| std::str::from_utf8(c).expect("ascii"))
+                .enumerate()
+                .map(|(i, data)| {
+                    format!(
+                        "{}:{}:{}/{}:{}:{}",
+                        PREFIX,
+                        id,
+                        i + 1,
+                        needed,
+                        digest(data),
+                        data
+                    )
+                })
+                .collect();
+            return Ok(parts);
+        }
+        count = needed;
+    }
+}
+
+struct Part {
+    id: String,
+    index: usize,
+    count: usize,
+    data: String,
+}
+
+impl FromStr for Part {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.trim().splitn(5, ':');
+        anyhow::ensure!(fields.next() == Some(PREFIX), "not a sendme ticket part");
+        let id = fields.next().context("missing id")?.to_string();
+        let (index, count) = fields
+            .next()
+            .and_then(|f| f.split_once('/'))
+            .context("missing sequence number")?;
+        let (index, count) = (index.parse::<usize>()?, count.parse::<usize>()?);
+        let checksum = fields.next().context("missing checksum")?;
+        let data = fields.next().context("missing data")?.to_string();
+        anyhow::ensure!((1..=count).contains(&index), "invalid sequence number");
+        anyhow::ensure!(
+            digest(&data) == checksum,
+            "part {}/{} is damaged, ask for it again",
+            index,
+            count
+        );
+        Ok(Self {
+            id,
+            index,
+            count,
+            data,
+        })
+    }
+}
+
+/// The parts of a ticket seen so far.
+#[derive(Debug)]
+pub enum Collected {
+    Complete {
+        /// The ticket text, with the password hint of a protected share.
+        ticket: String,
+        count: usize,
+    },
+    /// Some parts are missing, given by their 1-based index.
+    Partial { missing: Vec<usize>, count: usize },
+}
+
+/// Collect the parts of a ticket, in any order. Duplicates are fine.
+pub fn collect<'a>(parts: impl IntoIterator<Item = &'a str>) -> anyhow::Result<Collected> {
+    let mut found = BTreeMap::new();
+    let mut message: Option<(String, usize)> = None;
+    for part in parts {
+        let part = part.parse::<Part>()?;
+        match &message {
+            Some((id, count)) => anyhow::ensure!(
+                *id == part.id && *count == part.count,
+                "the parts belong to different tickets"
+            ),
+            None => message = Some((part.id.clone(), part.count)),
+        }
+        found.insert(part.index, part.data);
+    }
+    let (id, count) = message.context("no parts given")?;
+    let missing = (1..=count)
+        .filter(|i| !found.contains_key(i))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Ok(Collected::Partial { missing, count });
+    }
+    let ticket = found.into_values().collect::<String>();
+    anyhow::ensure!(digest(&ticket) == id, "the parts do not add up to a ticket");
+    password::parse_ticket(&ticket)?;
+    Ok(Collected::Complete { ticket, count })
+}
+
+/// Put the parts of a ticket back together, failing if any are missing.
+fn join<'a>(parts: impl IntoIterator<Item = &'a str>) -> anyhow::Result<String> {
+    match collect(parts)? {
+        Collected::Complete { ticket, .. } => Ok(ticket),
+        Collected::Partial { missing, count } => {
+            let missing = missing.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+            anyhow::bail!("parts {} of {} are missing", missing.join(", "), count)
+        }
+    }
+}
+
+/// Split a ticket into parts that fit into text messages.
+#[tauri::command]
+pub fn split_ticket(ticket: String, max_len: Option<usize>) -> Result<Vec<String>, String> {
+    let (ticket, hint) = password::parse_ticket(&ticket).map_err(|e| format!("{:#}", e))?;
+    let ticket = password::ticket_text(&ticket, hint.as_deref());
+    split(&ticket, max_len.unwrap_or(SMS_LEN)).map_err(|e| e.to_string())
+}
+
+/// Reassemble a ticket from its text message parts. Each entry may also hold
+/// several parts, e.g. a whole pasted conversation, one part per line.
+#[tauri::command]
+pub fn join_ticket(parts: Vec<String>) -> Result<String, String> {
+    let lines = parts
+        .iter()
+        .flat_map(|p| p.lines())
+        .filter(|line| line.trim().starts_with(PREFIX));
+    join(lines).map_err(|e| format!("{:#}", e))
+}
@@ -0,0 +1,199 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use iroh_bytes::{
+    get::db::get_to_db, protocol::ALPN, store::mem, util::progress::IgnoreProgressSender,
+    HashAndFormat,
+};
+use iroh_net::{ticket::BlobTicket, MagicEndpoint};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+use tokio_util::{sync::CancellationToken, task::LocalPoolHandle};
+
+use crate::{
+    auth::SessionToken,
+    fixtures::{self, FixtureSpec},
+    interop,
+    upload::{provide, ShareOptions},
+};
+
+/// How long a soak test runs and how hard it pulls.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct SoakOptions {
+    pub duration_secs: u64,
+    /// Time between two downloads of the share.
+    pub get_interval_secs: u64,
+    /// Time between two resource samples.
+    pub sample_interval_secs: u64,
+}
+
+impl Default for SoakOptions {
+    fn default() -> Self {
+        Self {
+            duration_secs: 4 * 60 * 60,
+            get_interval_secs: 10,
+            sample_interval_secs: 60,
+        }
+    }
+}
+
+/// Resource usage of the process, sent as `soak-sample`.
+///
+/// Values the platform does not expose are unset.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SoakSample {
+    pub elapsed_secs: u64,
+    pub gets_ok: u64,
+    pub gets_failed: u64,
+    pub rss_bytes: Option<u64>,
+    pub open_fds: Option<u64>,
+    pub threads: Option<u64>,
+}
+
+/// The running soak test, if any.
+#[derive(Debug, Default)]
+pub struct Soak(Mutex<Option<CancellationToken>>);
+
+/// A field of `/proc/self/status`, e.g. `VmRSS`.
+fn proc_status(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status
+        .lines()
+        .find(|line| line.strip_prefix(field).is_some_and(|r| r.starts_with(':')))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn sample(start: Instant, gets_ok: u64, gets_failed: u64) -> SoakSample {
+    SoakSample {
+        elapsed_secs: start.elapsed().as_secs(),
+        gets_ok,
+        gets_failed,
+        rss_bytes: proc_status("VmRSS").map(|kb| kb * 1024),
+        open_fds: std::fs::read_dir("/dev/fd")
+            .ok()
+            .map(|dir| dir.count() as u64),
+        threads: proc_status("Threads"),
+    }
+}
+
+/// Download the whole collection of `ticket` into a fresh in memory store.
+async fn fetch(ticket: &BlobTicket) -> anyhow::Result<u64> {
+    let endpoint = MagicEndpoint::builder().alpns(vec![]).bind(0).await?;
+    let connection = endpoint.connect(ticket.node_addr().clone(), ALPN).await?;
+    let db = mem::Store::new();
+    let content = HashAndFormat::hash_seq(ticket.hash());
+    // checking partial blobs is not Send, so the fetch runs on its own thread
+    let stats = LocalPoolHandle::new(1)
+        .spawn_pinned(move || async move {
+            get_to_db(&db, connection, &content, IgnoreProgressSender::default()).await
+        })
+        .await??;
+    endpoint.close(0u32.into(), b"done").await.ok();
+    Ok(stats.bytes_read)
+}
+
+/// Share a generated tree and download it over and over, sampling resource
+/// usage, until `opts.duration_secs` passed or `cancel` is cancelled.
+async fn run(
+    app: &AppHandle,
+    scratch: &Path,
+    opts: SoakOptions,
+    cancel: CancellationToken,
+) -> anyhow::Result<SoakSample> {
+    let fixture = fixtures::generate(&scratch.join("fixture"), &FixtureSpec::default())?;
+    let (downloads, _) = tokio::sync::watch::channel(Default::default());
+    let (ticket, handle) = provide(
+        vec![fixture.root],
+        ShareOptions::default(),
+        interop::env(scratch),
+        Arc::new(downloads),
+    )
+    .await?;
+    log!("soak test of {} started", ticket.hash().to_hex());
+    let start = Instant::now();
+    let deadline = tokio::time::sleep(Duration::from_secs(opts.duration_secs));
+    tokio::pin!(deadline);
+    let mut gets = tokio::time::interval(Duration::from_secs(opts.get_interval_secs.max(1)));
+    let mut samples = tokio::time::interval(Duration::from_secs(opts.sample_interval_secs.max(1)));
+    let (mut gets_ok, mut gets_failed) = (0, 0);
+    loop {
+        tokio::select! {
+            _ = gets.tick() => match fetch(&ticket).await {
+                Ok(_) => gets_ok += 1,
+                Err(err) => {
+                    log!("soak get failed: {:#}", err);
+                    gets_failed += 1;
+                }
+            },
+            _ = samples.tick() => {
+                app.emit_all("soak-sample", sample(start, gets_ok, gets_failed)).ok();
+            }
+            _ = &mut deadline => break,
+            _ = cancel.cancelled() => break,
+        }
+    }
+    handle.stop().await;
+    Ok(sample(start, gets_ok, gets_failed))
+}
+
+/// Developer command: start a soak test in the background.
+///
+/// Samples are sent as `soak-sample`, the last one as `soak-finished` once
+/// the test ends.
+#[tauri::command]
+pub fn start_soak(
+    options: Option<SoakOptions>,
+    token: String,
+    session: State<'_, SessionToken>,
+    soak: State<'_, Soak>,
+    app: AppHandle,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let mut running = soak.0.lock().unwrap();
+    if running
+        .as_ref()
+        .is_some_and(|cancel| !cancel.is_cancelled())
+    {
+        return Err("a soak test is already running".to_string());
+    }
+    let cancel = CancellationToken::new();
+    *running = Some(cancel.clone());
+    let opts = options.unwrap_or_default();
+    tauri::async_runtime::spawn(async move {
+        let res = async {
+            let scratch = interop::scratch_dir()?;
+            let res = run(&app, &scratch, opts, cancel.clone()).await;
+            std::fs::remove_dir_all(&scratch).ok();
+            res
+        }
+        .await;
+        cancel.cancel();
+        match res {
+            Ok(last) => {
+                app.emit_all("soak-finished", last).ok();
+            }
+            Err(err) => {
+                log!("soak test failed: {:#}", err);
+                app.emit_all("soak-failed", format!("{:#}", err)).ok();
+            }
+        }
+    });
+    Ok(())
+}
+
+#[tauri::command]
+pub fn stop_soak(
+    token: String,
+    session: State<'_, SessionToken>,
+    soak: State<'_, Soak>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    if let Some(cancel) = soak.0.lock().unwrap().take() {
+        cancel.cancel();
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+
+use crate::{auth::SessionToken, settings::SettingsStore, upload::ShareOptions};
+
+/// How often the spool folder is scanned.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Sent to the frontend as `spool-shared` for every document shared from the
+/// spool folder.
+#[derive(Debug, Clone, Serialize)]
+struct SpoolShare {
+    name: String,
+    ticket: String,
+}
+
+/// The PDFs in `dir` with their current size.
+fn scan(dir: &Path) -> HashMap<PathBuf, u64> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+        })
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok()?;
+            meta.is_file().then(|| (entry.path(), meta.len()))
+        })
+        .collect()
+}
+
+/// Share every PDF that appears in the spool folder.
+///
+/// Files already in the folder when it is first scanned are left alone. A new
+/// file is shared once its size stayed the same between two scans, so
+/// documents still being printed are not shared half written.
+pub fn spawn_watcher(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut folder: Option<PathBuf> = None;
+        let mut seen = HashSet::new();
+        let mut pending = HashMap::new();
+        loop {
+            let current = app.state::<SettingsStore>().get().spool_folder;
+            if current != folder {
+                folder = current;
+                pending.clear();
+                seen = folder
+                    .as_deref()
+                    .map(|dir| scan(dir).into_keys().collect())
+                    .unwrap_or_default();
+            }
+            if let Some(dir) = &folder {
+                for (path, size) in scan(dir) {
+                    if seen.contains(&path) {
+                        continue;
+                    }
+                    if pending.insert(path.clone(), size) != Some(size) {
+                        continue;
+                    }
+                    pending.remove(&path);
+                    seen.insert(path.clone());
+                    share(&app, path).await;
+                }
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
+
+async fn share(app: &AppHandle, path: PathBuf) {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match crate::share(app, vec![path], ShareOptions::default()).await {
+        Ok(ticket) => {
+            let share = SpoolShare {
+                name,
+                ticket: ticket.to_string(),
+            };
+            app.emit_all("spool-shared", share).ok();
+        }
+        Err(err) => log!("failed to share {} from the spool folder: {:#}", name, err),
+    }
+}
+
+#[tauri::command]
+pub fn get_spool_folder(settings: State<'_, SettingsStore>) -> Option<PathBuf> {
+    settings.get().spool_folder
+}
+
+/// Change the watched folder, or stop watching with `None`.
+#[tauri::command]
+pub fn set_spool_folder(
+    folder: Option<PathBuf>,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    if let Some(folder) = &folder {
+        std::fs::create_dir_all(folder).map_err(|e| e.to_string())?;
+    }
+    settings
+        .update(|s| s.spool_folder = folder)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::{future::Future, path::PathBuf};
+
+use iroh_bytes::store::{flat, mem, Store};
+use serde::{Deserialize, Serialize};
+
+/// With [`StoreKind::Auto`], shares up to this size are kept in memory.
+const MEM_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Which store backend a share uses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    /// In memory for small shares, on disk otherwise.
+    #[default]
+    Auto,
+    /// A flat file store in a scratch directory, referencing the shared files.
+    Flat,
+    /// Everything in memory, nothing is written to disk.
+    Mem,
+}
+
+impl StoreKind {
+    fn resolve(self, size: u64) -> Self {
+        match self {
+            StoreKind::Auto if size <= MEM_THRESHOLD => StoreKind::Mem,
+            StoreKind::Auto => StoreKind::Flat,
+            kind => kind,
+        }
+    }
+}
+
+/// A scratch directory, removed when dropped.
+#[derive(Debug)]
+pub struct Scratch(Option<PathBuf>);
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        if let Some(dir) = self.0.take() {
+            std::fs::remove_dir_all(dir).ok();
+        }
+    }
+}
+
+/// Something done with a store, independent of its backend.
+pub trait WithStore {
+    type Output;
+
+    /// Run with the store `db`. `scratch` has to be kept alive as long as `db` is used.
+    fn run<S: Store>(self, db: S, scratch: Scratch) -> impl Future<Output = Self::Output> + Send;
+}
+
+#[derive(Debug)]
+enum Backend {
+    Flat(flat::Store),
+    Mem(mem::Store),
+}
+
+/// The store of a single share.
+#[derive(Debug)]
+pub struct ShareStore {
+    backend: Backend,
+    scratch: Scratch,
+}
+
+impl ShareStore {
+    /// Open a store of `kind` for a share of `size` bytes. On disk stores live in `dir`.
+    pub async fn open(kind: StoreKind, size: u64, dir: PathBuf) -> anyhow::Result<Self> {
+        let res = match kind.resolve(size) {
+            StoreKind::Mem => Self {
+                backend: Backend::Mem(mem::Store::new()),
+                scratch: Scratch(None),
+            },
+            _ => {
+                std::fs::create_dir_all(&dir)?;
+                let scratch = Scratch(Some(dir.clone()));
+                Self {
+                    backend: Backend::Flat(flat::Store::load(&dir).await?),
+                    scratch,
+                }
+            }
+        };
+        Ok(res)
+    }
+
+    pub async fn with<W: WithStore>(self, op: W) -> W::Output {
+        match self.backend {
+            Backend::Flat(db) => op.run(db, self.scratch).await,
+            Backend::Mem(db) => op.run(db, self.scratch).await,
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{
+    api::http::{Body, ClientBuilder, HttpRequestBuilder},
+    AppHandle, Manager, State,
+};
+
+use crate::{auth::SessionToken, settings::SettingsStore};
+
+/// How often a report is sent, if telemetry is enabled.
+const REPORT_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The counts since the last report, persisted so they survive restarts.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct Counters {
+    shares: u64,
+    shares_failed: u64,
+    downloads: u64,
+    downloads_failed: u64,
+    /// Unix time of the last report that was sent, or of the first start.
+    last_report: u64,
+}
+
+/// Anonymous usage counters.
+///
+/// Only counts are recorded, never paths, hashes, tickets or node ids.
+#[derive(Debug)]
+pub struct Telemetry {
+    path: PathBuf,
+    counters: Mutex<Counters>,
+}
+
+/// Exactly what is sent when telemetry is enabled.
+#[derive(Debug, Clone, Serialize)]
+pub struct TelemetryReport {
+    pub app_version: &'static str,
+    pub os: &'static str,
+    pub shares: u64,
+    pub shares_failed: u64,
+    pub downloads: u64,
+    pub downloads_failed: u64,
+}
+
+impl Telemetry {
+    /// Load the counters persisted at `path`.
+    pub fn load(path: PathBuf) -> Self {
+        let counters = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_else(|| Counters {
+                last_report: now(),
+                ..Default::default()
+            });
+        Self {
+            path,
+            counters: Mutex::new(counters),
+        }
+    }
+
+    fn save(&self, counters: &Counters) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(counters)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Counters)) {
+        let mut counters = self.counters.lock().unwrap();
+        f(&mut counters);
+        if let Err(err) = self.save(&counters) {
+            log!("failed to save telemetry counters: {:#}", err);
+        }
+    }
+
+    pub fn record_share(&self, ok: bool) {
+        self.update(|c| match ok {
+            true => c.shares += 1,
+            false => c.shares_failed += 1,
+        });
+    }
+
+    pub fn record_download(&self, ok: bool) {
+        self.update(|c| match ok {
+            true => c.downloads += 1,
+            false => c.downloads_failed += 1,
+        });
+    }
+
+    pub fn report(&self) -> TelemetryReport {
+        let counters = self.counters.lock().unwrap();
+        TelemetryReport {
+            app_version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            shares: counters.shares,
+            shares_failed: counters.shares_failed,
+            downloads: counters.downloads,
+            downloads_failed: counters.downloads_failed,
+        }
+    }
+
+    /// Subtract a sent report, so the next one only contains new counts.
+    fn sent(&self, report: &TelemetryReport) {
+        self.update(|c| {
+            c.shares -= report.shares;
+            c.shares_failed -= report.shares_failed;
+            c.downloads -= report.downloads;
+            c.downloads_failed -= report.downloads_failed;
+            c.last_report = now();
+        });
+    }
+
+    /// How long until the next report is due, none if it is overdue.
+    fn until_due(&self) -> Duration {
+        let last_report = self.counters.lock().unwrap().last_report;
+        let since = Duration::from_secs(now().saturating_sub(last_report));
+        REPORT_INTERVAL.saturating_sub(since)
+    }
+}
+
+async fn send(endpoint: &str, report: &TelemetryReport) -> anyhow::Result<()> {
+    let client = ClientBuilder::new()
+        .connect_timeout(Duration::from_secs(10))
+        .build()?;
+    let request =
+        HttpRequestBuilder::new("POST", endpoint)?.body(Body::Json(serde_json::to_value(report)?));
+    let response = client.send(request).await?;
+    anyhow::ensure!(
+        response.status().is_success(),
+        "telemetry endpoint returned {}",
+        response.status()
+    );
+    Ok(())
+}
+
+/// Periodically send a report, as long as the user opted in. A report that
+/// came due while the app was not running is sent right away.
+pub fn spawn_reporter(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut wait = app.state::<Telemetry>().until_due();
+        loop {
+            tokio::time::sleep(wait).await;
+            wait = REPORT_INTERVAL;
+            let settings = app.state::<SettingsStore>().get();
+            let Some(endpoint) = settings.telemetry_endpoint.filter(|_| settings.telemetry) else {
+                continue;
+            };
+            let telemetry = app.state::<Telemetry>();
+            let report = telemetry.report();
+            match send(&endpoint, &report).await {
+                Ok(()) => telemetry.sent(&report),
+                Err(err) => log!("failed to send telemetry: {}", err),
+            }
+        }
+    });
+}
+
+/// Show what would be sent, whether or not telemetry is enabled.
+#[tauri::command]
+pub fn telemetry_preview(telemetry: State<'_, Telemetry>) -> TelemetryReport {
+    telemetry.report()
+}
+
+#[tauri::command]
+pub fn set_telemetry_enabled(
+    enabled: bool,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| s.telemetry = enabled)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_counts_until_sent() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("telemetry.json");
+        let telemetry = Telemetry::load(path.clone());
+        assert!(telemetry.until_due() > REPORT_INTERVAL - Duration::from_secs(60));
+        telemetry.record_share(true);
+        telemetry.record_download(false);
+        let report = telemetry.report();
+        telemetry.record_share(true);
+
+        let telemetry = Telemetry::load(path.clone());
+        assert_eq!(telemetry.report().shares, 2);
+        telemetry.sent(&report);
+        let report = Telemetry::load(path.clone()).report();
+        assert_eq!((report.shares, report.downloads_failed), (1, 0));
+
+        // a day passed without the app running
+        telemetry.update(|c| c.last_report -= REPORT_INTERVAL.as_secs());
+        assert_eq!(Telemetry::load(path).until_due(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use serde::Serialize;
+use tauri::State;
+use tracing::{
+    field::{Field, Visit},
+    span,
+    subscriber::Interest,
+    Event, Metadata, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Only spans and events of this crate are recorded, everything else is
+/// disabled at the call site.
+const TARGET: &str = env!("CARGO_PKG_NAME");
+
+/// How many transfers are kept, older ones are dropped.
+const MAX_TRACES: usize = 64;
+
+/// Spans and events kept per transfer, so a long running share does not grow
+/// without bound.
+const MAX_RECORDS: usize = 10_000;
+
+/// The field that ties a span and everything below it to a transfer.
+const TRANSFER_FIELD: &str = "transfer";
+
+/// A new id for the spans of a transfer.
+pub fn next_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Something that happened within a span.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEvent {
+    /// Microseconds since the transfer's first span started.
+    pub at_us: u64,
+    pub message: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+/// A span and the spans it contains.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceSpan {
+    pub name: &'static str,
+    pub fields: BTreeMap<String, String>,
+    /// Microseconds since the transfer's first span started.
+    pub start_us: u64,
+    /// Unset while the span is still open. Spans stay open as long as any
+    /// span they contain.
+    pub duration_us: Option<u64>,
+    pub events: Vec<TraceEvent>,
+    pub children: Vec<TraceSpan>,
+}
+
+/// The span tree of a transfer, as returned by [`trace_transfer`].
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferTrace {
+    pub transfer: u64,
+    pub spans: Vec<TraceSpan>,
+    /// Spans and events dropped because the transfer had too many.
+    pub dropped: u64,
+}
+
+#[derive(Debug)]
+struct SpanRecord {
+    parent: Option<usize>,
+    name: &'static str,
+    fields: BTreeMap<String, String>,
+    start: Instant,
+    end: Option<Instant>,
+    events: Vec<TraceEvent>,
+}
+
+#[derive(Debug)]
+struct Trace {
+    transfer: u64,
+    started: Instant,
+    spans: Vec<SpanRecord>,
+    records: usize,
+    dropped: u64,
+}
+
+impl Trace {
+    fn since_start(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.started).as_micros() as u64
+    }
+
+    fn node(&self, span: &SpanRecord) -> TraceSpan {
+        TraceSpan {
+            name: span.name,
+            fields: span.fields.clone(),
+            start_us: self.since_start(span.start),
+            duration_us: span
+                .end
+                .map(|end| end.saturating_duration_since(span.start).as_micros() as u64),
+            events: span.events.clone(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Nest the recorded spans. Parents are always recorded before their
+    /// children, so walking backwards finds every child before its parent.
+    fn tree(&self) -> Vec<TraceSpan> {
+        let mut nodes = self
+            .spans
+            .iter()
+            .map(|span| Some(self.node(span)))
+            .collect::<Vec<_>>();
+        let mut roots = Vec::new();
+        for i in (0..nodes.len()).rev() {
+            let mut node = nodes[i].take().expect("children are taken after parents");
+            node.children.reverse();
+            match self.spans[i].parent.and_then(|p| nodes[p].as_mut()) {
+                Some(parent) => parent.children.push(node),
+                None => roots.push(node),
+            }
+        }
+        roots.reverse();
+        roots
+    }
+}
+
+/// Recorded transfers, kept in the tauri state and fed by [`TraceLayer`].
+#[derive(Debug, Default)]
+pub struct Traces(Mutex<VecDeque<Trace>>);
+
+impl Traces {
+    /// Record a new span, returning its index in the transfer's trace, or
+    /// `None` if the trace is full.
+    fn open(
+        &self,
+        transfer: u64,
+        parent: Option<usize>,
+        name: &'static str,
+        fields: BTreeMap<String, String>,
+    ) -> Option<usize> {
+        let mut traces = self.0.lock().unwrap();
+        let trace = match traces.iter().position(|t| t.transfer == transfer) {
+            Some(i) => &mut traces[i],
+            None => {
+                if traces.len() == MAX_TRACES {
+                    traces.pop_front();
+                }
+                traces.push_back(Trace {
+                    transfer,
+                    started: Instant::now(),
+                    spans: Vec::new(),
+                    records: 0,
+                    dropped: 0,
+                });
+                traces.back_mut().unwrap()
+            }
+        };
+        if trace.records == MAX_RECORDS {
+            trace.dropped += 1;
+            return None;
+        }
+        trace.records += 1;
+        trace.spans.push(SpanRecord {
+            parent,
+            name,
+            fields,
+            start: Instant::now(),
+            end: None,
+            events: Vec::new(),
+        });
+        Some(trace.spans.len() - 1)
+    }
+
+    fn with_span(&self, slot: &Slot, f: impl FnOnce(&mut Trace, usize)) {
+        let Some(index) = slot.index else {
+            return;
+        };
+        let mut traces = self.0.lock().unwrap();
+        if let Some(trace) = traces.iter_mut().find(|t| t.transfer == slot.transfer) {
+            f(trace, index);
+        }
+    }
+
+    fn close(&self, slot: &Slot) {
+        self.with_span(slot, |trace, index| {
+            trace.spans[index].end = Some(Instant::now());
+        });
+    }
+
+    fn event(&self, slot: &Slot, message: String, fields: BTreeMap<String, String>) {
+        self.with_span(slot, |trace, index| {
+            if trace.records == MAX_RECORDS {
+                trace.dropped += 1;
+                return;
+            }
+            trace.records += 1;
+            let at_us = trace.since_start(Instant::now());
+            trace.spans[index].events.push(TraceEvent {
+                at_us,
+                message,
+                fields,
+            });
+        });
+    }
+
+    pub fn get(&self, transfer: u64) -> Option<TransferTrace> {
+        let traces = self.0.lock().unwrap();
+        let trace = traces.iter().find(|t| t.transfer == transfer)?;
+        Some(TransferTrace {
+            transfer,
+            spans: trace.tree(),
+            dropped: trace.dropped,
+        })
+    }
+}
+
+/// Where a span is recorded, kept in the span's extensions.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    transfer: u64,
+    index: Option<usize>,
+}
+
+#[derive(Default)]
+struct Fields {
+    transfer: Option<u64>,
+    message: String,
+    fields: BTreeMap<String, String>,
+}
+
+impl Visit for Fields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == TRANSFER_FIELD {
+            self.transfer = Some(value);
+        } else {
+            self.fields
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields
+            .insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.fields
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+}
+
+/// Records the spans of this crate that belong to a transfer into [`Traces`].
+///
+/// A span belongs to a transfer if it has a `transfer` field, or its parent
+/// belongs to one.
+#[derive(Debug, Clone)]
+pub struct TraceLayer(Arc<Traces>);
+
+impl TraceLayer {
+    pub fn new(traces: Arc<Traces>) -> Self {
+        Self(traces)
+    }
+}
+
+impl<S> Layer<S> for TraceLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if metadata.target().starts_with(TARGET) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<Slot>().copied());
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        let Some(transfer) = fields.transfer.or(parent.map(|p| p.transfer)) else {
+            return;
+        };
+        let parent = parent
+            .filter(|p| p.transfer == transfer)
+            .and_then(|p| p.index);
+        let index = self
+            .0
+            .open(transfer, parent, attrs.metadata().name(), fields.fields);
+        span.extensions_mut().insert(Slot { transfer, index });
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.event_span(event) else {
+            return;
+        };
+        let Some(slot) = span.extensions().get::<Slot>().copied() else {
+            return;
+        };
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        self.0.event(&slot, fields.message, fields.fields);
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        if let Some(slot) = span.extensions().get::<Slot>() {
+            self.0.close(slot);
+        };
+    }
+}
+
+/// Developer command: the span tree of a share or download as json, for
+/// finding out where a slow transfer spends its time.
+///
+/// The id is the `trace` of the transfer's info or download stats.
+#[tauri::command]
+pub fn trace_transfer(id: u64, traces: State<'_, Arc<Traces>>) -> Result<TransferTrace, String> {
+    traces
+        .get(id)
+        .ok_or_else(|| format!("no trace for transfer {}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[test]
+    fn spans_nest_by_transfer() {
+        let traces = Arc::new(Traces::default());
+        let subscriber = tracing_subscriber::registry().with(TraceLayer::new(traces.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let share = tracing::info_span!("share", transfer = 7u64);
+            let _share = share.enter();
+            tracing::info_span!("import", files = 2).in_scope(|| {
+                tracing::info!(size = 10, "imported");
+            });
+            let connection = tracing::info_span!("connection", peer = "a");
+            connection.in_scope(|| tracing::info_span!("request", id = 0).in_scope(|| {}));
+            // not part of any transfer
+            drop(_share);
+            tracing::info_span!("unrelated").in_scope(|| {});
+        });
+        assert!(traces.get(8).is_none());
+        let trace = traces.get(7).unwrap();
+        assert_eq!(trace.spans.len(), 1);
+        let share = &trace.spans[0];
+        assert_eq!(share.name, "share");
+        let names = share.children.iter().map(|s| s.name).collect::<Vec<_>>();
+        assert_eq!(names, ["import", "connection"]);
+        let import = &share.children[0];
+        assert_eq!(import.fields["files"], "2");
+        assert!(import.duration_us.is_some());
+        assert_eq!(import.events[0].message, "imported");
+        assert_eq!(import.events[0].fields["size"], "10");
+        assert_eq!(share.children[1].children[0].name, "request");
+    }
+}
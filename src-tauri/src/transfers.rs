@@ -0,0 +1,522 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    auth::SessionToken,
+    pause::SharePause,
+    serve::{Downloads, StatsReport, TimingsReport},
+    upload::{Reachability, ShareHandle, ShareHealth, SubShares},
+};
+
+/// How long a share that stopped on its own stays listed, so the frontend
+/// can still show that it stopped or expired.
+const STOPPED_GRACE: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferStatus {
+    Serving,
+    /// The share stopped on its own, e.g. because its endpoint closed.
+    Stopped,
+    /// The share was stopped by its [`ExpiryPolicy`].
+    Expired,
+}
+
+/// When a share stops on its own, chosen per share. Downloads that are still
+/// running then are cut off.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExpiryPolicy {
+    /// Stop after this many complete downloads.
+    pub max_downloads: Option<u64>,
+    /// Stop this many seconds after the share started.
+    pub expires_after_secs: Option<u64>,
+    /// Stop after the first complete download, like a `max_downloads` of 1.
+    pub stop_after_first_download: bool,
+}
+
+impl ExpiryPolicy {
+    fn max_downloads(&self) -> Option<u64> {
+        if self.stop_after_first_download {
+            Some(1)
+        } else {
+            self.max_downloads.filter(|max| *max > 0)
+        }
+    }
+
+    fn is_set(&self) -> bool {
+        self.max_downloads().is_some() || self.expires_after_secs.is_some()
+    }
+
+    /// Wait until the policy is reached, returning false if the share stopped
+    /// before that.
+    async fn reached(&self, mut downloads: watch::Receiver<Downloads>) -> bool {
+        let deadline = async {
+            match self.expires_after_secs {
+                Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+                None => std::future::pending().await,
+            }
+        };
+        let downloaded = async {
+            match self.max_downloads() {
+                Some(max) => downloads.wait_for(|d| d.count >= max).await.is_ok(),
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = deadline => true,
+            reached = downloaded => reached,
+        }
+    }
+}
+
+/// What the frontend gets to see of a share.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferInfo {
+    pub id: u64,
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    pub ticket: String,
+    /// Unix time the share was started.
+    pub started: u64,
+    pub downloads: u64,
+    pub status: TransferStatus,
+    /// How long the first download took to connect, start and complete,
+    /// telling slow recipients apart from connectivity problems.
+    pub timings: TimingsReport,
+    /// Whether the ticket still reaches the share, with a new ticket if not.
+    pub health: ShareHealth,
+    /// Id of the share's spans, see [`crate::trace::trace_transfer`].
+    pub trace: u64,
+    pub expiry: ExpiryPolicy,
+    /// Whether the share was paused on its own, see [`pause_transfer`].
+    pub paused: SharePause,
+}
+
+#[derive(Debug)]
+struct Transfer {
+    name: String,
+    paths: Vec<PathBuf>,
+    ticket: String,
+    started: u64,
+    downloads: watch::Receiver<Downloads>,
+    handle: ShareHandle,
+    trace: u64,
+    expiry: ExpiryPolicy,
+    /// Set once the share was stopped by its expiry policy.
+    expired: Arc<AtomicBool>,
+    /// When the share was first seen stopped.
+    stopped: Option<Instant>,
+}
+
+impl Transfer {
+    fn info(&self, id: u64) -> TransferInfo {
+        let status = if !self.handle.is_finished() {
+            TransferStatus::Serving
+        } else if self.expired.load(Ordering::Relaxed) {
+            TransferStatus::Expired
+        } else {
+            TransferStatus::Stopped
+        };
+        TransferInfo {
+            id,
+            name: self.name.clone(),
+            paths: self.paths.clone(),
+            ticket: self.ticket.clone(),
+            started: self.started,
+            downloads: self.downloads.borrow().count,
+            status,
+            timings: self.handle.timings(),
+            health: self.handle.health().borrow().clone(),
+            trace: self.trace,
+            expiry: self.expiry,
+            paused: self.handle.pause_state(),
+        }
+    }
+}
+
+/// Stop share `id` through `stop` once `expiry` is reached.
+fn enforce_expiry(
+    id: u64,
+    expiry: ExpiryPolicy,
+    downloads: watch::Receiver<Downloads>,
+    stop: CancellationToken,
+    expired: Arc<AtomicBool>,
+) {
+    tauri::async_runtime::spawn(async move {
+        tokio::select! {
+            reached = expiry.reached(downloads) => {
+                if reached {
+                    log!("share {} expired", id);
+                    expired.store(true, Ordering::Relaxed);
+                    stop.cancel();
+                }
+            }
+            _ = stop.cancelled() => {}
+        }
+    });
+}
+
+#[derive(Debug, Default)]
+struct Transfers {
+    next_id: u64,
+    active: BTreeMap<u64, Transfer>,
+}
+
+impl Transfers {
+    /// Forget shares that stopped on their own more than [`STOPPED_GRACE`]
+    /// ago, their data is cleaned up already.
+    fn prune(&mut self) {
+        let now = Instant::now();
+        self.active.retain(|id, transfer| {
+            if !transfer.handle.is_finished() {
+                return true;
+            }
+            let stopped = *transfer.stopped.get_or_insert(now);
+            let keep = now.duration_since(stopped) < STOPPED_GRACE;
+            if !keep {
+                log!("forgetting stopped share {} of {}", id, transfer.name);
+            }
+            keep
+        });
+    }
+}
+
+/// The shares started in this session, kept in the tauri state.
+#[derive(Debug, Default)]
+pub struct TransferManager(Mutex<Transfers>);
+
+impl TransferManager {
+    /// Track a started share, returning its id, and stop it once `expiry` is
+    /// reached.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add(
+        &self,
+        name: String,
+        paths: Vec<PathBuf>,
+        ticket: String,
+        downloads: watch::Receiver<Downloads>,
+        handle: ShareHandle,
+        trace: u64,
+        expiry: ExpiryPolicy,
+    ) -> u64 {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut transfers = self.0.lock().unwrap();
+        transfers.prune();
+        transfers.next_id += 1;
+        let id = transfers.next_id;
+        let expired = Arc::new(AtomicBool::new(false));
+        if expiry.is_set() {
+            let stop = handle.stop_token();
+            enforce_expiry(id, expiry, downloads.clone(), stop, expired.clone());
+        }
+        let transfer = Transfer {
+            name,
+            paths,
+            ticket,
+            started,
+            downloads,
+            handle,
+            trace,
+            expiry,
+            expired,
+            stopped: None,
+        };
+        transfers.active.insert(id, transfer);
+        id
+    }
+
+    pub fn list(&self) -> Vec<TransferInfo> {
+        let mut transfers = self.0.lock().unwrap();
+        transfers.prune();
+        transfers
+            .active
+            .iter()
+            .map(|(id, transfer)| transfer.info(*id))
+            .collect()
+    }
+
+    pub fn status(&self, id: u64) -> Option<TransferInfo> {
+        let transfers = self.0.lock().unwrap();
+        transfers.active.get(&id).map(|transfer| transfer.info(id))
+    }
+
+    pub fn stats(&self, id: u64) -> Option<StatsReport> {
+        let transfers = self.0.lock().unwrap();
+        transfers
+            .active
+            .get(&id)
+            .map(|transfer| transfer.handle.stats())
+    }
+
+    pub fn sub_shares(&self, id: u64) -> Option<SubShares> {
+        let transfers = self.0.lock().unwrap();
+        transfers
+            .active
+            .get(&id)
+            .map(|transfer| transfer.handle.sub_shares())
+    }
+
+    /// Pause or resume share `id`, returning whether that changed anything
+    /// or `None` if there is no such share.
+    pub fn set_pause(&self, id: u64, state: SharePause) -> Option<bool> {
+        let transfers = self.0.lock().unwrap();
+        let transfer = transfers.active.get(&id)?;
+        Some(transfer.handle.set_pause(state))
+    }
+
+    /// The paths served by shares that are still running.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let transfers = self.0.lock().unwrap();
+        transfers
+            .active
+            .values()
+            .filter(|transfer| !transfer.handle.is_finished())
+            .flat_map(|transfer| transfer.paths.iter().cloned())
+            .collect()
+    }
+
+    /// Stop serving a share and wait until its data is cleaned up.
+    ///
+    /// Returns false if there is no such share.
+    pub async fn cancel(&self, id: u64) -> bool {
+        let transfer = self.0.lock().unwrap().active.remove(&id);
+        match transfer {
+            Some(transfer) => {
+                log!("stopping share {} of {}", id, transfer.name);
+                transfer.handle.stop().await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stop all shares, returning how many there were.
+    pub async fn stop_all(&self) -> usize {
+        let active = std::mem::take(&mut self.0.lock().unwrap().active);
+        let count = active.len();
+        log!("stopping {} shares", count);
+        futures::future::join_all(active.into_values().map(|transfer| transfer.handle.stop()))
+            .await;
+        count
+    }
+}
+
+/// Sent to the frontend as `share-warning` when a share becomes unreachable
+/// or its ticket stale.
+#[derive(Debug, Clone, Serialize)]
+struct ShareWarning {
+    id: u64,
+    name: String,
+    #[serde(flatten)]
+    health: ShareHealth,
+}
+
+/// Warn the frontend whenever share `id` stops being reachable.
+pub fn watch_health(
+    app: AppHandle,
+    id: u64,
+    name: String,
+    mut health: watch::Receiver<ShareHealth>,
+) {
+    tauri::async_runtime::spawn(async move {
+        while health.changed().await.is_ok() {
+            let current = health.borrow_and_update().clone();
+            if current.reachability == Reachability::Reachable {
+                continue;
+            }
+            let warning = ShareWarning {
+                id,
+                name: name.clone(),
+                health: current,
+            };
+            app.emit_all("share-warning", warning).ok();
+        }
+    });
+}
+
+#[tauri::command]
+pub fn list_transfers(transfers: State<'_, TransferManager>) -> Vec<TransferInfo> {
+    transfers.list()
+}
+
+#[tauri::command]
+pub fn transfer_status(
+    id: u64,
+    transfers: State<'_, TransferManager>,
+) -> Result<TransferInfo, String> {
+    transfers
+        .status(id)
+        .ok_or_else(|| format!("no transfer {}", id))
+}
+
+/// What share `id` sent so far.
+#[tauri::command]
+pub fn transfer_stats(
+    id: u64,
+    transfers: State<'_, TransferManager>,
+) -> Result<StatsReport, String> {
+    transfers
+        .stats(id)
+        .ok_or_else(|| format!("no transfer {}", id))
+}
+
+/// Stop serving a share and remove its data.
+#[tauri::command]
+pub async fn cancel_transfer(
+    id: u64,
+    token: String,
+    session: State<'_, SessionToken>,
+    transfers: State<'_, TransferManager>,
+    app: AppHandle,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    if transfers.cancel(id).await {
+        crate::tray::rebuild(&app);
+        Ok(())
+    } else {
+        Err(format!("no transfer {}", id))
+    }
+}
+
+/// Sent to the frontend as `transfer-paused` when a single share is paused
+/// or resumed.
+#[derive(Debug, Clone, Serialize)]
+struct TransferPaused {
+    id: u64,
+    paused: SharePause,
+}
+
+/// Pause or resume share `id`, telling the frontend and the tray.
+pub fn set_pause(app: &AppHandle, id: u64, state: SharePause) -> Result<(), String> {
+    let changed = app
+        .state::<TransferManager>()
+        .set_pause(id, state)
+        .ok_or_else(|| format!("no transfer {}", id))?;
+    if changed {
+        app.emit_all("transfer-paused", TransferPaused { id, paused: state })
+            .ok();
+        crate::tray::rebuild(app);
+    }
+    Ok(())
+}
+
+/// Stop accepting new downloads of share `id`. With `suspend`, running
+/// downloads are dropped too, receivers continue where they stopped once the
+/// share is resumed.
+#[tauri::command]
+pub fn pause_transfer(
+    id: u64,
+    suspend: Option<bool>,
+    token: String,
+    session: State<'_, SessionToken>,
+    app: AppHandle,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let state = if suspend.unwrap_or(false) {
+        SharePause::Suspended
+    } else {
+        SharePause::Paused
+    };
+    set_pause(&app, id, state)
+}
+
+#[tauri::command]
+pub fn resume_transfer(
+    id: u64,
+    token: String,
+    session: State<'_, SessionToken>,
+    app: AppHandle,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    set_pause(&app, id, SharePause::Running)
+}
+
+/// A ticket for some of the files and directories of share `id`, without
+/// importing them again. It stays valid as long as the share runs.
+#[tauri::command]
+pub async fn create_sub_share(
+    id: u64,
+    names: Vec<String>,
+    token: String,
+    session: State<'_, SessionToken>,
+    transfers: State<'_, TransferManager>,
+) -> Result<String, String> {
+    session.verify(&token)?;
+    let sub_shares = transfers
+        .sub_shares(id)
+        .ok_or_else(|| format!("no transfer {}", id))?;
+    let ticket = sub_shares.create(names).await.map_err(|e| e.to_string())?;
+    Ok(ticket.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn forgets_stopped_shares() {
+        let manager = TransferManager::default();
+        let add = |name: &str| {
+            let (_, downloads) = watch::channel(Downloads::default());
+            let handle = ShareHandle::idle();
+            let stop = handle.stop_token();
+            let name = name.to_string();
+            let id = manager.add(
+                name,
+                vec![],
+                String::new(),
+                downloads,
+                handle,
+                0,
+                Default::default(),
+            );
+            (id, stop)
+        };
+        let (stopped, stop) = add("stopped");
+        let (running, _) = add("running");
+        stop.cancel();
+        while !manager.0.lock().unwrap().active[&stopped]
+            .handle
+            .is_finished()
+        {
+            tokio::task::yield_now().await;
+        }
+        // still shown as stopped for a while
+        let status = |id| manager.status(id).map(|info| info.status);
+        assert_eq!(manager.list().len(), 2);
+        assert_eq!(status(stopped), Some(TransferStatus::Stopped));
+
+        let since = manager.0.lock().unwrap().active[&stopped].stopped.unwrap();
+        let long_ago = since.checked_sub(STOPPED_GRACE).unwrap();
+        manager
+            .0
+            .lock()
+            .unwrap()
+            .active
+            .get_mut(&stopped)
+            .unwrap()
+            .stopped = Some(long_ago);
+        let listed = manager.list();
+        assert_eq!(
+            listed.iter().map(|info| info.id).collect::<Vec<_>>(),
+            [running]
+        );
+        assert_eq!(status(running), Some(TransferStatus::Serving));
+    }
+}
@@ -0,0 +1,307 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tauri::{
+    AppHandle, ClipboardManager, CustomMenuItem, Manager, State, SystemTrayMenu,
+    SystemTrayMenuItem, SystemTraySubmenu,
+};
+use tokio::sync::watch;
+
+use crate::{
+    auth::SessionToken,
+    i18n::I18n,
+    pause::{PauseState, SharePause},
+    serve::Downloads,
+    settings::SettingsStore,
+    transfers::{self, TransferInfo, TransferManager, TransferStatus},
+};
+
+/// Which actions are shown in the tray menu.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrayLayout {
+    pub share: bool,
+    pub receive: bool,
+    pub pause_all: bool,
+    /// Stopping all shares and cleaning up cached data.
+    pub maintenance: bool,
+    /// Number of recently shared items to list, 0 to hide them.
+    pub recent_items: usize,
+    /// List the running shares, with actions to copy their ticket or stop them.
+    pub active_shares: bool,
+    /// Ask before quitting, since quitting stops all shares.
+    pub confirm_quit: bool,
+}
+
+impl Default for TrayLayout {
+    fn default() -> Self {
+        Self {
+            share: true,
+            receive: true,
+            pause_all: true,
+            maintenance: true,
+            recent_items: 5,
+            active_shares: true,
+            confirm_quit: false,
+        }
+    }
+}
+
+/// Upper bound for `TrayLayout::recent_items`.
+const MAX_RECENT: usize = 20;
+
+/// Names of the most recently shared items, newest first.
+#[derive(Debug, Default)]
+pub struct RecentShares(Mutex<VecDeque<String>>);
+
+impl RecentShares {
+    pub fn push(&self, name: String) {
+        let mut recent = self.0.lock().unwrap();
+        recent.retain(|n| n != &name);
+        recent.push_front(name);
+        recent.truncate(MAX_RECENT);
+    }
+
+    fn list(&self, n: usize) -> Vec<String> {
+        self.0.lock().unwrap().iter().take(n).cloned().collect()
+    }
+}
+
+/// A submenu for a running share, titled with its name and downloads.
+fn share_menu(share: &TransferInfo, i18n: &I18n) -> SystemTraySubmenu {
+    let paused = share.paused.refuses();
+    let (toggle, toggle_label) = if paused {
+        ("resume", "tray.resume_share")
+    } else {
+        ("pause", "tray.pause_share")
+    };
+    let actions = SystemTrayMenu::new()
+        .add_item(CustomMenuItem::new(
+            format!("share-copy-{}", share.id),
+            i18n.translate("tray.copy_ticket", &[]),
+        ))
+        .add_item(CustomMenuItem::new(
+            format!("share-{}-{}", toggle, share.id),
+            i18n.translate(toggle_label, &[]),
+        ))
+        .add_item(CustomMenuItem::new(
+            format!("share-stop-{}", share.id),
+            i18n.translate("tray.stop_sharing", &[]),
+        ));
+    let count = share.downloads.to_string();
+    let title = i18n.translate(
+        if paused {
+            "tray.share_title_paused"
+        } else {
+            "tray.share_title"
+        },
+        &[("name", &share.name), ("count", &count)],
+    );
+    SystemTraySubmenu::new(title, actions)
+}
+
+fn build_menu(
+    layout: &TrayLayout,
+    i18n: &I18n,
+    paused: bool,
+    recent: &[String],
+    shares: &[TransferInfo],
+) -> SystemTrayMenu {
+    let mut menu = SystemTrayMenu::new();
+    if layout.share {
+        menu = menu.add_item(CustomMenuItem::new(
+            "share",
+            i18n.translate("tray.share", &[]),
+        ));
+    }
+    if layout.receive {
+        menu = menu.add_item(CustomMenuItem::new(
+            "receive",
+            i18n.translate("tray.receive", &[]),
+        ));
+    }
+    if layout.pause_all {
+        let title = if paused {
+            i18n.translate("tray.resume_all", &[])
+        } else {
+            i18n.translate("tray.pause_all", &[])
+        };
+        menu = menu.add_item(CustomMenuItem::new("pause_all", title));
+    }
+    if layout.maintenance {
+        menu = menu
+            .add_item(CustomMenuItem::new(
+                "stop_all",
+                i18n.translate("tray.stop_all", &[]),
+            ))
+            .add_item(CustomMenuItem::new(
+                "cleanup",
+                i18n.translate("tray.cleanup", &[]),
+            ));
+    }
+    if !shares.is_empty() {
+        menu = menu.add_native_item(SystemTrayMenuItem::Separator);
+        for share in shares {
+            menu = menu.add_submenu(share_menu(share, i18n));
+        }
+    }
+    if !recent.is_empty() {
+        menu = menu.add_native_item(SystemTrayMenuItem::Separator);
+        for (i, name) in recent.iter().enumerate() {
+            menu = menu.add_item(CustomMenuItem::new(format!("recent-{}", i), name));
+        }
+    }
+    menu.add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new(
+            "quit",
+            i18n.translate("tray.quit", &[]),
+        ))
+}
+
+/// Rebuild the tray menu from the current settings and shares.
+pub fn rebuild(app: &AppHandle) {
+    let layout = app.state::<SettingsStore>().get().tray;
+    let recent = app
+        .state::<RecentShares>()
+        .list(layout.recent_items.min(MAX_RECENT));
+    let paused = app.state::<PauseState>().is_paused();
+    let shares = if layout.active_shares {
+        let mut shares = app.state::<TransferManager>().list();
+        shares.retain(|share| share.status == TransferStatus::Serving);
+        shares
+    } else {
+        Vec::new()
+    };
+    let menu = build_menu(&layout, &app.state::<I18n>(), paused, &recent, &shares);
+    if let Err(err) = app.tray_handle().set_menu(menu) {
+        log!("failed to update tray menu: {}", err);
+    }
+}
+
+/// Rebuild the menu whenever a share is downloaded, and once it stopped.
+pub fn watch_share(app: AppHandle, mut downloads: watch::Receiver<Downloads>) {
+    tauri::async_runtime::spawn(async move {
+        while downloads.changed().await.is_ok() {
+            rebuild(&app);
+        }
+        // the counter is dropped just before the share's task finishes
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        rebuild(&app);
+    });
+}
+
+/// Put the ticket of share `id` on the clipboard.
+fn copy_ticket(app: &AppHandle, id: u64) {
+    let Some(share) = app.state::<TransferManager>().status(id) else {
+        return;
+    };
+    if let Err(err) = app.clipboard_manager().write_text(share.ticket) {
+        log!("failed to copy ticket: {}", err);
+    }
+}
+
+fn stop_share(app: &AppHandle, id: u64) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        app.state::<TransferManager>().cancel(id).await;
+        rebuild(&app);
+    });
+}
+
+/// Show the window with `label`, creating it if needed.
+fn show_window(app: &AppHandle, label: &str, url: &str) {
+    if let Some(window) = app.get_window(label) {
+        window.set_focus().ok();
+        return;
+    }
+    tauri::WindowBuilder::new(app, label, tauri::WindowUrl::App(url.into()))
+        .build()
+        .expect("unable to create window");
+}
+
+pub fn handle_menu_click(app: &AppHandle, id: &str) {
+    match id {
+        "quit" => {
+            if !app.state::<SettingsStore>().get().tray.confirm_quit {
+                crate::maintenance::quit(app);
+                return;
+            }
+            let i18n = app.state::<I18n>();
+            let app = app.clone();
+            tauri::api::dialog::ask(
+                None::<&tauri::Window>,
+                "SendMe",
+                i18n.translate("tray.confirm_quit", &[]),
+                move |confirmed| {
+                    if confirmed {
+                        crate::maintenance::quit(&app);
+                    }
+                },
+            );
+        }
+        "share" => show_window(app, "share", "index.html"),
+        "receive" => show_window(app, "receive", "index.html#receive"),
+        "pause_all" => crate::pause::toggle(app),
+        "stop_all" => {
+            let i18n = app.state::<I18n>();
+            let app = app.clone();
+            tauri::api::dialog::ask(
+                None::<&tauri::Window>,
+                "SendMe",
+                i18n.translate("tray.confirm_stop_all", &[]),
+                move |stop| {
+                    if stop {
+                        crate::maintenance::stop_all(&app);
+                    }
+                },
+            );
+        }
+        "cleanup" => crate::maintenance::cleanup_old(app),
+        id if id.starts_with("recent-") => show_window(app, "share", "index.html"),
+        id => {
+            if let Some(id) = id
+                .strip_prefix("share-copy-")
+                .and_then(|id| id.parse().ok())
+            {
+                copy_ticket(app, id);
+            } else if let Some(id) = id
+                .strip_prefix("share-stop-")
+                .and_then(|id| id.parse().ok())
+            {
+                stop_share(app, id);
+            } else if let Some(id) = id
+                .strip_prefix("share-pause-")
+                .and_then(|id| id.parse().ok())
+            {
+                // running downloads finish
+                transfers::set_pause(app, id, SharePause::Paused).ok();
+            } else if let Some(id) = id
+                .strip_prefix("share-resume-")
+                .and_then(|id| id.parse().ok())
+            {
+                transfers::set_pause(app, id, SharePause::Running).ok();
+            }
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_tray_layout(settings: State<'_, SettingsStore>) -> TrayLayout {
+    settings.get().tray
+}
+
+#[tauri::command]
+pub fn set_tray_layout(
+    layout: TrayLayout,
+    token: String,
+    app: AppHandle,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    settings
+        .update(|s| s.tray = layout)
+        .map_err(|e| e.to_string())?;
+    rebuild(&app);
+    Ok(())
+}
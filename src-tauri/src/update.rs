@@ -0,0 +1,158 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::{auth::SessionToken, i18n::I18n, settings::SettingsStore};
+
+/// Longest the startup check can be put off for.
+const MAX_REMIND_DAYS: u64 = 90;
+
+/// Release channel to look for updates in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl UpdateChannel {
+    #[cfg(feature = "updater")]
+    fn endpoint(&self) -> &'static str {
+        match self {
+            UpdateChannel::Stable => {
+                "https://github.com/dignifiedquire/sendme-tauri/releases/latest/download/latest.json"
+            }
+            UpdateChannel::Beta => {
+                "https://github.com/dignifiedquire/sendme-tauri/releases/download/beta/latest.json"
+            }
+        }
+    }
+}
+
+/// Result of an update check, also the payload of the `update-available` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateInfo {
+    pub channel: UpdateChannel,
+    pub available: bool,
+    pub current_version: String,
+    pub latest_version: String,
+    pub notes: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Ask the release endpoint of `channel` for a newer version.
+#[cfg(feature = "updater")]
+async fn fetch(app: &AppHandle, channel: UpdateChannel) -> anyhow::Result<UpdateInfo> {
+    let response = tauri::updater::builder(app.clone())
+        .endpoints(&[channel.endpoint().to_string()])
+        .skip_events()
+        .check()
+        .await;
+    let info = match response {
+        Ok(update) => UpdateInfo {
+            channel,
+            available: update.is_update_available(),
+            current_version: update.current_version().to_string(),
+            latest_version: update.latest_version().to_string(),
+            notes: update.body().cloned(),
+        },
+        Err(tauri::updater::Error::UpToDate) => UpdateInfo {
+            channel,
+            available: false,
+            current_version: app.package_info().version.to_string(),
+            latest_version: app.package_info().version.to_string(),
+            notes: None,
+        },
+        Err(err) => return Err(err.into()),
+    };
+    Ok(info)
+}
+
+/// Builds without the `updater` feature have no public key to verify
+/// releases with, so they never look for updates.
+#[cfg(not(feature = "updater"))]
+async fn fetch(_app: &AppHandle, _channel: UpdateChannel) -> anyhow::Result<UpdateInfo> {
+    anyhow::bail!("updates are not enabled in this build")
+}
+
+async fn check(app: &AppHandle, channel: UpdateChannel) -> anyhow::Result<UpdateInfo> {
+    // updates can't be verified without the public key of the release signatures
+    anyhow::ensure!(
+        app.config().tauri.updater.active,
+        "updates are not enabled in this build"
+    );
+    let info = fetch(app, channel).await?;
+    if info.available {
+        log!("update {} available on {:?}", info.latest_version, channel);
+        app.emit_all("update-available", info.clone()).ok();
+        let tooltip = app
+            .state::<I18n>()
+            .translate("update.available", &[("version", &info.latest_version)]);
+        app.tray_handle().set_tooltip(&tooltip).ok();
+    }
+    Ok(info)
+}
+
+/// Check for updates once at startup, unless the user asked to be reminded later.
+pub fn spawn_startup_check(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        if !app.config().tauri.updater.active {
+            return;
+        }
+        let settings = app.state::<SettingsStore>().get();
+        if settings.update_remind_after.is_some_and(|t| t > now()) {
+            return;
+        }
+        if let Err(err) = check(&app, settings.update_channel).await {
+            log!("update check failed: {}", err);
+        }
+    });
+}
+
+/// Check for updates, switching to `channel` if given.
+#[tauri::command]
+pub async fn check_for_updates(
+    channel: Option<UpdateChannel>,
+    token: String,
+    app: AppHandle,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<UpdateInfo, String> {
+    session.verify(&token)?;
+    let channel = match channel {
+        Some(channel) => {
+            settings
+                .update(|s| s.update_channel = channel)
+                .map_err(|e| e.to_string())?;
+            channel
+        }
+        None => settings.get().update_channel,
+    };
+    check(&app, channel).await.map_err(|e| e.to_string())
+}
+
+/// Don't check for updates at startup for the next `days` days, at most
+/// [`MAX_REMIND_DAYS`].
+#[tauri::command]
+pub fn remind_me_later(
+    days: u64,
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+) -> Result<(), String> {
+    session.verify(&token)?;
+    let days = days.clamp(1, MAX_REMIND_DAYS);
+    let until = now().saturating_add(Duration::from_secs(days * 24 * 60 * 60).as_secs());
+    settings
+        .update(|s| s.update_remind_after = Some(until))
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -2,22 +2,58 @@ use anyhow::Context;
 use futures::{future::BoxFuture, FutureExt, StreamExt};
 use iroh_bytes::{
     format::collection::Collection,
-    provider::{handle_connection, Event, EventSender},
-    store::{ExportMode, ImportMode},
+    provider::{Event, EventSender},
+    store::{ImportMode, MapEntry, Store},
     BlobFormat, Hash, TempTag,
 };
-use iroh_net::{key::SecretKey, ticket::BlobTicket, MagicEndpoint};
-use rand::Rng;
+use iroh_net::{key::SecretKey, ticket::BlobTicket, MagicEndpoint, NodeAddr, NodeId};
+use serde::{Deserialize, Serialize};
 use std::{
+    borrow::Cow,
+    collections::HashSet,
+    ffi::OsStr,
     fmt::{Display, Formatter},
+    future::Future,
     path::{Component, Path, PathBuf},
     str::FromStr,
+    sync::Arc,
 };
-use tokio::task::JoinHandle;
-use tokio_util::task::LocalPoolHandle;
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    task::{JoinHandle, JoinSet},
+};
+use tokio_util::{sync::CancellationToken, task::LocalPoolHandle};
+use tracing::Instrument;
 use walkdir::WalkDir;
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+use crate::{
+    activity::ActivityLog,
+    audit::AuditLog,
+    bandwidth::Bucket,
+    cache::ChunkCache,
+    discovery::{DnsDiscovery, DnsRecords},
+    errors::ErrorCode,
+    keepalive::KeepAlive,
+    network::NetworkSettings,
+    pack::{self, MAX_PACKED_FILE, PACK_DIR},
+    password::{self, PasswordGate},
+    pause::{PauseState, SharePause},
+    progress::{Progress, ShareProgress},
+    publish::{self, PublishStore},
+    reputation::Reputation,
+    revoke::Revocations,
+    sched::{Priority, Scheduler},
+    serve::{
+        handle_connection, AllowedPeers, DownloadCounter, ServeContext, ShareStats, ShareTimings,
+        StatsReport, TimingsReport,
+    },
+    store::{Scratch, ShareStore, StoreKind, WithStore},
+    transfers::ExpiryPolicy,
+    webdav::{ShareView, WebDavShares},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Format {
     #[default]
     Hex,
@@ -52,25 +88,158 @@ fn print_hash(hash: &Hash, format: Format) -> String {
     }
 }
 
-/// Get the secret key or generate a new one.
+/// A path sent by the frontend.
 ///
-/// Print the secret key to stderr if it was generated, so the user can save it.
-fn get_or_create_secret() -> anyhow::Result<SecretKey> {
-    match std::env::var("IROH_SECRET") {
-        Ok(secret) => SecretKey::from_str(&secret).context("invalid secret"),
-        Err(_) => {
-            let key = SecretKey::generate();
-            Ok(key)
+/// Paths that are not valid unicode can not be sent as strings. They are
+/// sent as their bytes on unix and as their UTF-16 code units on Windows.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PathPayload {
+    Text(String),
+    Raw { raw: Vec<u8> },
+    Wide { wide: Vec<u16> },
+}
+
+impl PathPayload {
+    #[cfg(unix)]
+    pub fn from_path(path: &Path) -> Self {
+        use std::os::unix::ffi::OsStrExt;
+        match path.to_str() {
+            Some(s) => PathPayload::Text(s.to_string()),
+            None => PathPayload::Raw {
+                raw: path.as_os_str().as_bytes().to_vec(),
+            },
+        }
+    }
+
+    #[cfg(windows)]
+    pub fn from_path(path: &Path) -> Self {
+        use std::os::windows::ffi::OsStrExt;
+        match path.to_str() {
+            Some(s) => PathPayload::Text(s.to_string()),
+            None => PathPayload::Wide {
+                wide: path.as_os_str().encode_wide().collect(),
+            },
+        }
+    }
+
+    pub fn into_path(self) -> anyhow::Result<PathBuf> {
+        match self {
+            PathPayload::Text(s) => Ok(PathBuf::from(s)),
+            #[cfg(unix)]
+            PathPayload::Raw { raw } => {
+                use std::os::unix::ffi::OsStringExt;
+                Ok(PathBuf::from(std::ffi::OsString::from_vec(raw)))
+            }
+            #[cfg(windows)]
+            PathPayload::Wide { wide } => {
+                use std::os::windows::ffi::OsStringExt;
+                Ok(PathBuf::from(std::ffi::OsString::from_wide(&wide)))
+            }
+            _ => anyhow::bail!("path is encoded for another platform"),
         }
     }
 }
 
-fn validate_path_component(component: &str) -> anyhow::Result<()> {
-    anyhow::ensure!(
-        !component.contains('/'),
-        "path components must not contain the only correct path separator, /"
-    );
-    Ok(())
+/// `path` without the `\\?\` prefix Windows adds when canonicalizing, e.g.
+/// `C:\dir` for `\\?\C:\dir` and `\\server\share` for
+/// `\\?\UNC\server\share`.
+///
+/// `None` if the path needs the prefix, because it is too long without it or
+/// is no drive or UNC path.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn strip_verbatim(path: &str) -> Option<String> {
+    // longer paths only work with the prefix
+    const MAX_PATH: usize = 260;
+    let rest = path.strip_prefix(r"\\?\")?;
+    let simple = if let Some(unc) = rest.strip_prefix(r"UNC\") {
+        format!(r"\\{}", unc)
+    } else {
+        let mut chars = rest.chars();
+        let drive = chars.next()?.is_ascii_alphabetic() && chars.next()? == ':';
+        if !drive {
+            return None;
+        }
+        rest.to_string()
+    };
+    (simple.len() < MAX_PATH).then_some(simple)
+}
+
+/// Canonicalize `path`, in the form users know on Windows.
+fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    let path = path.canonicalize()?;
+    #[cfg(windows)]
+    if let Some(simple) = path.to_str().and_then(strip_verbatim) {
+        return Ok(PathBuf::from(simple));
+    }
+    Ok(path)
+}
+
+/// The collection entry name of a path component.
+///
+/// Unicode names are kept as they are. Bytes that are not valid unicode are
+/// percent-encoded on unix and replaced on other platforms. [`entry_path`]
+/// undoes this.
+#[cfg(unix)]
+fn entry_name(name: &OsStr) -> Cow<'_, str> {
+    use std::{fmt::Write, os::unix::ffi::OsStrExt};
+    if let Some(name) = name.to_str() {
+        return Cow::Borrowed(name);
+    }
+    let mut encoded = String::new();
+    for chunk in name.as_bytes().utf8_chunks() {
+        encoded.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            write!(encoded, "%{:02X}", byte).ok();
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+#[cfg(not(unix))]
+fn entry_name(name: &OsStr) -> Cow<'_, str> {
+    name.to_string_lossy()
+}
+
+/// The file name of a path component of a collection entry name, decoding
+/// what [`entry_name`] encoded.
+///
+/// Only `%80` to `%FF` are decoded, ascii bytes are always valid unicode and
+/// never encoded. Bytes that are not valid unicode can only be restored on
+/// unix, other platforms keep such names encoded.
+pub fn entry_path(name: &str) -> Cow<'_, OsStr> {
+    if !name.contains('%') {
+        return Cow::Borrowed(OsStr::new(name));
+    }
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&first, tail)) = rest.split_first() {
+        let byte = std::str::from_utf8(tail.get(..2).unwrap_or_default())
+            .ok()
+            .filter(|hex| first == b'%' && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .filter(|byte| !byte.is_ascii());
+        match byte {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(first);
+                rest = tail;
+            }
+        }
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        Cow::Owned(std::ffi::OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    match String::from_utf8(bytes) {
+        Ok(decoded) => Cow::Owned(decoded.into()),
+        Err(_) => Cow::Borrowed(OsStr::new(name)),
+    }
 }
 
 /// This function converts an already canonicalized path to a string.
@@ -80,7 +249,11 @@ fn validate_path_component(component: &str) -> anyhow::Result<()> {
 ///
 /// This function will also fail if the path is non canonical, i.e. contains
 /// `..` or `.`, or if the path components contain any windows or unix path
-/// separators.
+/// separators. Components that are not valid unicode are mapped with
+/// [`entry_name`], and it fails for names that would be received as a
+/// different one, e.g. a literal `a%FF` that reads like an encoded byte, or
+/// names that could only be replaced. Two files can't end up with the same
+/// entry name this way.
 pub fn canonicalized_path_to_string(
     path: impl AsRef<Path>,
     must_be_relative: bool,
@@ -91,15 +264,16 @@ pub fn canonicalized_path_to_string(
         .components()
         .filter_map(|c| match c {
             Component::Normal(x) => {
-                let c = match x.to_str() {
-                    Some(c) => c,
-                    None => return Some(Err(anyhow::anyhow!("invalid character in path"))),
-                };
-
-                if !c.contains('/') && !c.contains('\\') {
-                    Some(Ok(c))
-                } else {
+                let c = entry_name(x);
+                if c.contains('/') || c.contains('\\') {
                     Some(Err(anyhow::anyhow!("invalid path component {:?}", c)))
+                } else if entry_path(&c) != x {
+                    Some(Err(anyhow::anyhow!(
+                        "{:?} can not be shared under its own name",
+                        x
+                    )))
+                } else {
+                    Some(Ok(c))
                 }
             }
             Component::RootDir => {
@@ -118,39 +292,63 @@ pub fn canonicalized_path_to_string(
     Ok(path_str)
 }
 
-/// Import from a file or directory into the database.
+/// Import files and directories into the database.
 ///
-/// The returned tag always refers to a collection. If the input is a file, this
-/// is a collection with a single blob, named like the file.
+/// The returned tag always refers to a collection. Each input is named like
+/// the file or directory, files in directories keep their relative path.
 ///
-/// If the input is a directory, the collection contains all the files in the
-/// directory.
+/// With `pack_small_files`, files up to [`MAX_PACKED_FILE`] are packed into a
+/// few blobs instead of one blob each, see [`pack`].
 async fn import(
-    path: PathBuf,
+    paths: &[PathBuf],
     db: impl iroh_bytes::store::Store,
+    pack_small_files: bool,
+    progress: Progress,
 ) -> anyhow::Result<(TempTag, u64, Collection)> {
-    let path = path.canonicalize()?;
-    anyhow::ensure!(path.exists(), "path {} does not exist", path.display());
-    let root = path.parent().context("context get parent")?;
-    // walkdir also works for files, so we don't need to special case them
-    let files = WalkDir::new(path.clone()).into_iter();
-    // flatten the directory structure into a list of (name, path) pairs.
-    // ignore symlinks.
-    let data_sources: Vec<(String, PathBuf)> = files
-        .map(|entry| {
+    let mut data_sources: Vec<(String, PathBuf)> = Vec::new();
+    let mut top_level = HashSet::new();
+    for path in paths {
+        let path = canonicalize(path)?;
+        anyhow::ensure!(path.exists(), "path {} does not exist", path.display());
+        let root = path.parent().context("context get parent")?;
+        let top = canonicalized_path_to_string(path.strip_prefix(root)?, true)?;
+        anyhow::ensure!(top_level.insert(top.clone()), "{} is shared twice", top);
+        // walkdir also works for files, so we don't need to special case them
+        let files = WalkDir::new(path.clone()).into_iter();
+        // flatten the directory structure into a list of (name, path) pairs.
+        // ignore symlinks.
+        for entry in files {
             let entry = entry?;
             if !entry.file_type().is_file() {
                 // Skip symlinks. Directories are handled by WalkDir.
-                return Ok(None);
+                continue;
             }
             let path = entry.into_path();
             let relative = path.strip_prefix(root)?;
             let name = canonicalized_path_to_string(relative, true)?;
-            anyhow::Ok(Some((name, path)))
-        })
-        .filter_map(Result::transpose)
-        .collect::<anyhow::Result<Vec<_>>>()?;
+            data_sources.push((name, path));
+        }
+    }
+    let mut packed = Vec::new();
+    if pack_small_files {
+        anyhow::ensure!(
+            !top_level.contains(PACK_DIR),
+            "{} can not be shared with packing on",
+            PACK_DIR
+        );
+        let mut sources = Vec::new();
+        for (name, path) in data_sources {
+            let size = path.metadata()?.len();
+            if size <= MAX_PACKED_FILE {
+                packed.push((name, path, size));
+            } else {
+                sources.push((name, path));
+            }
+        }
+        data_sources = sources;
+    }
     let (send, recv) = flume::bounded(32);
+    tokio::spawn(progress.import(display_name(paths), data_sources.clone(), recv));
     let progress = iroh_bytes::util::progress::FlumeProgressSender::new(send);
     // import all the files, using num_cpus workers, return names and temp tags
     let names_and_tags = futures::stream::iter(data_sources)
@@ -161,6 +359,7 @@ async fn import(
                 let (temp_tag, file_size) = db
                     .import_file(path, ImportMode::TryReference, BlobFormat::Raw, progress)
                     .await?;
+                tracing::info!(name, size = file_size, "imported");
                 anyhow::Ok((name, temp_tag, file_size))
             }
         })
@@ -171,12 +370,22 @@ async fn import(
         .collect::<anyhow::Result<Vec<_>>>()?;
     drop(progress);
     // total size of all files
-    let size = names_and_tags.iter().map(|(_, _, size)| *size).sum::<u64>();
+    let size = names_and_tags.iter().map(|(_, _, size)| *size).sum::<u64>()
+        + packed.iter().map(|(_, _, size)| *size).sum::<u64>();
+    let packs = if packed.is_empty() {
+        Vec::new()
+    } else {
+        pack::pack(&db, packed)
+            .instrument(tracing::info_span!("pack"))
+            .await?
+    };
     // collect the (name, hash) tuples into a collection
     // we must also keep the tags around so the data does not get gced.
     let (collection, tags) = names_and_tags
         .into_iter()
-        .map(|(name, tag, _)| ((name, *tag.hash()), tag))
+        .map(|(name, tag, _)| (name, tag))
+        .chain(packs)
+        .map(|(name, tag)| ((name, *tag.hash()), tag))
         .unzip::<_, _, Collection, Vec<_>>();
     let temp_tag = collection.clone().store(&db).await?;
     // now that the collection is stored, we can drop the tags
@@ -185,167 +394,672 @@ async fn import(
     Ok((temp_tag, size, collection))
 }
 
-fn get_export_path(root: &Path, name: &str) -> anyhow::Result<PathBuf> {
-    let parts = name.split('/');
-    let mut path = root.to_path_buf();
-    for part in parts {
-        validate_path_component(part)?;
-        path.push(part);
+/// How long to wait for a relay connection before falling back to direct addresses.
+const RELAY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Per share options chosen by the user.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShareOptions {
+    /// Keep serving during quiet hours.
+    pub urgent: bool,
+    /// Weight of this share in the bandwidth scheduler.
+    pub priority: Priority,
+    /// Leave the direct addresses out of the ticket, so it stays valid when they
+    /// change. Receivers look them up through DNS discovery instead.
+    pub stable_ticket: bool,
+    /// Share downscaled photos and videos instead of the originals.
+    pub preview: bool,
+    /// Store backend for this share, overriding the one in the settings.
+    pub store: Option<StoreKind>,
+    /// Pack small files into a few large blobs, for directories with very
+    /// many tiny files. Only sendme unpacks them again, and packed files are
+    /// neither shown over WebDAV nor can they be picked for sub-shares.
+    pub pack_small_files: bool,
+    /// When the share stops on its own.
+    pub expiry: ExpiryPolicy,
+    /// Maximum upload rate of this share in bytes per second, instead of the
+    /// app wide cap.
+    pub upload_limit: Option<u64>,
+    /// Node ids of the recipients, only they can download the share. Anyone
+    /// with the ticket can if empty.
+    pub allowed_peers: Vec<NodeId>,
+    /// Receivers have to give this password before anything is served.
+    pub password: Option<String>,
+    /// Name of the publication this share is the latest version of, set by
+    /// [`crate::publish`] only.
+    #[serde(skip)]
+    pub publication: Option<String>,
+}
+
+/// App wide state and settings a share runs with.
+#[derive(Debug)]
+pub struct ShareEnv {
+    pub secret_key: SecretKey,
+    pub pause: PauseState,
+    pub scheduler: Arc<Scheduler>,
+    pub activity: Arc<ActivityLog>,
+    pub keep_alive: KeepAlive,
+    pub network: NetworkSettings,
+    /// How hashes are logged.
+    pub hash_format: Format,
+    /// Domain to publish the share's addresses under, if DNS discovery is enabled.
+    pub dns_discovery: Option<String>,
+    pub dns_records: Arc<DnsRecords>,
+    pub store: StoreKind,
+    /// Where on disk stores are created, one directory per share.
+    pub scratch_dir: PathBuf,
+    pub webdav: Arc<WebDavShares>,
+    /// Where import and transfer progress is reported to.
+    pub progress: Progress,
+    pub revocations: Arc<Revocations>,
+    pub reputation: Arc<Reputation>,
+    pub cache: Arc<ChunkCache>,
+    pub audit: Arc<AuditLog>,
+    pub publish: Arc<PublishStore>,
+}
+
+/// Total size of the files below `paths`.
+pub fn total_size(paths: &[PathBuf]) -> anyhow::Result<u64> {
+    let mut size = 0;
+    for entry in paths.iter().flat_map(WalkDir::new) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            size += entry.metadata()?.len();
+        }
     }
-    Ok(path)
+    Ok(size)
 }
 
-async fn export(db: impl iroh_bytes::store::Store, collection: Collection) -> anyhow::Result<()> {
-    let root = std::env::current_dir()?;
-    for (name, hash) in collection.iter() {
-        let target = get_export_path(&root, name)?;
-        db.export(*hash, target, ExportMode::TryReference, |_position| Ok(()))
-            .await?;
+/// How a share of `paths` is shown to the user, the file names joined by commas.
+pub fn display_name(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| {
+            path.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// How often a share checks whether its ticket still reaches it.
+const REACHABILITY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Whether peers can still connect with a share's ticket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reachability {
+    #[default]
+    Reachable,
+    /// The addresses in the ticket are outdated, a new ticket would work.
+    Stale,
+    /// Neither a relay nor any direct address is available.
+    Unreachable,
+}
+
+/// The reachability of a share, with a fresh ticket if the old one is stale.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ShareHealth {
+    pub reachability: Reachability,
+    pub ticket: Option<String>,
+}
+
+/// Compare the addresses a ticket was made with to the current ones.
+fn reachability(ticket: &NodeAddr, current: &NodeAddr) -> Reachability {
+    let relay = current.info.derp_url.is_some();
+    if !relay && current.info.direct_addresses.is_empty() {
+        return Reachability::Unreachable;
     }
-    Ok(())
-}
-
-pub async fn provide(path: PathBuf) -> anyhow::Result<(BlobTicket, JoinHandle<()>)> {
-    let secret_key = get_or_create_secret()?;
-    // create a magicsocket endpoint
-    let endpoint_fut = MagicEndpoint::builder()
-        .alpns(vec![iroh_bytes::protocol::ALPN.to_vec()])
-        .secret_key(secret_key)
-        .bind(0);
-    // use a flat store - todo: use a partial in mem store instead
-    let suffix = rand::thread_rng().gen::<[u8; 16]>();
-    let iroh_data_dir = path
-        .parent()
-        .unwrap()
-        .join(format!(".sendme-provide-{}", hex::encode(suffix)));
-    if iroh_data_dir.exists() {
-        println!("can not share twice from the same directory");
-        std::process::exit(1);
-    }
-    std::fs::create_dir_all(&iroh_data_dir)?;
-    let db = iroh_bytes::store::flat::Store::load(&iroh_data_dir).await?;
-    let (temp_tag, size, collection) = import(path.clone(), db.clone()).await?;
-    let hash = *temp_tag.hash();
-    // wait for the endpoint to be ready
-    let endpoint = endpoint_fut.await?;
-    // wait for the endpoint to figure out its address before making a ticket
-    while endpoint.my_derp().is_none() {
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-    }
-    // make a ticket
-    let addr = endpoint.my_addr().await?;
-    let ticket = BlobTicket::new(addr, hash, BlobFormat::HashSeq)?;
-    let entry_type = if path.is_file() { "file" } else { "directory" };
-    println!(
-        "imported {} {}, {}, hash {}",
-        entry_type,
-        path.display(),
-        size,
-        print_hash(&hash, Format::Hex)
+    let same_relay = relay && ticket.info.derp_url == current.info.derp_url;
+    let direct = ticket
+        .info
+        .direct_addresses
+        .iter()
+        .any(|addr| current.info.direct_addresses.contains(addr));
+    // tickets without any address rely on DNS discovery, which stays current
+    let discovered = ticket.info.derp_url.is_none() && ticket.info.direct_addresses.is_empty();
+    if same_relay || direct || discovered {
+        Reachability::Reachable
+    } else {
+        Reachability::Stale
+    }
+}
+
+/// Whether `entry` of a collection is selected by `name`, either the entry
+/// itself or a directory containing it.
+fn selects(name: &str, entry: &str) -> bool {
+    let dir = name.trim_end_matches('/');
+    entry == name
+        || entry
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The entries of `collection` selected by `names`.
+fn select(collection: &Collection, names: &[String]) -> anyhow::Result<Collection> {
+    anyhow::ensure!(!names.is_empty(), "nothing selected");
+    for name in names {
+        anyhow::ensure!(
+            collection.iter().any(|(entry, _)| selects(name, entry)),
+            "{} is not part of the share",
+            name
+        );
+    }
+    Ok(collection
+        .iter()
+        .filter(|(entry, _)| names.iter().any(|name| selects(name, entry)))
+        .cloned()
+        .collect())
+}
+
+#[derive(Debug)]
+struct SubShareRequest {
+    names: Vec<String>,
+    reply: oneshot::Sender<anyhow::Result<BlobTicket>>,
+}
+
+/// Creates tickets for parts of a running share.
+#[derive(Debug, Clone)]
+pub struct SubShares(mpsc::Sender<SubShareRequest>);
+
+impl SubShares {
+    /// A ticket for the entries named by `names`, served by the same endpoint
+    /// until the share stops.
+    ///
+    /// A name selects a file or everything below a directory. The selected
+    /// blobs are already in the share's store, so nothing is hashed again.
+    pub async fn create(&self, names: Vec<String>) -> anyhow::Result<BlobTicket> {
+        let (reply, rx) = oneshot::channel();
+        self.0
+            .send(SubShareRequest { names, reply })
+            .await
+            .ok()
+            .context("the share stopped")?;
+        rx.await.context("the share stopped")?
+    }
+}
+
+/// A running share.
+#[derive(Debug)]
+pub struct ShareHandle {
+    task: JoinHandle<()>,
+    cancel: CancellationToken,
+    timings: Arc<ShareTimings>,
+    stats: Arc<ShareStats>,
+    health: watch::Receiver<ShareHealth>,
+    sub_shares: SubShares,
+    pause: watch::Sender<SharePause>,
+}
+
+impl ShareHandle {
+    /// A share that serves nothing until it is stopped, for demo mode.
+    pub fn idle() -> Self {
+        let cancel = CancellationToken::new();
+        let cancelled = cancel.clone();
+        let task = tokio::task::spawn(async move { cancelled.cancelled().await });
+        let (_, health) = watch::channel(ShareHealth::default());
+        let (sub_shares, _) = mpsc::channel(1);
+        Self {
+            task,
+            cancel,
+            timings: Default::default(),
+            stats: Default::default(),
+            health,
+            sub_shares: SubShares(sub_shares),
+            pause: watch::channel(SharePause::Running).0,
+        }
+    }
+
+    /// Whether the share stopped serving.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    pub fn timings(&self) -> TimingsReport {
+        self.timings.report()
+    }
+
+    pub fn stats(&self) -> StatsReport {
+        self.stats.report()
+    }
+
+    /// Updated whenever the reachability of the share changes.
+    pub fn health(&self) -> watch::Receiver<ShareHealth> {
+        self.health.clone()
+    }
+
+    pub fn sub_shares(&self) -> SubShares {
+        self.sub_shares.clone()
+    }
+
+    pub fn pause_state(&self) -> SharePause {
+        *self.pause.borrow()
+    }
+
+    /// Pause or resume just this share, returning whether that changed
+    /// anything.
+    pub fn set_pause(&self, state: SharePause) -> bool {
+        self.pause.send_if_modified(|current| {
+            let changed = *current != state;
+            *current = state;
+            changed
+        })
+    }
+
+    /// Stops the share without waiting for it, e.g. when it expires.
+    pub fn stop_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Stop serving and wait until the share's data is cleaned up.
+    pub async fn stop(self) {
+        self.cancel.cancel();
+        self.task.await.ok();
+    }
+}
+
+/// Share `paths` in a single collection.
+pub async fn provide(
+    paths: Vec<PathBuf>,
+    opts: ShareOptions,
+    env: ShareEnv,
+    downloads: DownloadCounter,
+) -> anyhow::Result<(BlobTicket, ShareHandle)> {
+    anyhow::ensure!(!paths.is_empty(), "nothing to share");
+    // one scratch dir per set of shared paths, outside of the user's folders
+    let key = paths
+        .iter()
+        .map(|path| path.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("\0");
+    let iroh_data_dir = env.scratch_dir.join(Hash::new(key).to_hex());
+    anyhow::ensure!(
+        !iroh_data_dir.exists(),
+        "{} is already being shared",
+        display_name(&paths)
     );
-    for (name, hash) in collection.iter() {
-        println!("    {} {name}", print_hash(hash, Format::Hex));
+    let size = total_size(&paths)?;
+    let store = ShareStore::open(env.store, size, iroh_data_dir).await?;
+    store
+        .with(Share {
+            paths,
+            opts,
+            env,
+            downloads,
+        })
+        .await
+}
+
+/// A share, started once its store is open.
+struct Share {
+    paths: Vec<PathBuf>,
+    opts: ShareOptions,
+    env: ShareEnv,
+    downloads: DownloadCounter,
+}
+
+impl WithStore for Share {
+    type Output = anyhow::Result<(BlobTicket, ShareHandle)>;
+
+    fn run<S: Store>(self, db: S, scratch: Scratch) -> impl Future<Output = Self::Output> + Send {
+        self.start(db, scratch)
     }
+}
 
-    println!("to get this data, use");
+impl Share {
+    async fn start<S: Store>(
+        self,
+        db: S,
+        scratch: Scratch,
+    ) -> anyhow::Result<(BlobTicket, ShareHandle)> {
+        let Share {
+            paths,
+            opts,
+            env,
+            downloads,
+        } = self;
+        let ShareEnv {
+            secret_key,
+            pause,
+            scheduler,
+            activity,
+            keep_alive,
+            network,
+            hash_format,
+            dns_discovery,
+            dns_records,
+            store: _,
+            scratch_dir: _,
+            webdav,
+            progress,
+            revocations,
+            reputation,
+            cache,
+            audit,
+            publish,
+        } = env;
+        let node_id = secret_key.public();
+        let discoverable = dns_discovery.is_some();
+        // create a magicsocket endpoint
+        let mut alpns = crate::version::alpns();
+        if opts.password.is_some() {
+            alpns.push(password::ALPN.to_vec());
+        }
+        if opts.publication.is_some() {
+            alpns.push(publish::ALPN.to_vec());
+        }
+        let mut builder = MagicEndpoint::builder()
+            .alpns(alpns)
+            .secret_key(secret_key)
+            .transport_config(keep_alive.transport_config());
+        if let Some(origin) = dns_discovery {
+            let discovery = DnsDiscovery::new(origin, node_id, dns_records.clone());
+            builder = builder.discovery(Box::new(discovery));
+        }
+        // binding runs while importing, the ticket has to wait for the import
+        // though: it names the collection, whose hash covers every blob
+        let endpoint_fut = network.bind(builder);
+        let imported = import(&paths, db.clone(), opts.pack_small_files, progress.clone());
+        let (temp_tag, size, collection) = imported
+            .instrument(tracing::info_span!("import", paths = paths.len()))
+            .await?;
+        let hash = *temp_tag.hash();
+        // wait for the endpoint to be ready
+        let endpoint = endpoint_fut.await?;
+        tracing::info!(node = %node_id.fmt_short(), "endpoint bound");
+        // wait for the endpoint to figure out its address before making a ticket
+        let start = std::time::Instant::now();
+        while endpoint.my_derp().is_none() && start.elapsed() < RELAY_TIMEOUT {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        // make a ticket
+        let mut addr = endpoint.my_addr().await?;
+        let family = network.family;
+        family.restrict(&mut addr);
+        if addr.info.derp_url.is_none() {
+            // without a relay, peers on the same network can still connect directly
+            if addr.info.direct_addresses.is_empty() {
+                return Err(ErrorCode::RelayUnreachable.into());
+            }
+            log!(
+                "no relay reachable, the ticket only contains direct addresses {:?}",
+                addr.info.direct_addresses
+            );
+        } else if opts.stable_ticket && discoverable {
+            addr.info.direct_addresses.clear();
+        }
+        let ticket = BlobTicket::new(addr, hash, BlobFormat::HashSeq)?;
+        let timings = Arc::new(ShareTimings::default());
+        for path in &paths {
+            let entry_type = if path.is_file() { "file" } else { "directory" };
+            log!("imported {} {}", entry_type, path.display());
+        }
+        log!("{} bytes, hash {}", size, print_hash(&hash, hash_format));
+        for (name, hash) in collection.iter() {
+            log!("    {} {name}", print_hash(hash, hash_format));
+        }
+
+        log!("to get this data, use");
 
-    let handle = tokio::task::spawn(async move {
         let rt = LocalPoolHandle::new(1);
-        loop {
-            let Some(connecting) = endpoint.accept().await else {
-                break;
+        // WebDAV clients can give neither a password nor a node id
+        let restricted = opts.password.is_some() || !opts.allowed_peers.is_empty();
+        let webdav_key = (!restricted).then(|| {
+            let files = collection
+                .iter()
+                .filter(|(name, _)| !pack::is_internal(name))
+                .map(|(name, hash)| {
+                    let size = db.get(hash).map(|entry| entry.size()).unwrap_or_default();
+                    (name.clone(), *hash, size)
+                })
+                .collect();
+            let view = ShareView {
+                files,
+                read: crate::webdav::reader(db.clone(), rt.clone()),
             };
-            let db = db.clone();
-            let rt = rt.clone();
-            tokio::spawn(handle_connection(connecting, db, Events {}, rt));
-        }
-        drop(temp_tag);
-        std::fs::remove_dir_all(iroh_data_dir).ok();
-    });
-    Ok((ticket, handle))
+            webdav.insert(&hash, view)
+        });
+        let progress = ShareProgress {
+            share: display_name(&paths),
+            files: collection.iter().map(|(name, _)| name.clone()).collect(),
+            total: size,
+            progress,
+        };
+        let cancel = CancellationToken::new();
+        let cancelled = cancel.clone();
+        let handle_timings = timings.clone();
+        let stats = Arc::new(ShareStats::default());
+        let events = Events {
+            stats: stats.clone(),
+        };
+        let (health_tx, health_rx) = watch::channel(ShareHealth::default());
+        let ticket_addr = ticket.node_addr().clone();
+        let stable = opts.stable_ticket && discoverable;
+        let (sub_share_tx, mut sub_share_rx) = mpsc::channel::<SubShareRequest>(4);
+        let (share_pause_tx, mut share_paused) = watch::channel(SharePause::Running);
+        let upload_cap = opts
+            .upload_limit
+            .map(|rate| Arc::new(Bucket::new(Some(rate))));
+        let allowed_peers = (!opts.allowed_peers.is_empty()).then(|| {
+            log!(
+                "share {} is restricted to {} peers",
+                hash.to_hex(),
+                opts.allowed_peers.len()
+            );
+            Arc::new(AllowedPeers {
+                hash,
+                peers: opts.allowed_peers.iter().copied().collect(),
+                audit: audit.clone(),
+            })
+        });
+        let password = match opts.password.as_deref() {
+            Some(password) => Some(Arc::new(PasswordGate::new(hash, password, audit).await?)),
+            None => None,
+        };
+        let publish = opts.publication.is_some().then_some(publish);
+        let serve = async move {
+            let mut paused = pause.subscribe();
+            let mut connections = JoinSet::new();
+            let group = scheduler.group();
+            let health_interval = keep_alive.health_check_interval();
+            let mut health = tokio::time::interval(health_interval.unwrap_or(RELAY_TIMEOUT));
+            let mut reachable = tokio::time::interval(REACHABILITY_INTERVAL);
+            // keeps the collections of sub-shares from being collected
+            let mut sub_share_tags = Vec::new();
+            loop {
+                tokio::select! {
+                    connecting = endpoint.accept() => {
+                        let Some(connecting) = connecting else {
+                            break;
+                        };
+                        if pause.get().applies(opts.urgent) || share_paused.borrow().refuses() {
+                            // refuse with a reason instead of letting the peer time out
+                            tokio::spawn(async move {
+                                if let Ok(connection) = connecting.await {
+                                    connection.close(1u32.into(), b"paused");
+                                }
+                            });
+                            continue;
+                        }
+                        let db = db.clone();
+                        let rt = rt.clone();
+                        let ctx = ServeContext {
+                            flow: scheduler.flow(group, opts.priority, upload_cap.clone()),
+                            downloads: downloads.clone(),
+                            activity: activity.clone(),
+                            progress: progress.clone(),
+                            timings: timings.clone(),
+                            revocations: revocations.clone(),
+                            reputation: reputation.clone(),
+                            cache: cache.clone(),
+                            allowed_peers: allowed_peers.clone(),
+                            password: password.clone(),
+                            publish: publish.clone(),
+                        };
+                        let events = events.clone();
+                        let connection = handle_connection(connecting, db, events, rt, ctx);
+                        connections.spawn(connection.in_current_span());
+                    }
+                    Ok(()) = paused.changed() => {
+                        if paused.borrow().applies(opts.urgent) {
+                            connections.abort_all();
+                        }
+                    }
+                    Ok(()) = share_paused.changed() => {
+                        let state = *share_paused.borrow();
+                        log!("share {} is {:?}", hash.to_hex(), state);
+                        if state == SharePause::Suspended {
+                            connections.abort_all();
+                        }
+                    }
+                    Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                    _ = health.tick(), if health_interval.is_some() => {
+                        crate::keepalive::probe(&endpoint).await;
+                    }
+                    _ = reachable.tick() => {
+                        let Ok(mut current) = endpoint.my_addr().await else {
+                            continue;
+                        };
+                        family.restrict(&mut current);
+                        let reachability = reachability(&ticket_addr, &current);
+                        if reachability == health_tx.borrow().reachability {
+                            continue;
+                        }
+                        log!("share {} is {:?}", hash.to_hex(), reachability);
+                        if stable {
+                            current.info.direct_addresses.clear();
+                        }
+                        let ticket = match reachability {
+                            Reachability::Stale => BlobTicket::new(current, hash, BlobFormat::HashSeq)
+                                .ok()
+                                .map(|ticket| ticket.to_string()),
+                            _ => None,
+                        };
+                        health_tx.send_replace(ShareHealth { reachability, ticket });
+                    }
+                    Some(request) = sub_share_rx.recv() => {
+                        let res = async {
+                            let sub = select(&collection, &request.names)?;
+                            let tag = sub.store(&db).await?;
+                            let mut addr = endpoint.my_addr().await?;
+                            family.restrict(&mut addr);
+                            if stable {
+                                addr.info.direct_addresses.clear();
+                            }
+                            let ticket = BlobTicket::new(addr, *tag.hash(), BlobFormat::HashSeq)?;
+                            log!("sub-share {} of {}", tag.hash().to_hex(), hash.to_hex());
+                            sub_share_tags.push(tag);
+                            anyhow::Ok(ticket)
+                        }
+                        .await;
+                        request.reply.send(res).ok();
+                    }
+                    _ = cancelled.cancelled() => break,
+                }
+            }
+            connections.shutdown().await;
+            endpoint.close(0u32.into(), b"stopped").await.ok();
+            dns_records.remove(&node_id);
+            if let Some(key) = &webdav_key {
+                webdav.remove(key);
+            }
+            drop(sub_share_tags);
+            drop(temp_tag);
+            drop(db);
+            drop(scratch);
+        };
+        let span = tracing::info_span!("endpoint", hash = %hash.to_hex());
+        let task = tokio::task::spawn(serve.instrument(span));
+        let handle = ShareHandle {
+            task,
+            cancel,
+            timings: handle_timings,
+            stats,
+            health: health_rx,
+            sub_shares: SubShares(sub_share_tx),
+            pause: share_pause_tx,
+        };
+        Ok((ticket, handle))
+    }
 }
 
+/// Feeds the provider events of a share into its statistics.
 #[derive(Debug, Clone)]
-struct Events {}
+struct Events {
+    stats: Arc<ShareStats>,
+}
 
 impl EventSender for Events {
-    fn send(&self, event: Event) -> BoxFuture<()> {
-        println!("ev: {:?}", event);
-        async move { () }.boxed()
-    }
-}
-
-// async fn get(args: GetArgs) -> anyhow::Result<()> {
-//     let ticket = args.ticket;
-//     let addr = ticket.node_addr().clone();
-//     let secret_key = get_or_create_secret(args.common.verbose > 0)?;
-//     let endpoint = MagicEndpoint::builder()
-//         .alpns(vec![])
-//         .secret_key(secret_key)
-//         .bind(args.common.magic_port)
-//         .await?;
-//     let dir_name = format!(".sendme-get-{}", ticket.hash().to_hex());
-//     let iroh_data_dir = std::env::current_dir()?.join(dir_name);
-//     let db = iroh_bytes::store::flat::Store::load(&iroh_data_dir).await?;
-//     let mp = MultiProgress::new();
-//     let connect_progress = mp.add(ProgressBar::hidden());
-//     connect_progress.set_draw_target(ProgressDrawTarget::stderr());
-//     connect_progress.set_style(ProgressStyle::default_spinner());
-//     connect_progress.set_message(format!("connecting to {}", addr.node_id));
-//     let connection = endpoint.connect(addr, iroh_bytes::protocol::ALPN).await?;
-//     let hash_and_format = HashAndFormat {
-//         hash: ticket.hash(),
-//         format: ticket.format(),
-//     };
-//     connect_progress.finish_and_clear();
-//     let (send, recv) = flume::bounded(32);
-//     let progress = iroh_bytes::util::progress::FlumeProgressSender::new(send);
-//     let (_hash_seq, sizes) =
-//         get_hash_seq_and_sizes(&connection, &hash_and_format.hash, 1024 * 1024 * 32)
-//             .await
-//             .map_err(show_get_error)?;
-//     let total_size = sizes.iter().sum::<u64>();
-//     let total_files = sizes.len().saturating_sub(1);
-//     let payload_size = sizes.iter().skip(1).sum::<u64>();
-//     eprintln!(
-//         "getting collection {} {} files, {}",
-//         print_hash(&ticket.hash(), args.common.format),
-//         total_files,
-//         HumanBytes(payload_size)
-//     );
-//     // print the details of the collection only in verbose mode
-//     if args.common.verbose > 0 {
-//         eprintln!(
-//             "getting {} blobs in total, {}",
-//             sizes.len(),
-//             HumanBytes(total_size)
-//         );
-//     }
-//     let _task = tokio::spawn(show_download_progress(recv.into_stream(), total_size));
-//     let stats = iroh_bytes::get::db::get_to_db(&db, connection, &hash_and_format, progress)
-//         .await
-//         .map_err(show_get_error)?;
-//     let collection = Collection::load(&db, &hash_and_format.hash).await?;
-//     if args.common.verbose > 0 {
-//         for (name, hash) in collection.iter() {
-//             println!("    {} {name}", print_hash(hash, args.common.format));
-//         }
-//     }
-//     if let Some((name, _)) = collection.iter().next() {
-//         if let Some(first) = name.split('/').next() {
-//             println!("downloading to: {};", first);
-//         }
-//     }
-//     export(db, collection).await?;
-//     std::fs::remove_dir_all(iroh_data_dir)?;
-//     if args.common.verbose > 0 {
-//         println!(
-//             "downloaded {} files, {}. took {} ({}/s)",
-//             total_files,
-//             HumanBytes(payload_size),
-//             HumanDuration(stats.elapsed),
-//             HumanBytes((stats.bytes_read as f64 / stats.elapsed.as_secs_f64()) as u64),
-//         );
-//     }
-//     Ok(())
-// }
+    fn send(&self, event: Event) -> BoxFuture<'_, ()> {
+        self.stats.record(&event);
+        if let Event::TransferBlobCompleted { index, size, .. } = event {
+            tracing::info!(index, size, "blob sent");
+        }
+        async {}.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_verbatim_prefixes() {
+        assert_eq!(
+            strip_verbatim(r"\\?\C:\dir\a.txt").unwrap(),
+            r"C:\dir\a.txt"
+        );
+        assert_eq!(
+            strip_verbatim(r"\\?\UNC\server\share\dir").unwrap(),
+            r"\\server\share\dir"
+        );
+        // volume paths and long paths need the prefix
+        assert_eq!(strip_verbatim(r"\\?\Volume{1234}\dir"), None);
+        let long = format!(r"\\?\C:\{}", "a".repeat(300));
+        assert_eq!(strip_verbatim(&long), None);
+        assert_eq!(strip_verbatim(r"C:\dir"), None);
+        assert_eq!(strip_verbatim("/home/user"), None);
+    }
+
+    #[test]
+    fn names_unicode_entries() {
+        let path = Path::new("grüße").join("日本語 ✓.txt");
+        let name = canonicalized_path_to_string(&path, true).unwrap();
+        assert_eq!(name, "grüße/日本語 ✓.txt");
+        let payload: PathPayload = serde_json::from_str("\"grüße\"").unwrap();
+        assert_eq!(payload.into_path().unwrap(), Path::new("grüße"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn encodes_non_unicode_entries() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let path = Path::new("dir").join(OsStr::from_bytes(b"caf\xe9 \xff.txt"));
+        let name = canonicalized_path_to_string(&path, true).unwrap();
+        assert_eq!(name, "dir/caf%E9 %FF.txt");
+        assert_eq!(&*entry_path("caf%E9 %FF.txt"), path.file_name().unwrap());
+        // a literal `%` is kept, unless it reads like an encoded byte
+        for literal in ["100%.txt", "a%20b", "%41", "%zz%"] {
+            assert_eq!(
+                canonicalized_path_to_string(Path::new(literal), true).unwrap(),
+                literal
+            );
+            assert_eq!(&*entry_path(literal), OsStr::new(literal));
+        }
+        let invalid = canonicalized_path_to_string(OsStr::from_bytes(b"a\xff"), true).unwrap();
+        assert_eq!(invalid, "a%FF");
+        assert!(canonicalized_path_to_string(Path::new("a%FF"), true).is_err());
+        // and received under the original name
+        let root = Path::new("root");
+        let exported = crate::download::get_export_path(root, &invalid).unwrap();
+        assert_eq!(exported, root.join(OsStr::from_bytes(b"a\xff")));
+        let payload = PathPayload::from_path(&path);
+        let json = serde_json::to_string(&payload).unwrap();
+        let payload: PathPayload = serde_json::from_str(&json).unwrap();
+        assert_eq!(payload.into_path().unwrap(), path);
+        let windows: PathPayload = serde_json::from_str(r#"{"wide":[97]}"#).unwrap();
+        assert!(windows.into_path().is_err());
+    }
+}
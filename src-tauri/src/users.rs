@@ -0,0 +1,102 @@
+/// Name of the OS user running the app, to keep the files of users apart
+/// where they share a directory.
+pub fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|name| !name.is_empty() && !name.contains(['/', '\\']))
+        .unwrap_or_else(|| "user".to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// Uid of the first socket bound to `port` in a `/proc/net` table, only
+/// listening ones for TCP.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn socket_owner(table: &str, protocol: Protocol, port: u16) -> Option<u32> {
+    // e.g. `0: 0100007F:1F90 00000000:0000 0A ... 1000 ...`, the uid is the 8th field
+    const LISTEN: &str = "0A";
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let local = fields.get(1)?;
+        let (_, hex_port) = local.rsplit_once(':')?;
+        if u16::from_str_radix(hex_port, 16).ok()? != port {
+            return None;
+        }
+        if protocol == Protocol::Tcp && fields.get(3) != Some(&LISTEN) {
+            return None;
+        }
+        fields.get(7)?.parse().ok()
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn user_name(uid: u32) -> Option<String> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        (fields.nth(1)?.parse::<u32>().ok()? == uid).then(|| name.to_string())
+    })
+}
+
+/// Fail with guidance if another user holds `port`, e.g. their SendMe
+/// instance on a terminal server with the same fixed port configured.
+///
+/// Only Linux tells who holds a port. Elsewhere binding fails or falls back
+/// to a random port as before.
+#[cfg(target_os = "linux")]
+pub fn check_port(protocol: Protocol, port: u16) -> anyhow::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let files: &[&str] = match protocol {
+        Protocol::Tcp => &["/proc/net/tcp", "/proc/net/tcp6"],
+        Protocol::Udp => &["/proc/net/udp", "/proc/net/udp6"],
+    };
+    let me = std::fs::metadata("/proc/self")?.uid();
+    for file in files {
+        let Ok(table) = std::fs::read_to_string(file) else {
+            continue;
+        };
+        match socket_owner(&table, protocol, port) {
+            Some(uid) if uid != me => {
+                let user = user_name(uid).unwrap_or_else(|| format!("uid {}", uid));
+                anyhow::bail!(
+                    "port {} is in use by another user ({}). On a shared machine every \
+                     user needs a port of their own: choose another one in the settings, \
+                     or leave it empty to use a random port",
+                    port,
+                    user
+                );
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_port(_protocol: Protocol, _port: u16) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_socket_owners() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1001        0 1234 1
+   1: 00000000:1F91 0100007F:D431 01 00000000:00000000 00:00000000 00000000  1002        0 1235 1
+";
+        assert_eq!(socket_owner(table, Protocol::Tcp, 8080), Some(1001));
+        // connected, not listening
+        assert_eq!(socket_owner(table, Protocol::Tcp, 8081), None);
+        assert_eq!(socket_owner(table, Protocol::Udp, 8081), Some(1002));
+        assert_eq!(socket_owner(table, Protocol::Udp, 9000), None);
+    }
+}
@@ -0,0 +1,41 @@
+use iroh_bytes::protocol::ALPN;
+
+/// Close code for connections from peers speaking a protocol version we can't serve.
+pub const INCOMPATIBLE: u32 = 2;
+
+/// Older protocol versions peers may still offer. They are accepted during the
+/// handshake only to tell the peer which version we speak, instead of letting
+/// the handshake fail without any explanation.
+const LEGACY_ALPNS: &[&[u8]] = &[b"/iroh-bytes/1", b"/iroh-bytes/2"];
+
+const REFUSAL_PREFIX: &str = "incompatible version, expected ";
+
+/// The protocols to advertise, ours first.
+pub fn alpns() -> Vec<Vec<u8>> {
+    std::iter::once(ALPN)
+        .chain(LEGACY_ALPNS.iter().copied())
+        .map(<[u8]>::to_vec)
+        .collect()
+}
+
+/// What we speak, as sent to incompatible peers.
+fn advertised() -> String {
+    format!(
+        "{} (sendme {})",
+        String::from_utf8_lossy(ALPN),
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Close reason for incompatible peers, readable for older clients as well.
+pub fn refusal() -> Vec<u8> {
+    format!("{}{}", REFUSAL_PREFIX, advertised()).into_bytes()
+}
+
+/// The version an incompatible peer advertised in its close reason.
+pub fn parse_refusal(reason: &[u8]) -> Option<String> {
+    std::str::from_utf8(reason)
+        .ok()?
+        .strip_prefix(REFUSAL_PREFIX)
+        .map(str::to_string)
+}
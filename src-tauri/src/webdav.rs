@@ -0,0 +1,529 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use base64::Engine;
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt};
+use iroh_bytes::{
+    store::{Map, MapEntry},
+    Hash,
+};
+use iroh_io::AsyncSliceReader;
+use rand::Rng;
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_util::task::LocalPoolHandle;
+
+use crate::{
+    auth::{tokens_equal, SessionToken},
+    cloud::encode_segment,
+    message::escape_html,
+    settings::SettingsStore,
+};
+
+/// Size of the chunks file contents are read and sent in.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Longest request head that is accepted.
+const MAX_HEAD: usize = 16 * 1024;
+
+/// User name of the WebDAV login, the password changes every session.
+const USERNAME: &str = "sendme";
+
+/// Reads `len` bytes at `offset` of a blob from a share's store.
+pub type ReadFn =
+    Arc<dyn Fn(Hash, u64, usize) -> BoxFuture<'static, io::Result<Bytes>> + Send + Sync>;
+
+/// Read access to the blobs of `db`. Store readers need not be `Send`, so
+/// they are driven on `rt`.
+pub fn reader<D: Map>(db: D, rt: LocalPoolHandle) -> ReadFn {
+    Arc::new(move |hash, offset, len| {
+        let db = db.clone();
+        rt.spawn_pinned(move || async move {
+            let entry = db
+                .get(&hash)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "blob not found"))?;
+            let mut reader = entry.data_reader().await?;
+            reader.read_at(offset, len).await
+        })
+        .map(|res| res.unwrap_or_else(|err| Err(io::Error::other(err))))
+        .boxed()
+    })
+}
+
+/// An active share as seen over WebDAV.
+#[derive(Clone)]
+pub struct ShareView {
+    /// The collection entries as (name, hash, size).
+    pub files: Vec<(String, Hash, u64)>,
+    pub read: ReadFn,
+}
+
+impl std::fmt::Debug for ShareView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShareView")
+            .field("files", &self.files)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The active shares, by the folder name they are shown under.
+///
+/// Shares with a password or a list of allowed peers are not shown, WebDAV
+/// clients can not give either. Clients log in with [`USERNAME`] and a
+/// password made at startup, so other local users and programs can not read
+/// the shares.
+#[derive(Debug)]
+pub struct WebDavShares {
+    shares: Mutex<BTreeMap<String, ShareView>>,
+    password: String,
+}
+
+impl Default for WebDavShares {
+    fn default() -> Self {
+        Self {
+            shares: Default::default(),
+            password: hex::encode(rand::thread_rng().gen::<[u8; 16]>()),
+        }
+    }
+}
+
+impl WebDavShares {
+    /// Show a share, returning the folder name to remove it with.
+    pub fn insert(&self, hash: &Hash, view: ShareView) -> String {
+        let root = view
+            .files
+            .first()
+            .and_then(|(name, _, _)| name.split('/').next())
+            .unwrap_or("share");
+        let mut shares = self.shares.lock().unwrap();
+        // a ticket can be mounted while the same collection is shared
+        let mut key = format!("{} ({})", root, &hash.to_hex()[..8]);
+        for n in 2.. {
+            if !shares.contains_key(&key) {
+                break;
+            }
+            key = format!("{} ({}, {})", root, &hash.to_hex()[..8], n);
+        }
+        shares.insert(key.clone(), view);
+        key
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.shares.lock().unwrap().remove(key);
+    }
+
+    /// Whether `request` carries the login of this session.
+    fn authorized(&self, request: &Request) -> bool {
+        let Some(encoded) = request
+            .headers
+            .get("authorization")
+            .and_then(|value| value.strip_prefix("Basic "))
+        else {
+            return false;
+        };
+        let Ok(credentials) = base64::engine::general_purpose::STANDARD.decode(encoded.trim())
+        else {
+            return false;
+        };
+        let expected = format!("{}:{}", USERNAME, self.password);
+        tokens_equal(&expected, &String::from_utf8_lossy(&credentials))
+    }
+}
+
+/// What a request path points to.
+enum Node {
+    /// A folder, with its children and their sizes, `None` for folders.
+    Dir(BTreeMap<String, Option<u64>>),
+    File {
+        hash: Hash,
+        size: u64,
+        read: ReadFn,
+    },
+}
+
+fn resolve(shares: &WebDavShares, segments: &[String]) -> Option<Node> {
+    let shares = shares.shares.lock().unwrap();
+    let Some((key, rest)) = segments.split_first() else {
+        let children = shares.keys().map(|key| (key.clone(), None)).collect();
+        return Some(Node::Dir(children));
+    };
+    let share = shares.get(key)?;
+    let path = rest.join("/");
+    if let Some((_, hash, size)) = share.files.iter().find(|(name, _, _)| *name == path) {
+        return Some(Node::File {
+            hash: *hash,
+            size: *size,
+            read: share.read.clone(),
+        });
+    }
+    let prefix = if path.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", path)
+    };
+    let mut children = BTreeMap::new();
+    for (name, _, size) in &share.files {
+        let Some(rest) = name.strip_prefix(&prefix) else {
+            continue;
+        };
+        match rest.split_once('/') {
+            Some((dir, _)) => children.insert(dir.to_string(), None),
+            None => children.insert(rest.to_string(), Some(*size)),
+        };
+    }
+    (!children.is_empty()).then_some(Node::Dir(children))
+}
+
+pub struct Request {
+    pub method: String,
+    pub segments: Vec<String>,
+    /// Header names are lower case.
+    pub headers: HashMap<String, String>,
+}
+
+fn percent_decode(s: &str) -> anyhow::Result<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [
+                iter.next().context("bad escape")?,
+                iter.next().context("bad escape")?,
+            ];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex)?, 16)?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+pub async fn read_request(stream: &mut BufReader<TcpStream>) -> anyhow::Result<Request> {
+    let mut head = String::new();
+    loop {
+        // one more byte than allowed, so overlong lines fail below
+        let limit = (MAX_HEAD + 1).saturating_sub(head.len()) as u64;
+        let n = (&mut *stream).take(limit).read_line(&mut head).await?;
+        anyhow::ensure!(n > 0, "connection closed");
+        anyhow::ensure!(head.len() <= MAX_HEAD, "request too large");
+        if head.ends_with("\r\n\r\n") || head == "\r\n" {
+            break;
+        }
+    }
+    let mut lines = head.lines();
+    let mut request_line = lines.next().context("empty request")?.split_whitespace();
+    let method = request_line.next().context("no method")?.to_string();
+    let target = request_line.next().context("no path")?;
+    let path = target.split('?').next().unwrap_or_default();
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(percent_decode)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    anyhow::ensure!(
+        segments.iter().all(|s| s != ".." && s != "."),
+        "invalid path"
+    );
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect::<HashMap<_, _>>();
+    // requests are answered without looking at their body, e.g. the props
+    // asked for by PROPFIND, so just skip it
+    if let Some(len) = headers.get("content-length") {
+        let len = len.parse::<u64>()?;
+        anyhow::ensure!(len <= MAX_HEAD as u64, "request body too large");
+        let mut body = Vec::new();
+        (&mut *stream).take(len).read_to_end(&mut body).await?;
+    }
+    Ok(Request {
+        method,
+        segments,
+        headers,
+    })
+}
+
+async fn respond(
+    stream: &mut BufReader<TcpStream>,
+    status: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !headers.iter().any(|(name, _)| *name == "Content-Length") {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+    let stream = stream.get_mut();
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await
+}
+
+fn href(segments: &[String], child: Option<&str>, dir: bool) -> String {
+    let mut href = String::new();
+    for segment in segments.iter().map(String::as_str).chain(child) {
+        href.push('/');
+        href.push_str(&encode_segment(segment));
+    }
+    if dir {
+        href.push('/');
+    }
+    href
+}
+
+fn prop_response(href: &str, name: &str, size: Option<u64>) -> String {
+    let props = match size {
+        Some(size) => format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+             <D:getcontenttype>application/octet-stream</D:getcontenttype>",
+            size
+        ),
+        None => "<D:resourcetype><D:collection/></D:resourcetype>".to_string(),
+    };
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname>{}</D:prop>\
+         <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        escape_html(href),
+        escape_html(name),
+        props
+    )
+}
+
+fn propfind(request: &Request, node: &Node) -> String {
+    let name = request.segments.last().map(String::as_str).unwrap_or("");
+    let mut body =
+        String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?><D:multistatus xmlns:D=\"DAV:\">");
+    match node {
+        Node::File { size, .. } => {
+            let own = href(&request.segments, None, false);
+            body.push_str(&prop_response(&own, name, Some(*size)));
+        }
+        Node::Dir(children) => {
+            let own = href(&request.segments, None, true);
+            body.push_str(&prop_response(&own, name, None));
+            let depth = request.headers.get("depth").map(String::as_str);
+            if depth != Some("0") {
+                for (child, size) in children {
+                    let link = href(&request.segments, Some(child), size.is_none());
+                    body.push_str(&prop_response(&link, child, *size));
+                }
+            }
+        }
+    }
+    body.push_str("</D:multistatus>");
+    body
+}
+
+/// The requested range of a file of `size` bytes, as (start, end exclusive).
+fn parse_range(header: Option<&String>, size: u64) -> Option<(u64, u64)> {
+    let spec = header?.strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) => (start, end.saturating_add(1).min(size)),
+        (Ok(start), Err(_)) => (start, size),
+        // a suffix range, the last `end` bytes
+        (Err(_), Ok(end)) => (size.saturating_sub(end), size),
+        _ => return None,
+    };
+    (start < end).then_some((start, end))
+}
+
+async fn send_file(
+    stream: &mut BufReader<TcpStream>,
+    request: &Request,
+    hash: Hash,
+    size: u64,
+    read: ReadFn,
+) -> anyhow::Result<()> {
+    let range = parse_range(request.headers.get("range"), size);
+    let (start, end) = range.unwrap_or((0, size));
+    let mut headers = vec![
+        ("Content-Type", "application/octet-stream".to_string()),
+        ("Content-Length", (end - start).to_string()),
+        ("Accept-Ranges", "bytes".to_string()),
+        ("ETag", format!("\"{}\"", hash.to_hex())),
+    ];
+    let status = if range.is_some() {
+        headers.push((
+            "Content-Range",
+            format!("bytes {}-{}/{}", start, end - 1, size),
+        ));
+        "206 Partial Content"
+    } else {
+        "200 OK"
+    };
+    respond(stream, status, &headers, b"").await?;
+    if request.method == "HEAD" {
+        return Ok(());
+    }
+    let mut offset = start;
+    while offset < end {
+        let len = (end - offset).min(CHUNK_SIZE as u64) as usize;
+        let data = read(hash, offset, len).await?;
+        anyhow::ensure!(!data.is_empty(), "blob ended early");
+        stream.get_mut().write_all(&data).await?;
+        offset += data.len() as u64;
+    }
+    Ok(())
+}
+
+async fn handle(stream: TcpStream, shares: &WebDavShares, port: u16) -> anyhow::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let request = read_request(&mut stream).await?;
+    // only answer requests meant for us, so web pages can't reach the shares
+    // through DNS rebinding
+    if !is_local_host(&request, port) {
+        respond(&mut stream, "403 Forbidden", &[], b"").await?;
+        return Ok(());
+    }
+    if !shares.authorized(&request) {
+        let challenge = [(
+            "WWW-Authenticate",
+            "Basic realm=\"sendme\", charset=\"UTF-8\"".to_string(),
+        )];
+        respond(&mut stream, "401 Unauthorized", &challenge, b"").await?;
+        return Ok(());
+    }
+    let read_only = [
+        ("DAV", "1".to_string()),
+        ("Allow", "OPTIONS, PROPFIND, GET, HEAD".to_string()),
+    ];
+    if request.method == "OPTIONS" {
+        respond(&mut stream, "200 OK", &read_only, b"").await?;
+        return Ok(());
+    }
+    let Some(node) = resolve(shares, &request.segments) else {
+        respond(&mut stream, "404 Not Found", &[], b"").await?;
+        return Ok(());
+    };
+    match (request.method.as_str(), node) {
+        ("PROPFIND", node) => {
+            let body = propfind(&request, &node);
+            let headers = [("Content-Type", "application/xml; charset=utf-8".to_string())];
+            respond(&mut stream, "207 Multi-Status", &headers, body.as_bytes()).await?;
+        }
+        ("GET" | "HEAD", Node::File { hash, size, read }) => {
+            send_file(&mut stream, &request, hash, size, read).await?;
+        }
+        _ => respond(&mut stream, "405 Method Not Allowed", &read_only, b"").await?,
+    }
+    Ok(())
+}
+
+/// Whether `request` was sent to localhost on `port`, and not to a name
+/// resolving to it.
+pub fn is_local_host(request: &Request, port: u16) -> bool {
+    let host = request.headers.get("host");
+    host.is_some_and(|host| is_local(host, port))
+}
+
+/// Whether the `Host` header `host` names localhost on `port`.
+pub fn is_local(host: &str, port: u16) -> bool {
+    host == format!("127.0.0.1:{}", port) || host == format!("localhost:{}", port)
+}
+
+/// Serve the active shares read only over WebDAV on localhost, if enabled.
+pub fn spawn_server(app: AppHandle) {
+    let Some(port) = app.state::<SettingsStore>().get().webdav_port else {
+        return;
+    };
+    if let Err(err) = crate::users::check_port(crate::users::Protocol::Tcp, port) {
+        log!("not starting the webdav server: {:#}", err);
+        return;
+    }
+    let shares = app.state::<Arc<WebDavShares>>().inner().clone();
+    tauri::async_runtime::spawn(async move {
+        let listener = match tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                log!(
+                    "failed to start the webdav server on port {}: {}",
+                    port,
+                    err
+                );
+                return;
+            }
+        };
+        log!(
+            "serving active shares over webdav at http://127.0.0.1:{}/",
+            port
+        );
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let shares = shares.clone();
+            tokio::spawn(async move {
+                if let Err(err) = handle(stream, &shares, port).await {
+                    log!("webdav request failed: {:#}", err);
+                }
+            });
+        }
+    });
+}
+
+/// How to mount the shares, shown in the settings.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebDavLogin {
+    pub url: String,
+    pub username: String,
+    pub password: String,
+}
+
+/// The address and login of the WebDAV server, if it is enabled.
+#[tauri::command]
+pub fn webdav_login(
+    token: String,
+    session: State<'_, SessionToken>,
+    settings: State<'_, SettingsStore>,
+    shares: State<'_, Arc<WebDavShares>>,
+) -> Result<Option<WebDavLogin>, String> {
+    session.verify(&token)?;
+    Ok(settings.get().webdav_port.map(|port| WebDavLogin {
+        url: format!("http://127.0.0.1:{}/", port),
+        username: USERNAME.to_string(),
+        password: shares.password.clone(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_the_login() {
+        let shares = WebDavShares::default();
+        let request = |auth: Option<String>| Request {
+            method: "PROPFIND".to_string(),
+            segments: Vec::new(),
+            headers: auth
+                .map(|auth| ("authorization".to_string(), auth))
+                .into_iter()
+                .collect(),
+        };
+        let basic = |credentials: &str| {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+            Some(format!("Basic {}", encoded))
+        };
+        let login = format!("{}:{}", USERNAME, shares.password);
+        assert!(shares.authorized(&request(basic(&login))));
+        assert!(!shares.authorized(&request(None)));
+        assert!(!shares.authorized(&request(basic("sendme:guess"))));
+        assert!(!shares.authorized(&request(Some(format!("Bearer {}", shares.password)))));
+    }
+}
@@ -1,6 +1,5 @@
 import { useState } from "react";
 import reactLogo from "./assets/react.svg";
-import { invoke } from "@tauri-apps/api/tauri";
 import "./App.css";
 import { listen } from '@tauri-apps/api/event'
 
@@ -9,13 +8,8 @@ import { listen } from '@tauri-apps/api/event'
 function App() {
   const [uploadMsg, setUploadMsg] = useState("");
 
-  listen('tauri://file-drop', event => {
-    console.log(uploadMsg, event);
-    
-    if (!uploadMsg) {
-      invoke("upload", { file: event.payload[0] })
-      .then(msg => setUploadMsg(msg));
-    }
+  listen<{ name: string, ticket: string }>('share-created', event => {
+    setUploadMsg(event.payload.ticket);
   });
 
   let output = <div>Drop it</div>;